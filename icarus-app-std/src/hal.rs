@@ -0,0 +1,264 @@
+//
+// hal.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 22 2022
+//
+
+use crate::stat::StatLed;
+
+use icarus_core::mixer::NUM_MOTORS;
+use icarus_flight::{
+    bus::{I2cBus, I2cLines, ManagedI2c},
+    esc::{DShotMotors, DShotTiming, DShotTx, PwmTiming, DSHOT_FRAME_BITS},
+    hal::{BatterySense, Clock, Led, Motors},
+    led::Rgb,
+};
+
+use esp_idf_hal::{
+    adc::{Atten11dB, PoweredAdc, ADC1},
+    gpio::{Gpio0, Gpio1, Gpio2, Gpio3, Input, OutputPin, Unknown},
+    i2c::{Master, I2C0},
+    ledc::config::Resolution,
+    rmt::{config::TransmitConfig, FixedLengthSignal, HwChannel, PinState, Pulse, Transmit},
+};
+use esp_idf_sys::{self as sys, EspError};
+
+use embedded_hal_0_2::{adc::OneShot, digital::v2::InputPin, PwmPin};
+
+use std::{convert::Infallible, sync::Mutex, time::{Duration, Instant}};
+
+/// I2C0, shared by every sensor on the board
+pub type SensorBus = I2cBus<Mutex<ManagedI2c<Master<I2C0, Gpio1<Unknown>, Gpio2<Unknown>>, EspI2cLines>>>;
+
+/// Raw control of the I2C0 pins for bus recovery
+pub struct EspI2cLines {
+    sda: i32,
+    scl: i32,
+}
+
+impl EspI2cLines {
+    pub fn new(sda: i32, scl: i32) -> Self {
+        Self { sda, scl }
+    }
+}
+
+impl I2cLines for EspI2cLines {
+    fn acquire(&mut self) {
+        unsafe {
+            sys::gpio_set_direction(self.sda, sys::gpio_mode_t_GPIO_MODE_INPUT_OUTPUT_OD);
+            sys::gpio_set_direction(self.scl, sys::gpio_mode_t_GPIO_MODE_INPUT_OUTPUT_OD);
+        }
+    }
+
+    fn release(&mut self) {
+        // Route the pins back through the GPIO matrix to the I2C controller
+        unsafe {
+            sys::i2c_set_pin(sys::i2c_port_t_I2C_NUM_0, self.sda, self.scl, true, true, sys::i2c_mode_t_I2C_MODE_MASTER);
+        }
+    }
+
+    fn set_scl(&mut self, high: bool) {
+        unsafe { sys::gpio_set_level(self.scl, high as u32) };
+    }
+
+    fn set_sda(&mut self, high: bool) {
+        unsafe { sys::gpio_set_level(self.sda, high as u32) };
+    }
+
+    fn sda_is_high(&self) -> bool {
+        unsafe { sys::gpio_get_level(self.sda) != 0 }
+    }
+
+    fn delay(&mut self) {
+        // Half a period at 100 kHz
+        unsafe { sys::ets_delay_us(5) };
+    }
+}
+
+/// Full scale of the 11 dB attenuated ADC (mV)
+const ADC_FULL_SCALE_MV: u32 = 2500;
+/// Maximum raw reading of the 12 bit ADC
+const ADC_MAX: u32 = 4095;
+
+/// Battery voltage divider on ADC1 channel 3 and the charger's charge complete output on GPIO0
+///
+/// The charge complete output is open drain and pulled low once charging has finished.
+pub struct EspBattery {
+    adc: PoweredAdc<ADC1>,
+    sense: Gpio3<Atten11dB<ADC1>>,
+    charge_complete: Gpio0<Input>,
+}
+
+impl EspBattery {
+    pub fn new(adc: PoweredAdc<ADC1>, sense: Gpio3<Atten11dB<ADC1>>, charge_complete: Gpio0<Input>) -> Self {
+        Self { adc, sense, charge_complete }
+    }
+}
+
+impl BatterySense for EspBattery {
+    type Error = EspError;
+
+    fn read_raw(&mut self) -> Result<u16, Self::Error> {
+        // ESP-IDF reports calibrated millivolts. Scale back to counts so the divider is the only board parameter
+        let mv = nb::block!(self.adc.read(&mut self.sense))?;
+        Ok((mv as u32 * ADC_MAX / ADC_FULL_SCALE_MV).min(ADC_MAX) as u16)
+    }
+
+    fn charge_complete(&mut self) -> bool {
+        self.charge_complete.is_low().unwrap_or(false)
+    }
+}
+
+/// Clock feeding the LEDC timers
+pub const LEDC_SOURCE_HZ: u32 = 80_000_000;
+
+/// LEDC timer resolution for a PWM timing
+pub fn ledc_resolution(timing: &PwmTiming) -> Resolution {
+    match timing.duty_bits(LEDC_SOURCE_HZ) {
+        1 => Resolution::Bits1,
+        2 => Resolution::Bits2,
+        3 => Resolution::Bits3,
+        4 => Resolution::Bits4,
+        5 => Resolution::Bits5,
+        6 => Resolution::Bits6,
+        7 => Resolution::Bits7,
+        8 => Resolution::Bits8,
+        9 => Resolution::Bits9,
+        10 => Resolution::Bits10,
+        11 => Resolution::Bits11,
+        12 => Resolution::Bits12,
+        13 => Resolution::Bits13,
+        _ => Resolution::Bits14,
+    }
+}
+
+/// Rotor control PWM channels, in mixer order
+pub struct PwmMotors {
+    channels: [Box<dyn PwmPin<Duty = u32> + Send>; NUM_MOTORS],
+    timing: PwmTiming,
+}
+
+impl PwmMotors {
+    /// Channels must share a timer running at the timing's frequency
+    pub fn new(channels: [Box<dyn PwmPin<Duty = u32> + Send>; NUM_MOTORS], timing: PwmTiming) -> Self {
+        Self { channels, timing }
+    }
+}
+
+impl Motors for PwmMotors {
+    type Error = Infallible;
+
+    fn set(&mut self, outputs: &[f32; NUM_MOTORS]) -> Result<(), Self::Error> {
+        for (channel, output) in self.channels.iter_mut().zip(outputs.iter()) {
+            let duty = self.timing.duty(*output, channel.get_max_duty());
+            channel.set_duty(duty);
+        }
+
+        Ok(())
+    }
+}
+
+/// DShot frames from a single RMT channel
+///
+/// The C3 only has two RMT transmit channels and the status LED uses one, so the remaining channel is moved between
+/// the motor pins. Frames are sent one motor at a time, about 55 us each at DShot300.
+pub struct RmtDShot<P: OutputPin, C: HwChannel> {
+    tx: Transmit<P, C>,
+    /// Motor pin numbers in mixer order
+    pins: [i32; NUM_MOTORS],
+    /// Motor the channel is currently routed to
+    current: usize,
+    timing: DShotTiming,
+}
+
+impl<P: OutputPin, C: HwChannel> RmtDShot<P, C> {
+    /// `pin` must be the first motor pin in `pins`
+    pub fn new(pin: P, channel: C, pins: [i32; NUM_MOTORS], timing: DShotTiming) -> Result<Self, EspError> {
+        let config = TransmitConfig::new().clock_divider(1);
+        let tx = Transmit::new(pin, channel, &config)?;
+
+        // The other pins idle low until the channel is routed to them
+        for pin in &pins[1..] {
+            unsafe {
+                sys::gpio_set_direction(*pin, sys::gpio_mode_t_GPIO_MODE_OUTPUT);
+                sys::gpio_set_level(*pin, 0);
+            }
+        }
+
+        Ok(Self { tx, pins, current: 0, timing })
+    }
+}
+
+impl<P: OutputPin, C: HwChannel> DShotTx for RmtDShot<P, C> {
+    type Error = EspError;
+
+    fn send(&mut self, motor: usize, frame: u16) -> Result<(), Self::Error> {
+        if motor != self.current {
+            // Switching the previous pin back to a plain output disconnects it from the RMT signal
+            unsafe {
+                sys::gpio_set_direction(self.pins[self.current], sys::gpio_mode_t_GPIO_MODE_OUTPUT);
+                sys::gpio_set_level(self.pins[self.current], 0);
+                sys::esp!(sys::rmt_set_gpio(C::channel(), sys::rmt_mode_t_RMT_MODE_TX, self.pins[motor], false))?;
+            }
+            self.current = motor;
+        }
+
+        let ticks_hz = self.tx.counter_clock()?;
+        let mut signal = FixedLengthSignal::<DSHOT_FRAME_BITS>::new();
+
+        for (i, (high, low)) in self.timing.pulses(frame).iter().enumerate() {
+            let high = Pulse::new_with_duration(ticks_hz, PinState::High, &Duration::from_nanos(*high as u64))?;
+            let low = Pulse::new_with_duration(ticks_hz, PinState::Low, &Duration::from_nanos(*low as u64))?;
+            signal.set(i, &(high, low))?;
+        }
+
+        self.tx.start_blocking(&signal)
+    }
+}
+
+/// Motor output selected by the motor protocol parameter
+pub enum EspMotors<P: OutputPin, C: HwChannel> {
+    Pwm(PwmMotors),
+    DShot(DShotMotors<RmtDShot<P, C>>),
+}
+
+impl<P: OutputPin, C: HwChannel> Motors for EspMotors<P, C> {
+    type Error = EspError;
+
+    fn set(&mut self, outputs: &[f32; NUM_MOTORS]) -> Result<(), Self::Error> {
+        match self {
+            EspMotors::Pwm(motors) => {
+                motors.set(outputs).ok();
+                Ok(())
+            }
+            EspMotors::DShot(motors) => motors.set(outputs),
+        }
+    }
+}
+
+/// Time since boot. Copies share the same start time
+#[derive(Clone, Copy)]
+pub struct StdClock {
+    start: Instant,
+}
+
+impl Default for StdClock {
+    fn default() -> Self {
+        Self { start: Instant::now() }
+    }
+}
+
+impl Clock for StdClock {
+    fn now_us(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+}
+
+impl<P: OutputPin, C: HwChannel> Led for StatLed<P, C> {
+    type Error = EspError;
+
+    fn set(&mut self, color: Rgb) -> Result<(), Self::Error> {
+        self.update(color)
+    }
+}
@@ -6,39 +6,56 @@
 //
 
 use icarus_app_std::{
-    stat::{StatColor, StatLed},
+    stat::StatLed,
     wifi::AppWifi,
     console::{self, ConsoleCommand, WirelessCommands},
-    ImuCalibrationOffset,
+    params::ParameterStore,
+    hal::{ledc_resolution, EspBattery, EspI2cLines, EspMotors, PwmMotors, RmtDShot, SensorBus, StdClock},
 };
 use icarus_core::{
-    data::{AccelerometerData, GyroscopeData},
-    EstimatorInput, StateEstimator,
+    button::{ButtonAction, ButtonConfig, ButtonDetector},
+    params::Parameters,
+};
+use icarus_flight::{
+    bus::I2cBus,
+    esc::{DShotMotors, MotorOutput},
+    drivers::mpu6050::{self, Mpu6050, Mpu6050Config},
+    hal::{Clock, Imu, Led, NoBarometer, NoInterrupt},
+    led::{Indications, LedEngine},
+    FlightTask,
+};
+use icarus_wire::{
+    self, CommandRequest, IcarusCommand, IcarusState, NackReason, MessageCounts, FrameAccumulator, FeedResult,
 };
-use icarus_wire::{self, IcarusCommand, IcarusState, CobsAccumulator, FeedResult};
 
-use esp_idf_hal::{delay::FreeRtos, i2c, ledc::*, peripherals::Peripherals, prelude::*};
+use esp_idf_hal::{adc, gpio::Pull, i2c, ledc::*, peripherals::Peripherals, prelude::*};
+use esp_idf_svc::nvs::EspDefaultNvs;
 use esp_idf_sys as _; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 
-use mpu6050::Mpu6050;
-
 use std::{
     io::{self, Read, Write},
     net::{TcpListener, TcpStream},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
         // mpsc::channel,
         Arc,
     },
     thread,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
+use embedded_hal_0_2::digital::v2::InputPin;
+
 use heapless::spsc::Queue;
 
 const WIFI_SSID: &str = env!("ICARUS_WIFI_SSID");
 const WIFI_PASS: &str = env!("ICARUS_WIFI_PASS");
 
+/// Status LED refresh period
+const LED_PERIOD_MS: u64 = 20;
+/// Time between acknowledging a reboot and restarting
+const REBOOT_DELAY_MS: u64 = 100;
+
 #[allow(unreachable_code)]
 fn main() -> anyhow::Result<()> {
     // Temporary. Will disappear once ESP-IDF 4.4 is released, but for now it is necessary to call this function once,
@@ -51,6 +68,15 @@ fn main() -> anyhow::Result<()> {
 
     // let mut logger = defmt_bbq::init().unwrap();
 
+    // -----------------------------------------------------------------------------------------------------------------
+    // Parameters
+    // -----------------------------------------------------------------------------------------------------------------
+
+    let default_nvs = Arc::new(EspDefaultNvs::new()?);
+
+    let mut param_store = ParameterStore::new(default_nvs.clone())?;
+    let params = param_store.load();
+
     // -----------------------------------------------------------------------------------------------------------------
     // Hardware Init
     // -----------------------------------------------------------------------------------------------------------------
@@ -61,16 +87,42 @@ fn main() -> anyhow::Result<()> {
     let _drv1_en = p.pins.gpio10.into_output()?;
     let _drv2_en = p.pins.gpio6.into_output()?;
 
-    let config = config::TimerConfig::default().frequency(50.Hz().into());
-    let timer = Arc::new(Timer::new(p.ledc.timer0, &config)?);
+    // Output stage for the configured protocol. Changing protocol needs a reboot
+    let motors = match MotorOutput::from_protocol(params.motor_protocol) {
+        MotorOutput::Pwm(timing) => {
+            let config = config::TimerConfig::default()
+                .frequency(timing.frequency_hz.Hz().into())
+                .resolution(ledc_resolution(&timing));
+            let timer = Arc::new(Timer::new(p.ledc.timer0, &config)?);
+
+            let rtrctl1 = Channel::new(p.ledc.channel0, timer.clone(), p.pins.gpio8)?;
+            let rtrctl2 = Channel::new(p.ledc.channel1, timer.clone(), p.pins.gpio7)?;
+            let rtrctl3 = Channel::new(p.ledc.channel2, timer.clone(), p.pins.gpio5)?;
+            let rtrctl4 = Channel::new(p.ledc.channel3, timer.clone(), p.pins.gpio4)?;
+
+            EspMotors::Pwm(PwmMotors::new(
+                [Box::new(rtrctl1), Box::new(rtrctl2), Box::new(rtrctl3), Box::new(rtrctl4)],
+                timing,
+            ))
+        }
+        MotorOutput::DShot(timing) => {
+            let rmt = RmtDShot::new(p.pins.gpio8, p.rmt.channel1, [8, 7, 5, 4], timing)?;
+            EspMotors::DShot(DShotMotors::new(rmt))
+        }
+    };
 
-    let _rtrctl1 = Channel::new(p.ledc.channel0, timer.clone(), p.pins.gpio8)?;
-    let _rtrctl2 = Channel::new(p.ledc.channel1, timer.clone(), p.pins.gpio7)?;
-    let _rtrctl3 = Channel::new(p.ledc.channel2, timer.clone(), p.pins.gpio5)?;
-    let _rtrctl4 = Channel::new(p.ledc.channel3, timer.clone(), p.pins.gpio4)?;
+    // GPIO. The user button is a strapping pin, held through reset the chip enters download mode. A boot hold has to
+    // start just after power on
+    let mut user_button = p.pins.gpio9.into_input()?;
+    user_button.set_pull_up()?;
 
-    // GPIO
-    let _user_button = p.pins.gpio9.into_input()?;
+    // Battery sense and charger status
+    let battery_adc = adc::PoweredAdc::new(p.adc1, adc::config::Config::new().calibration(true))?;
+    let battery_sense = p.pins.gpio3.into_analog_atten_11db()?;
+    let mut charge_complete = p.pins.gpio0.into_input()?;
+    charge_complete.set_pull_up()?;
+
+    let battery = EspBattery::new(battery_adc, battery_sense, charge_complete);
 
     // Stat LED
     let mut stat_led = StatLed::new(p.pins.gpio21, p.rmt.channel0)?;
@@ -83,13 +135,18 @@ fn main() -> anyhow::Result<()> {
     let i2c =
         i2c::Master::<i2c::I2C0, _, _>::new(p.i2c0, i2c::MasterPins { sda, scl }, i2c_config)?;
 
-    let mut delay = FreeRtos {};
+    // Every sensor gets a proxy to the one bus. Lives for the life of the program
+    let bus: &'static SensorBus = Box::leak(Box::new(I2cBus::new(i2c, EspI2cLines::new(1, 2))));
+
+    // The IMU and flight task share a time base so samples can be timestamped
+    let clock = StdClock::default();
 
-    let mut imu = Mpu6050::new_with_addr(i2c, 0x68);
+    // The INT line is not routed on this board. The FIFO collects every sample between loop iterations
+    let mut imu = Mpu6050::new(bus.acquire(), mpu6050::DEFAULT_ADDRESS, Mpu6050Config::default(), clock, NoInterrupt);
 
     for i in 0..5 {
         println!("Initializing IMU. Attempt {}", i + 1);
-        if imu.init(&mut delay).is_ok() {
+        if imu.reinit().is_ok() {
             break;
         }
         thread::sleep(Duration::from_millis(10));
@@ -102,7 +159,7 @@ fn main() -> anyhow::Result<()> {
     // -----------------------------------------------------------------------------------------------------------------
 
     // Setup WiFi (in the future this will be Bluetooth LE)
-    let mut wifi = AppWifi::new()?;
+    let mut wifi = AppWifi::new(default_nvs)?;
     wifi.connect(WIFI_SSID, WIFI_PASS)?;
 
     // -----------------------------------------------------------------------------------------------------------------
@@ -110,10 +167,10 @@ fn main() -> anyhow::Result<()> {
     // -----------------------------------------------------------------------------------------------------------------
 
     // Setup task queues and shared state
-    static mut COMMAND_QUEUE: Queue<IcarusCommand, 2> = Queue::new();
+    static mut COMMAND_QUEUE: Queue<CommandRequest, 2> = Queue::new();
     let (mut cmd_tx, mut cmd_rx) = unsafe { COMMAND_QUEUE.split() };
 
-    static mut STATE_QUEUE: Queue<IcarusState, 4> = Queue::new();
+    static mut STATE_QUEUE: Queue<IcarusState, 8> = Queue::new();
     let (mut state_tx, mut state_rx) = unsafe { STATE_QUEUE.split() };
 
     static mut CONSOLE_COMMAND_QUEUE: Queue<ConsoleCommand, 2> = Queue::new();
@@ -126,6 +183,18 @@ fn main() -> anyhow::Result<()> {
     let wireless_connected_read1 = wireless_connected.clone();
     let wireless_connected_read2 = wireless_connected.clone();
 
+    // Status from the control task for the LED
+    let indications = Arc::new(AtomicU16::new(Indications::BOOT.0));
+    let indications_read = indications.clone();
+
+    // Access point toggle requested with the user button
+    let toggle_access_point = Arc::new(AtomicBool::new(false));
+    let toggle_access_point_request = toggle_access_point.clone();
+
+    // Command frames the idle task dropped for failing the integrity check, reported by the control task
+    let corrupt_frames = Arc::new(AtomicU32::new(0));
+    let corrupt_frames_read = corrupt_frames.clone();
+
     // Spawn serial console command task
     thread::spawn(move || {
         let mut read_buf: [u8; 64] = [0; 64];
@@ -170,77 +239,98 @@ fn main() -> anyhow::Result<()> {
     });
 
     // Control task
-    //
-    // 1. Get sensor input
-    // 2. Pass sensor data to the state estimator
-    // 3. Use estimated state in PID control loop
-    // 4. 'Mix' motor output
     thread::spawn(move || {
-        let offsets = calibrate_imu(500, 20, || {
-            let a = imu.get_acc().unwrap();
-            let g = imu.get_gyro().unwrap();
+        let mut flight = FlightTask::new(imu, NoBarometer, battery, motors, clock, params);
+        let mut bus_stats = bus.stats();
+        let mut param_fault = false;
+        let mut button = ButtonDetector::new(ButtonConfig::default());
 
-            ((a.x, a.y, a.z), (g.x, g.y, g.z))
-        });
+        loop {
+            // Telemetry that did not fit in the queue this iteration
+            let mut dropped = MessageCounts::default();
+
+            // Process commands from the host
+            while let Some(request) = cmd_rx.dequeue() {
+                let result = flight.handle_request(request, |state| {
+                    if let Err(state) = state_tx.enqueue(state) {
+                        dropped.record(&state);
+                    }
+                });
+
+                match result {
+                    Some(Ok(())) => match request.command {
+                        IcarusCommand::SetParameter(_) => {
+                            param_fault = !save_params(&mut param_store, flight.params());
+                        }
+                        IcarusCommand::Reboot => {
+                            println!("Rebooting");
+                            // Give the comms task time to send the acknowledgement
+                            thread::sleep(Duration::from_millis(REBOOT_DELAY_MS));
+                            unsafe { esp_idf_sys::esp_restart() };
+                        }
+                        _ => {}
+                    },
+                    Some(Err(e)) => eprintln!("Command rejected: {:?}", e),
+                    None => {}
+                }
+            }
 
-        let mut estimator = StateEstimator::default();
-        let mut last_measurement = Instant::now();
+            // User button, active low
+            let pressed = user_button.is_low().unwrap_or(false);
+            if let Some(gesture) = button.update(pressed, clock.now_us() / 1000) {
+                let action = flight.params().button_map.action(gesture);
 
-        loop {
-            // Read IMU data
-            let accel = imu.get_acc();
-            let gyro = imu.get_gyro();
-            let temp = imu.get_temp();
-
-            let now = Instant::now();
-            let delta_time = now.duration_since(last_measurement).as_secs_f32();
-            last_measurement = now;
-
-            if let (Ok(accel), Ok(gyro), Ok(_temp)) = (accel, gyro, temp) {
-                let accel = AccelerometerData {
-                    x: accel.x - offsets.ax_offset,
-                    y: accel.y - offsets.ay_offset,
-                    z: accel.z - offsets.az_offset,
-                };
-                let gyro = GyroscopeData {
-                    x: gyro.x - offsets.gx_offset,
-                    y: gyro.y - offsets.gy_offset,
-                    z: gyro.z - offsets.gz_offset,
-                };
-
-                let input = EstimatorInput {
-                    accel,
-                    gyro,
-                    altitude: 0.0,
-                };
-
-                state_tx.enqueue(IcarusState::Sensors(input.clone())).ok();
-
-                if let Ok(estimated_state) = estimator.update(input, delta_time) {
-                    state_tx.enqueue(IcarusState::EstimatedState(estimated_state)).ok();
+                match flight.handle_action(action) {
+                    Ok(()) => match action {
+                        ButtonAction::FactoryReset => param_fault = !save_params(&mut param_store, flight.params()),
+                        ButtonAction::ToggleAccessPoint => toggle_access_point_request.store(true, Ordering::Relaxed),
+                        _ => {}
+                    },
+                    Err(e) => eprintln!("Button action rejected: {:?}", e),
+                }
+            }
+
+            // Sleep until the next deadline
+            let wait = flight.time_until_next_us();
+            if wait > 0 {
+                thread::sleep(Duration::from_micros(wait));
+            }
+
+            flight.update(|state| {
+                if let Err(state) = state_tx.enqueue(state) {
+                    dropped.record(&state);
                 }
+            });
+            flight.record_dropped(&dropped);
+            flight.record_corrupt_frames(corrupt_frames_read.swap(0, Ordering::Relaxed));
+
+            // A completed calibration is stored in the parameters
+            if flight.take_params_changed() {
+                param_fault = !save_params(&mut param_store, flight.params());
             }
 
-            thread::sleep(Duration::from_millis(20));
+            let mut status = flight.indications();
+            status.set(Indications::PARAMETER_FAULT, param_fault);
+            indications.store(status.0, Ordering::Relaxed);
+
+            let stats = bus.stats();
+            if stats.recoveries != bus_stats.recoveries || stats.failed_recoveries != bus_stats.failed_recoveries {
+                eprintln!("I2C bus recovery: {:?}", stats);
+            }
+            bus_stats = stats;
         }
     });
 
     // Spawn LED task
     thread::spawn(move || {
-        loop {
-            let is_connected = wireless_connected_read1.load(Ordering::Relaxed);
+        let mut engine = LedEngine::default();
 
-            let (color, duration) = if is_connected {
-                (StatColor::Green, 1000)
-            } else {
-                (StatColor::Red, 300)
-            };
-
-            stat_led.update(color).unwrap();
-            thread::sleep(Duration::from_millis(duration));
+        loop {
+            let mut status = Indications(indications_read.load(Ordering::Relaxed));
+            status.set(Indications::LINK, wireless_connected_read1.load(Ordering::Relaxed));
 
-            stat_led.update(StatColor::Black).unwrap();
-            thread::sleep(Duration::from_millis(duration));
+            stat_led.set(engine.update(status, clock.now_us() / 1000)).ok();
+            thread::sleep(Duration::from_millis(LED_PERIOD_MS));
         }
     });
 
@@ -249,7 +339,7 @@ fn main() -> anyhow::Result<()> {
     // Raw data buffer for store pre-deserialized data
     let mut raw_buf: [u8; 128] = [0; 128];
     // COBS deooder
-    let mut cmd_decoder: CobsAccumulator<64> = CobsAccumulator::new();
+    let mut cmd_decoder: FrameAccumulator<64> = FrameAccumulator::new();
 
     loop {
         // Attempt to get the connected stream
@@ -264,13 +354,18 @@ fn main() -> anyhow::Result<()> {
                 Ok(n) => {
                     let mut window = &raw_buf[..n];
                     'cobs: while !window.is_empty() {
-                        window = match cmd_decoder.feed::<IcarusCommand>(window) {
+                        window = match cmd_decoder.feed::<CommandRequest>(window) {
                             FeedResult::Consumed => break 'cobs,
                             FeedResult::OverFull(new_window) => new_window,
                             FeedResult::DeserError(new_window) => new_window,
+                            FeedResult::IntegrityError(new_window) => {
+                                corrupt_frames.fetch_add(1, Ordering::Relaxed);
+                                new_window
+                            }
                             FeedResult::Success { data, remaining } => {
-                                // cmd_tx.enqueue(data).ok();
-                                println!("{:?}", data);
+                                if cmd_tx.enqueue(data).is_err() {
+                                    send_nack(&mut stream, data.id, NackReason::Busy);
+                                }
                                 remaining
                             }
                         }
@@ -306,6 +401,20 @@ fn main() -> anyhow::Result<()> {
             }
         }
 
+        if toggle_access_point.swap(false, Ordering::Relaxed) {
+            let result = if wifi.is_access_point() {
+                wifi.connect(WIFI_SSID, WIFI_PASS)
+            }
+            else {
+                wifi.start_access_point()
+            };
+
+            if let Err(e) = result {
+                eprintln!("Failed to switch network: {:?}", e);
+            }
+            wireless_connected.store(false, Ordering::Relaxed);
+        }
+
         let connected = wireless_connected.load(Ordering::Relaxed);
         if !connected {
             // Check if wifi is connected
@@ -319,6 +428,25 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Persist parameters. Returns false if they could not be saved
+fn save_params(store: &mut ParameterStore, params: &Parameters) -> bool {
+    match store.save(params) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Failed to save parameters: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Reject a command without passing it to the control task
+fn send_nack<W: Write>(stream: &mut W, id: u16, reason: NackReason) {
+    let mut buf: [u8; 16] = [0; 16];
+    if let Ok(used) = icarus_wire::encode(&IcarusState::Nack(id, reason), &mut buf) {
+        stream.write_all(used).ok();
+    }
+}
+
 fn print_wifi_settings(wifi: &mut AppWifi) -> anyhow::Result<()> {
     let connected = wifi.is_connected().unwrap_or(false);
     if connected {
@@ -332,57 +460,6 @@ fn print_wifi_settings(wifi: &mut AppWifi) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Sample accelerometer and gyro data and calculate the device specific offset
-fn calibrate_imu<F>(samples: usize, delay_ms: u64, mut f: F) -> ImuCalibrationOffset
-where
-    F: FnMut() -> ((f32, f32, f32), (f32, f32, f32)),
-{
-    let (a, g) = f();
-
-    // Min / Max values for each axis on the accelerometer and the gyro
-    let mut ax_min: f32 = a.0;
-    let mut ax_max: f32 = a.0;
-    let mut ay_min: f32 = a.1;
-    let mut ay_max: f32 = a.1;
-    let mut az_min: f32 = a.2;
-    let mut az_max: f32 = a.2;
-    let mut gx_min: f32 = g.0;
-    let mut gx_max: f32 = g.0;
-    let mut gy_min: f32 = g.1;
-    let mut gy_max: f32 = g.1;
-    let mut gz_min: f32 = g.2;
-    let mut gz_max: f32 = g.2;
-
-    for _ in 0..samples {
-        let (a, g) = f();
-
-        ax_min = ax_min.min(a.0);
-        ax_max = ax_max.max(a.0);
-        ay_min = ay_min.min(a.1);
-        ay_max = ay_max.max(a.1);
-        az_min = az_min.min(a.2);
-        az_max = az_max.max(a.2);
-
-        gx_min = gx_min.min(g.0);
-        gx_max = gx_max.max(g.0);
-        gy_min = gy_min.min(g.1);
-        gy_max = gy_max.max(g.1);
-        gz_min = gz_min.min(g.2);
-        gz_max = gz_max.max(g.2);
-
-        thread::sleep(Duration::from_millis(delay_ms))
-    }
-
-    ImuCalibrationOffset {
-        ax_offset: (ax_max - ax_min) / 2.0 + ax_min,
-        ay_offset: (ay_max - ay_min) / 2.0 + ay_min,
-        az_offset: (az_max - az_min) / 2.0 + az_min,
-        gx_offset: (gx_max - gx_min) / 2.0 + gx_min,
-        gy_offset: (gy_max - gy_min) / 2.0 + gy_min,
-        gz_offset: (gz_max - gz_min) / 2.0 + gz_min,
-    }
-}
-
 // fn write_to_stream<S: Write, V: Deserialize>(stream: &mut S, value: &V) -> anyhow::Result<()> {
 
 //     Ok(())
@@ -0,0 +1,56 @@
+//
+// params.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 16 2022
+//
+
+use icarus_core::params::Parameters;
+
+use esp_idf_svc::{
+    nvs::EspDefaultNvs,
+    nvs_storage::EspNvsStorage,
+};
+
+use embedded_svc::storage::RawStorage;
+
+use anyhow::Result;
+
+use std::sync::Arc;
+
+/// NVS namespace for controller configuration
+const NAMESPACE: &str = "icarus";
+/// NVS key the parameter blob is stored under
+const PARAMS_KEY: &str = "params";
+
+/// Parameters persisted to NVS
+pub struct ParameterStore {
+    storage: EspNvsStorage,
+}
+
+impl ParameterStore {
+    pub fn new(default_nvs: Arc<EspDefaultNvs>) -> Result<Self> {
+        let storage = EspNvsStorage::new_default(default_nvs, NAMESPACE, true)?;
+        Ok(Self { storage })
+    }
+
+    /// Load stored parameters. Falls back to defaults if nothing is stored or the stored layout no longer matches
+    pub fn load(&self) -> Parameters {
+        let mut buf: [u8; 256] = [0; 256];
+
+        match self.storage.get_raw(PARAMS_KEY, &mut buf) {
+            Ok(Some((data, _))) => postcard::from_bytes(data).unwrap_or_default(),
+            _ => Parameters::default(),
+        }
+    }
+
+    pub fn save(&mut self, params: &Parameters) -> Result<()> {
+        let mut buf: [u8; 256] = [0; 256];
+        let used = postcard::to_slice(params, &mut buf)
+            .map_err(|e| anyhow::anyhow!("Failed to encode parameters: {:?}", e))?;
+
+        self.storage.put_raw(PARAMS_KEY, used)?;
+
+        Ok(())
+    }
+}
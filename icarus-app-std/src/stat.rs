@@ -14,25 +14,18 @@ use esp_idf_hal::{
 
 use esp_idf_sys::EspError;
 
+use icarus_flight::led::Rgb;
+
 use core::time::Duration;
 
-#[derive(Debug, Clone, Copy)]
-pub enum StatColor {
-    Red, Green, Blue, Yellow, Black
-}
+/// Full brightness is uncomfortable to look at
+const BRIGHTNESS: u8 = 40;
 
-impl From<StatColor> for u32 {
-    fn from(c: StatColor) -> Self {
-        // The ws2812 is GRB format.
-        // The values here represent the bit pattern which is written to the LED LSB first
-        match c {
-            StatColor::Green   => 0x00_00_FFu32,
-            StatColor::Red     => 0x00_FF_00u32,
-            StatColor::Blue    => 0xFF_00_00u32,
-            StatColor::Yellow  => 0x00_FF_FFu32,
-            StatColor::Black   => 0x00_00_00u32,
-        }
-    }
+/// Bit pattern for a color, written to the LED LSB first
+fn to_bits(c: Rgb) -> u32 {
+    // The ws2812 is GRB format with each channel sent MSB first
+    let c = c.scale(BRIGHTNESS);
+    (c.g.reverse_bits() as u32) | ((c.r.reverse_bits() as u32) << 8) | ((c.b.reverse_bits() as u32) << 16)
 }
 
 pub struct StatLed<P: OutputPin, C: HwChannel> {
@@ -47,8 +40,8 @@ impl<P: OutputPin, C: HwChannel> StatLed<P, C> {
         Ok(StatLed {tx})
     }
 
-    pub fn update(&mut self, c: StatColor) -> Result<(), EspError> {
-        let rgb: u32 = c.into();
+    pub fn update(&mut self, c: Rgb) -> Result<(), EspError> {
+        let rgb = to_bits(c);
 
         let ticks_hz = self.tx.counter_clock()?;
         let t0h = Pulse::new_with_duration(ticks_hz, PinState::High, &ns(350))?;
@@ -28,20 +28,25 @@ use std::{
     time::Duration,
 };
 
+/// SSID of the on-board access point
+const AP_SSID: &str = "icarus";
+/// Access point channel
+const AP_CHANNEL: u8 = 1;
+
 /// WiFi Network Stack
 pub struct AppWifi {
     // netif_stack: Arc<EspNetifStack>,
     // sys_loop_stack: Arc<EspSysLoopStack>,
     // default_nvs: Arc<EspDefaultNvs>,
     wifi: Box<EspWifi>,
+    access_point: bool,
 }
 
 impl AppWifi {
-    pub fn new() -> Result<Self> {
+    pub fn new(default_nvs: Arc<EspDefaultNvs>) -> Result<Self> {
 
         let netif_stack = Arc::new(EspNetifStack::new()?);
         let sys_loop_stack = Arc::new(EspSysLoopStack::new()?);
-        let default_nvs = Arc::new(EspDefaultNvs::new()?);
 
         let wifi = Box::new(
             EspWifi::new(netif_stack.clone(), sys_loop_stack.clone(), default_nvs.clone())?
@@ -52,6 +57,7 @@ impl AppWifi {
             // sys_loop_stack,
             // default_nvs,
             wifi,
+            access_point: false,
         })
     }
 
@@ -69,9 +75,28 @@ impl AppWifi {
         };
 
         self.wifi.set_configuration(&Configuration::Client(config))?;
+        self.access_point = false;
+
+        Ok(())
+
+    }
+
+    /// Host an open network so the board can be reached without the configured network
+    pub fn start_access_point(&mut self) -> Result<()> {
+        let config = AccessPointConfiguration {
+            ssid: AP_SSID.into(),
+            channel: AP_CHANNEL,
+            ..Default::default()
+        };
+
+        self.wifi.set_configuration(&Configuration::AccessPoint(config))?;
+        self.access_point = true;
 
         Ok(())
+    }
 
+    pub fn is_access_point(&self) -> bool {
+        self.access_point
     }
 
     pub fn is_connected(&self) -> anyhow::Result<bool> {
@@ -80,11 +105,10 @@ impl AppWifi {
 
         // let status = self.wifi.get_status();
         let status = self.get_status()?;
-        if let Status(ClientStatus::Started(ClientConnectionStatus::Connected(_)), _) = status {
-            Ok(true)
-        }
-        else {
-            Ok(false)
+        match status {
+            Status(ClientStatus::Started(ClientConnectionStatus::Connected(_)), _) => Ok(true),
+            Status(_, ApStatus::Started(ApIpStatus::Done)) => Ok(true),
+            _ => Ok(false),
         }
     }
 
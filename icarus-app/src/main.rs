@@ -12,37 +12,195 @@ use panic_halt as _;
 
 use embassy::{
     executor::Spawner,
-    time::{Duration, Timer},
+    time::{Duration, Instant, Timer},
 };
 
 use embassy_esp::pac::Peripherals;
 
 use icarus::{
-    hal::{
-        gpio::Gpio10,
-        gpio_types::{Output, PushPull},
-        UsbSerialJtag,
-    },
+    battery::BatteryAdc,
+    clock::SystemClock,
+    hal::{gpio::Gpio9, gpio_types::{Floating, Input}},
+    led::StatLed,
+    motors::RotorPwm,
     prelude::*,
-    Icarus,
+    usb::UsbSerial,
+    Barometer, Icarus, Imu,
 };
+use icarus_core::{
+    button::{ButtonConfig, ButtonDetector},
+    params::Parameters,
+};
+use icarus_flight::{
+    led::{Indications, LedEngine},
+    FlightTask,
+};
+use icarus_wire::{
+    CommandRequest, FeedResult, FrameAccumulator, IcarusCommand, IcarusState, MessageCounts, NackReason,
+};
+
+use heapless::spsc::{Consumer, Producer, Queue};
+
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
 
+type Flight = FlightTask<Imu, Barometer, BatteryAdc, RotorPwm, SystemClock>;
+
+/// Poll period of the host link
+const COMMS_PERIOD_MS: u64 = 5;
+/// Status LED refresh period
+const LED_PERIOD_MS: u64 = 20;
+/// The host link is considered down after this long without a command
+const LINK_TIMEOUT_MS: u32 = 2000;
+/// Time between acknowledging a reboot and restarting
+const REBOOT_DELAY_MS: u64 = 100;
+
+/// Status from the control task for the LED
+static INDICATIONS: AtomicU16 = AtomicU16::new(Indications::BOOT.0);
+/// Time the last command was received (ms). No 64 bit atomics on this core
+static LAST_COMMAND_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Sensors, estimation, control and mixing at the configured loop rate
 #[embassy::task]
-async fn pong(mut led: Gpio10<Output<PushPull>>) {
+async fn control(
+    mut flight: Flight,
+    user_btn: Gpio9<Input<Floating>>,
+    mut cmd_rx: Consumer<'static, CommandRequest, 4>,
+    mut state_tx: Producer<'static, IcarusState, 16>,
+) {
+    let mut button = ButtonDetector::new(ButtonConfig::default());
+
     loop {
-        led.toggle().unwrap();
-        Timer::after(Duration::from_millis(1000)).await;
+        // Telemetry that did not fit in the queue this iteration
+        let mut dropped = MessageCounts::default();
+
+        // Process commands from the host. No parameter storage on this board yet, changes last until reset
+        while let Some(request) = cmd_rx.dequeue() {
+            let result = flight.handle_request(request, |state| {
+                if let Err(state) = state_tx.enqueue(state) {
+                    dropped.record(&state);
+                }
+            });
+
+            if let (IcarusCommand::Reboot, Some(Ok(()))) = (request.command, result) {
+                // Give the comms task time to send the acknowledgement
+                Timer::after(Duration::from_millis(REBOOT_DELAY_MS)).await;
+                icarus::reset();
+            }
+        }
+
+        // User button, active low. No wireless on this firmware so the access point action does nothing
+        let pressed = user_btn.is_low().unwrap_or(false);
+        if let Some(gesture) = button.update(pressed, Instant::now().as_millis()) {
+            let action = flight.params().button_map.action(gesture);
+            flight.handle_action(action).ok();
+        }
+
+        // Sleep until the next deadline
+        let wait = flight.time_until_next_us();
+        if wait > 0 {
+            Timer::after(Duration::from_micros(wait)).await;
+        }
+
+        flight.update(|state| {
+            if let Err(state) = state_tx.enqueue(state) {
+                dropped.record(&state);
+            }
+        });
+        flight.record_dropped(&dropped);
+
+        INDICATIONS.store(flight.indications().0, Ordering::Relaxed);
+    }
+}
+
+/// Telemetry out and commands in over USB serial / JTAG
+#[embassy::task]
+async fn comms(
+    mut serial: UsbSerial,
+    mut cmd_tx: Producer<'static, CommandRequest, 4>,
+    mut state_rx: Consumer<'static, IcarusState, 16>,
+) {
+    // Raw data buffer for store pre-deserialized data
+    let mut raw_buf: [u8; 128] = [0; 128];
+    // COBS decoder
+    let mut cmd_decoder: FrameAccumulator<64> = FrameAccumulator::new();
+
+    loop {
+        // Read commands from the host
+        let n = serial.read(&mut raw_buf);
+        let mut window = &raw_buf[..n];
+
+        'cobs: while !window.is_empty() {
+            window = match cmd_decoder.feed::<CommandRequest>(window) {
+                FeedResult::Consumed => break 'cobs,
+                FeedResult::OverFull(new_window) => new_window,
+                FeedResult::DeserError(new_window) => new_window,
+                FeedResult::IntegrityError(new_window) => new_window,
+                FeedResult::Success { data, remaining } => {
+                    if cmd_tx.enqueue(data).is_err() {
+                        // Queue is full, reject it here so the host retries
+                        let nack = IcarusState::Nack(data.id, NackReason::Busy);
+                        let mut nack_buf: [u8; 16] = [0; 16];
+                        if let Ok(used) = icarus_wire::encode(&nack, &mut nack_buf) {
+                            serial.write(used);
+                        }
+                    }
+                    LAST_COMMAND_MS.store(Instant::now().as_millis() as u32, Ordering::Relaxed);
+                    remaining
+                }
+            }
+        }
+
+        // Write telemetry to the host
+        while let Some(state) = state_rx.dequeue() {
+            if let Ok(used) = icarus_wire::encode(&state, &mut raw_buf) {
+                serial.write(used);
+            }
+        }
+
+        Timer::after(Duration::from_millis(COMMS_PERIOD_MS)).await;
+    }
+}
+
+/// Status LED patterns
+#[embassy::task]
+async fn led(mut stat: StatLed) {
+    let mut engine = LedEngine::default();
+
+    loop {
+        let now = Instant::now().as_millis();
+        let last_command = LAST_COMMAND_MS.load(Ordering::Relaxed);
+
+        let mut status = Indications(INDICATIONS.load(Ordering::Relaxed));
+        status.set(Indications::LINK, last_command != 0 && (now as u32).wrapping_sub(last_command) < LINK_TIMEOUT_MS);
+
+        stat.set(engine.update(status, now)).ok();
+        Timer::after(Duration::from_millis(LED_PERIOD_MS)).await;
     }
 }
 
 #[embassy::main]
 async fn main(spawner: Spawner, p: Peripherals) {
+    // No parameter storage on this board yet, the defaults pick the motor protocol
+    let params = Parameters::default();
+    let hw = Icarus::init(p, params.motor_protocol).unwrap();
+
+    static mut COMMAND_QUEUE: Queue<CommandRequest, 4> = Queue::new();
+    let (cmd_tx, cmd_rx) = unsafe { COMMAND_QUEUE.split() };
+
+    static mut STATE_QUEUE: Queue<IcarusState, 16> = Queue::new();
+    let (state_tx, state_rx) = unsafe { STATE_QUEUE.split() };
 
-    let hw = Icarus::init(p).unwrap();
+    // Motors are held at zero until armed
+    let mut drv1_en = hw.drv1_en;
+    let mut drv2_en = hw.drv2_en;
+    drv1_en.set_high().ok();
+    drv2_en.set_high().ok();
 
-    let led = hw.drv1_en;
+    let flight = FlightTask::new(hw.imu, hw.barometer, hw.battery, hw.rotors, hw.clock, params);
 
-    spawner.spawn(pong(led)).unwrap();
+    spawner.spawn(control(flight, hw.user_btn, cmd_rx, state_tx)).unwrap();
+    spawner.spawn(comms(hw.serial, cmd_tx, state_rx)).unwrap();
+    spawner.spawn(led(hw.stat)).unwrap();
 
     loop {
         Timer::after(Duration::from_millis(1000)).await;
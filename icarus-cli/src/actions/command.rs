@@ -4,12 +4,28 @@
 // @author Natesh Narain <nnaraindev@gmail.com>
 // @date Jul 31 2022
 //
-use icarus_wire::{self, IcarusCommand};
-use clap::Parser;
+use super::link::Link;
 
-use tokio::{
-    net::TcpStream
+use icarus_wire::{IcarusCommand, IcarusState, TelemetryChannel};
+use icarus_core::{
+    button::{ButtonAction, Gesture},
+    calibration::{CalibrationKind, CalibrationStage, CalibrationStatus, Face},
+    control::{AttitudeSetpoint, ControllerConfig, RateSetpoint, MAX_TILT},
+    mode::{FlightMode, ModeLimits, PilotInput},
+    motor::{DShotSpeed, MotorCurve, MotorProtocol},
+    orientation::BoardRotation,
+    params::{Parameter, Parameters},
 };
+use clap::{Parser, ValueEnum};
+
+use tokio::time;
+
+use anyhow::bail;
+
+use std::time::Duration;
+
+/// How long to wait for a calibration to finish. Long enough to place the board in all six positions
+const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(180);
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -17,25 +33,508 @@ pub struct Args {
     cmd: Subcommand
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CalibrationArg {
+    /// Board sitting level
+    Level,
+    /// Board placed on each of its six faces
+    SixPosition,
+}
+
+impl From<CalibrationArg> for CalibrationKind {
+    fn from(arg: CalibrationArg) -> Self {
+        match arg {
+            CalibrationArg::Level => CalibrationKind::Level,
+            CalibrationArg::SixPosition => CalibrationKind::SixPosition,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum BoardRotationArg {
+    Cw0,
+    Cw90,
+    Cw180,
+    Cw270,
+    Cw0Flip,
+    Cw90Flip,
+    Cw180Flip,
+    Cw270Flip,
+}
+
+impl From<BoardRotationArg> for BoardRotation {
+    fn from(arg: BoardRotationArg) -> Self {
+        match arg {
+            BoardRotationArg::Cw0 => BoardRotation::Cw0,
+            BoardRotationArg::Cw90 => BoardRotation::Cw90,
+            BoardRotationArg::Cw180 => BoardRotation::Cw180,
+            BoardRotationArg::Cw270 => BoardRotation::Cw270,
+            BoardRotationArg::Cw0Flip => BoardRotation::Cw0Flip,
+            BoardRotationArg::Cw90Flip => BoardRotation::Cw90Flip,
+            BoardRotationArg::Cw180Flip => BoardRotation::Cw180Flip,
+            BoardRotationArg::Cw270Flip => BoardRotation::Cw270Flip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GestureArg {
+    /// Press and release
+    ShortPress,
+    /// Two presses in quick succession
+    DoubleClick,
+    /// Hold for three seconds
+    LongPress,
+    /// Hold while the board starts
+    BootHold,
+    /// Start of any press
+    Press,
+}
+
+impl From<GestureArg> for Gesture {
+    fn from(arg: GestureArg) -> Self {
+        match arg {
+            GestureArg::ShortPress => Gesture::ShortPress,
+            GestureArg::DoubleClick => Gesture::DoubleClick,
+            GestureArg::LongPress => Gesture::LongPress,
+            GestureArg::BootHold => Gesture::BootHold,
+            GestureArg::Press => Gesture::Press,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ButtonActionArg {
+    /// Do nothing
+    None,
+    /// Level IMU calibration
+    Calibrate,
+    /// Switch between the configured network and the on-board access point
+    ToggleAccessPoint,
+    /// Restore default parameters
+    FactoryReset,
+    /// Stop the motors
+    EmergencyDisarm,
+}
+
+impl From<ButtonActionArg> for ButtonAction {
+    fn from(arg: ButtonActionArg) -> Self {
+        match arg {
+            ButtonActionArg::None => ButtonAction::None,
+            ButtonActionArg::Calibrate => ButtonAction::Calibrate,
+            ButtonActionArg::ToggleAccessPoint => ButtonAction::ToggleAccessPoint,
+            ButtonActionArg::FactoryReset => ButtonAction::FactoryReset,
+            ButtonActionArg::EmergencyDisarm => ButtonAction::EmergencyDisarm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum FlightModeArg {
+    /// Sticks command body rates
+    Rate,
+    /// Sticks command roll and pitch angles and the yaw rate
+    Angle,
+    /// Angle mode holding altitude
+    AltHold,
+}
+
+impl From<FlightModeArg> for FlightMode {
+    fn from(arg: FlightModeArg) -> Self {
+        match arg {
+            FlightModeArg::Rate => FlightMode::Rate,
+            FlightModeArg::Angle => FlightMode::Angle,
+            FlightModeArg::AltHold => FlightMode::AltHold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum TelemetryChannelArg {
+    /// Calibrated IMU and barometer readings
+    Sensors,
+    /// Estimated attitude
+    State,
+    Battery,
+    Health,
+    LoopStats,
+    /// Subscribed rates and dropped messages
+    Stats,
+}
+
+impl From<TelemetryChannelArg> for TelemetryChannel {
+    fn from(arg: TelemetryChannelArg) -> Self {
+        match arg {
+            TelemetryChannelArg::Sensors => TelemetryChannel::Sensors,
+            TelemetryChannelArg::State => TelemetryChannel::State,
+            TelemetryChannelArg::Battery => TelemetryChannel::Battery,
+            TelemetryChannelArg::Health => TelemetryChannel::Health,
+            TelemetryChannelArg::LoopStats => TelemetryChannel::LoopStats,
+            TelemetryChannelArg::Stats => TelemetryChannel::Stats,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum MotorProtocolArg {
+    /// PWM duty cycle to a brushed motor driver
+    Brushed,
+    /// 1000 - 2000 us pulses at 50 Hz
+    Standard,
+    /// 125 - 250 us pulses
+    Oneshot125,
+    /// 5 - 25 us pulses
+    Multishot,
+    Dshot150,
+    Dshot300,
+    Dshot600,
+}
+
+impl MotorProtocolArg {
+    fn protocol(self, frequency_hz: u32) -> MotorProtocol {
+        match self {
+            MotorProtocolArg::Brushed => MotorProtocol::Brushed { frequency_hz },
+            MotorProtocolArg::Standard => MotorProtocol::Standard,
+            MotorProtocolArg::Oneshot125 => MotorProtocol::OneShot125,
+            MotorProtocolArg::Multishot => MotorProtocol::Multishot,
+            MotorProtocolArg::Dshot150 => MotorProtocol::DShot(DShotSpeed::DShot150),
+            MotorProtocolArg::Dshot300 => MotorProtocol::DShot(DShotSpeed::DShot300),
+            MotorProtocolArg::Dshot600 => MotorProtocol::DShot(DShotSpeed::DShot600),
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 pub enum Subcommand {
-    Throttle {x_throttle: i8, y_throttle: i8, z_throttle: i8}
+    /// Angle mode setpoint as roll (deg), pitch (deg) and thrust (%). Prefer `attitude`
+    #[clap(allow_negative_numbers = true)]
+    Throttle {roll: i8, pitch: i8, thrust: i8},
+    /// Angle mode or altitude hold setpoint. A positive thrust arms, zero thrust disarms
+    #[clap(allow_negative_numbers = true)]
+    Attitude {
+        /// Collective thrust (%)
+        #[clap(value_parser = clap::value_parser!(u8).range(0..=100))]
+        thrust: u8,
+        /// Roll angle (deg), right side down is positive
+        #[clap(long, default_value_t = 0.0, value_parser = parse_tilt)]
+        roll: f32,
+        /// Pitch angle (deg), nose down is positive
+        #[clap(long, default_value_t = 0.0, value_parser = parse_tilt)]
+        pitch: f32,
+        /// Yaw rate (deg/s), counter-clockwise viewed from above is positive
+        #[clap(long, default_value_t = 0.0, value_parser = parse_rate)]
+        yaw_rate: f32,
+    },
+    /// Rate mode setpoint in deg/s. A positive thrust arms, zero thrust disarms
+    #[clap(allow_negative_numbers = true)]
+    Rate {
+        /// Collective thrust (%)
+        #[clap(value_parser = clap::value_parser!(u8).range(0..=100))]
+        thrust: u8,
+        #[clap(long, default_value_t = 0.0, value_parser = parse_rate)]
+        roll: f32,
+        #[clap(long, default_value_t = 0.0, value_parser = parse_rate)]
+        pitch: f32,
+        #[clap(long, default_value_t = 0.0, value_parser = parse_rate)]
+        yaw: f32,
+    },
+    /// Normalized sticks, interpreted by the current flight mode. Mid thrust holds altitude in altitude hold
+    #[clap(allow_negative_numbers = true)]
+    Sticks {
+        /// Thrust stick (%)
+        #[clap(value_parser = clap::value_parser!(u8).range(0..=100))]
+        thrust: u8,
+        /// Roll stick (-1 - 1)
+        #[clap(long, default_value_t = 0.0, value_parser = parse_stick)]
+        roll: f32,
+        /// Pitch stick (-1 - 1)
+        #[clap(long, default_value_t = 0.0, value_parser = parse_stick)]
+        pitch: f32,
+        /// Yaw stick (-1 - 1)
+        #[clap(long, default_value_t = 0.0, value_parser = parse_stick)]
+        yaw: f32,
+    },
+    /// Select the flight mode. Setpoints must match the mode. Altitude hold is entered in flight and needs a barometer
+    Mode {
+        #[clap(value_enum)]
+        mode: FlightModeArg,
+    },
+    /// Restart the controller. Rejected while armed
+    Reboot,
+    /// Disarm and stop the motors immediately
+    Stop,
+    /// Set the rate of a telemetry channel. Lasts until the controller restarts
+    Subscribe {
+        #[clap(value_enum)]
+        channel: TelemetryChannelArg,
+        /// Rate (Hz). Zero turns the channel off, rates above the loop rate are capped
+        rate: u16,
+    },
+    /// Calibrate the IMU and report progress
+    Calibrate {
+        /// Calibration procedure
+        #[clap(value_enum, default_value = "level")]
+        kind: CalibrationArg,
+    },
+    /// Set how the board is rotated on the frame (clockwise, looking down on the board)
+    BoardRotation {
+        #[clap(value_enum)]
+        rotation: BoardRotationArg,
+    },
+    /// Set the board alignment trim in degrees
+    #[clap(allow_negative_numbers = true)]
+    BoardTrim {roll: f32, pitch: f32, yaw: f32},
+    /// Set the control loop rate in Hz. Takes effect after a reboot
+    LoopRate {
+        #[clap(value_parser = clap::value_parser!(u16).range(Parameters::MIN_LOOP_RATE as i64..=Parameters::MAX_LOOP_RATE as i64))]
+        rate: u16,
+    },
+    /// Set the battery voltage divider ratio (battery voltage / ADC pin voltage)
+    BatteryDivider {
+        #[clap(value_parser = parse_battery_divider)]
+        divider: f32,
+    },
+    /// Set the action for a user button gesture
+    Button {
+        #[clap(value_enum)]
+        gesture: GestureArg,
+        #[clap(value_enum)]
+        action: ButtonActionArg,
+    },
+    /// Set the motor output protocol. Takes effect after a reboot
+    MotorProtocol {
+        #[clap(value_enum)]
+        protocol: MotorProtocolArg,
+        /// PWM frequency in Hz for brushed motors
+        #[clap(long, default_value_t = 20_000, value_parser = clap::value_parser!(u32).range(MotorProtocol::MIN_BRUSHED_FREQUENCY as i64..=MotorProtocol::MAX_BRUSHED_FREQUENCY as i64))]
+        frequency: u32,
+    },
+    /// Set the motor curve by hand. `motor-bench` fits one from measurements
+    MotorCurve {
+        /// Thrust expo, 0 (linear) - 1 (thrust proportional to duty squared)
+        #[clap(value_parser = parse_unit)]
+        expo: f32,
+        /// Duty where the motors start producing thrust (0 - 1)
+        #[clap(value_parser = parse_unit)]
+        min_output: f32,
+        /// Duty at full thrust (0 - 1)
+        #[clap(value_parser = parse_unit)]
+        max_output: f32,
+        /// Battery voltage the curve applies at. Zero disables battery compensation
+        #[clap(long, default_value_t = 0.0)]
+        nominal_voltage: f32,
+    },
+    /// Set the highest duty (%) a motor bench step may use
+    BenchMaxDuty {
+        #[clap(value_parser = clap::value_parser!(u8).range(0..=100))]
+        duty: u8,
+    },
+    /// Set what full stick commands in each flight mode
+    ModeLimits {
+        /// Roll and pitch rate in rate mode (deg/s)
+        #[clap(long, default_value_t = 200.0, value_parser = parse_rate)]
+        rate: f32,
+        /// Yaw rate (deg/s)
+        #[clap(long, default_value_t = 150.0, value_parser = parse_rate)]
+        yaw_rate: f32,
+        /// Roll and pitch angle in angle mode (deg)
+        #[clap(long, default_value_t = 30.0, value_parser = parse_tilt)]
+        angle: f32,
+        /// Climb and descent rate in altitude hold (m/s)
+        #[clap(long, default_value_t = 1.0, value_parser = parse_climb_rate)]
+        climb_rate: f32,
+    },
 }
 
-pub async fn run(args: Args, ip_addr: String) -> anyhow::Result<()> {
-    let stream = TcpStream::connect(ip_addr).await?;
+fn parse_battery_divider(s: &str) -> Result<f32, String> {
+    let divider: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (Parameters::MIN_BATTERY_DIVIDER..=Parameters::MAX_BATTERY_DIVIDER).contains(&divider) {
+        Ok(divider)
+    }
+    else {
+        Err(format!("must be between {} and {}", Parameters::MIN_BATTERY_DIVIDER, Parameters::MAX_BATTERY_DIVIDER))
+    }
+}
+
+/// Roll or pitch angle in degrees, within the controller's tilt limit
+fn parse_tilt(s: &str) -> Result<f32, String> {
+    let angle: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    let limit = MAX_TILT.to_degrees();
+    if angle.abs() <= limit {
+        Ok(angle)
+    }
+    else {
+        Err(format!("must be within +/-{:.0} deg", limit))
+    }
+}
+
+/// Body rate in deg/s, within the controller's rate limit
+fn parse_rate(s: &str) -> Result<f32, String> {
+    let rate: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    let limit = ControllerConfig::default().max_rate.to_degrees();
+    if rate.abs() <= limit {
+        Ok(rate)
+    }
+    else {
+        Err(format!("must be within +/-{:.0} deg/s", limit))
+    }
+}
+
+fn parse_stick(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (-1.0..=1.0).contains(&value) {
+        Ok(value)
+    }
+    else {
+        Err(String::from("must be between -1 and 1"))
+    }
+}
+
+fn parse_climb_rate(s: &str) -> Result<f32, String> {
+    let rate: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if rate > 0.0 && rate <= ModeLimits::MAX_CLIMB_RATE {
+        Ok(rate)
+    }
+    else {
+        Err(format!("must be above 0 and at most {} m/s", ModeLimits::MAX_CLIMB_RATE))
+    }
+}
 
-    let mut buf: [u8; 64] = [0; 64];
+fn parse_unit(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    }
+    else {
+        Err(String::from("must be between 0 and 1"))
+    }
+}
 
-    match args.cmd {
-        Subcommand::Throttle { x_throttle, y_throttle, z_throttle } => {
-            let throttle_cmd = IcarusCommand::Throttle(x_throttle, y_throttle, z_throttle);
-            if let Ok(used) = icarus_wire::encode(&throttle_cmd, &mut buf) {
-                stream.writable().await?;
-                if let Ok(_) = stream.try_write(used) {}
+pub async fn run(args: Args, ip_addr: String) -> anyhow::Result<()> {
+    let cmd = match args.cmd {
+        Subcommand::Throttle { roll, pitch, thrust } => IcarusCommand::Throttle(roll, pitch, thrust),
+        Subcommand::Attitude { thrust, roll, pitch, yaw_rate } => {
+            let setpoint = AttitudeSetpoint {
+                roll: roll.to_radians(),
+                pitch: pitch.to_radians(),
+                yaw_rate: yaw_rate.to_radians(),
+                thrust: thrust as f32 / 100.0,
+            };
+            IcarusCommand::Attitude(setpoint)
+        }
+        Subcommand::Rate { thrust, roll, pitch, yaw } => {
+            let setpoint = RateSetpoint {
+                roll: roll.to_radians(),
+                pitch: pitch.to_radians(),
+                yaw: yaw.to_radians(),
+                thrust: thrust as f32 / 100.0,
+            };
+            IcarusCommand::Rate(setpoint)
+        }
+        Subcommand::Sticks { thrust, roll, pitch, yaw } => {
+            let input = PilotInput { roll, pitch, yaw, thrust: thrust as f32 / 100.0 };
+            IcarusCommand::Sticks(input)
+        }
+        Subcommand::Mode { mode } => IcarusCommand::SetMode(mode.into()),
+        Subcommand::Reboot => IcarusCommand::Reboot,
+        Subcommand::Stop => IcarusCommand::MotorStop,
+        Subcommand::Subscribe { channel, rate } => IcarusCommand::Subscribe(channel.into(), rate),
+        Subcommand::Calibrate { kind } => IcarusCommand::Calibrate(kind.into()),
+        Subcommand::BoardRotation { rotation } => {
+            let param = Parameter::BoardRotation(rotation.into());
+            IcarusCommand::SetParameter(param)
+        }
+        Subcommand::BoardTrim { roll, pitch, yaw } => {
+            let param = Parameter::BoardTrim(roll, pitch, yaw);
+            IcarusCommand::SetParameter(param)
+        }
+        Subcommand::LoopRate { rate } => IcarusCommand::SetParameter(Parameter::LoopRate(rate)),
+        Subcommand::BatteryDivider { divider } => IcarusCommand::SetParameter(Parameter::BatteryDivider(divider)),
+        Subcommand::Button { gesture, action } => {
+            let param = Parameter::Button(gesture.into(), action.into());
+            IcarusCommand::SetParameter(param)
+        }
+        Subcommand::MotorProtocol { protocol, frequency } => {
+            let param = Parameter::MotorProtocol(protocol.protocol(frequency));
+            IcarusCommand::SetParameter(param)
+        }
+        Subcommand::MotorCurve { expo, min_output, max_output, nominal_voltage } => {
+            if max_output <= min_output {
+                bail!("max output must be above min output");
             }
+
+            let curve = MotorCurve { expo, min_output, max_output, nominal_voltage };
+            IcarusCommand::SetParameter(Parameter::MotorCurve(curve))
+        }
+        Subcommand::BenchMaxDuty { duty } => IcarusCommand::SetParameter(Parameter::BenchMaxDuty(duty)),
+        Subcommand::ModeLimits { rate, yaw_rate, angle, climb_rate } => {
+            let limits = ModeLimits {
+                max_rate: rate.to_radians(),
+                max_yaw_rate: yaw_rate.to_radians(),
+                max_angle: angle.to_radians(),
+                max_climb_rate: climb_rate,
+            };
+            IcarusCommand::SetParameter(Parameter::ModeLimits(limits))
         }
+    };
+
+    let mut link = Link::connect(&ip_addr).await?;
+    link.request(cmd).await?;
+    println!("Command accepted");
+
+    if let IcarusCommand::Calibrate(_) = cmd {
+        time::timeout(CALIBRATION_TIMEOUT, wait_for_calibration(&mut link))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for calibration"))??;
     }
 
     Ok(())
 }
+
+/// Print calibration progress reported by the controller until the calibration finishes
+async fn wait_for_calibration(link: &mut Link) -> anyhow::Result<()> {
+    loop {
+        if let IcarusState::Calibration(status) = link.recv().await? {
+            if print_calibration_status(status)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Print calibration status. Returns true once the calibration is complete
+fn print_calibration_status(status: CalibrationStatus) -> anyhow::Result<bool> {
+    match status.stage {
+        CalibrationStage::Idle => {},
+        CalibrationStage::WaitingForStill => println!("Waiting for the board to be still..."),
+        CalibrationStage::Sampling => println!("Sampling... {}%", status.progress),
+        CalibrationStage::WaitingForPosition => {
+            let remaining = Face::ALL
+                .iter()
+                .filter(|face| status.faces & face.mask() == 0)
+                .map(|face| face_name(*face))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            println!("Place the board with one of these axes pointing up: {}", remaining);
+        },
+        CalibrationStage::Complete => {
+            println!("Calibration complete");
+            return Ok(true);
+        },
+        CalibrationStage::Failed(e) => bail!("Calibration failed: {:?}", e),
+    }
+
+    Ok(false)
+}
+
+fn face_name(face: Face) -> &'static str {
+    match face {
+        Face::PosX => "+X",
+        Face::NegX => "-X",
+        Face::PosY => "+Y",
+        Face::NegY => "-Y",
+        Face::PosZ => "+Z",
+        Face::NegZ => "-Z",
+    }
+}
@@ -0,0 +1,217 @@
+//
+// estimate.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 24 2022
+//
+use super::command::BoardRotationArg;
+
+use icarus_core::{
+    data::{AccelerometerData, GyroscopeData},
+    orientation::{BoardAlignment, BoardRotation},
+    EstimatorConfig, EstimatorInput, StateEstimator,
+};
+
+use clap::Parser;
+
+use serde::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Directory containing sensors.csv and attitude.csv recorded with `log`
+    #[clap(value_parser, default_value = "output")]
+    input_dir: PathBuf,
+    /// Output CSV. Defaults to estimate.csv in the input directory
+    #[clap(short = 'o', long = "output")]
+    output: Option<PathBuf>,
+    /// Madgwick filter gain
+    #[clap(long = "beta", default_value_t = EstimatorConfig::default().madgwick_beta)]
+    beta: f32,
+    /// Skip the accelerometer correction when the measured acceleration differs from 1g by more than this (g)
+    #[clap(long = "accel-rejection", default_value_t = EstimatorConfig::default().accel_rejection)]
+    accel_rejection: f32,
+    /// Disable the dynamic notch on the gyro
+    #[clap(long = "no-gyro-notch")]
+    no_gyro_notch: bool,
+    /// Controller loop rate (Hz). Used for the sample period unless `--timestamps` is set
+    #[clap(long = "rate", default_value_t = 50.0)]
+    rate: f32,
+    /// Use the logged timestamps for the sample period. These are host receive times and include network jitter
+    #[clap(long = "timestamps")]
+    timestamps: bool,
+    /// Board rotation
+    #[clap(long = "rotation", value_enum, default_value = "cw0")]
+    rotation: BoardRotationArg,
+    /// Board alignment trim in degrees (roll, pitch, yaw)
+    #[clap(long = "trim", number_of_values = 3, allow_hyphen_values = true)]
+    trim: Option<Vec<f32>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SensorRow {
+    ts: f32,
+    ax: f32,
+    ay: f32,
+    az: f32,
+    gx: f32,
+    gy: f32,
+    gz: f32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct AttitudeRow {
+    ts: f32,
+    pitch: f32,
+    roll: f32,
+    yaw: f32,
+}
+
+/// Replayed attitude next to the on-board estimate. Column names match the `log` output so the plot scripts work as is
+#[derive(Serialize, Debug)]
+struct EstimateRow {
+    ts: f32,
+    pitch: f32,
+    roll: f32,
+    yaw: f32,
+    onboard_pitch: Option<f32>,
+    onboard_roll: Option<f32>,
+    onboard_yaw: Option<f32>,
+}
+
+/// Feed a recorded sensor log through the state estimator
+pub fn run(args: Args) -> anyhow::Result<()> {
+    let sensors_path = args.input_dir.join("sensors.csv");
+    let attitude_path = args.input_dir.join("attitude.csv");
+    let output_path = args.output.clone().unwrap_or_else(|| args.input_dir.join("estimate.csv"));
+
+    let sensors = csv::Reader::from_path(&sensors_path)?
+        .deserialize()
+        .collect::<Result<Vec<SensorRow>, _>>()?;
+
+    // The on-board estimate is optional
+    let onboard = match csv::Reader::from_path(&attitude_path) {
+        Ok(mut reader) => reader.deserialize().collect::<Result<Vec<AttitudeRow>, _>>()?,
+        Err(_) => {
+            println!("No on-board estimate found at {}", attitude_path.display());
+            vec![]
+        }
+    };
+
+    let period = 1.0 / args.rate;
+
+    let mut estimator = StateEstimator::new(EstimatorConfig {
+        madgwick_beta: args.beta,
+        sample_period: period,
+        accel_rejection: args.accel_rejection,
+        gyro_notch: if args.no_gyro_notch { None } else { EstimatorConfig::default().gyro_notch },
+    });
+    estimator.set_alignment(alignment(&args));
+
+    if !args.no_gyro_notch && !estimator.has_gyro_notch() {
+        println!("Gyro notch disabled, its band is above the Nyquist frequency at {} Hz", args.rate);
+    }
+
+    let mut writer = csv::Writer::from_path(&output_path)?;
+    let mut matcher = NearestMatcher::new(&onboard);
+
+    let mut error_sq = [0.0f64; 3];
+    let mut matched = 0usize;
+    let mut last_ts: Option<f32> = None;
+
+    for row in sensors.iter() {
+        let delta = match (args.timestamps, last_ts) {
+            (true, Some(last)) if row.ts > last => row.ts - last,
+            _ => period,
+        };
+        last_ts = Some(row.ts);
+
+        let input = EstimatorInput {
+            accel: AccelerometerData { x: row.ax, y: row.ay, z: row.az },
+            gyro: GyroscopeData { x: row.gx, y: row.gy, z: row.gz },
+            altitude: 0.0,
+        };
+
+        let state = estimator
+            .update(input, delta)
+            .map_err(|_| anyhow::anyhow!("Estimator failed at t={}", row.ts))?;
+        let attitude = state.attitude;
+
+        let reference = matcher.nearest(row.ts);
+        if let Some(reference) = reference {
+            error_sq[0] += ((attitude.pitch - reference.pitch) as f64).powi(2);
+            error_sq[1] += ((attitude.roll - reference.roll) as f64).powi(2);
+            error_sq[2] += (wrap_angle(attitude.yaw - reference.yaw) as f64).powi(2);
+            matched += 1;
+        }
+
+        writer.serialize(EstimateRow {
+            ts: row.ts,
+            pitch: attitude.pitch,
+            roll: attitude.roll,
+            yaw: attitude.yaw,
+            onboard_pitch: reference.map(|r| r.pitch),
+            onboard_roll: reference.map(|r| r.roll),
+            onboard_yaw: reference.map(|r| r.yaw),
+        })?;
+    }
+
+    writer.flush()?;
+
+    println!("Replayed {} samples to {}", sensors.len(), output_path.display());
+
+    if matched > 0 {
+        let rms = |e: f64| (e / matched as f64).sqrt().to_degrees();
+        println!(
+            "RMS difference from on-board estimate (deg): pitch {:.2}, roll {:.2}, yaw {:.2}",
+            rms(error_sq[0]),
+            rms(error_sq[1]),
+            rms(error_sq[2]),
+        );
+    }
+
+    Ok(())
+}
+
+fn alignment(args: &Args) -> BoardAlignment {
+    let (roll_trim, pitch_trim, yaw_trim) = match args.trim.as_deref() {
+        Some([roll, pitch, yaw]) => (*roll, *pitch, *yaw),
+        _ => (0.0, 0.0, 0.0),
+    };
+
+    BoardAlignment {
+        rotation: BoardRotation::from(args.rotation),
+        roll_trim,
+        pitch_trim,
+        yaw_trim,
+    }
+}
+
+/// Wrap an angle difference into -pi..pi
+fn wrap_angle(angle: f32) -> f32 {
+    use std::f32::consts::PI;
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// Finds the on-board estimate closest in time to each sensor sample. Both logs are in time order
+struct NearestMatcher<'a> {
+    rows: &'a [AttitudeRow],
+    index: usize,
+}
+
+impl<'a> NearestMatcher<'a> {
+    fn new(rows: &'a [AttitudeRow]) -> Self {
+        Self { rows, index: 0 }
+    }
+
+    fn nearest(&mut self, ts: f32) -> Option<AttitudeRow> {
+        while self.index + 1 < self.rows.len()
+            && (self.rows[self.index + 1].ts - ts).abs() <= (self.rows[self.index].ts - ts).abs()
+        {
+            self.index += 1;
+        }
+
+        self.rows.get(self.index).copied()
+    }
+}
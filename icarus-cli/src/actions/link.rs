@@ -0,0 +1,170 @@
+//
+// link.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+use icarus_wire::{self, CommandRequest, IcarusCommand, IcarusState, NackReason, FrameAccumulator, FeedResult};
+
+use icarus_core::arming::ArmingBlockers;
+
+use tokio::{io, net::TcpStream, time};
+
+use anyhow::bail;
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How long to wait for the controller to answer a command
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(300);
+/// Attempts before giving up on a command
+const ATTEMPTS: usize = 4;
+/// Oldest telemetry is dropped beyond this many unread messages
+const MAX_RECEIVED: usize = 1024;
+
+/// Connection to the controller. Commands are tagged with an id and retried until the controller answers
+pub struct Link {
+    stream: TcpStream,
+    raw_buf: Vec<u8>,
+    cobs_buf: FrameAccumulator<256>,
+    /// Telemetry received while waiting for a response
+    received: VecDeque<IcarusState>,
+    next_id: u16,
+}
+
+impl Link {
+    pub async fn connect(ip_addr: &str) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(ip_addr).await?;
+
+        // Start somewhere new each run so a retry window left over from the last run can't swallow the first command
+        let next_id = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos() as u16).unwrap_or(0);
+
+        Ok(Self {
+            stream,
+            raw_buf: vec![0; 1024],
+            cobs_buf: FrameAccumulator::new(),
+            received: VecDeque::new(),
+            next_id,
+        })
+    }
+
+    /// Send a command and wait for the controller to accept it
+    pub async fn request(&mut self, command: IcarusCommand) -> anyhow::Result<()> {
+        let request = CommandRequest { id: self.next_id, command };
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let mut buf: [u8; 64] = [0; 64];
+        let frame = icarus_wire::encode(&request, &mut buf).map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+        let mut busy = false;
+
+        for _ in 0..ATTEMPTS {
+            self.stream.writable().await?;
+            self.stream.try_write(frame).ok();
+
+            match time::timeout(RESPONSE_TIMEOUT, self.response(request.id)).await {
+                Ok(Ok(None)) => return Ok(()),
+                Ok(Ok(Some(NackReason::Busy))) => busy = true,
+                Ok(Ok(Some(reason))) => bail!("Command rejected: {}", describe(reason)),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => busy = false,
+            }
+        }
+
+        if busy {
+            bail!("Command rejected: {}", describe(NackReason::Busy))
+        }
+        else {
+            bail!("No response from the controller after {} attempts", ATTEMPTS)
+        }
+    }
+
+    /// Next message from the controller
+    pub async fn recv(&mut self) -> anyhow::Result<IcarusState> {
+        loop {
+            if let Some(state) = self.received.pop_front() {
+                return Ok(state);
+            }
+
+            self.read().await?;
+        }
+    }
+
+    /// Wait for the answer to a request. `None` if it was accepted
+    async fn response(&mut self, id: u16) -> anyhow::Result<Option<NackReason>> {
+        loop {
+            let position = self.received.iter().position(|state| match state {
+                IcarusState::Ack(ack_id) | IcarusState::Nack(ack_id, _) => *ack_id == id,
+                _ => false,
+            });
+
+            match position.and_then(|i| self.received.remove(i)) {
+                Some(IcarusState::Nack(_, reason)) => return Ok(Some(reason)),
+                Some(_) => return Ok(None),
+                None => self.read().await?,
+            }
+        }
+    }
+
+    /// Read whatever is available and decode it. Safe to cancel, nothing is held across the await
+    async fn read(&mut self) -> anyhow::Result<()> {
+        self.stream.readable().await?;
+
+        let n = match self.stream.try_read(&mut self.raw_buf) {
+            Ok(0) => bail!("Connection closed"),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut window = &self.raw_buf[..n];
+        'cobs: while !window.is_empty() {
+            window = match self.cobs_buf.feed::<IcarusState>(window) {
+                FeedResult::Consumed => break 'cobs,
+                FeedResult::OverFull(new_window) => new_window,
+                FeedResult::DeserError(new_window) => new_window,
+                FeedResult::IntegrityError(new_window) => new_window,
+                FeedResult::Success { data, remaining } => {
+                    if self.received.len() >= MAX_RECEIVED {
+                        self.received.pop_front();
+                    }
+                    self.received.push_back(data);
+                    remaining
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn describe(reason: NackReason) -> String {
+    match reason {
+        NackReason::Armed => String::from("not allowed while armed"),
+        NackReason::ArmingBlocked(blockers) => {
+            let names = [
+                (ArmingBlockers::SENSOR_FAULT, "sensor fault"),
+                (ArmingBlockers::SAMPLE_RATE, "IMU sample rate"),
+                (ArmingBlockers::CALIBRATING, "calibrating"),
+                (ArmingBlockers::BATTERY, "battery low"),
+            ];
+            let failed = names
+                .iter()
+                .filter(|(blocker, _)| blockers.contains(*blocker))
+                .map(|(_, name)| *name)
+                .collect::<Vec<_>>();
+            format!("pre-arm checks failed: {}", failed.join(", "))
+        }
+        NackReason::Calibrating => String::from("not allowed while calibrating"),
+        NackReason::PropsOn => String::from("remove the propellers first"),
+        NackReason::InvalidMotor => String::from("no such motor"),
+        NackReason::WrongMode => String::from("setpoint does not match the flight mode"),
+        NackReason::NoAltitude => String::from("altitude hold needs a barometer"),
+        NackReason::NotArmed => String::from("only allowed while flying"),
+        NackReason::Busy => String::from("controller busy"),
+        NackReason::NoThrustStand => String::from("thrust steps need the rotor on a thrust stand"),
+        NackReason::DutyLimit => String::from("duty is above the bench limit, see `command bench-max-duty`"),
+    }
+}
@@ -4,7 +4,7 @@
 // @author Natesh Narain <nnaraindev@gmail.com>
 // @date Dec 14 2021
 //
-use icarus_wire::IcarusState;
+use icarus_wire::{IcarusState, TelemetryChannel};
 
 use clap::Parser;
 
@@ -35,6 +35,51 @@ struct SensorRow {
     gz: f32,
 }
 
+#[derive(Serialize, Debug)]
+struct LoopStatsRow {
+    ts: f32,
+    rate: u16,
+    measured_rate: f32,
+    overruns: u32,
+    load: f32,
+    jitter_mean_us: u32,
+    jitter_max_us: u32,
+    sensors_mean_us: u32,
+    sensors_max_us: u32,
+    estimation_mean_us: u32,
+    estimation_max_us: u32,
+    control_mean_us: u32,
+    control_max_us: u32,
+    output_mean_us: u32,
+    output_max_us: u32,
+}
+
+#[derive(Serialize, Debug)]
+struct BatteryRow {
+    ts: f32,
+    voltage: f32,
+    compensated_voltage: f32,
+    sag: f32,
+    state_of_charge: u8,
+    adc_raw: u16,
+    charge_complete: bool,
+    status: String,
+}
+
+/// Messages the controller dropped since boot, per channel, and command frames it received corrupted
+#[derive(Serialize, Debug)]
+struct TelemetryRow {
+    ts: f32,
+    dropped_sensors: u32,
+    dropped_state: u32,
+    dropped_battery: u32,
+    dropped_health: u32,
+    dropped_loop_stats: u32,
+    dropped_stats: u32,
+    dropped_other: u32,
+    corrupt_frames: u32,
+}
+
 #[derive(Serialize, Debug)]
 struct AttitudeRow {
     ts: f32,
@@ -48,13 +93,20 @@ pub async fn run(args: Args, mut recv: Receiver<IcarusState>) -> anyhow::Result<
 
     let sensors_path = out_dir.join("sensors.csv");
     let attitude_path = out_dir.join("attitude.csv");
+    let loop_stats_path = out_dir.join("loop_stats.csv");
+    let battery_path = out_dir.join("battery.csv");
+    let telemetry_path = out_dir.join("telemetry.csv");
 
     // TODO: async csv writer
     let mut sensors_writer = csv::Writer::from_path(sensors_path)?;
     let mut attitude_writer = csv::Writer::from_path(attitude_path)?;
+    let mut loop_stats_writer = csv::Writer::from_path(loop_stats_path)?;
+    let mut battery_writer = csv::Writer::from_path(battery_path)?;
+    let mut telemetry_writer = csv::Writer::from_path(telemetry_path)?;
 
     let start = Instant::now();
 
+    #[allow(clippy::while_let_loop)]
     loop {
         match recv.recv().await {
             Some(state) => {
@@ -82,6 +134,54 @@ pub async fn run(args: Args, mut recv: Receiver<IcarusState>) -> anyhow::Result<
                         };
                         attitude_writer.serialize(attitude_row)?;
                     },
+                    IcarusState::LoopStats(stats) => {
+                        let loop_stats_row = LoopStatsRow {
+                            ts: now.duration_since(start).as_secs_f32(),
+                            rate: stats.rate,
+                            measured_rate: stats.measured_rate,
+                            overruns: stats.overruns,
+                            load: stats.load,
+                            jitter_mean_us: stats.jitter.mean_us,
+                            jitter_max_us: stats.jitter.max_us,
+                            sensors_mean_us: stats.sensors.mean_us,
+                            sensors_max_us: stats.sensors.max_us,
+                            estimation_mean_us: stats.estimation.mean_us,
+                            estimation_max_us: stats.estimation.max_us,
+                            control_mean_us: stats.control.mean_us,
+                            control_max_us: stats.control.max_us,
+                            output_mean_us: stats.output.mean_us,
+                            output_max_us: stats.output.max_us,
+                        };
+                        loop_stats_writer.serialize(loop_stats_row)?;
+                    },
+                    IcarusState::Battery(battery) => {
+                        let battery_row = BatteryRow {
+                            ts: now.duration_since(start).as_secs_f32(),
+                            voltage: battery.voltage as f32 / 1000.0,
+                            compensated_voltage: battery.compensated_voltage as f32 / 1000.0,
+                            sag: battery.sag as f32 / 1000.0,
+                            state_of_charge: battery.state_of_charge,
+                            adc_raw: battery.adc_raw,
+                            charge_complete: battery.charge_complete,
+                            status: format!("{:?}", battery.status),
+                        };
+                        battery_writer.serialize(battery_row)?;
+                    },
+                    IcarusState::TelemetryStats(stats) => {
+                        let dropped = stats.dropped;
+                        let telemetry_row = TelemetryRow {
+                            ts: now.duration_since(start).as_secs_f32(),
+                            dropped_sensors: dropped.get(TelemetryChannel::Sensors),
+                            dropped_state: dropped.get(TelemetryChannel::State),
+                            dropped_battery: dropped.get(TelemetryChannel::Battery),
+                            dropped_health: dropped.get(TelemetryChannel::Health),
+                            dropped_loop_stats: dropped.get(TelemetryChannel::LoopStats),
+                            dropped_stats: dropped.get(TelemetryChannel::Stats),
+                            dropped_other: dropped.other,
+                            corrupt_frames: stats.corrupt_frames,
+                        };
+                        telemetry_writer.serialize(telemetry_row)?;
+                    },
                     _ => {}
                 }
             },
@@ -5,4 +5,8 @@
 // @date Dec 14 2021
 //
 pub mod log;
+pub mod link;
 pub mod command;
+pub mod estimate;
+pub mod motor_test;
+pub mod motor_bench;
@@ -0,0 +1,181 @@
+//
+// motor_bench.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 05 2022
+//
+use super::link::Link;
+
+use icarus_wire::{IcarusCommand, IcarusState, ThrustStep};
+use icarus_core::{
+    mixer::NUM_MOTORS,
+    motor::{MotorCurve, ThrustSample},
+    params::Parameter,
+};
+
+use clap::Parser;
+
+use tokio::time;
+
+use serde::{Deserialize, Serialize};
+
+use anyhow::bail;
+
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Rotor on the thrust stand (1 - 4)
+    #[clap(short = 'm', long = "motor", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=NUM_MOTORS as i64))]
+    motor: u8,
+    /// Number of throttle steps
+    #[clap(short = 's', long = "steps", default_value_t = 10, value_parser = clap::value_parser!(u8).range(3..=20))]
+    steps: u8,
+    /// Duty of the last step (%). Must be within the controller's bench limit
+    #[clap(long = "max-duty", default_value_t = 80, value_parser = clap::value_parser!(u8).range(10..=100))]
+    max_duty: u8,
+    /// How long to hold each step (ms)
+    #[clap(short = 't', long = "duration", default_value_t = 3000, value_parser = clap::value_parser!(u16).range(500..=5000))]
+    duration: u16,
+    /// Confirm the frame is secured to a thrust stand
+    #[clap(long = "thrust-stand")]
+    thrust_stand: bool,
+    /// CSV the recorded steps are written to
+    #[clap(short = 'o', long = "output", default_value = "motor_bench.csv")]
+    output: PathBuf,
+    /// Fit a previous recording instead of running the bench
+    #[clap(short = 'i', long = "input")]
+    input: Option<PathBuf>,
+    /// Send the fitted curve to the controller
+    #[clap(long = "apply")]
+    apply: bool,
+}
+
+/// One recorded throttle step
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct StepRow {
+    /// Duty (%)
+    duty: u8,
+    /// Scale reading, any unit
+    thrust: f32,
+    /// Battery voltage during the step (V). Zero if the board does not report it
+    voltage: f32,
+}
+
+impl From<StepRow> for ThrustSample {
+    fn from(row: StepRow) -> Self {
+        ThrustSample { duty: row.duty as f32 / 100.0, thrust: row.thrust, voltage: row.voltage }
+    }
+}
+
+/// Record thrust at increasing duty and fit a motor curve to it
+pub async fn run(args: Args, ip_addr: String) -> anyhow::Result<()> {
+    let rows = match &args.input {
+        Some(input) => {
+            let mut reader = csv::Reader::from_path(input)?;
+            reader.deserialize().collect::<Result<Vec<StepRow>, _>>()?
+        }
+        None => {
+            if !args.thrust_stand {
+                bail!("Secure the frame to a thrust stand and pass --thrust-stand to run the bench");
+            }
+
+            let mut link = Link::connect(&ip_addr).await?;
+            let rows = record(&args, &mut link).await?;
+
+            let mut writer = csv::Writer::from_path(&args.output)?;
+            for row in rows.iter() {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+            println!("Recorded steps written to {}", args.output.display());
+
+            rows
+        }
+    };
+
+    let samples = rows.iter().copied().map(ThrustSample::from).collect::<Vec<_>>();
+    let curve = match MotorCurve::fit(&samples) {
+        Some(curve) => curve,
+        None => bail!("Not enough steps produced thrust to fit a curve"),
+    };
+
+    println!("Thrust expo:     {:.3}", curve.expo);
+    println!("Min output:      {:.1}%", curve.min_output * 100.0);
+    println!("Max output:      {:.1}%", curve.max_output * 100.0);
+    println!("Nominal voltage: {:.2} V", curve.nominal_voltage);
+
+    if args.apply {
+        let mut link = Link::connect(&ip_addr).await?;
+        link.request(IcarusCommand::SetParameter(Parameter::MotorCurve(curve))).await?;
+        println!("Motor curve applied");
+    }
+
+    Ok(())
+}
+
+/// Run each step and ask for the scale reading
+async fn record(args: &Args, link: &mut Link) -> anyhow::Result<Vec<StepRow>> {
+    let mut voltage = 0.0;
+
+    let motor = args.motor - 1;
+    let mut rows = Vec::new();
+
+    prompt("Zero the scale with the motor stopped, then press enter")?;
+
+    for step in 1..=args.steps {
+        let duty = (args.max_duty as u32 * step as u32 / args.steps as u32) as u8;
+
+        prompt(&format!("Step {}/{}: press enter to spin rotor {} at {}%", step, args.steps, args.motor, duty))?;
+
+        let cmd = IcarusCommand::ThrustStep(ThrustStep {
+            motor,
+            duty,
+            duration_ms: args.duration,
+            thrust_stand: args.thrust_stand,
+        });
+        link.request(cmd).await?;
+        watch_battery(link, Duration::from_millis(args.duration as u64), &mut voltage).await?;
+
+        let thrust = loop {
+            match prompt("Peak scale reading")?.parse::<f32>() {
+                Ok(thrust) if thrust >= 0.0 => break thrust,
+                _ => println!("Enter a number"),
+            }
+        };
+
+        rows.push(StepRow { duty, thrust, voltage });
+    }
+
+    Ok(rows)
+}
+
+/// Track the battery voltage reported by the controller for `duration`
+async fn watch_battery(link: &mut Link, duration: Duration, voltage: &mut f32) -> anyhow::Result<()> {
+    let watch = async {
+        loop {
+            if let IcarusState::Battery(battery) = link.recv().await? {
+                *voltage = battery.voltage as f32 / 1000.0;
+            }
+        }
+    };
+
+    match time::timeout(duration, watch).await {
+        Ok(result) => result,
+        Err(_) => Ok(()),
+    }
+}
+
+fn prompt(message: &str) -> anyhow::Result<String> {
+    print!("{}: ", message);
+    std::io::stdout().flush()?;
+
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+
+    Ok(line.trim().to_string())
+}
@@ -0,0 +1,122 @@
+//
+// motor_test.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 03 2022
+//
+use super::link::Link;
+
+use icarus_wire::{IcarusCommand, MotorTest};
+use icarus_core::mixer::NUM_MOTORS;
+
+use clap::Parser;
+
+use tokio::time;
+
+use anyhow::bail;
+
+use std::{
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+/// Rotor positions and spin direction viewed from above, in mixer order
+const ROTORS: [(&str, &str); NUM_MOTORS] = [
+    ("front right", "counter-clockwise"),
+    ("rear right", "clockwise"),
+    ("rear left", "counter-clockwise"),
+    ("front left", "clockwise"),
+];
+
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Only test this rotor (1 - 4). Walks through every rotor by default
+    #[clap(short = 'm', long = "motor", value_parser = clap::value_parser!(u8).range(1..=NUM_MOTORS as i64))]
+    motor: Option<u8>,
+    /// Motor output (%)
+    #[clap(short = 'd', long = "duty", default_value_t = 15, value_parser = clap::value_parser!(u8).range(1..=50))]
+    duty: u8,
+    /// How long to spin each rotor (ms)
+    #[clap(short = 't', long = "duration", default_value_t = 2000, value_parser = clap::value_parser!(u16).range(100..=5000))]
+    duration: u16,
+    /// Confirm the propellers have been removed
+    #[clap(long = "props-off")]
+    props_off: bool,
+}
+
+/// Result of a single rotor check
+enum Check {
+    Pass,
+    Fail,
+    Repeat,
+}
+
+pub async fn run(args: Args, ip_addr: String) -> anyhow::Result<()> {
+    if !args.props_off {
+        bail!("Remove the propellers and pass --props-off to run a motor test");
+    }
+
+    let mut link = Link::connect(&ip_addr).await?;
+
+    let motors = match args.motor {
+        Some(motor) => vec![motor - 1],
+        None => (0..NUM_MOTORS as u8).collect(),
+    };
+
+    let mut failed = Vec::new();
+
+    for motor in motors {
+        let (position, direction) = ROTORS[motor as usize];
+
+        loop {
+            prompt(&format!(
+                "Rotor {} ({}) should spin {} viewed from above. Press enter to spin it",
+                motor + 1,
+                position,
+                direction
+            ))?;
+
+            let test = MotorTest { motor, duty: args.duty, duration_ms: args.duration, props_off: true };
+            link.request(IcarusCommand::MotorTest(test)).await?;
+            time::sleep(Duration::from_millis(args.duration as u64)).await;
+
+            match ask_result()? {
+                Check::Pass => break,
+                Check::Fail => {
+                    failed.push(motor + 1);
+                    break;
+                }
+                Check::Repeat => continue,
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        println!("All rotors passed");
+        Ok(())
+    }
+    else {
+        bail!("Rotors failed: {:?}. Check the motor wiring and the rotor order", failed)
+    }
+}
+
+fn prompt(message: &str) -> anyhow::Result<String> {
+    print!("{}: ", message);
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+
+    Ok(line.trim().to_lowercase())
+}
+
+fn ask_result() -> anyhow::Result<Check> {
+    loop {
+        match prompt("Did it spin the right way? [y]es, [n]o, [r]epeat")?.as_str() {
+            "y" | "yes" => return Ok(Check::Pass),
+            "n" | "no" => return Ok(Check::Fail),
+            "r" | "repeat" => return Ok(Check::Repeat),
+            _ => {}
+        }
+    }
+}
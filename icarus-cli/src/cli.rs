@@ -6,7 +6,7 @@
 //
 
 use clap::Parser;
-use crate::actions::{log, command};
+use crate::actions::{log, command, estimate, motor_bench, motor_test};
 
 #[derive(Parser, Debug)]
 pub enum Action {
@@ -14,6 +14,12 @@ pub enum Action {
     Log(log::Args),
     /// Send a command
     Command(command::Args),
+    /// Replay a recorded sensor log through the state estimator
+    Estimate(estimate::Args),
+    /// Spin each rotor in turn to check wiring and direction
+    MotorTest(motor_test::Args),
+    /// Record thrust on a test stand and fit the motor curve
+    MotorBench(motor_bench::Args),
     /// Monitor system state
     Monitor,
 }
@@ -25,7 +31,7 @@ pub struct Args {
     #[clap(subcommand)]
     pub action: Action,
     /// Serial port Icarus is connected to
-    #[clap(short = 'i', long = "ip")]
+    #[clap(short = 'i', long = "ip", default_value = "127.0.0.1")]
     pub ip: String,
     /// Serial baud rate
     #[clap(short = 'p', long = "port", default_value_t = 5000)]
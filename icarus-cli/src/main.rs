@@ -16,7 +16,7 @@ use tokio::{
     sync::mpsc::{channel, Sender},
     net::TcpStream
 };
-use icarus_wire::{IcarusState, CobsAccumulator, FeedResult};
+use icarus_wire::{IcarusState, FrameAccumulator, FeedResult};
 
 use clap::Parser;
 
@@ -42,6 +42,15 @@ async fn main() -> anyhow::Result<()> {
             let task = tokio::spawn(actions::command::run(args, ip_addr));
             tokio::join!(task).0??;
         }
+        Action::Estimate(args) => {
+            actions::estimate::run(args)?;
+        }
+        Action::MotorTest(args) => {
+            actions::motor_test::run(args, ip_addr).await?;
+        }
+        Action::MotorBench(args) => {
+            actions::motor_bench::run(args, ip_addr).await?;
+        }
         _ => {}
     }
 
@@ -54,7 +63,7 @@ async fn recv_task(ip_addr: String, sender: Sender<IcarusState>) -> anyhow::Resu
     let stream = TcpStream::connect(ip_addr).await?;
 
     let mut raw_buf: [u8; 1024] = [0; 1024];
-    let mut cobs_buf: CobsAccumulator<256> = CobsAccumulator::new();
+    let mut cobs_buf: FrameAccumulator<256> = FrameAccumulator::new();
 
     loop {
         stream.readable().await?;
@@ -68,6 +77,7 @@ async fn recv_task(ip_addr: String, sender: Sender<IcarusState>) -> anyhow::Resu
                         FeedResult::Consumed => break 'cobs,
                         FeedResult::OverFull(new_window) => new_window,
                         FeedResult::DeserError(new_window) => new_window,
+                        FeedResult::IntegrityError(new_window) => new_window,
                         FeedResult::Success { data, remaining } => {
                             sender.send(data).await?;
 
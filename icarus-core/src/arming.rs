@@ -0,0 +1,119 @@
+//
+// arming.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 09 2022
+//
+
+use crate::{battery::BatteryStatus, health::HealthFlags};
+
+use serde::{Serialize, Deserialize};
+
+/// Reasons the controller refuses to arm
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ArmingBlockers(pub u16);
+
+impl ArmingBlockers {
+    /// IMU has a critical fault
+    pub const SENSOR_FAULT: ArmingBlockers = ArmingBlockers(1 << 0);
+    /// IMU samples are not arriving at the expected rate
+    pub const SAMPLE_RATE: ArmingBlockers = ArmingBlockers(1 << 1);
+    /// IMU calibration in progress
+    pub const CALIBRATING: ArmingBlockers = ArmingBlockers(1 << 2);
+    /// Battery too low to take off
+    pub const BATTERY: ArmingBlockers = ArmingBlockers(1 << 3);
+
+    pub const fn empty() -> Self {
+        ArmingBlockers(0)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(&self, other: ArmingBlockers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn set(&mut self, other: ArmingBlockers, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+/// Armed state of the controller
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ArmingState {
+    Disarmed,
+    Armed,
+}
+
+/// Pre-arm checks and armed state
+pub struct Arming {
+    state: ArmingState,
+    blockers: ArmingBlockers,
+}
+
+impl Default for Arming {
+    fn default() -> Self {
+        Self {
+            state: ArmingState::Disarmed,
+            blockers: ArmingBlockers::empty(),
+        }
+    }
+}
+
+impl Arming {
+    /// Update arming checks from the current sensor health
+    ///
+    /// A critical sensor fault while armed forces a disarm.
+    pub fn update_health(&mut self, health: HealthFlags) {
+        let fault = health.intersects(HealthFlags::CRITICAL);
+
+        self.blockers.set(ArmingBlockers::SENSOR_FAULT, fault);
+        self.blockers.set(ArmingBlockers::SAMPLE_RATE, health.contains(HealthFlags::SAMPLE_RATE));
+
+        if fault {
+            self.disarm();
+        }
+    }
+
+    /// Block arming while the IMU is being calibrated
+    pub fn set_calibrating(&mut self, calibrating: bool) {
+        self.blockers.set(ArmingBlockers::CALIBRATING, calibrating);
+    }
+
+    /// Block arming once the battery is too low to fly
+    pub fn update_battery(&mut self, status: BatteryStatus) {
+        self.blockers.set(ArmingBlockers::BATTERY, status >= BatteryStatus::Land);
+    }
+
+    /// Attempt to arm. Fails with the active blockers if any check does not pass
+    pub fn arm(&mut self) -> Result<(), ArmingBlockers> {
+        if self.blockers.is_empty() {
+            self.state = ArmingState::Armed;
+            Ok(())
+        } else {
+            Err(self.blockers)
+        }
+    }
+
+    pub fn disarm(&mut self) {
+        self.state = ArmingState::Disarmed;
+    }
+
+    pub fn state(&self) -> ArmingState {
+        self.state
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.state == ArmingState::Armed
+    }
+
+    pub fn blockers(&self) -> ArmingBlockers {
+        self.blockers
+    }
+}
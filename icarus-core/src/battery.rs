@@ -0,0 +1,250 @@
+//
+// battery.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 31 2022
+//
+
+use crate::filter::{Pt1Filter, SignalFilter};
+
+use serde::{Serialize, Deserialize};
+
+/// Battery condition, ordered from best to worst
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BatteryStatus {
+    Ok,
+    /// Getting low, land soon
+    Warning,
+    /// Too low to keep flying. Thrust is ramped down until the vehicle is on the ground
+    Land,
+    /// Damage to the cell is imminent. Motors are stopped
+    Critical,
+}
+
+/// Battery monitor configuration
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BatteryConfig {
+    /// Battery voltage / ADC pin voltage
+    pub divider: f32,
+    /// ADC input voltage at the maximum reading (V)
+    pub adc_full_scale: f32,
+    /// Maximum ADC reading
+    pub adc_max: u16,
+    /// Sag compensated cell voltage thresholds (V)
+    pub warning_voltage: f32,
+    pub land_voltage: f32,
+    pub critical_voltage: f32,
+    /// Time a threshold must be crossed before the status changes (s)
+    pub trigger_time: f32,
+    /// Recovery above a threshold needed to clear a status while disarmed (V)
+    pub hysteresis: f32,
+    /// Initial voltage drop at full load (V). Refined in flight
+    pub sag_at_full_load: f32,
+    /// Voltage filter cutoff (Hz)
+    pub filter_cutoff: f32,
+}
+
+impl Default for BatteryConfig {
+    /// 1S LiPo behind a 1:2 divider on the ESP32-C3 ADC at 11 dB attenuation
+    fn default() -> Self {
+        Self {
+            divider: 2.0,
+            adc_full_scale: 2.5,
+            adc_max: 4095,
+            warning_voltage: 3.5,
+            land_voltage: 3.4,
+            critical_voltage: 3.2,
+            trigger_time: 2.0,
+            hysteresis: 0.1,
+            sag_at_full_load: 0.4,
+            filter_cutoff: 1.0,
+        }
+    }
+}
+
+/// Resting 1S LiPo voltage against state of charge (%)
+const DISCHARGE_CURVE: [(f32, u8); 11] = [
+    (3.27, 0),
+    (3.69, 10),
+    (3.73, 20),
+    (3.77, 30),
+    (3.80, 40),
+    (3.84, 50),
+    (3.87, 60),
+    (3.95, 70),
+    (4.02, 80),
+    (4.11, 90),
+    (4.20, 100),
+];
+
+/// State of charge (%) from the resting cell voltage
+pub fn state_of_charge(voltage: f32) -> u8 {
+    let (first, last) = (DISCHARGE_CURVE[0], DISCHARGE_CURVE[DISCHARGE_CURVE.len() - 1]);
+
+    if voltage <= first.0 {
+        return first.1;
+    }
+    if voltage >= last.0 {
+        return last.1;
+    }
+
+    DISCHARGE_CURVE
+        .windows(2)
+        .find(|w| voltage < w[1].0)
+        .map(|w| {
+            let ((v0, s0), (v1, s1)) = (w[0], w[1]);
+            let t = (voltage - v0) / (v1 - v0);
+            (s0 as f32 + t * (s1 as f32 - s0 as f32)) as u8
+        })
+        .unwrap_or(last.1)
+}
+
+/// Minimum change in load used to learn the sag
+const MIN_LOAD_STEP: f32 = 0.1;
+/// Weight of each new sag measurement
+const SAG_LEARNING_RATE: f32 = 0.1;
+
+/// Tracks battery voltage, sag under load, state of charge and low battery status
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    sample_period: f32,
+    filter: Pt1Filter,
+    initialized: bool,
+
+    adc_raw: u16,
+    voltage: f32,
+    load: f32,
+    /// Voltage drop per unit of load (V)
+    sag_per_load: f32,
+    previous: Option<(f32, f32)>,
+
+    charge_complete: bool,
+    status: BatteryStatus,
+    /// Time spent below the warning, land and critical thresholds
+    below: [f32; 3],
+}
+
+impl BatteryMonitor {
+    /// Monitor sampled at `sample_rate` Hz
+    pub fn new(config: BatteryConfig, sample_rate: f32) -> Self {
+        Self {
+            config,
+            sample_period: 1.0 / sample_rate,
+            filter: Pt1Filter::new(config.filter_cutoff, sample_rate),
+            initialized: false,
+            adc_raw: 0,
+            voltage: 0.0,
+            load: 0.0,
+            sag_per_load: config.sag_at_full_load,
+            previous: None,
+            charge_complete: false,
+            status: BatteryStatus::Ok,
+            below: [0.0; 3],
+        }
+    }
+
+    pub fn config(&self) -> &BatteryConfig {
+        &self.config
+    }
+
+    /// Change the divider ratio, e.g. after a parameter update
+    pub fn set_divider(&mut self, divider: f32) {
+        self.config.divider = divider;
+        self.initialized = false;
+        self.previous = None;
+    }
+
+    /// Battery voltage (V) from a raw ADC reading
+    pub fn to_voltage(&self, adc_raw: u16) -> f32 {
+        adc_raw as f32 / self.config.adc_max as f32 * self.config.adc_full_scale * self.config.divider
+    }
+
+    /// Record a sample. `load` is the mean motor output (0 - 1). The status only improves while disarmed so a
+    /// recovering voltage cannot cancel a landing
+    pub fn update(&mut self, adc_raw: u16, load: f32, charge_complete: bool, armed: bool) -> BatteryStatus {
+        let raw_voltage = self.to_voltage(adc_raw);
+        let load = load.clamp(0.0, 1.0);
+
+        if !self.initialized {
+            self.filter.reset_to(raw_voltage);
+            self.initialized = true;
+        }
+
+        self.adc_raw = adc_raw;
+        self.voltage = self.filter.apply(raw_voltage);
+        self.load = load;
+        self.charge_complete = charge_complete;
+
+        // Learn the sag from load steps. Comparing neighbouring samples ignores the slow discharge
+        if let Some((previous_voltage, previous_load)) = self.previous {
+            let load_step = load - previous_load;
+            if libm::fabsf(load_step) >= MIN_LOAD_STEP {
+                let sag = ((previous_voltage - raw_voltage) / load_step).clamp(0.0, 2.0 * self.config.sag_at_full_load);
+                self.sag_per_load += SAG_LEARNING_RATE * (sag - self.sag_per_load);
+            }
+        }
+        self.previous = Some((raw_voltage, load));
+
+        let compensated = self.compensated_voltage();
+        let thresholds = [self.config.warning_voltage, self.config.land_voltage, self.config.critical_voltage];
+        let levels = [BatteryStatus::Warning, BatteryStatus::Land, BatteryStatus::Critical];
+
+        let mut status = BatteryStatus::Ok;
+        for ((threshold, level), below) in thresholds.iter().zip(levels).zip(self.below.iter_mut()) {
+            if compensated < *threshold {
+                *below += self.sample_period;
+            }
+            else {
+                *below = 0.0;
+            }
+
+            if *below >= self.config.trigger_time {
+                status = level;
+            }
+        }
+
+        if status > self.status {
+            self.status = status;
+        }
+        else if !armed && self.status != BatteryStatus::Ok {
+            // Clear once the voltage is comfortably above the active threshold, e.g. while charging
+            let threshold = thresholds[self.status as usize - 1];
+            if compensated > threshold + self.config.hysteresis {
+                self.status = status;
+            }
+        }
+
+        self.status
+    }
+
+    pub fn status(&self) -> BatteryStatus {
+        self.status
+    }
+
+    pub fn adc_raw(&self) -> u16 {
+        self.adc_raw
+    }
+
+    /// Filtered battery voltage under the present load (V)
+    pub fn voltage(&self) -> f32 {
+        self.voltage
+    }
+
+    /// Estimated voltage drop caused by the present load (V)
+    pub fn sag(&self) -> f32 {
+        self.sag_per_load * self.load
+    }
+
+    /// Estimated resting voltage (V)
+    pub fn compensated_voltage(&self) -> f32 {
+        self.voltage + self.sag()
+    }
+
+    pub fn state_of_charge(&self) -> u8 {
+        state_of_charge(self.compensated_voltage())
+    }
+
+    pub fn charge_complete(&self) -> bool {
+        self.charge_complete
+    }
+}
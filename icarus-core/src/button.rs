@@ -0,0 +1,216 @@
+//
+// button.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 02 2022
+//
+
+use serde::{Serialize, Deserialize};
+
+/// Recognized button gestures
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// Press and release
+    ShortPress,
+    /// Two short presses in quick succession
+    DoubleClick,
+    /// Held past the long press time. Reported while still held
+    LongPress,
+    /// Held from boot past the long press time
+    ///
+    /// Where the button is also a boot strapping pin, holding it through reset changes how the chip boots. Press it
+    /// just after power on instead, it only has to be held by the time the detector takes its first sample.
+    BootHold,
+    /// Debounced start of any press. Reported ahead of any other gesture the same press makes
+    Press,
+}
+
+/// What a gesture does
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonAction {
+    None,
+    /// Start a level IMU calibration
+    Calibrate,
+    /// Switch between the configured network and the on-board access point
+    ToggleAccessPoint,
+    /// Restore default parameters
+    FactoryReset,
+    /// Stop the motors
+    EmergencyDisarm,
+}
+
+/// Gesture to action mapping
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonMap {
+    pub short_press: ButtonAction,
+    pub double_click: ButtonAction,
+    pub long_press: ButtonAction,
+    pub boot_hold: ButtonAction,
+    pub press: ButtonAction,
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        Self {
+            short_press: ButtonAction::None,
+            double_click: ButtonAction::Calibrate,
+            long_press: ButtonAction::ToggleAccessPoint,
+            boot_hold: ButtonAction::FactoryReset,
+            // Stops the motors as soon as the button goes down, whatever the press turns into
+            press: ButtonAction::EmergencyDisarm,
+        }
+    }
+}
+
+impl ButtonMap {
+    pub fn action(&self, gesture: Gesture) -> ButtonAction {
+        match gesture {
+            Gesture::ShortPress => self.short_press,
+            Gesture::DoubleClick => self.double_click,
+            Gesture::LongPress => self.long_press,
+            Gesture::BootHold => self.boot_hold,
+            Gesture::Press => self.press,
+        }
+    }
+
+    pub fn set(&mut self, gesture: Gesture, action: ButtonAction) {
+        match gesture {
+            Gesture::ShortPress => self.short_press = action,
+            Gesture::DoubleClick => self.double_click = action,
+            Gesture::LongPress => self.long_press = action,
+            Gesture::BootHold => self.boot_hold = action,
+            Gesture::Press => self.press = action,
+        }
+    }
+}
+
+/// Gesture timing (ms)
+#[derive(Debug, Clone, Copy)]
+pub struct ButtonConfig {
+    /// Time the input must be stable before a change is accepted
+    pub debounce: u32,
+    /// Hold time for a long press
+    pub long_press: u32,
+    /// Maximum time between the release of the first click and the press of the second
+    pub double_click: u32,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            debounce: 30,
+            long_press: 3000,
+            double_click: 300,
+        }
+    }
+}
+
+/// Debounces a button and detects gestures
+///
+/// Every press is reported as [Gesture::Press] on its debounced edge. A short press is only reported once the double
+/// click window has passed, so a double click never produces a short press as well.
+pub struct ButtonDetector {
+    config: ButtonConfig,
+    /// Debounced state
+    pressed: bool,
+    /// Raw state and when it last changed
+    raw: bool,
+    raw_since: u64,
+    /// When the debounced press started
+    pressed_at: u64,
+    /// Release time of a click waiting for a possible second click
+    pending_click: Option<u64>,
+    /// Current press started at boot
+    boot_press: bool,
+    /// Current press already produced a gesture
+    handled: bool,
+    /// Double click found on the same sample as its press, reported on the next update
+    queued: Option<Gesture>,
+    started: bool,
+}
+
+impl ButtonDetector {
+    pub fn new(config: ButtonConfig) -> Self {
+        Self {
+            config,
+            pressed: false,
+            raw: false,
+            raw_since: 0,
+            pressed_at: 0,
+            pending_click: None,
+            boot_press: false,
+            handled: false,
+            queued: None,
+            started: false,
+        }
+    }
+
+    /// Sample the button. Call at least every few milliseconds
+    pub fn update(&mut self, pressed: bool, now_ms: u64) -> Option<Gesture> {
+        // Held on the first sample is a boot hold. Nothing to debounce against
+        if !self.started {
+            self.started = true;
+            self.raw = pressed;
+            self.raw_since = now_ms;
+            self.pressed = pressed;
+            self.pressed_at = now_ms;
+            self.boot_press = pressed;
+            return None;
+        }
+
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since = now_ms;
+        }
+
+        if let Some(gesture) = self.queued.take() {
+            return Some(gesture);
+        }
+
+        let stable = now_ms.saturating_sub(self.raw_since) >= self.config.debounce as u64;
+
+        if stable && self.raw != self.pressed {
+            self.pressed = self.raw;
+
+            if self.pressed {
+                self.pressed_at = now_ms;
+                self.handled = false;
+
+                if self.pending_click.take().is_some() {
+                    self.handled = true;
+                    self.queued = Some(Gesture::DoubleClick);
+                }
+
+                return Some(Gesture::Press);
+            }
+            else {
+                let short = !self.handled && !self.boot_press;
+                self.boot_press = false;
+                self.handled = false;
+
+                if short {
+                    self.pending_click = Some(now_ms);
+                }
+            }
+        }
+
+        if self.pressed && !self.handled && now_ms.saturating_sub(self.pressed_at) >= self.config.long_press as u64 {
+            self.handled = true;
+            return Some(if self.boot_press { Gesture::BootHold } else { Gesture::LongPress });
+        }
+
+        if let Some(released_at) = self.pending_click {
+            if now_ms.saturating_sub(released_at) > self.config.double_click as u64 {
+                self.pending_click = None;
+                return Some(Gesture::ShortPress);
+            }
+        }
+
+        None
+    }
+
+    /// Debounced button state
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
@@ -0,0 +1,558 @@
+//
+// calibration.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 13 2022
+//
+
+use crate::data::{AccelerometerData, GyroscopeData};
+
+use serde::{Serialize, Deserialize};
+
+/// Running mean and variance (Welford's algorithm)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunningStats {
+    n: u32,
+    mean: f32,
+    m2: f32,
+}
+
+impl RunningStats {
+    pub fn update(&mut self, x: f32) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f32;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.n
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.mean
+    }
+
+    /// Sample variance
+    pub fn variance(&self) -> f32 {
+        if self.n > 1 {
+            self.m2 / (self.n - 1) as f32
+        } else {
+            0.0
+        }
+    }
+
+    pub fn std_dev(&self) -> f32 {
+        libm::sqrtf(self.variance())
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Running statistics for a tri-axial sensor
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TriAxialStats {
+    axes: [RunningStats; 3],
+}
+
+impl TriAxialStats {
+    pub fn update<T: Into<(f32, f32, f32)>>(&mut self, data: T) {
+        let (x, y, z) = data.into();
+        self.axes[0].update(x);
+        self.axes[1].update(y);
+        self.axes[2].update(z);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.axes[0].count()
+    }
+
+    pub fn mean(&self) -> [f32; 3] {
+        [self.axes[0].mean(), self.axes[1].mean(), self.axes[2].mean()]
+    }
+
+    /// Largest standard deviation of any axis
+    pub fn max_std_dev(&self) -> f32 {
+        self.axes.iter().map(RunningStats::std_dev).fold(0.0, f32::max)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Calibration tuning
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CalibrationConfig {
+    /// Number of still samples averaged for each measurement
+    pub samples: u16,
+    /// Number of consecutive still samples before the sensor is considered at rest
+    pub settle_samples: u16,
+    /// Maximum accelerometer standard deviation (g) while still
+    pub accel_still_threshold: f32,
+    /// Maximum gyroscope standard deviation (rad/s) while still
+    pub gyro_still_threshold: f32,
+    /// Abort the calibration after this many samples
+    pub timeout_samples: u32,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            samples: 250,
+            settle_samples: 25,
+            accel_still_threshold: 0.02,
+            gyro_still_threshold: 0.02,
+            // 2 minutes at 50 Hz, enough time to place the board in all six positions
+            timeout_samples: 6000,
+        }
+    }
+}
+
+/// Detects when the IMU is at rest by tracking the short-term variance of each axis
+#[derive(Debug, Clone, Copy)]
+pub struct StillnessDetector {
+    accel_threshold: f32,
+    gyro_threshold: f32,
+    settle_samples: u16,
+
+    accel_mean: [f32; 3],
+    accel_var: [f32; 3],
+    gyro_mean: [f32; 3],
+    gyro_var: [f32; 3],
+
+    initialized: bool,
+    still_count: u16,
+}
+
+impl StillnessDetector {
+    /// Smoothing factor for the mean and variance estimates
+    const ALPHA: f32 = 0.1;
+
+    pub fn new(config: &CalibrationConfig) -> Self {
+        Self {
+            accel_threshold: config.accel_still_threshold,
+            gyro_threshold: config.gyro_still_threshold,
+            settle_samples: config.settle_samples,
+            accel_mean: [0.0; 3],
+            accel_var: [0.0; 3],
+            gyro_mean: [0.0; 3],
+            gyro_var: [0.0; 3],
+            initialized: false,
+            still_count: 0,
+        }
+    }
+
+    /// Add a sample. Returns true if the sensor has been still for at least the settle period
+    pub fn update(&mut self, accel: AccelerometerData, gyro: GyroscopeData) -> bool {
+        let accel: (f32, f32, f32) = accel.into();
+        let gyro: (f32, f32, f32) = gyro.into();
+        let accel = [accel.0, accel.1, accel.2];
+        let gyro = [gyro.0, gyro.1, gyro.2];
+
+        if !self.initialized {
+            self.initialized = true;
+            self.accel_mean = accel;
+            self.gyro_mean = gyro;
+        }
+
+        let accel_std = Self::update_axes(&mut self.accel_mean, &mut self.accel_var, accel);
+        let gyro_std = Self::update_axes(&mut self.gyro_mean, &mut self.gyro_var, gyro);
+
+        if accel_std < self.accel_threshold && gyro_std < self.gyro_threshold {
+            self.still_count = self.still_count.saturating_add(1);
+        } else {
+            self.still_count = 0;
+        }
+
+        self.is_still()
+    }
+
+    pub fn is_still(&self) -> bool {
+        self.still_count >= self.settle_samples
+    }
+
+    /// Smoothed accelerometer reading
+    pub fn accel_mean(&self) -> [f32; 3] {
+        self.accel_mean
+    }
+
+    pub fn reset(&mut self) {
+        self.initialized = false;
+        self.still_count = 0;
+        self.accel_var = [0.0; 3];
+        self.gyro_var = [0.0; 3];
+    }
+
+    /// Update exponential mean and variance. Returns the largest standard deviation of any axis
+    fn update_axes(mean: &mut [f32; 3], var: &mut [f32; 3], x: [f32; 3]) -> f32 {
+        let mut max_var: f32 = 0.0;
+
+        for i in 0..3 {
+            let delta = x[i] - mean[i];
+            mean[i] += Self::ALPHA * delta;
+            var[i] = (1.0 - Self::ALPHA) * (var[i] + Self::ALPHA * delta * delta);
+            max_var = max_var.max(var[i]);
+        }
+
+        libm::sqrtf(max_var)
+    }
+}
+
+/// IMU calibration parameters
+///
+/// Calibrated values are `(raw - bias) * scale` for the accelerometer and `raw - bias` for the gyroscope. Gravity is
+/// preserved, a level board reads +1g on the Z axis.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ImuCalibration {
+    pub accel_bias: [f32; 3],
+    pub accel_scale: [f32; 3],
+    pub gyro_bias: [f32; 3],
+}
+
+impl Default for ImuCalibration {
+    fn default() -> Self {
+        Self {
+            accel_bias: [0.0; 3],
+            accel_scale: [1.0; 3],
+            gyro_bias: [0.0; 3],
+        }
+    }
+}
+
+impl ImuCalibration {
+    pub fn apply_accel(&self, accel: AccelerometerData) -> AccelerometerData {
+        AccelerometerData {
+            x: (accel.x - self.accel_bias[0]) * self.accel_scale[0],
+            y: (accel.y - self.accel_bias[1]) * self.accel_scale[1],
+            z: (accel.z - self.accel_bias[2]) * self.accel_scale[2],
+        }
+    }
+
+    pub fn apply_gyro(&self, gyro: GyroscopeData) -> GyroscopeData {
+        GyroscopeData {
+            x: gyro.x - self.gyro_bias[0],
+            y: gyro.y - self.gyro_bias[1],
+            z: gyro.z - self.gyro_bias[2],
+        }
+    }
+}
+
+/// Calibration procedures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalibrationKind {
+    /// Board sitting level. Gyro bias and accelerometer bias
+    Level,
+    /// Board placed on each of its six faces. Gyro bias, accelerometer bias and scale
+    SixPosition,
+}
+
+/// Why a calibration failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalibrationError {
+    /// The board was not held still long enough to complete the calibration
+    Timeout,
+    /// The board was not level
+    NotLevel,
+    /// Measured accelerometer scale is not plausible
+    BadScale,
+}
+
+/// Current step of the calibration procedure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalibrationStage {
+    Idle,
+    /// Waiting for the board to be still
+    WaitingForStill,
+    /// Collecting samples
+    Sampling,
+    /// Waiting for the board to be moved to a face that has not been measured yet
+    WaitingForPosition,
+    Complete,
+    Failed(CalibrationError),
+}
+
+/// Calibration progress reported to the host
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CalibrationStatus {
+    pub kind: CalibrationKind,
+    pub stage: CalibrationStage,
+    /// Sampling progress of the current measurement (0 - 100)
+    pub progress: u8,
+    /// Bitmask of measured six-position faces. See [Face]
+    pub faces: u8,
+}
+
+/// Face of the board pointing up during six-position calibration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Face {
+    PosX = 0,
+    NegX = 1,
+    PosY = 2,
+    NegY = 3,
+    PosZ = 4,
+    NegZ = 5,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::PosX, Face::NegX, Face::PosY, Face::NegY, Face::PosZ, Face::NegZ];
+
+    /// Determine which face is pointing up from a mean accelerometer reading
+    ///
+    /// Returns `None` if the board is not close to sitting on a face.
+    pub fn from_accel(mean: [f32; 3]) -> Option<Face> {
+        let (axis, value) = mean
+            .iter()
+            .enumerate()
+            .max_by(|a, b| libm::fabsf(*a.1).total_cmp(&libm::fabsf(*b.1)))?;
+
+        // The dominant axis should carry most of gravity
+        let others = mean
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != axis)
+            .map(|(_, v)| v * v)
+            .sum::<f32>();
+
+        if libm::sqrtf(others) > 0.3 * libm::fabsf(*value) {
+            return None;
+        }
+
+        let face = match (axis, *value > 0.0) {
+            (0, true) => Face::PosX,
+            (0, false) => Face::NegX,
+            (1, true) => Face::PosY,
+            (1, false) => Face::NegY,
+            (2, true) => Face::PosZ,
+            _ => Face::NegZ,
+        };
+
+        Some(face)
+    }
+
+    pub fn mask(self) -> u8 {
+        1 << (self as u8)
+    }
+}
+
+/// Mask with all six faces measured
+const ALL_FACES: u8 = 0x3F;
+
+/// Drives a calibration procedure from a stream of raw IMU samples
+pub struct Calibrator {
+    config: CalibrationConfig,
+    kind: CalibrationKind,
+    stage: CalibrationStage,
+
+    detector: StillnessDetector,
+    accel_stats: TriAxialStats,
+    gyro_stats: TriAxialStats,
+    // Gyro bias is averaged over every still measurement
+    gyro_bias: TriAxialStats,
+
+    face_means: [[f32; 3]; 6],
+    faces: u8,
+
+    elapsed: u32,
+    result: Option<ImuCalibration>,
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self::new(CalibrationConfig::default())
+    }
+}
+
+impl Calibrator {
+    pub fn new(config: CalibrationConfig) -> Self {
+        Self {
+            config,
+            kind: CalibrationKind::Level,
+            stage: CalibrationStage::Idle,
+            detector: StillnessDetector::new(&config),
+            accel_stats: TriAxialStats::default(),
+            gyro_stats: TriAxialStats::default(),
+            gyro_bias: TriAxialStats::default(),
+            face_means: [[0.0; 3]; 6],
+            faces: 0,
+            elapsed: 0,
+            result: None,
+        }
+    }
+
+    /// Begin a new calibration, discarding any procedure in progress
+    pub fn start(&mut self, kind: CalibrationKind) {
+        let config = self.config;
+        *self = Self::new(config);
+        self.kind = kind;
+        self.stage = CalibrationStage::WaitingForStill;
+    }
+
+    /// Whether a calibration is in progress
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.stage,
+            CalibrationStage::WaitingForStill | CalibrationStage::Sampling | CalibrationStage::WaitingForPosition
+        )
+    }
+
+    /// Take the result of a completed calibration
+    pub fn take_result(&mut self) -> Option<ImuCalibration> {
+        self.result.take()
+    }
+
+    pub fn status(&self) -> CalibrationStatus {
+        let progress = match self.stage {
+            CalibrationStage::Complete => 100,
+            CalibrationStage::Sampling => {
+                (self.accel_stats.count() * 100 / self.config.samples.max(1) as u32).min(100) as u8
+            }
+            _ => 0,
+        };
+
+        CalibrationStatus {
+            kind: self.kind,
+            stage: self.stage,
+            progress,
+            faces: self.faces,
+        }
+    }
+
+    /// Feed a raw (uncalibrated) sample. Returns the current stage
+    pub fn update(&mut self, accel: AccelerometerData, gyro: GyroscopeData) -> CalibrationStage {
+        if !self.is_active() {
+            return self.stage;
+        }
+
+        self.elapsed += 1;
+        if self.elapsed > self.config.timeout_samples {
+            self.stage = CalibrationStage::Failed(CalibrationError::Timeout);
+            return self.stage;
+        }
+
+        let still = self.detector.update(accel, gyro);
+
+        self.stage = match self.stage {
+            CalibrationStage::WaitingForPosition => {
+                // Only start sampling once the board rests on a face that has not been measured
+                let new_face = Face::from_accel(self.detector.accel_mean())
+                    .map(|face| self.faces & face.mask() == 0)
+                    .unwrap_or(false);
+
+                if still && new_face {
+                    self.accel_stats.reset();
+                    self.gyro_stats.reset();
+                    CalibrationStage::Sampling
+                } else {
+                    self.stage
+                }
+            }
+            CalibrationStage::WaitingForStill => {
+                if still {
+                    self.accel_stats.reset();
+                    self.gyro_stats.reset();
+                    CalibrationStage::Sampling
+                } else {
+                    self.stage
+                }
+            }
+            CalibrationStage::Sampling => {
+                if !still {
+                    // Bumped. Start this measurement over
+                    CalibrationStage::WaitingForStill
+                } else {
+                    self.accel_stats.update(accel);
+                    self.gyro_stats.update(gyro);
+
+                    if self.accel_stats.count() >= self.config.samples as u32 {
+                        self.measurement_complete()
+                    } else {
+                        CalibrationStage::Sampling
+                    }
+                }
+            }
+            stage => stage,
+        };
+
+        self.stage
+    }
+
+    fn measurement_complete(&mut self) -> CalibrationStage {
+        let accel = self.accel_stats.mean();
+
+        match self.kind {
+            CalibrationKind::Level => {
+                if Face::from_accel(accel) != Some(Face::PosZ) {
+                    return CalibrationStage::Failed(CalibrationError::NotLevel);
+                }
+
+                self.accumulate_gyro_bias();
+
+                self.result = Some(ImuCalibration {
+                    // Keep 1g on the Z axis
+                    accel_bias: [accel[0], accel[1], accel[2] - 1.0],
+                    accel_scale: [1.0; 3],
+                    gyro_bias: self.gyro_bias.mean(),
+                });
+
+                CalibrationStage::Complete
+            }
+            CalibrationKind::SixPosition => {
+                let face = match Face::from_accel(accel) {
+                    Some(face) if self.faces & face.mask() == 0 => face,
+                    // Tilted or already measured
+                    _ => return CalibrationStage::WaitingForPosition,
+                };
+
+                self.accumulate_gyro_bias();
+                self.face_means[face as usize] = accel;
+                self.faces |= face.mask();
+
+                if self.faces == ALL_FACES {
+                    match self.solve_six_position() {
+                        Some(cal) => {
+                            self.result = Some(cal);
+                            CalibrationStage::Complete
+                        }
+                        None => CalibrationStage::Failed(CalibrationError::BadScale),
+                    }
+                } else {
+                    CalibrationStage::WaitingForPosition
+                }
+            }
+        }
+    }
+
+    fn accumulate_gyro_bias(&mut self) {
+        let mean = self.gyro_stats.mean();
+        self.gyro_bias.update((mean[0], mean[1], mean[2]));
+    }
+
+    /// Each axis sees +1g and -1g. The midpoint is the bias and the span gives the scale
+    fn solve_six_position(&self) -> Option<ImuCalibration> {
+        let mut cal = ImuCalibration {
+            gyro_bias: self.gyro_bias.mean(),
+            ..Default::default()
+        };
+
+        for axis in 0..3 {
+            let pos = self.face_means[axis * 2][axis];
+            let neg = self.face_means[axis * 2 + 1][axis];
+
+            let span = pos - neg;
+            let scale = 2.0 / span;
+
+            // Anything more than 20% off is a bad measurement, not a sensor error
+            if !(0.8..=1.2).contains(&scale) {
+                return None;
+            }
+
+            cal.accel_bias[axis] = (pos + neg) / 2.0;
+            cal.accel_scale[axis] = scale;
+        }
+
+        Some(cal)
+    }
+}
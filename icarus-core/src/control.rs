@@ -0,0 +1,201 @@
+//
+// control.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use crate::{
+    data::{Attitude, GyroscopeData},
+    filter::{LowPassConfig, LowPassFilter, LowPassType, SignalFilter, FrequencyFilter},
+};
+
+use serde::{Serialize, Deserialize};
+
+/// PID gains
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Limit on the magnitude of the integral term
+    pub i_limit: f32,
+}
+
+/// PID controller with derivative on measurement and a low-pass filtered D-term
+pub struct Pid {
+    gains: PidGains,
+    integral: f32,
+    last_measurement: Option<f32>,
+    d_filter: LowPassFilter,
+}
+
+impl Pid {
+    pub fn new(gains: PidGains, d_filter: LowPassConfig, sample_rate_hz: f32) -> Self {
+        Self {
+            gains,
+            integral: 0.0,
+            last_measurement: None,
+            d_filter: LowPassFilter::new(d_filter, sample_rate_hz),
+        }
+    }
+
+    pub fn gains(&self) -> &PidGains {
+        &self.gains
+    }
+
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.gains = gains;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate_hz: f32) {
+        self.d_filter.set_sample_rate(sample_rate_hz);
+    }
+
+    pub fn update(&mut self, setpoint: f32, measurement: f32, dt: f32) -> f32 {
+        let error = setpoint - measurement;
+
+        let p = self.gains.kp * error;
+
+        self.integral += self.gains.ki * error * dt;
+        self.integral = self.integral.clamp(-self.gains.i_limit, self.gains.i_limit);
+
+        // Derivative on measurement avoids a kick when the setpoint changes
+        let derivative = match self.last_measurement {
+            Some(last) if dt > 0.0 => -(measurement - last) / dt,
+            _ => 0.0,
+        };
+        self.last_measurement = Some(measurement);
+
+        let d = self.gains.kd * self.d_filter.apply(derivative);
+
+        p + self.integral + d
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_measurement = None;
+        self.d_filter.reset();
+    }
+}
+
+/// Controller tuning
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ControllerConfig {
+    /// Angle error (rad) to rate setpoint (rad/s) gain for roll and pitch
+    pub angle_p: f32,
+    /// Rate loop gains for roll, pitch and yaw
+    pub rate: [PidGains; 3],
+    /// D-term low-pass filter
+    pub d_filter: LowPassConfig,
+    /// Maximum rate setpoint (rad/s)
+    pub max_rate: f32,
+}
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        let roll_pitch = PidGains { kp: 0.01, ki: 0.01, kd: 0.0002, i_limit: 0.1 };
+        let yaw = PidGains { kp: 0.02, ki: 0.01, kd: 0.0, i_limit: 0.1 };
+
+        Self {
+            angle_p: 4.0,
+            rate: [roll_pitch, roll_pitch, yaw],
+            d_filter: LowPassConfig { kind: LowPassType::Pt1, cutoff_hz: 10.0 },
+            max_rate: 4.0,
+        }
+    }
+}
+
+/// Largest roll or pitch angle an attitude setpoint may ask for (rad)
+pub const MAX_TILT: f32 = 0.785;
+
+/// Target attitude and collective thrust
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct AttitudeSetpoint {
+    /// Roll angle (rad)
+    pub roll: f32,
+    /// Pitch angle (rad)
+    pub pitch: f32,
+    /// Yaw rate (rad/s)
+    pub yaw_rate: f32,
+    /// Collective thrust (0 - 1)
+    pub thrust: f32,
+}
+
+/// Body rate targets and collective thrust
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct RateSetpoint {
+    /// Roll rate (rad/s)
+    pub roll: f32,
+    /// Pitch rate (rad/s)
+    pub pitch: f32,
+    /// Yaw rate (rad/s)
+    pub yaw: f32,
+    /// Collective thrust (0 - 1)
+    pub thrust: f32,
+}
+
+/// Normalized torque and thrust demand passed to the mixer
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ControlOutput {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub thrust: f32,
+}
+
+/// Cascaded attitude controller. An angle P loop feeds a rate PID loop on each axis
+pub struct AttitudeController {
+    config: ControllerConfig,
+    rate_pids: [Pid; 3],
+}
+
+impl AttitudeController {
+    pub fn new(config: ControllerConfig, sample_rate_hz: f32) -> Self {
+        let pid = |gains| Pid::new(gains, config.d_filter, sample_rate_hz);
+
+        Self {
+            config,
+            rate_pids: [pid(config.rate[0]), pid(config.rate[1]), pid(config.rate[2])],
+        }
+    }
+
+    pub fn config(&self) -> &ControllerConfig {
+        &self.config
+    }
+
+    /// Angle loop. Convert an attitude setpoint into a rate setpoint
+    pub fn angle_to_rate(&self, setpoint: AttitudeSetpoint, attitude: Attitude) -> RateSetpoint {
+        let max_rate = self.config.max_rate;
+
+        RateSetpoint {
+            roll: (self.config.angle_p * (setpoint.roll - attitude.roll)).clamp(-max_rate, max_rate),
+            pitch: (self.config.angle_p * (setpoint.pitch - attitude.pitch)).clamp(-max_rate, max_rate),
+            yaw: setpoint.yaw_rate.clamp(-max_rate, max_rate),
+            thrust: setpoint.thrust,
+        }
+    }
+
+    /// Rate loop. `gyro` is the body rate in rad/s
+    pub fn update_rate(&mut self, setpoint: RateSetpoint, gyro: GyroscopeData, dt: f32) -> ControlOutput {
+        ControlOutput {
+            roll: self.rate_pids[0].update(setpoint.roll, gyro.x, dt),
+            pitch: self.rate_pids[1].update(setpoint.pitch, gyro.y, dt),
+            yaw: self.rate_pids[2].update(setpoint.yaw, gyro.z, dt),
+            thrust: setpoint.thrust,
+        }
+    }
+
+    /// Run both loops
+    pub fn update(&mut self, setpoint: AttitudeSetpoint, attitude: Attitude, gyro: GyroscopeData, dt: f32) -> ControlOutput {
+        let rate = self.angle_to_rate(setpoint, attitude);
+        self.update_rate(rate, gyro, dt)
+    }
+
+    /// Clear integral and derivative state. Call while disarmed so nothing winds up on the ground
+    pub fn reset(&mut self) {
+        for pid in self.rate_pids.iter_mut() {
+            pid.reset();
+        }
+    }
+}
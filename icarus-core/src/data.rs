@@ -20,7 +20,7 @@ pub struct AccelerometerData {
 
 impl From<AccelerometerData> for (f32, f32, f32) {
     fn from(a: AccelerometerData) -> Self {
-        (a.x, a.y, a.x)
+        (a.x, a.y, a.z)
     }
 }
 
@@ -34,7 +34,7 @@ pub struct GyroscopeData {
 
 impl From<GyroscopeData> for (f32, f32, f32) {
     fn from(g: GyroscopeData) -> Self {
-        (g.x, g.y, g.x)
+        (g.x, g.y, g.z)
     }
 }
 
@@ -0,0 +1,336 @@
+//
+// dynamic_notch.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 06 2022
+//
+
+use crate::{
+    fft::Fft,
+    filter::{BiquadFilter, FrequencyFilter, Pt1Filter, SignalFilter},
+};
+
+use serde::{Serialize, Deserialize};
+
+/// Dynamic notch filter configuration
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DynamicNotchConfig {
+    /// Lowest frequency tracked
+    pub min_hz: f32,
+    /// Highest frequency tracked
+    pub max_hz: f32,
+    /// Quality factor of each notch
+    pub q: f32,
+    /// Cutoff for smoothing successive peak estimates, relative to the analysis rate
+    pub smoothing_hz: f32,
+    /// How far above the average spectrum power a bin must be to count as a peak
+    pub threshold: f32,
+    /// Smallest vibration amplitude tracked, in signal units. Keeps the notches from chasing sensor noise
+    pub min_amplitude: f32,
+}
+
+impl Default for DynamicNotchConfig {
+    fn default() -> Self {
+        Self {
+            min_hz: 80.0,
+            max_hz: 400.0,
+            q: 3.0,
+            smoothing_hz: 10.0,
+            threshold: 2.0,
+            min_amplitude: 0.02,
+        }
+    }
+}
+
+/// Tracks up to `P` vibration peaks in a signal using a windowed FFT over the last `N` samples
+///
+/// A new spectrum is computed every `N / 2` samples. Each slot keeps following the peak it first locked onto, so a
+/// new peak appearing below it does not pull it away. Slots hold their last position when their peak drops below the
+/// detection threshold. A frequency of zero means the slot hasn't found a peak yet.
+#[derive(Clone)]
+pub struct PeakTracker<const N: usize, const P: usize> {
+    config: DynamicNotchConfig,
+    sample_rate_hz: f32,
+
+    fft: Fft<N>,
+    samples: [f32; N],
+    head: usize,
+    pending: usize,
+
+    peaks: [f32; P],
+    smoothing: [Pt1Filter; P],
+}
+
+impl<const N: usize, const P: usize> PeakTracker<N, P> {
+    pub fn new(config: DynamicNotchConfig, sample_rate_hz: f32) -> Self {
+        let analysis_rate = sample_rate_hz / Self::hop() as f32;
+
+        Self {
+            config,
+            sample_rate_hz,
+            fft: Fft::new(),
+            samples: [0.0; N],
+            head: 0,
+            pending: 0,
+            peaks: [0.0; P],
+            smoothing: [Pt1Filter::new(config.smoothing_hz, analysis_rate); P],
+        }
+    }
+
+    /// Number of new samples between each analysis
+    pub const fn hop() -> usize {
+        N / 2
+    }
+
+    /// Frequency width of each FFT bin
+    pub fn resolution(&self) -> f32 {
+        self.sample_rate_hz / N as f32
+    }
+
+    /// FFT bins searched for peaks, as (first, last). The top of the band is limited to below Nyquist
+    ///
+    /// `None` when no bins lie between `min_hz` and `max_hz` at this sample rate. Nothing is tracked then.
+    pub fn band(&self) -> Option<(usize, usize)> {
+        let resolution = self.resolution();
+        let min_bin = libm::ceilf(self.config.min_hz / resolution).max(1.0) as usize;
+        let max_bin = (libm::floorf(self.config.max_hz / resolution) as usize).min(N / 2 - 1);
+
+        if min_bin < max_bin { Some((min_bin, max_bin)) } else { None }
+    }
+
+    /// Current peak frequency of each slot
+    pub fn peaks(&self) -> &[f32; P] {
+        &self.peaks
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate_hz: f32) {
+        self.sample_rate_hz = sample_rate_hz;
+        let analysis_rate = sample_rate_hz / Self::hop() as f32;
+        for s in self.smoothing.iter_mut() {
+            s.set_sample_rate(analysis_rate);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.samples = [0.0; N];
+        self.head = 0;
+        self.pending = 0;
+        self.peaks = [0.0; P];
+    }
+
+    /// Add a sample. Returns true if the peak estimates were updated
+    pub fn push(&mut self, sample: f32) -> bool {
+        self.samples[self.head] = sample;
+        self.head = (self.head + 1) % N;
+        self.pending += 1;
+
+        if self.pending >= Self::hop() {
+            self.pending = 0;
+            self.analyze();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn analyze(&mut self) {
+        // Unroll the ring buffer, oldest sample first
+        let mut power = [0.0; N];
+        for (i, p) in power.iter_mut().enumerate() {
+            *p = self.samples[(self.head + i) % N];
+        }
+
+        // Remove DC so it does not leak into the low bins through the window
+        let mean = power.iter().sum::<f32>() / N as f32;
+        power.iter_mut().for_each(|p| *p -= mean);
+
+        let mut scratch = [0.0; N];
+        self.fft.power_spectrum(&mut power, &mut scratch);
+
+        let (min_bin, max_bin) = match self.band() {
+            Some(band) => band,
+            None => return,
+        };
+        let resolution = self.resolution();
+
+        let floor = power[min_bin..=max_bin].iter().sum::<f32>() / (max_bin - min_bin + 1) as f32;
+
+        // A Hann windowed sine of amplitude A peaks at A * N / 4
+        let min_power = libm::powf(self.config.min_amplitude * N as f32 / 4.0, 2.0);
+        let threshold = (floor * self.config.threshold).max(min_power);
+
+        // Strongest local maxima in the search range as (power, frequency)
+        let mut found: [(f32, f32); P] = [(0.0, 0.0); P];
+
+        for bin in min_bin..=max_bin {
+            let p = power[bin];
+            if p <= threshold || p <= power[bin - 1] || p < power[bin + 1] {
+                continue;
+            }
+
+            // Replace the weakest peak found so far
+            if let Some(weakest) = found.iter_mut().min_by(|a, b| a.0.total_cmp(&b.0)) {
+                if p > weakest.0 {
+                    *weakest = (p, self.interpolate(&power, bin) * resolution);
+                }
+            }
+        }
+
+        // New peaks fill free slots in frequency order
+        found.sort_unstable_by(|a, b| {
+            // Unused slots go last
+            let a = if a.0 > 0.0 { a.1 } else { f32::MAX };
+            let b = if b.0 > 0.0 { b.1 } else { f32::MAX };
+            a.total_cmp(&b)
+        });
+
+        // Slot each found peak is assigned to
+        let mut slots: [Option<usize>; P] = [None; P];
+
+        // Tracked peaks keep the closest new estimate, closest pairs first
+        while let Some((slot, i)) = Self::closest_pair(&self.peaks, &found, &slots) {
+            slots[i] = Some(slot);
+        }
+
+        for i in 0..P {
+            if found[i].0 <= 0.0 || slots[i].is_some() {
+                continue;
+            }
+
+            let free = (0..P).find(|slot| self.peaks[*slot] == 0.0 && !slots.contains(&Some(*slot)));
+            slots[i] = free;
+        }
+
+        for ((_, freq), slot) in found.iter().zip(slots) {
+            if let Some(slot) = slot {
+                let peak = &mut self.peaks[slot];
+                let smoothing = &mut self.smoothing[slot];
+
+                *peak = if *peak == 0.0 {
+                    smoothing.reset_to(*freq);
+                    *freq
+                } else {
+                    smoothing.apply(*freq)
+                };
+            }
+        }
+    }
+
+    /// Closest tracked peak and found peak that have not been paired yet, as (slot, found index)
+    fn closest_pair(peaks: &[f32; P], found: &[(f32, f32); P], slots: &[Option<usize>; P]) -> Option<(usize, usize)> {
+        let mut closest: Option<(usize, usize, f32)> = None;
+
+        for (slot, &peak) in peaks.iter().enumerate() {
+            if peak == 0.0 || slots.contains(&Some(slot)) {
+                continue;
+            }
+
+            for (i, &(power, freq)) in found.iter().enumerate() {
+                if power <= 0.0 || slots[i].is_some() {
+                    continue;
+                }
+
+                let distance = libm::fabsf(freq - peak);
+                if closest.map(|(_, _, d)| distance < d).unwrap_or(true) {
+                    closest = Some((slot, i, distance));
+                }
+            }
+        }
+
+        closest.map(|(slot, i, _)| (slot, i))
+    }
+
+    /// Refine a peak location to a fractional bin using parabolic interpolation
+    fn interpolate(&self, power: &[f32; N], bin: usize) -> f32 {
+        let (y0, y1, y2) = (
+            libm::sqrtf(power[bin - 1]),
+            libm::sqrtf(power[bin]),
+            libm::sqrtf(power[bin + 1]),
+        );
+
+        let denom = y0 - 2.0 * y1 + y2;
+        let delta = if denom != 0.0 {
+            0.5 * (y0 - y2) / denom
+        } else {
+            0.0
+        };
+
+        bin as f32 + delta.clamp(-0.5, 0.5)
+    }
+}
+
+/// Bank of `P` notch filters whose center frequencies follow the vibration peaks found by a [PeakTracker]
+#[derive(Clone)]
+pub struct DynamicNotch<const N: usize, const P: usize> {
+    tracker: PeakTracker<N, P>,
+    notches: [BiquadFilter; P],
+    q: f32,
+    y: f32,
+}
+
+impl<const N: usize, const P: usize> DynamicNotch<N, P> {
+    pub fn new(config: DynamicNotchConfig, sample_rate_hz: f32) -> Self {
+        Self {
+            tracker: PeakTracker::new(config, sample_rate_hz),
+            // Notches pass through until a peak is found
+            notches: [BiquadFilter::notch(0.0, config.q, sample_rate_hz); P],
+            q: config.q,
+            y: 0.0,
+        }
+    }
+
+    /// Current notch center frequencies
+    pub fn centers(&self) -> [f32; P] {
+        let mut centers = [0.0; P];
+        for (c, n) in centers.iter_mut().zip(self.notches.iter()) {
+            *c = n.cutoff();
+        }
+
+        centers
+    }
+
+    /// False when the tracked band is empty at this sample rate. The signal then passes through unchanged
+    pub fn is_active(&self) -> bool {
+        self.tracker.band().is_some()
+    }
+
+    pub fn tracker(&self) -> &PeakTracker<N, P> {
+        &self.tracker
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate_hz: f32) {
+        self.tracker.set_sample_rate(sample_rate_hz);
+        for n in self.notches.iter_mut() {
+            n.set_sample_rate(sample_rate_hz);
+        }
+    }
+}
+
+impl<const N: usize, const P: usize> SignalFilter for DynamicNotch<N, P> {
+    fn apply(&mut self, input: f32) -> f32 {
+        // Track peaks on the unfiltered signal, otherwise the notches hide the peaks they are following
+        if self.tracker.push(input) {
+            for (notch, &center) in self.notches.iter_mut().zip(self.tracker.peaks().iter()) {
+                if center > 0.0 {
+                    notch.set_notch(center, self.q);
+                }
+            }
+        }
+
+        self.y = self.notches.iter_mut().fold(input, |x, n| n.apply(x));
+        self.y
+    }
+
+    fn value(&self) -> f32 {
+        self.y
+    }
+
+    fn reset(&mut self) {
+        self.tracker.reset();
+        for n in self.notches.iter_mut() {
+            n.set_cutoff(0.0);
+            n.reset();
+        }
+        self.y = 0.0;
+    }
+}
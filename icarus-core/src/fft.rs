@@ -0,0 +1,105 @@
+//
+// fft.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 06 2022
+//
+
+use core::f32::consts::PI;
+
+/// Radix-2 FFT over a fixed number of samples
+///
+/// Twiddle factors and the Hann window are computed once up front so the transform itself only does multiply/adds.
+/// `N` must be a power of two.
+#[derive(Clone)]
+pub struct Fft<const N: usize> {
+    cos: [f32; N],
+    sin: [f32; N],
+    window: [f32; N],
+}
+
+impl<const N: usize> Default for Fft<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Fft<N> {
+    pub fn new() -> Self {
+        assert!(N >= 2 && N.is_power_of_two(), "FFT size must be a power of two");
+
+        let mut cos = [0.0; N];
+        let mut sin = [0.0; N];
+        let mut window = [0.0; N];
+
+        for k in 0..N {
+            let theta = 2.0 * PI * (k as f32) / (N as f32);
+            cos[k] = libm::cosf(theta);
+            sin[k] = libm::sinf(theta);
+            // Periodic Hann window
+            window[k] = 0.5 - 0.5 * cos[k];
+        }
+
+        Self { cos, sin, window }
+    }
+
+    /// Apply the Hann window to a block of samples
+    pub fn apply_window(&self, samples: &mut [f32; N]) {
+        for (s, w) in samples.iter_mut().zip(self.window.iter()) {
+            *s *= w;
+        }
+    }
+
+    /// In-place forward transform
+    pub fn transform(&self, re: &mut [f32; N], im: &mut [f32; N]) {
+        // Bit reversal permutation
+        let bits = N.trailing_zeros();
+        for i in 0..N {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if j > i {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+
+        // Butterflies
+        let mut size = 2;
+        while size <= N {
+            let half = size / 2;
+            let step = N / size;
+
+            for start in (0..N).step_by(size) {
+                for k in 0..half {
+                    let (wr, wi) = (self.cos[k * step], -self.sin[k * step]);
+
+                    let a = start + k;
+                    let b = a + half;
+
+                    let tr = wr * re[b] - wi * im[b];
+                    let ti = wr * im[b] + wi * re[b];
+
+                    re[b] = re[a] - tr;
+                    im[b] = im[a] - ti;
+                    re[a] += tr;
+                    im[a] += ti;
+                }
+            }
+
+            size *= 2;
+        }
+    }
+
+    /// Window and transform a block of real samples, leaving the squared magnitude of each bin in `samples`
+    ///
+    /// Only the first `N / 2 + 1` bins are meaningful for real input.
+    pub fn power_spectrum(&self, samples: &mut [f32; N], scratch: &mut [f32; N]) {
+        self.apply_window(samples);
+        scratch.iter_mut().for_each(|s| *s = 0.0);
+
+        self.transform(samples, scratch);
+
+        for (re, im) in samples.iter_mut().zip(scratch.iter()) {
+            *re = *re * *re + *im * *im;
+        }
+    }
+}
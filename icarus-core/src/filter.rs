@@ -6,13 +6,37 @@
 //
 
 use heapless::HistoryBuffer;
+use serde::{Serialize, Deserialize};
+
+use core::f32::consts::PI;
+
+/// Q factor of a second order butterworth filter
+pub const BUTTERWORTH_Q: f32 = core::f32::consts::FRAC_1_SQRT_2;
+
+/// Common interface for single channel filters
+pub trait SignalFilter {
+    /// Push a new sample through the filter and get the filtered output
+    fn apply(&mut self, input: f32) -> f32;
+    /// Last filter output
+    fn value(&self) -> f32;
+    /// Clear the filter state
+    fn reset(&mut self);
+}
+
+/// Filters with a runtime configurable cutoff frequency and sample rate
+pub trait FrequencyFilter: SignalFilter {
+    /// Set the cutoff (or center) frequency in Hz
+    fn set_cutoff(&mut self, cutoff_hz: f32);
+    /// Set the rate, in Hz, that samples are pushed through the filter
+    fn set_sample_rate(&mut self, sample_rate_hz: f32);
+}
 
 /// Simple windowed average filter
-pub struct Filter<const N: usize> {
+pub struct MovingAverage<const N: usize> {
     buf: HistoryBuffer<f32, N>,
 }
 
-impl<const N: usize> Default for Filter<N> {
+impl<const N: usize> Default for MovingAverage<N> {
     fn default() -> Self {
         Self {
             buf: HistoryBuffer::new(),
@@ -20,40 +44,438 @@ impl<const N: usize> Default for Filter<N> {
     }
 }
 
-impl<const N: usize> Filter<N> {
+impl<const N: usize> MovingAverage<N> {
     pub fn update(&mut self, value: f32) {
         self.buf.write(value)
     }
+}
+
+impl<const N: usize> SignalFilter for MovingAverage<N> {
+    fn apply(&mut self, input: f32) -> f32 {
+        self.update(input);
+        self.value()
+    }
+
+    fn value(&self) -> f32 {
+        if self.buf.is_empty() {
+            return 0.0;
+        }
 
-    pub fn value(&self) -> f32 {
         self.buf.as_slice().iter().sum::<f32>() / (self.buf.len() as f32)
     }
+
+    fn reset(&mut self) {
+        self.buf = HistoryBuffer::new();
+    }
+}
+
+/// Chain of N first order low-pass filters (PT1, PT2, PT3...)
+///
+/// The gain of each stage is matched to the sampled response, so the -3dB point of the whole chain lands on the
+/// requested cutoff frequency for any order.
+#[derive(Debug, Clone, Copy)]
+pub struct PtFilter<const ORDER: usize> {
+    state: [f32; ORDER],
+    k: f32,
+    cutoff_hz: f32,
+    sample_rate_hz: f32,
+}
+
+/// First order low-pass filter
+pub type Pt1Filter = PtFilter<1>;
+/// Second order low-pass filter
+pub type Pt2Filter = PtFilter<2>;
+/// Third order low-pass filter
+pub type Pt3Filter = PtFilter<3>;
+
+impl<const ORDER: usize> PtFilter<ORDER> {
+    pub fn new(cutoff_hz: f32, sample_rate_hz: f32) -> Self {
+        Self {
+            state: [0.0; ORDER],
+            k: pt_gain(ORDER, cutoff_hz, sample_rate_hz),
+            cutoff_hz,
+            sample_rate_hz,
+        }
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff_hz
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate_hz
+    }
+
+    /// Reset the filter as if it had settled at `value`
+    pub fn reset_to(&mut self, value: f32) {
+        self.state = [value; ORDER];
+    }
+
+    fn update_gain(&mut self) {
+        self.k = pt_gain(ORDER, self.cutoff_hz, self.sample_rate_hz);
+    }
+}
+
+impl<const ORDER: usize> SignalFilter for PtFilter<ORDER> {
+    fn apply(&mut self, input: f32) -> f32 {
+        let mut x = input;
+        for s in self.state.iter_mut() {
+            *s += self.k * (x - *s);
+            x = *s;
+        }
+
+        x
+    }
+
+    fn value(&self) -> f32 {
+        self.state.last().copied().unwrap_or(0.0)
+    }
+
+    fn reset(&mut self) {
+        self.state = [0.0; ORDER];
+    }
+}
+
+impl<const ORDER: usize> FrequencyFilter for PtFilter<ORDER> {
+    fn set_cutoff(&mut self, cutoff_hz: f32) {
+        self.cutoff_hz = cutoff_hz;
+        self.update_gain();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate_hz: f32) {
+        self.sample_rate_hz = sample_rate_hz;
+        self.update_gain();
+    }
+}
+
+/// Gain of a single PT stage such that `order` cascaded stages have a -3dB point at `cutoff_hz`
+fn pt_gain(order: usize, cutoff_hz: f32, sample_rate_hz: f32) -> f32 {
+    if cutoff_hz <= 0.0 || sample_rate_hz <= 0.0 {
+        // Pass through
+        return 1.0;
+    }
+
+    // Squared magnitude each stage should have at the cutoff
+    let stage = libm::powf(0.5, 1.0 / order as f32);
+    // Cutoffs past Nyquist are treated as Nyquist
+    let omega = (2.0 * PI * cutoff_hz / sample_rate_hz).min(PI);
+
+    // A stage is k / (1 - (1 - k)z^-1). Solve |H(omega)|^2 = stage for the pole inside the unit circle
+    let b = (1.0 - stage * libm::cosf(omega)) / (1.0 - stage);
+    let pole = b - libm::sqrtf(b * b - 1.0);
+
+    1.0 - pole
+}
+
+/// Response of a biquad filter
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BiquadType {
+    LowPass,
+    Notch,
+}
+
+/// Second order IIR filter (direct form II transposed)
+///
+/// Coefficients are from the RBJ audio EQ cookbook.
+#[derive(Debug, Clone, Copy)]
+pub struct BiquadFilter {
+    kind: BiquadType,
+    cutoff_hz: f32,
+    q: f32,
+    sample_rate_hz: f32,
+
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+
+    s1: f32,
+    s2: f32,
+    y: f32,
+}
+
+impl BiquadFilter {
+    pub fn new(kind: BiquadType, cutoff_hz: f32, q: f32, sample_rate_hz: f32) -> Self {
+        let mut filter = Self {
+            kind,
+            cutoff_hz,
+            q,
+            sample_rate_hz,
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            s1: 0.0,
+            s2: 0.0,
+            y: 0.0,
+        };
+        filter.update_coefficients();
+
+        filter
+    }
+
+    /// Second order butterworth low-pass filter
+    pub fn low_pass(cutoff_hz: f32, sample_rate_hz: f32) -> Self {
+        Self::new(BiquadType::LowPass, cutoff_hz, BUTTERWORTH_Q, sample_rate_hz)
+    }
+
+    /// Notch filter at `center_hz` with quality factor `q`
+    pub fn notch(center_hz: f32, q: f32, sample_rate_hz: f32) -> Self {
+        Self::new(BiquadType::Notch, center_hz, q, sample_rate_hz)
+    }
+
+    pub fn kind(&self) -> BiquadType {
+        self.kind
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff_hz
+    }
+
+    pub fn q(&self) -> f32 {
+        self.q
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate_hz
+    }
+
+    /// Move the center frequency and Q of the filter without clearing its state
+    pub fn set_notch(&mut self, center_hz: f32, q: f32) {
+        self.cutoff_hz = center_hz;
+        self.q = q;
+        self.update_coefficients();
+    }
+
+    fn update_coefficients(&mut self) {
+        let nyquist = self.sample_rate_hz / 2.0;
+        if self.cutoff_hz <= 0.0 || self.cutoff_hz >= nyquist || self.q <= 0.0 {
+            // Pass through
+            self.b0 = 1.0;
+            self.b1 = 0.0;
+            self.b2 = 0.0;
+            self.a1 = 0.0;
+            self.a2 = 0.0;
+            return;
+        }
+
+        let omega = 2.0 * PI * self.cutoff_hz / self.sample_rate_hz;
+        let sn = libm::sinf(omega);
+        let cs = libm::cosf(omega);
+        let alpha = sn / (2.0 * self.q);
+
+        let (b0, b1, b2) = match self.kind {
+            BiquadType::LowPass => {
+                let b1 = 1.0 - cs;
+                (b1 / 2.0, b1, b1 / 2.0)
+            }
+            BiquadType::Notch => (1.0, -2.0 * cs, 1.0),
+        };
+
+        let a0 = 1.0 + alpha;
+
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = (-2.0 * cs) / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+}
+
+impl SignalFilter for BiquadFilter {
+    fn apply(&mut self, input: f32) -> f32 {
+        let y = self.b0 * input + self.s1;
+        self.s1 = self.b1 * input - self.a1 * y + self.s2;
+        self.s2 = self.b2 * input - self.a2 * y;
+        self.y = y;
+
+        y
+    }
+
+    fn value(&self) -> f32 {
+        self.y
+    }
+
+    fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        self.y = 0.0;
+    }
+}
+
+impl FrequencyFilter for BiquadFilter {
+    fn set_cutoff(&mut self, cutoff_hz: f32) {
+        self.cutoff_hz = cutoff_hz;
+        self.update_coefficients();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate_hz: f32) {
+        self.sample_rate_hz = sample_rate_hz;
+        self.update_coefficients();
+    }
+}
+
+/// Q factor for a notch centered at `center_hz` whose -3dB points start at `cutoff_hz`
+///
+/// The cutoff must be above zero and below the center. Returns `None` otherwise.
+pub fn notch_q(center_hz: f32, cutoff_hz: f32) -> Option<f32> {
+    // Also rejects NaN
+    if !(cutoff_hz > 0.0 && cutoff_hz < center_hz) {
+        return None;
+    }
+
+    Some(center_hz * cutoff_hz / (center_hz * center_hz - cutoff_hz * cutoff_hz))
+}
+
+/// Low-pass filter implementations selectable at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LowPassType {
+    Pt1,
+    Pt2,
+    Pt3,
+    Biquad,
+}
+
+/// Low-pass filter configuration. A cutoff of zero disables the filter
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LowPassConfig {
+    pub kind: LowPassType,
+    pub cutoff_hz: f32,
+}
+
+impl Default for LowPassConfig {
+    fn default() -> Self {
+        Self {
+            kind: LowPassType::Pt1,
+            cutoff_hz: 0.0,
+        }
+    }
+}
+
+/// Low-pass filter with a runtime selected implementation
+#[derive(Debug, Clone, Copy)]
+pub enum LowPassFilter {
+    Pt1(Pt1Filter),
+    Pt2(Pt2Filter),
+    Pt3(Pt3Filter),
+    Biquad(BiquadFilter),
+}
+
+impl LowPassFilter {
+    pub fn new(config: LowPassConfig, sample_rate_hz: f32) -> Self {
+        let LowPassConfig { kind, cutoff_hz } = config;
+
+        match kind {
+            LowPassType::Pt1 => LowPassFilter::Pt1(Pt1Filter::new(cutoff_hz, sample_rate_hz)),
+            LowPassType::Pt2 => LowPassFilter::Pt2(Pt2Filter::new(cutoff_hz, sample_rate_hz)),
+            LowPassType::Pt3 => LowPassFilter::Pt3(Pt3Filter::new(cutoff_hz, sample_rate_hz)),
+            LowPassType::Biquad => LowPassFilter::Biquad(BiquadFilter::low_pass(cutoff_hz, sample_rate_hz)),
+        }
+    }
+
+    pub fn kind(&self) -> LowPassType {
+        match self {
+            LowPassFilter::Pt1(_) => LowPassType::Pt1,
+            LowPassFilter::Pt2(_) => LowPassType::Pt2,
+            LowPassFilter::Pt3(_) => LowPassType::Pt3,
+            LowPassFilter::Biquad(_) => LowPassType::Biquad,
+        }
+    }
+
+    fn inner(&self) -> &dyn FrequencyFilter {
+        match self {
+            LowPassFilter::Pt1(f) => f,
+            LowPassFilter::Pt2(f) => f,
+            LowPassFilter::Pt3(f) => f,
+            LowPassFilter::Biquad(f) => f,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut dyn FrequencyFilter {
+        match self {
+            LowPassFilter::Pt1(f) => f,
+            LowPassFilter::Pt2(f) => f,
+            LowPassFilter::Pt3(f) => f,
+            LowPassFilter::Biquad(f) => f,
+        }
+    }
+}
+
+impl Default for LowPassFilter {
+    fn default() -> Self {
+        // Disabled filter
+        LowPassFilter::Pt1(Pt1Filter::new(0.0, 0.0))
+    }
+}
+
+impl SignalFilter for LowPassFilter {
+    fn apply(&mut self, input: f32) -> f32 {
+        self.inner_mut().apply(input)
+    }
+
+    fn value(&self) -> f32 {
+        self.inner().value()
+    }
+
+    fn reset(&mut self) {
+        self.inner_mut().reset()
+    }
+}
+
+impl FrequencyFilter for LowPassFilter {
+    fn set_cutoff(&mut self, cutoff_hz: f32) {
+        self.inner_mut().set_cutoff(cutoff_hz)
+    }
+
+    fn set_sample_rate(&mut self, sample_rate_hz: f32) {
+        self.inner_mut().set_sample_rate(sample_rate_hz)
+    }
 }
 
 /// Filter for tri-axial sensor
-pub struct TriAxialFilter<const N: usize> {
-    x_filter: Filter<N>,
-    y_filter: Filter<N>,
-    z_filter: Filter<N>,
+pub struct TriAxialFilter<F> {
+    x_filter: F,
+    y_filter: F,
+    z_filter: F,
 }
 
-impl<const N: usize> Default for TriAxialFilter<N> {
+impl<F: Default> Default for TriAxialFilter<F> {
     fn default() -> Self {
         Self {
-            x_filter: Filter::default(),
-            y_filter: Filter::default(),
-            z_filter: Filter::default(),
+            x_filter: F::default(),
+            y_filter: F::default(),
+            z_filter: F::default(),
+        }
+    }
+}
+
+impl<F: Clone> TriAxialFilter<F> {
+    /// Use a copy of the same filter on each axis
+    pub fn new(filter: F) -> Self {
+        Self {
+            x_filter: filter.clone(),
+            y_filter: filter.clone(),
+            z_filter: filter,
         }
     }
 }
 
-impl<const N: usize> TriAxialFilter<N> {
+impl<F: SignalFilter> TriAxialFilter<F> {
     pub fn update<T: Into<(f32, f32, f32)>>(&mut self, data: T) {
+        self.apply(data);
+    }
+
+    /// Push a sample through each axis filter and get the filtered output
+    pub fn apply<T: Into<(f32, f32, f32)>>(&mut self, data: T) -> (f32, f32, f32) {
         let (x, y, z) = data.into();
 
-        self.x_filter.update(x);
-        self.y_filter.update(y);
-        self.z_filter.update(z);
+        (
+            self.x_filter.apply(x),
+            self.y_filter.apply(y),
+            self.z_filter.apply(z),
+        )
     }
 
     pub fn value(&self) -> (f32, f32, f32) {
@@ -69,10 +491,40 @@ impl<const N: usize> TriAxialFilter<N> {
     }
 
     pub fn get_y(&self) -> f32 {
-        self.x_filter.value()
+        self.y_filter.value()
     }
 
     pub fn get_z(&self) -> f32 {
         self.z_filter.value()
     }
+
+    pub fn reset(&mut self) {
+        self.x_filter.reset();
+        self.y_filter.reset();
+        self.z_filter.reset();
+    }
+
+    /// The individual axis filters
+    pub fn axes(&self) -> [&F; 3] {
+        [&self.x_filter, &self.y_filter, &self.z_filter]
+    }
+
+    /// Access the individual axis filters
+    pub fn axes_mut(&mut self) -> [&mut F; 3] {
+        [&mut self.x_filter, &mut self.y_filter, &mut self.z_filter]
+    }
+}
+
+impl<F: FrequencyFilter> TriAxialFilter<F> {
+    pub fn set_cutoff(&mut self, cutoff_hz: f32) {
+        for f in self.axes_mut() {
+            f.set_cutoff(cutoff_hz);
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate_hz: f32) {
+        for f in self.axes_mut() {
+            f.set_sample_rate(sample_rate_hz);
+        }
+    }
 }
@@ -0,0 +1,321 @@
+//
+// health.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 09 2022
+//
+
+use crate::{
+    data::{AccelerometerData, GyroscopeData},
+    filter::{Pt1Filter, SignalFilter},
+};
+
+use serde::{Serialize, Deserialize};
+
+/// Sensor fault bitfield
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct HealthFlags(pub u16);
+
+impl HealthFlags {
+    /// Last accelerometer read failed
+    pub const ACCEL_READ_FAILURE: HealthFlags = HealthFlags(1 << 0);
+    /// Last gyroscope read failed
+    pub const GYRO_READ_FAILURE: HealthFlags = HealthFlags(1 << 1);
+    /// Accelerometer is reporting the exact same value over and over
+    pub const ACCEL_STUCK: HealthFlags = HealthFlags(1 << 2);
+    /// Gyroscope is reporting the exact same value over and over
+    pub const GYRO_STUCK: HealthFlags = HealthFlags(1 << 3);
+    /// Accelerometer reading is at the limit of the measurement range
+    pub const ACCEL_CLIPPING: HealthFlags = HealthFlags(1 << 4);
+    /// Gyroscope reading is at the limit of the measurement range
+    pub const GYRO_CLIPPING: HealthFlags = HealthFlags(1 << 5);
+    /// Accelerometer reported a value that is not physically possible
+    pub const ACCEL_OUT_OF_RANGE: HealthFlags = HealthFlags(1 << 6);
+    /// Gyroscope reported a value that is not physically possible
+    pub const GYRO_OUT_OF_RANGE: HealthFlags = HealthFlags(1 << 7);
+    /// Samples are not arriving at the expected rate
+    pub const SAMPLE_RATE: HealthFlags = HealthFlags(1 << 8);
+    /// Too many consecutive read failures. The sensor needs to be re-initialized
+    pub const SENSOR_LOST: HealthFlags = HealthFlags(1 << 9);
+
+    /// Faults that make the IMU unusable for flight
+    pub const CRITICAL: HealthFlags = HealthFlags(
+        Self::ACCEL_STUCK.0
+            | Self::GYRO_STUCK.0
+            | Self::ACCEL_OUT_OF_RANGE.0
+            | Self::GYRO_OUT_OF_RANGE.0
+            | Self::SENSOR_LOST.0,
+    );
+
+    pub const fn empty() -> Self {
+        HealthFlags(0)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(&self, other: HealthFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(&self, other: HealthFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn set(&mut self, other: HealthFlags, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+impl core::ops::BitOr for HealthFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        HealthFlags(self.0 | rhs.0)
+    }
+}
+
+/// IMU sensors monitored for faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImuSensor {
+    Accelerometer,
+    Gyroscope,
+}
+
+/// Thresholds used to detect sensor faults
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Accelerometer full scale range (g)
+    pub accel_range: f32,
+    /// Gyroscope full scale range (rad/s)
+    pub gyro_range: f32,
+    /// Fraction of full scale range considered to be clipping
+    pub clip_fraction: f32,
+    /// Number of identical consecutive samples before a sensor is considered stuck
+    pub stuck_samples: u16,
+    /// Number of consecutive failed reads before the sensor is considered lost
+    pub max_consecutive_failures: u16,
+    /// Expected sample rate (Hz)
+    pub expected_rate: f32,
+    /// Allowed deviation from the expected sample rate, as a fraction
+    pub rate_tolerance: f32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            // MPU6050 defaults: +/-2g and +/-250 deg/s
+            accel_range: 2.0,
+            gyro_range: 250.0 * core::f32::consts::PI / 180.0,
+            clip_fraction: 0.98,
+            stuck_samples: 50,
+            max_consecutive_failures: 10,
+            expected_rate: 50.0,
+            rate_tolerance: 0.25,
+        }
+    }
+}
+
+/// Sensor health summary reported in telemetry
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy)]
+pub struct SensorHealth {
+    /// Active faults
+    pub flags: HealthFlags,
+    /// Total accelerometer read failures
+    pub accel_failures: u32,
+    /// Total gyroscope read failures
+    pub gyro_failures: u32,
+    /// Number of times the sensor has been re-initialized
+    pub reinit_count: u16,
+    /// Measured sample rate (Hz)
+    pub sample_rate: f32,
+}
+
+/// Tracks IMU read failures, stuck values, clipping and sample-rate deviation
+pub struct SensorHealthMonitor {
+    config: HealthConfig,
+    flags: HealthFlags,
+
+    accel_failures: u32,
+    gyro_failures: u32,
+    consecutive_failures: u16,
+    reinit_count: u16,
+
+    last_accel: Option<(f32, f32, f32)>,
+    last_gyro: Option<(f32, f32, f32)>,
+    accel_repeats: u16,
+    gyro_repeats: u16,
+
+    rate_filter: Pt1Filter,
+}
+
+impl Default for SensorHealthMonitor {
+    fn default() -> Self {
+        Self::new(HealthConfig::default())
+    }
+}
+
+impl SensorHealthMonitor {
+    pub fn new(config: HealthConfig) -> Self {
+        let mut rate_filter = Pt1Filter::new(1.0, config.expected_rate);
+        rate_filter.reset_to(config.expected_rate);
+
+        Self {
+            config,
+            flags: HealthFlags::empty(),
+            accel_failures: 0,
+            gyro_failures: 0,
+            consecutive_failures: 0,
+            reinit_count: 0,
+            last_accel: None,
+            last_gyro: None,
+            accel_repeats: 0,
+            gyro_repeats: 0,
+            rate_filter,
+        }
+    }
+
+    pub fn config(&self) -> &HealthConfig {
+        &self.config
+    }
+
+    /// Record a failed read from the given sensor
+    pub fn record_failure(&mut self, sensor: ImuSensor) {
+        self.count_failure(sensor);
+        self.record_failed_read();
+    }
+
+    /// Record a failed read of both sensors in the same transaction. Counts once towards losing the sensor
+    pub fn record_imu_failure(&mut self) {
+        self.count_failure(ImuSensor::Accelerometer);
+        self.count_failure(ImuSensor::Gyroscope);
+        self.record_failed_read();
+    }
+
+    fn count_failure(&mut self, sensor: ImuSensor) {
+        match sensor {
+            ImuSensor::Accelerometer => {
+                self.accel_failures = self.accel_failures.saturating_add(1);
+                self.flags.set(HealthFlags::ACCEL_READ_FAILURE, true);
+            }
+            ImuSensor::Gyroscope => {
+                self.gyro_failures = self.gyro_failures.saturating_add(1);
+                self.flags.set(HealthFlags::GYRO_READ_FAILURE, true);
+            }
+        }
+    }
+
+    fn record_failed_read(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.flags.set(
+            HealthFlags::SENSOR_LOST,
+            self.consecutive_failures >= self.config.max_consecutive_failures,
+        );
+    }
+
+    /// Check a successful sample. `delta` is the time since the previous sample in seconds
+    pub fn record_sample(&mut self, accel: AccelerometerData, gyro: GyroscopeData, delta: f32) {
+        self.consecutive_failures = 0;
+        self.flags.set(
+            HealthFlags::ACCEL_READ_FAILURE | HealthFlags::GYRO_READ_FAILURE | HealthFlags::SENSOR_LOST,
+            false,
+        );
+
+        let accel: (f32, f32, f32) = accel.into();
+        let gyro: (f32, f32, f32) = gyro.into();
+
+        // Stuck values
+        self.accel_repeats = repeats(self.last_accel, accel, self.accel_repeats);
+        self.gyro_repeats = repeats(self.last_gyro, gyro, self.gyro_repeats);
+        self.last_accel = Some(accel);
+        self.last_gyro = Some(gyro);
+
+        self.flags.set(HealthFlags::ACCEL_STUCK, self.accel_repeats >= self.config.stuck_samples);
+        self.flags.set(HealthFlags::GYRO_STUCK, self.gyro_repeats >= self.config.stuck_samples);
+
+        // Clipping and impossible readings
+        let (accel_clip, accel_invalid) = range_check(accel, self.config.accel_range, self.config.clip_fraction);
+        let (gyro_clip, gyro_invalid) = range_check(gyro, self.config.gyro_range, self.config.clip_fraction);
+
+        self.flags.set(HealthFlags::ACCEL_CLIPPING, accel_clip);
+        self.flags.set(HealthFlags::ACCEL_OUT_OF_RANGE, accel_invalid);
+        self.flags.set(HealthFlags::GYRO_CLIPPING, gyro_clip);
+        self.flags.set(HealthFlags::GYRO_OUT_OF_RANGE, gyro_invalid);
+
+        // Sample rate
+        if delta > 0.0 {
+            let rate = self.rate_filter.apply(1.0 / delta);
+            let deviation = libm::fabsf(rate - self.config.expected_rate) / self.config.expected_rate;
+            self.flags.set(HealthFlags::SAMPLE_RATE, deviation > self.config.rate_tolerance);
+        }
+    }
+
+    /// The sensor has been re-initialized. Clears the failure streak and stuck detection
+    pub fn record_reinit(&mut self) {
+        self.reinit_count = self.reinit_count.saturating_add(1);
+        self.consecutive_failures = 0;
+        self.last_accel = None;
+        self.last_gyro = None;
+        self.accel_repeats = 0;
+        self.gyro_repeats = 0;
+        self.flags.set(HealthFlags::SENSOR_LOST, false);
+    }
+
+    /// Whether the sensor should be re-initialized
+    pub fn needs_reinit(&self) -> bool {
+        self.flags.contains(HealthFlags::SENSOR_LOST)
+    }
+
+    pub fn consecutive_failures(&self) -> u16 {
+        self.consecutive_failures
+    }
+
+    pub fn flags(&self) -> HealthFlags {
+        self.flags
+    }
+
+    /// No critical faults are active
+    pub fn is_healthy(&self) -> bool {
+        !self.flags.intersects(HealthFlags::CRITICAL)
+    }
+
+    pub fn report(&self) -> SensorHealth {
+        SensorHealth {
+            flags: self.flags,
+            accel_failures: self.accel_failures,
+            gyro_failures: self.gyro_failures,
+            reinit_count: self.reinit_count,
+            sample_rate: self.rate_filter.value(),
+        }
+    }
+}
+
+fn repeats(last: Option<(f32, f32, f32)>, current: (f32, f32, f32), count: u16) -> u16 {
+    match last {
+        Some(last) if last == current => count.saturating_add(1),
+        _ => 0,
+    }
+}
+
+/// Returns (clipping, invalid) for a tri-axial reading
+fn range_check(value: (f32, f32, f32), range: f32, clip_fraction: f32) -> (bool, bool) {
+    let (x, y, z) = value;
+
+    let mut clipping = false;
+    let mut invalid = false;
+
+    for v in [x, y, z] {
+        if !v.is_finite() || libm::fabsf(v) > range * 1.1 {
+            invalid = true;
+        } else if libm::fabsf(v) >= range * clip_fraction {
+            clipping = true;
+        }
+    }
+
+    (clipping, invalid)
+}
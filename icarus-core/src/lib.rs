@@ -7,15 +7,35 @@
 #![no_std]
 pub mod data;
 pub mod filter;
+pub mod fft;
+pub mod dynamic_notch;
+pub mod health;
+pub mod arming;
+pub mod calibration;
+pub mod orientation;
+pub mod params;
+pub mod control;
+pub mod mixer;
+pub mod battery;
+pub mod button;
+pub mod motor;
+pub mod mode;
 
 use crate::{
     data::{AccelerometerData, GyroscopeData, Attitude},
-    filter::TriAxialFilter,
+    dynamic_notch::{DynamicNotch, DynamicNotchConfig},
+    filter::{MovingAverage, TriAxialFilter},
+    orientation::{BoardAlignment, SensorAlignment},
 };
 
 use serde::{Serialize, Deserialize};
 use ahrs::{Ahrs, Madgwick};
-use nalgebra::Vector3;
+use nalgebra::{UnitQuaternion, Vector3};
+
+/// Gyro samples analyzed per spectrum by the dynamic notch
+pub const GYRO_NOTCH_SAMPLES: usize = 64;
+/// Vibration peaks notched out of each gyro axis
+pub const GYRO_NOTCHES: usize = 2;
 
 pub enum EstimatorError {
     AhrsError,
@@ -38,36 +58,106 @@ pub struct EstimatorInput {
     pub altitude: f32,
 }
 
+/// State estimator tuning
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EstimatorConfig {
+    /// Madgwick filter gain. Higher trusts the accelerometer more
+    pub madgwick_beta: f32,
+    /// Nominal sample period (s)
+    pub sample_period: f32,
+    /// Skip the accelerometer correction when the measured acceleration differs from 1g by more than this (g)
+    pub accel_rejection: f32,
+    /// Dynamic notch on the gyro. `None` disables it. Also left out when its band is above the Nyquist frequency
+    pub gyro_notch: Option<DynamicNotchConfig>,
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        Self {
+            madgwick_beta: 0.1,
+            sample_period: 0.02,
+            accel_rejection: 0.5,
+            gyro_notch: Some(DynamicNotchConfig::default()),
+        }
+    }
+}
+
 /// Consume Accelerometer, Gyro, Magetometer, Barometer data and determine system state
 pub struct StateEstimator {
     /// AHRS filter
     ahrs: Madgwick<f32>,
     /// Accelerometer filter. 3 samples.
-    accel_filter: TriAxialFilter<3>,
+    accel_filter: TriAxialFilter<MovingAverage<3>>,
+    /// Removes motor vibration from the gyro, ahead of the gyro filter
+    gyro_notch: Option<TriAxialFilter<DynamicNotch<GYRO_NOTCH_SAMPLES, GYRO_NOTCHES>>>,
     /// Gyro filter. 3 samples
-    gyro_filter: TriAxialFilter<3>,
+    gyro_filter: TriAxialFilter<MovingAverage<3>>,
+    /// Sensor to body frame rotation
+    alignment: SensorAlignment,
+    /// Last gyro sample in the body frame, after the notch
+    body_gyro: GyroscopeData,
+    config: EstimatorConfig,
 }
 
 impl Default for StateEstimator {
     fn default() -> Self {
+        Self::new(EstimatorConfig::default())
+    }
+}
+
+impl StateEstimator {
+    pub fn new(config: EstimatorConfig) -> Self {
         StateEstimator {
-            ahrs: Madgwick::new(0.02, 0.1),
+            ahrs: Madgwick::new(config.sample_period, config.madgwick_beta),
             accel_filter: TriAxialFilter::default(),
+            gyro_notch: config.gyro_notch
+                .map(|notch| DynamicNotch::new(notch, 1.0 / config.sample_period))
+                .filter(|notch| notch.is_active())
+                .map(TriAxialFilter::new),
             gyro_filter: TriAxialFilter::default(),
+            alignment: SensorAlignment::default(),
+            body_gyro: GyroscopeData::default(),
+            config,
         }
     }
-}
 
-impl StateEstimator {
+    /// Set how the sensor is mounted on the frame. Sensor data is rotated into the body frame before estimation
+    pub fn set_alignment(&mut self, alignment: BoardAlignment) {
+        self.alignment = alignment.into();
+    }
+
+    /// False if the gyro notch was disabled, or can't track anything at the configured sample period
+    pub fn has_gyro_notch(&self) -> bool {
+        self.gyro_notch.is_some()
+    }
+
+    /// Current gyro notch center frequencies for each axis (x, y, z). Zero means the notch has not found a peak
+    pub fn gyro_notch_centers(&self) -> Option<[[f32; GYRO_NOTCHES]; 3]> {
+        self.gyro_notch.as_ref().map(|notch| notch.axes().map(|axis| axis.centers()))
+    }
+
+    /// Body frame gyro from the last update with motor vibration notched out, for the rate loop
+    ///
+    /// Taken ahead of the estimator's own gyro filter, which adds more delay than the rate loop can tolerate.
+    pub fn body_gyro(&self) -> GyroscopeData {
+        self.body_gyro
+    }
+
     pub fn update(&mut self, input: EstimatorInput, delta: f32) -> Result<EstimatedState, EstimatorError> {
         let EstimatorInput{accel, gyro, altitude: _} = input;
 
-        // Filter raw IMU data
-        self.accel_filter.update(accel);
-        self.gyro_filter.update(gyro);
+        // Sensor frame to body frame
+        let accel = self.alignment.accel(accel);
+        let gyro = self.alignment.gyro(gyro);
 
-        let accel = self.accel_filter.value();
-        let gyro = self.gyro_filter.value();
+        // Filter raw IMU data
+        let accel = self.accel_filter.apply(accel);
+        let gyro: (f32, f32, f32) = match self.gyro_notch.as_mut() {
+            Some(notch) => notch.apply(gyro),
+            None => gyro.into(),
+        };
+        self.body_gyro = GyroscopeData { x: gyro.0, y: gyro.1, z: gyro.2 };
+        let gyro = self.gyro_filter.apply(gyro);
 
         let accel = Vector3::new(accel.0, accel.1, accel.2);
         let gyro = Vector3::new(gyro.0, gyro.1, gyro.2);
@@ -76,7 +166,19 @@ impl StateEstimator {
         let sample_period = self.ahrs.sample_period_mut();
         *sample_period = delta;
 
-        let quat = self.ahrs.update_imu(&gyro, &accel).map_err(|_| EstimatorError::AhrsError)?;
+        let previous = self.ahrs.quat();
+        // Gravity as the current estimate expects the accelerometer to measure it
+        let expected = previous.inverse_transform_vector(&Vector3::z());
+
+        let quat = match self.accel_correction(accel, expected) {
+            Some(accel) => *self.ahrs.update_imu(&gyro, &accel).map_err(|_| EstimatorError::AhrsError)?,
+            None => {
+                let quat = previous * UnitQuaternion::from_scaled_axis(gyro * delta);
+                *self.ahrs.quat_mut() = quat;
+                quat
+            }
+        };
+
         let (roll, pitch, yaw) = quat.euler_angles();
 
         Ok(EstimatedState{
@@ -84,4 +186,24 @@ impl StateEstimator {
             z_vel: 0.0
         })
     }
+
+    /// Normalized accelerometer reading to correct the attitude with, or `None` to integrate the gyro alone
+    fn accel_correction(&self, accel: Vector3<f32>, expected: Vector3<f32>) -> Option<Vector3<f32>> {
+        let norm = accel.norm();
+
+        // In free fall or under hard acceleration the accelerometer no longer points along gravity. A reading with no
+        // length has no direction at all
+        if !norm.is_finite() || norm <= f32::EPSILON || libm::fabsf(norm - 1.0) > self.config.accel_rejection {
+            return None;
+        }
+
+        let accel = accel / norm;
+
+        // Nothing to correct. The Madgwick step would normalize a zero gradient to NaN
+        if (accel - expected).norm() < 1e-6 {
+            return None;
+        }
+
+        Some(accel)
+    }
 }
@@ -0,0 +1,88 @@
+//
+// mixer.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use crate::control::ControlOutput;
+
+/// Number of rotors
+pub const NUM_MOTORS: usize = 4;
+
+/// Contribution of each axis to a single motor
+#[derive(Debug, Clone, Copy)]
+pub struct MixerRule {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+/// Quad X layout
+///
+/// Body frame is x forward, y left, z up. Motors are ordered front-right, rear-right, rear-left, front-left (rotor 1 to
+/// 4). Front-right and rear-left spin counter-clockwise when viewed from above, the other pair spins clockwise.
+pub const QUAD_X: [MixerRule; NUM_MOTORS] = [
+    MixerRule { roll: -1.0, pitch: -1.0, yaw: -1.0 },
+    MixerRule { roll: -1.0, pitch: 1.0, yaw: 1.0 },
+    MixerRule { roll: 1.0, pitch: 1.0, yaw: -1.0 },
+    MixerRule { roll: 1.0, pitch: -1.0, yaw: 1.0 },
+];
+
+/// Converts torque and thrust demand into per motor outputs in the range 0 - 1
+pub struct Mixer {
+    rules: [MixerRule; NUM_MOTORS],
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new(QUAD_X)
+    }
+}
+
+impl Mixer {
+    pub fn new(rules: [MixerRule; NUM_MOTORS]) -> Self {
+        Self { rules }
+    }
+
+    /// Mix a control output
+    ///
+    /// Torque demand has priority over thrust. If the motors would saturate the torque demand is scaled down to fit and
+    /// the thrust shifted to keep every motor in range. Zero or invalid thrust stops the motors, an invalid torque
+    /// demand is ignored.
+    pub fn mix(&self, output: ControlOutput) -> [f32; NUM_MOTORS] {
+        if output.thrust.is_nan() || output.thrust <= 0.0 {
+            return [0.0; NUM_MOTORS];
+        }
+
+        let mut torque = [0.0; NUM_MOTORS];
+        for (t, rule) in torque.iter_mut().zip(self.rules.iter()) {
+            *t = rule.roll * output.roll + rule.pitch * output.pitch + rule.yaw * output.yaw;
+        }
+
+        if torque.iter().any(|t| !t.is_finite()) {
+            torque = [0.0; NUM_MOTORS];
+        }
+
+        let min = torque.iter().copied().fold(f32::MAX, f32::min);
+        let max = torque.iter().copied().fold(f32::MIN, f32::max);
+
+        let spread = max - min;
+        let (min, max) = if spread > 1.0 {
+            torque.iter_mut().for_each(|t| *t /= spread);
+            (min / spread, max / spread)
+        } else {
+            (min, max)
+        };
+
+        // Rounding after scaling can leave the bounds crossed by an ulp, so no clamp. Staying above zero wins
+        let thrust = output.thrust.min(1.0).min(1.0 - max).max(-min);
+
+        let mut motors = [0.0; NUM_MOTORS];
+        for (m, t) in motors.iter_mut().zip(torque.iter()) {
+            *m = (thrust + t).clamp(0.0, 1.0);
+        }
+
+        motors
+    }
+}
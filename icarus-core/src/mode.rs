@@ -0,0 +1,398 @@
+//
+// mode.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 06 2022
+//
+
+use crate::{
+    control::{AttitudeSetpoint, Pid, PidGains, RateSetpoint, MAX_TILT},
+    filter::{LowPassConfig, LowPassType, Pt1Filter, SignalFilter},
+};
+
+use serde::{Serialize, Deserialize};
+
+/// How setpoints from the host are interpreted
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FlightMode {
+    /// Sticks command body rates. No self leveling
+    Rate,
+    /// Sticks command roll and pitch angles and the yaw rate
+    #[default]
+    Angle,
+    /// Angle mode with the thrust stick commanding a climb rate around a held altitude
+    AltHold,
+}
+
+
+/// Pilot stick limits
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ModeLimits {
+    /// Roll and pitch rate at full stick in rate mode (rad/s)
+    pub max_rate: f32,
+    /// Yaw rate at full stick (rad/s)
+    pub max_yaw_rate: f32,
+    /// Roll and pitch angle at full stick in angle mode (rad)
+    pub max_angle: f32,
+    /// Climb and descent rate at full stick in altitude hold (m/s)
+    pub max_climb_rate: f32,
+}
+
+impl Default for ModeLimits {
+    fn default() -> Self {
+        Self {
+            max_rate: 3.5,
+            max_yaw_rate: 2.6,
+            max_angle: 0.52,
+            max_climb_rate: 1.0,
+        }
+    }
+}
+
+impl ModeLimits {
+    /// Fastest rate the rate loop accepts (rad/s)
+    pub const MAX_RATE: f32 = 4.0;
+    pub const MAX_CLIMB_RATE: f32 = 3.0;
+
+    /// Keep values in a flyable range
+    pub fn clamped(self) -> Self {
+        Self {
+            max_rate: self.max_rate.clamp(0.5, Self::MAX_RATE),
+            max_yaw_rate: self.max_yaw_rate.clamp(0.5, Self::MAX_RATE),
+            max_angle: self.max_angle.clamp(0.1, MAX_TILT),
+            max_climb_rate: self.max_climb_rate.clamp(0.1, Self::MAX_CLIMB_RATE),
+        }
+    }
+}
+
+/// Normalized pilot sticks
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct PilotInput {
+    /// -1 to 1
+    pub roll: f32,
+    /// -1 to 1
+    pub pitch: f32,
+    /// -1 to 1
+    pub yaw: f32,
+    /// 0 to 1. Collective thrust, or the climb rate around mid stick in altitude hold
+    pub thrust: f32,
+}
+
+impl PilotInput {
+    pub fn clamped(self) -> Self {
+        Self {
+            roll: self.roll.clamp(-1.0, 1.0),
+            pitch: self.pitch.clamp(-1.0, 1.0),
+            yaw: self.yaw.clamp(-1.0, 1.0),
+            thrust: self.thrust.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Stick positions for an attitude setpoint
+    pub fn from_attitude(setpoint: AttitudeSetpoint, limits: &ModeLimits) -> Self {
+        Self {
+            roll: setpoint.roll / limits.max_angle,
+            pitch: setpoint.pitch / limits.max_angle,
+            yaw: setpoint.yaw_rate / limits.max_yaw_rate,
+            thrust: setpoint.thrust,
+        }
+        .clamped()
+    }
+
+    /// Stick positions for a rate setpoint
+    pub fn from_rate(setpoint: RateSetpoint, limits: &ModeLimits) -> Self {
+        Self {
+            roll: setpoint.roll / limits.max_rate,
+            pitch: setpoint.pitch / limits.max_rate,
+            yaw: setpoint.yaw / limits.max_yaw_rate,
+            thrust: setpoint.thrust,
+        }
+        .clamped()
+    }
+}
+
+/// Mode manager tuning
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ModeConfig {
+    pub limits: ModeLimits,
+    /// Altitude error (m) to climb rate (m/s) gain
+    pub altitude_p: f32,
+    /// Climb rate loop. Output is added to the thrust captured when altitude hold was entered
+    pub climb: PidGains,
+    /// Half width of the thrust stick band around mid stick that holds altitude
+    pub deadband: f32,
+    /// Longest the barometer can go without a reading before the altitude is considered lost (s)
+    pub altitude_timeout: f32,
+    /// Barometer altitude low-pass cutoff (Hz)
+    pub altitude_cutoff_hz: f32,
+    /// Climb rate low-pass cutoff (Hz)
+    pub climb_cutoff_hz: f32,
+}
+
+impl Default for ModeConfig {
+    fn default() -> Self {
+        Self {
+            limits: ModeLimits::default(),
+            altitude_p: 1.0,
+            climb: PidGains { kp: 0.2, ki: 0.1, kd: 0.0, i_limit: 0.3 },
+            deadband: 0.1,
+            altitude_timeout: 0.5,
+            altitude_cutoff_hz: 2.0,
+            climb_cutoff_hz: 1.0,
+        }
+    }
+}
+
+/// Reasons a mode change was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeError {
+    /// Altitude hold needs a valid barometer
+    NoAltitude,
+}
+
+/// What the controller should track this iteration
+#[derive(Debug, Clone, Copy)]
+pub enum ModeTarget {
+    Rate(RateSetpoint),
+    Attitude(AttitudeSetpoint),
+}
+
+impl ModeTarget {
+    pub fn thrust(&self) -> f32 {
+        match self {
+            ModeTarget::Rate(setpoint) => setpoint.thrust,
+            ModeTarget::Attitude(setpoint) => setpoint.thrust,
+        }
+    }
+
+    /// Same target with the thrust limited to `limit`
+    pub fn limit_thrust(self, limit: f32) -> Self {
+        match self {
+            ModeTarget::Rate(setpoint) => {
+                ModeTarget::Rate(RateSetpoint { thrust: setpoint.thrust.min(limit), ..setpoint })
+            }
+            ModeTarget::Attitude(setpoint) => {
+                ModeTarget::Attitude(AttitudeSetpoint { thrust: setpoint.thrust.min(limit), ..setpoint })
+            }
+        }
+    }
+}
+
+/// Maps pilot sticks to controller targets for the current flight mode
+///
+/// Mode changes are bumpless: the sticks are centred, leaving altitude hold hands the thrust it was flying at back to
+/// the thrust stick, and entering it holds the current altitude at the current thrust. Altitude hold is refused
+/// without a valid barometer and falls back to angle mode if the barometer is lost.
+pub struct ModeManager {
+    config: ModeConfig,
+    mode: FlightMode,
+    input: PilotInput,
+    altitude: Pt1Filter,
+    climb_rate: Pt1Filter,
+    last_altitude: Option<f32>,
+    /// Time since the last barometer reading (s)
+    altitude_age: f32,
+    /// Altitude held with the thrust stick centred (m)
+    hold_altitude: f32,
+    /// Thrust at the time altitude hold was entered
+    hover_thrust: f32,
+    climb_pid: Pid,
+    /// Thrust of the last target
+    thrust: f32,
+}
+
+impl ModeManager {
+    pub fn new(config: ModeConfig, sample_rate_hz: f32) -> Self {
+        let d_filter = LowPassConfig { kind: LowPassType::Pt1, cutoff_hz: config.climb_cutoff_hz };
+
+        Self {
+            config,
+            mode: FlightMode::default(),
+            input: PilotInput::default(),
+            altitude: Pt1Filter::new(config.altitude_cutoff_hz, sample_rate_hz),
+            climb_rate: Pt1Filter::new(config.climb_cutoff_hz, sample_rate_hz),
+            last_altitude: None,
+            altitude_age: f32::INFINITY,
+            hold_altitude: 0.0,
+            hover_thrust: 0.0,
+            climb_pid: Pid::new(config.climb, d_filter, sample_rate_hz),
+            thrust: 0.0,
+        }
+    }
+
+    pub fn mode(&self) -> FlightMode {
+        self.mode
+    }
+
+    pub fn input(&self) -> &PilotInput {
+        &self.input
+    }
+
+    pub fn limits(&self) -> &ModeLimits {
+        &self.config.limits
+    }
+
+    pub fn set_limits(&mut self, limits: ModeLimits) {
+        self.config.limits = limits.clamped();
+    }
+
+    pub fn set_input(&mut self, input: PilotInput) {
+        self.input = input.clamped();
+    }
+
+    /// Thrust of the last target. Before the first target this is the thrust stick
+    pub fn thrust(&self) -> f32 {
+        match self.mode {
+            FlightMode::AltHold => self.thrust,
+            FlightMode::Rate | FlightMode::Angle => self.input.thrust,
+        }
+    }
+
+    /// Filtered barometer altitude (m)
+    pub fn altitude(&self) -> f32 {
+        self.altitude.value()
+    }
+
+    /// Filtered climb rate (m/s)
+    pub fn climb_rate(&self) -> f32 {
+        self.climb_rate.value()
+    }
+
+    /// True while recent barometer readings are available
+    pub fn has_altitude(&self) -> bool {
+        self.altitude_age <= self.config.altitude_timeout
+    }
+
+    /// Feed the barometer. Called every iteration, `None` when there was no reading
+    pub fn update_altitude(&mut self, altitude: Option<f32>, dt: f32) {
+        let altitude = match altitude {
+            Some(altitude) => altitude,
+            None => {
+                self.altitude_age += dt;
+                if !self.has_altitude() {
+                    self.last_altitude = None;
+
+                    if self.mode == FlightMode::AltHold {
+                        self.leave_alt_hold(FlightMode::Angle);
+                    }
+                }
+                return;
+            }
+        };
+
+        // Start the filters settled so a fresh barometer does not look like a climb
+        let elapsed = self.altitude_age.min(self.config.altitude_timeout) + dt;
+        self.altitude_age = 0.0;
+
+        match self.last_altitude {
+            Some(last) => {
+                let filtered = self.altitude.apply(altitude);
+                self.climb_rate.apply((filtered - last) / elapsed.max(dt));
+            }
+            None => {
+                self.altitude.reset_to(altitude);
+                self.climb_rate.reset_to(0.0);
+            }
+        }
+        self.last_altitude = Some(self.altitude.value());
+    }
+
+    /// Change flight mode
+    pub fn set_mode(&mut self, mode: FlightMode) -> Result<(), ModeError> {
+        if mode == self.mode {
+            return Ok(());
+        }
+
+        match mode {
+            FlightMode::AltHold => {
+                if !self.has_altitude() {
+                    return Err(ModeError::NoAltitude);
+                }
+
+                self.hold_altitude = self.altitude();
+                self.hover_thrust = self.thrust();
+                self.thrust = self.hover_thrust;
+                self.climb_pid.reset();
+                self.input = PilotInput { thrust: 0.5, ..Default::default() };
+                self.mode = mode;
+            }
+            FlightMode::Rate | FlightMode::Angle if self.mode == FlightMode::AltHold => self.leave_alt_hold(mode),
+            FlightMode::Rate | FlightMode::Angle => {
+                self.input = PilotInput { thrust: self.input.thrust, ..Default::default() };
+                self.mode = mode;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Centre the sticks at zero thrust. Altitude hold only runs in flight and drops to angle mode
+    pub fn reset(&mut self) {
+        if self.mode == FlightMode::AltHold {
+            self.mode = FlightMode::Angle;
+        }
+        self.input = PilotInput::default();
+        self.thrust = 0.0;
+        self.climb_pid.reset();
+    }
+
+    /// Target for the controller this iteration
+    pub fn target(&mut self, dt: f32) -> ModeTarget {
+        let limits = self.config.limits;
+        let input = self.input;
+
+        let target = match self.mode {
+            FlightMode::Rate => ModeTarget::Rate(RateSetpoint {
+                roll: input.roll * limits.max_rate,
+                pitch: input.pitch * limits.max_rate,
+                yaw: input.yaw * limits.max_yaw_rate,
+                thrust: input.thrust,
+            }),
+            FlightMode::Angle => ModeTarget::Attitude(self.attitude_setpoint(input.thrust)),
+            FlightMode::AltHold => {
+                let thrust = self.alt_hold_thrust(dt);
+                ModeTarget::Attitude(self.attitude_setpoint(thrust))
+            }
+        };
+
+        self.thrust = target.thrust();
+        target
+    }
+
+    fn attitude_setpoint(&self, thrust: f32) -> AttitudeSetpoint {
+        let limits = &self.config.limits;
+
+        AttitudeSetpoint {
+            roll: self.input.roll * limits.max_angle,
+            pitch: self.input.pitch * limits.max_angle,
+            yaw_rate: self.input.yaw * limits.max_yaw_rate,
+            thrust,
+        }
+    }
+
+    fn alt_hold_thrust(&mut self, dt: f32) -> f32 {
+        let max_climb_rate = self.config.limits.max_climb_rate;
+        let deadband = self.config.deadband;
+
+        // Outside the deadband the stick commands a climb rate and the held altitude follows the vehicle
+        let stick = self.input.thrust - 0.5;
+        let climb_setpoint = if stick.abs() > deadband {
+            self.hold_altitude = self.altitude();
+            let scaled = (stick.abs() - deadband) / (0.5 - deadband);
+            scaled.copysign(stick) * max_climb_rate
+        }
+        else {
+            (self.config.altitude_p * (self.hold_altitude - self.altitude())).clamp(-max_climb_rate, max_climb_rate)
+        };
+
+        let correction = self.climb_pid.update(climb_setpoint, self.climb_rate(), dt);
+        (self.hover_thrust + correction).clamp(0.0, 1.0)
+    }
+
+    /// The thrust stick takes over at the thrust altitude hold was flying at
+    fn leave_alt_hold(&mut self, mode: FlightMode) {
+        self.input = PilotInput { thrust: self.thrust, ..Default::default() };
+        self.climb_pid.reset();
+        self.mode = mode;
+    }
+}
@@ -0,0 +1,221 @@
+//
+// motor.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 04 2022
+//
+
+use serde::{Serialize, Deserialize};
+
+/// DShot bit rate
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DShotSpeed {
+    DShot150,
+    DShot300,
+    DShot600,
+}
+
+/// How motor outputs are sent to the motor drivers or ESCs
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MotorProtocol {
+    /// Duty cycle PWM straight to a brushed motor driver
+    Brushed { frequency_hz: u32 },
+    /// Servo style 1000 - 2000 us pulses at 50 Hz
+    Standard,
+    /// 125 - 250 us pulses
+    OneShot125,
+    /// 5 - 25 us pulses
+    Multishot,
+    /// Digital throttle frames
+    DShot(DShotSpeed),
+}
+
+impl MotorProtocol {
+    pub const MIN_BRUSHED_FREQUENCY: u32 = 50;
+    pub const MAX_BRUSHED_FREQUENCY: u32 = 40_000;
+
+    /// Keep configurable values in range
+    pub fn clamped(self) -> Self {
+        match self {
+            MotorProtocol::Brushed { frequency_hz } => MotorProtocol::Brushed {
+                frequency_hz: frequency_hz.clamp(Self::MIN_BRUSHED_FREQUENCY, Self::MAX_BRUSHED_FREQUENCY),
+            },
+            protocol => protocol,
+        }
+    }
+}
+
+impl Default for MotorProtocol {
+    /// Same output as before the protocol was configurable
+    fn default() -> Self {
+        MotorProtocol::Brushed { frequency_hz: 50 }
+    }
+}
+
+/// Normalized thrust at which a motor counts as spinning up during a bench fit
+const SPIN_UP_THRUST: f32 = 0.01;
+/// Largest duty correction for a low battery
+const MAX_VOLTAGE_COMPENSATION: f32 = 1.5;
+/// Smallest duty correction for a battery above nominal
+const MIN_VOLTAGE_COMPENSATION: f32 = 0.75;
+
+/// Maps thrust demand from the mixer to motor duty
+///
+/// Thrust is modelled as `(1 - expo) * u + expo * u^2` where `u` is the duty scaled between the minimum and maximum
+/// outputs. Duty is scaled by the nominal over the measured battery voltage to hold motor speed as the battery drains.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct MotorCurve {
+    /// 0 is linear, 1 is thrust proportional to duty squared
+    pub expo: f32,
+    /// Duty where the motor starts producing thrust
+    pub min_output: f32,
+    /// Duty at full thrust
+    pub max_output: f32,
+    /// Battery voltage the curve was fitted at. Zero disables compensation
+    pub nominal_voltage: f32,
+}
+
+impl Default for MotorCurve {
+    /// Thrust demand passes straight through
+    fn default() -> Self {
+        Self {
+            expo: 0.0,
+            min_output: 0.0,
+            max_output: 1.0,
+            nominal_voltage: 0.0,
+        }
+    }
+}
+
+/// One bench measurement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThrustSample {
+    /// Motor duty (0 - 1)
+    pub duty: f32,
+    /// Measured thrust in any unit
+    pub thrust: f32,
+    /// Battery voltage during the measurement (V)
+    pub voltage: f32,
+}
+
+impl MotorCurve {
+    /// Keep values in a usable range
+    pub fn clamped(self) -> Self {
+        let min_output = self.min_output.clamp(0.0, 0.5);
+
+        Self {
+            expo: self.expo.clamp(0.0, 1.0),
+            min_output,
+            max_output: self.max_output.clamp(min_output + 0.1, 1.0),
+            nominal_voltage: self.nominal_voltage.max(0.0),
+        }
+    }
+
+    /// Normalized thrust for a scaled duty `u` (0 - 1)
+    fn model(&self, u: f32) -> f32 {
+        (1.0 - self.expo) * u + self.expo * u * u
+    }
+
+    /// Thrust produced at `duty`. Inverse of [`MotorCurve::duty`] without battery compensation
+    pub fn thrust(&self, duty: f32) -> f32 {
+        let span = self.max_output - self.min_output;
+        if duty <= self.min_output || span <= 0.0 {
+            return 0.0;
+        }
+
+        self.model(((duty - self.min_output) / span).min(1.0))
+    }
+
+    /// Duty for a thrust demand (0 - 1). Zero thrust stays at zero so stopped motors stay stopped, any other demand is
+    /// kept between the minimum and maximum outputs
+    pub fn duty(&self, thrust: f32, voltage: Option<f32>) -> f32 {
+        if thrust <= 0.0 {
+            return 0.0;
+        }
+
+        let thrust = thrust.min(1.0);
+
+        // Solve the model for u
+        let u = if self.expo < 1e-3 {
+            thrust
+        }
+        else {
+            let a = self.expo;
+            let b = 1.0 - self.expo;
+            (-b + libm::sqrtf(b * b + 4.0 * a * thrust)) / (2.0 * a)
+        };
+
+        let duty = self.min_output + u * (self.max_output - self.min_output);
+
+        let compensation = match voltage {
+            Some(voltage) if self.nominal_voltage > 0.0 && voltage > 0.0 => {
+                (self.nominal_voltage / voltage).clamp(MIN_VOLTAGE_COMPENSATION, MAX_VOLTAGE_COMPENSATION)
+            }
+            _ => 1.0,
+        };
+
+        // A spinning motor never drops below the minimum output, a full battery would otherwise pull it under and risk
+        // a stall at low throttle
+        (duty * compensation).min(self.max_output).max(self.min_output)
+    }
+
+    /// Apply to every mixer output
+    pub fn apply<const N: usize>(&self, outputs: &[f32; N], voltage: Option<f32>) -> [f32; N] {
+        let mut duty = [0.0; N];
+        for (d, output) in duty.iter_mut().zip(outputs.iter()) {
+            *d = self.duty(*output, voltage);
+        }
+        duty
+    }
+
+    /// Fit a curve to bench measurements taken at increasing duty
+    ///
+    /// The highest duty sets the maximum output and the last step before the motor produced thrust sets the minimum.
+    /// Expo is a least squares fit of the normalized thrust in between. Returns None without at least three spinning
+    /// samples.
+    pub fn fit(samples: &[ThrustSample]) -> Option<Self> {
+        let peak = samples.iter().map(|s| s.thrust).fold(0.0, f32::max);
+        if peak <= 0.0 {
+            return None;
+        }
+
+        let spinning = || samples.iter().filter(|s| s.thrust / peak >= SPIN_UP_THRUST);
+        if spinning().count() < 3 {
+            return None;
+        }
+
+        // The motor starts somewhere between the last stopped step and the first spinning one. The stopped step is the
+        // better estimate when it was measured
+        let first_spinning = spinning().map(|s| s.duty).fold(1.0, f32::min);
+        let min_output = samples
+            .iter()
+            .filter(|s| s.thrust / peak < SPIN_UP_THRUST && s.duty < first_spinning)
+            .map(|s| s.duty)
+            .fold(None, |stopped: Option<f32>, duty| Some(stopped.map_or(duty, |stopped| stopped.max(duty))))
+            .unwrap_or(first_spinning);
+        let max_output = spinning().map(|s| s.duty).fold(0.0, f32::max);
+        let span = max_output - min_output;
+        if span <= 0.0 {
+            return None;
+        }
+
+        // y - u = expo * (u^2 - u)
+        let (mut num, mut den) = (0.0, 0.0);
+        for s in spinning() {
+            let u = (s.duty - min_output) / span;
+            let y = s.thrust / peak;
+            let x = u * u - u;
+            num += (y - u) * x;
+            den += x * x;
+        }
+
+        let expo = if den > 0.0 { num / den } else { 0.0 };
+        let nominal_voltage = {
+            let voltages = spinning().map(|s| s.voltage).filter(|v| *v > 0.0);
+            let (sum, count) = voltages.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+            if count > 0 { sum / count as f32 } else { 0.0 }
+        };
+
+        Some(Self { expo, min_output, max_output, nominal_voltage }.clamped())
+    }
+}
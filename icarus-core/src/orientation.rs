@@ -0,0 +1,127 @@
+//
+// orientation.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 16 2022
+//
+
+use crate::data::{AccelerometerData, GyroscopeData};
+
+use serde::{Serialize, Deserialize};
+use nalgebra::{Rotation3, Vector3};
+
+use core::f32::consts::{FRAC_PI_2, PI};
+
+/// How the sensor is rotated on the frame, in 90 degree steps
+///
+/// Rotations are clockwise when looking down on the board. `Flip` variants have the board mounted upside down.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BoardRotation {
+    #[default]
+    Cw0,
+    Cw90,
+    Cw180,
+    Cw270,
+    Cw0Flip,
+    Cw90Flip,
+    Cw180Flip,
+    Cw270Flip,
+}
+
+impl BoardRotation {
+    pub const ALL: [BoardRotation; 8] = [
+        BoardRotation::Cw0,
+        BoardRotation::Cw90,
+        BoardRotation::Cw180,
+        BoardRotation::Cw270,
+        BoardRotation::Cw0Flip,
+        BoardRotation::Cw90Flip,
+        BoardRotation::Cw180Flip,
+        BoardRotation::Cw270Flip,
+    ];
+
+    /// Rotation of the sensor frame relative to the body frame
+    pub fn rotation(self) -> Rotation3<f32> {
+        let (steps, flip) = match self {
+            BoardRotation::Cw0 => (0.0, false),
+            BoardRotation::Cw90 => (1.0, false),
+            BoardRotation::Cw180 => (2.0, false),
+            BoardRotation::Cw270 => (3.0, false),
+            BoardRotation::Cw0Flip => (0.0, true),
+            BoardRotation::Cw90Flip => (1.0, true),
+            BoardRotation::Cw180Flip => (2.0, true),
+            BoardRotation::Cw270Flip => (3.0, true),
+        };
+
+        // Clockwise from above is a negative rotation about Z
+        let yaw = Rotation3::from_euler_angles(0.0, 0.0, -steps * FRAC_PI_2);
+        let flip = if flip {
+            Rotation3::from_euler_angles(0.0, PI, 0.0)
+        } else {
+            Rotation3::identity()
+        };
+
+        yaw * flip
+    }
+}
+
+/// Sensor mounting on the frame: a preset rotation plus a fine trim in degrees
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct BoardAlignment {
+    pub rotation: BoardRotation,
+    pub roll_trim: f32,
+    pub pitch_trim: f32,
+    pub yaw_trim: f32,
+}
+
+impl BoardAlignment {
+    /// Rotation of the sensor frame relative to the body frame
+    pub fn rotation(&self) -> Rotation3<f32> {
+        let trim = Rotation3::from_euler_angles(
+            self.roll_trim.to_radians(),
+            self.pitch_trim.to_radians(),
+            self.yaw_trim.to_radians(),
+        );
+
+        trim * self.rotation.rotation()
+    }
+}
+
+/// Transforms sensor readings into the body frame
+#[derive(Debug, Clone, Copy)]
+pub struct SensorAlignment {
+    rotation: Rotation3<f32>,
+}
+
+impl Default for SensorAlignment {
+    fn default() -> Self {
+        Self {
+            rotation: Rotation3::identity(),
+        }
+    }
+}
+
+impl From<BoardAlignment> for SensorAlignment {
+    fn from(alignment: BoardAlignment) -> Self {
+        Self {
+            rotation: alignment.rotation(),
+        }
+    }
+}
+
+impl SensorAlignment {
+    pub fn accel(&self, accel: AccelerometerData) -> AccelerometerData {
+        let (x, y, z) = self.apply(accel.into());
+        AccelerometerData { x, y, z }
+    }
+
+    pub fn gyro(&self, gyro: GyroscopeData) -> GyroscopeData {
+        let (x, y, z) = self.apply(gyro.into());
+        GyroscopeData { x, y, z }
+    }
+
+    fn apply(&self, (x, y, z): (f32, f32, f32)) -> (f32, f32, f32) {
+        let v = self.rotation * Vector3::new(x, y, z);
+        (v.x, v.y, v.z)
+    }
+}
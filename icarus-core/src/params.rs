@@ -0,0 +1,105 @@
+//
+// params.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 16 2022
+//
+
+use crate::{
+    button::{ButtonAction, ButtonMap, Gesture},
+    calibration::ImuCalibration,
+    mode::ModeLimits,
+    motor::{MotorCurve, MotorProtocol},
+    orientation::{BoardAlignment, BoardRotation},
+};
+
+use serde::{Serialize, Deserialize};
+
+/// Persistent controller configuration
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Parameters {
+    /// Sensor mounting on the frame
+    pub board_alignment: BoardAlignment,
+    /// Control loop rate (Hz). Applied on the next boot
+    pub loop_rate: u16,
+    /// Battery voltage divider ratio
+    pub battery_divider: f32,
+    /// User button gesture actions
+    pub button_map: ButtonMap,
+    /// Motor output protocol. Applied on the next boot
+    pub motor_protocol: MotorProtocol,
+    /// Thrust to duty mapping applied after mixing
+    pub motor_curve: MotorCurve,
+    /// Pilot stick limits for each flight mode
+    pub mode_limits: ModeLimits,
+    /// Highest duty a thrust step may ask for (%)
+    pub bench_max_duty: u8,
+    /// Result of the last level or six-position calibration. Saved by the flight task, not set by the host
+    pub imu_calibration: Option<ImuCalibration>,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            board_alignment: BoardAlignment::default(),
+            loop_rate: 500,
+            battery_divider: 2.0,
+            button_map: ButtonMap::default(),
+            motor_protocol: MotorProtocol::default(),
+            motor_curve: MotorCurve::default(),
+            mode_limits: ModeLimits::default(),
+            bench_max_duty: 80,
+            imu_calibration: None,
+        }
+    }
+}
+
+/// Update to a single parameter
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Parameter {
+    /// Preset board rotation
+    BoardRotation(BoardRotation),
+    /// Board alignment trim in degrees (roll, pitch, yaw)
+    BoardTrim(f32, f32, f32),
+    /// Control loop rate (Hz)
+    LoopRate(u16),
+    /// Battery voltage divider ratio
+    BatteryDivider(f32),
+    /// Action for a user button gesture
+    Button(Gesture, ButtonAction),
+    /// Motor output protocol
+    MotorProtocol(MotorProtocol),
+    /// Thrust to duty mapping
+    MotorCurve(MotorCurve),
+    /// Pilot stick limits
+    ModeLimits(ModeLimits),
+    /// Highest thrust step duty (%)
+    BenchMaxDuty(u8),
+}
+
+impl Parameters {
+    pub const MIN_LOOP_RATE: u16 = 50;
+    pub const MAX_LOOP_RATE: u16 = 1000;
+    pub const MIN_BATTERY_DIVIDER: f32 = 1.0;
+    pub const MAX_BATTERY_DIVIDER: f32 = 20.0;
+
+    pub fn set(&mut self, param: Parameter) {
+        match param {
+            Parameter::BoardRotation(rotation) => self.board_alignment.rotation = rotation,
+            Parameter::BoardTrim(roll, pitch, yaw) => {
+                self.board_alignment.roll_trim = roll;
+                self.board_alignment.pitch_trim = pitch;
+                self.board_alignment.yaw_trim = yaw;
+            }
+            Parameter::LoopRate(rate) => self.loop_rate = rate.clamp(Self::MIN_LOOP_RATE, Self::MAX_LOOP_RATE),
+            Parameter::BatteryDivider(divider) => {
+                self.battery_divider = divider.clamp(Self::MIN_BATTERY_DIVIDER, Self::MAX_BATTERY_DIVIDER)
+            }
+            Parameter::Button(gesture, action) => self.button_map.set(gesture, action),
+            Parameter::MotorProtocol(protocol) => self.motor_protocol = protocol.clamped(),
+            Parameter::MotorCurve(curve) => self.motor_curve = curve.clamped(),
+            Parameter::ModeLimits(limits) => self.mode_limits = limits.clamped(),
+            Parameter::BenchMaxDuty(duty) => self.bench_max_duty = duty.min(100),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//
+// arming.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::{
+    arming::{Arming, ArmingBlockers, ArmingState},
+    battery::BatteryStatus,
+    health::HealthFlags,
+};
+
+#[test]
+fn arms_with_no_blockers() {
+    let mut arming = Arming::default();
+    assert_eq!(arming.state(), ArmingState::Disarmed);
+
+    assert_eq!(arming.arm(), Ok(()));
+    assert!(arming.is_armed());
+
+    arming.disarm();
+    assert!(!arming.is_armed());
+}
+
+#[test]
+fn critical_sensor_fault_blocks_and_disarms() {
+    let mut arming = Arming::default();
+    arming.arm().unwrap();
+
+    arming.update_health(HealthFlags::GYRO_STUCK);
+    assert!(!arming.is_armed());
+    assert_eq!(arming.arm(), Err(ArmingBlockers::SENSOR_FAULT));
+
+    arming.update_health(HealthFlags::empty());
+    assert_eq!(arming.arm(), Ok(()));
+}
+
+#[test]
+fn minor_sensor_faults_do_not_block() {
+    let mut arming = Arming::default();
+
+    arming.update_health(HealthFlags::ACCEL_CLIPPING | HealthFlags::GYRO_READ_FAILURE);
+    assert!(arming.blockers().is_empty());
+    assert_eq!(arming.arm(), Ok(()));
+}
+
+#[test]
+fn sample_rate_blocks_without_disarming() {
+    let mut arming = Arming::default();
+    arming.arm().unwrap();
+
+    arming.update_health(HealthFlags::SAMPLE_RATE);
+    assert!(arming.is_armed());
+
+    arming.disarm();
+    assert_eq!(arming.arm(), Err(ArmingBlockers::SAMPLE_RATE));
+
+    arming.update_health(HealthFlags::empty());
+    assert_eq!(arming.arm(), Ok(()));
+}
+
+#[test]
+fn calibration_blocks() {
+    let mut arming = Arming::default();
+
+    arming.set_calibrating(true);
+    assert_eq!(arming.arm(), Err(ArmingBlockers::CALIBRATING));
+
+    arming.set_calibrating(false);
+    assert_eq!(arming.arm(), Ok(()));
+}
+
+#[test]
+fn low_battery_blocks() {
+    let mut arming = Arming::default();
+
+    arming.update_battery(BatteryStatus::Warning);
+    assert!(arming.blockers().is_empty());
+
+    for status in [BatteryStatus::Land, BatteryStatus::Critical] {
+        arming.update_battery(status);
+        assert_eq!(arming.arm(), Err(ArmingBlockers::BATTERY));
+    }
+
+    arming.update_battery(BatteryStatus::Ok);
+    assert_eq!(arming.arm(), Ok(()));
+}
+
+#[test]
+fn every_active_blocker_is_reported() {
+    let mut arming = Arming::default();
+
+    arming.update_health(HealthFlags::SENSOR_LOST | HealthFlags::SAMPLE_RATE);
+    arming.set_calibrating(true);
+    arming.update_battery(BatteryStatus::Critical);
+
+    let blockers = arming.arm().unwrap_err();
+    for blocker in [
+        ArmingBlockers::SENSOR_FAULT,
+        ArmingBlockers::SAMPLE_RATE,
+        ArmingBlockers::CALIBRATING,
+        ArmingBlockers::BATTERY,
+    ] {
+        assert!(blockers.contains(blocker), "{:?} missing from {:?}", blocker, blockers);
+    }
+    assert!(!arming.is_armed());
+}
@@ -0,0 +1,106 @@
+//
+// battery.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 31 2022
+//
+
+use icarus_core::battery::{self, BatteryConfig, BatteryMonitor, BatteryStatus};
+
+const RATE: f32 = 10.0;
+
+/// ADC reading for a battery voltage with the default configuration
+fn adc(voltage: f32) -> u16 {
+    let config = BatteryConfig::default();
+    (voltage / config.divider / config.adc_full_scale * config.adc_max as f32).round() as u16
+}
+
+/// Feed a constant reading for `seconds`
+fn run(monitor: &mut BatteryMonitor, voltage: f32, load: f32, armed: bool, seconds: f32) -> BatteryStatus {
+    let mut status = monitor.status();
+    for _ in 0..(seconds * RATE) as usize {
+        status = monitor.update(adc(voltage), load, false, armed);
+    }
+    status
+}
+
+#[test]
+fn converts_adc_to_voltage() {
+    let monitor = BatteryMonitor::new(BatteryConfig::default(), RATE);
+    assert!((monitor.to_voltage(adc(3.9)) - 3.9).abs() < 0.01);
+    assert_eq!(monitor.to_voltage(0), 0.0);
+}
+
+#[test]
+fn divider_is_configurable() {
+    let mut monitor = BatteryMonitor::new(BatteryConfig::default(), RATE);
+    let reading = adc(4.0);
+    monitor.set_divider(3.0);
+    assert!((monitor.to_voltage(reading) - 6.0).abs() < 0.01);
+}
+
+#[test]
+fn state_of_charge_follows_discharge_curve() {
+    assert_eq!(battery::state_of_charge(4.25), 100);
+    assert_eq!(battery::state_of_charge(3.0), 0);
+    assert_eq!(battery::state_of_charge(3.84), 50);
+
+    let mut previous = 0;
+    for mv in (3270..=4200).step_by(10) {
+        let soc = battery::state_of_charge(mv as f32 / 1000.0);
+        assert!(soc >= previous, "state of charge decreased at {} mV", mv);
+        previous = soc;
+    }
+}
+
+#[test]
+fn status_requires_sustained_low_voltage() {
+    let mut monitor = BatteryMonitor::new(BatteryConfig::default(), RATE);
+    run(&mut monitor, 3.45, 0.0, false, 10.0);
+
+    // A single dip does not change the status
+    monitor.update(adc(3.0), 0.0, false, false);
+    assert_eq!(monitor.status(), BatteryStatus::Warning);
+
+    assert_eq!(run(&mut monitor, 3.35, 0.0, false, 5.0), BatteryStatus::Land);
+    assert_eq!(run(&mut monitor, 3.1, 0.0, false, 5.0), BatteryStatus::Critical);
+}
+
+#[test]
+fn sag_under_load_is_compensated() {
+    let mut monitor = BatteryMonitor::new(BatteryConfig::default(), RATE);
+    run(&mut monitor, 3.9, 0.0, false, 5.0);
+
+    // 0.3 V sag at 60% load would read as Land without compensation
+    for _ in 0..20 {
+        run(&mut monitor, 3.9, 0.0, true, 1.0);
+        run(&mut monitor, 3.6, 0.6, true, 1.0);
+    }
+
+    assert!((monitor.sag() - 0.3).abs() < 0.05, "sag {}", monitor.sag());
+    assert!((monitor.compensated_voltage() - 3.9).abs() < 0.05);
+    assert_eq!(monitor.status(), BatteryStatus::Ok);
+}
+
+#[test]
+fn status_latched_while_armed() {
+    let mut monitor = BatteryMonitor::new(BatteryConfig::default(), RATE);
+    assert_eq!(run(&mut monitor, 3.3, 0.0, true, 5.0), BatteryStatus::Land);
+
+    // Not cleared within the hysteresis band
+    assert_eq!(run(&mut monitor, 3.45, 0.0, false, 5.0), BatteryStatus::Land);
+
+    // Voltage recovers when the load is removed, the landing continues
+    assert_eq!(run(&mut monitor, 3.8, 0.0, true, 5.0), BatteryStatus::Land);
+
+    // Cleared once disarmed and above the threshold plus hysteresis
+    assert_eq!(run(&mut monitor, 3.8, 0.0, false, 1.0), BatteryStatus::Ok);
+}
+
+#[test]
+fn reports_charge_complete() {
+    let mut monitor = BatteryMonitor::new(BatteryConfig::default(), RATE);
+    monitor.update(adc(4.2), 0.0, true, false);
+    assert!(monitor.charge_complete());
+    assert_eq!(monitor.state_of_charge(), 100);
+}
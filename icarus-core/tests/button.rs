@@ -0,0 +1,146 @@
+//
+// button.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 02 2022
+//
+
+use icarus_core::{
+    button::{ButtonAction, ButtonConfig, ButtonDetector, ButtonMap, Gesture},
+    params::{Parameter, Parameters},
+};
+
+const STEP_MS: u64 = 5;
+
+/// Replays a button input sampled every `STEP_MS`
+struct Bench {
+    detector: ButtonDetector,
+    now_ms: u64,
+    gestures: Vec<(Gesture, u64)>,
+}
+
+impl Bench {
+    fn new() -> Self {
+        let mut bench = Self { detector: ButtonDetector::new(ButtonConfig::default()), now_ms: 0, gestures: Vec::new() };
+        bench.hold(false, 100);
+        bench
+    }
+
+    /// Hold the input for `ms`
+    fn hold(&mut self, pressed: bool, ms: u64) {
+        for _ in 0..ms / STEP_MS {
+            if let Some(gesture) = self.detector.update(pressed, self.now_ms) {
+                self.gestures.push((gesture, self.now_ms));
+            }
+            self.now_ms += STEP_MS;
+        }
+    }
+
+    fn gestures(&self) -> Vec<Gesture> {
+        self.gestures.iter().map(|(g, _)| *g).collect()
+    }
+}
+
+#[test]
+fn press_reported_on_the_debounced_edge() {
+    let mut bench = Bench::new();
+    let pressed_at = bench.now_ms;
+    bench.hold(true, 100);
+    assert_eq!(bench.gestures(), [Gesture::Press]);
+
+    let (_, at) = bench.gestures[0];
+    let debounce = ButtonConfig::default().debounce as u64;
+    assert!(at - pressed_at <= debounce + STEP_MS, "{}", at - pressed_at);
+}
+
+#[test]
+fn short_press_after_double_click_window() {
+    let mut bench = Bench::new();
+    bench.hold(true, 100);
+    bench.hold(false, 200);
+    assert_eq!(bench.gestures(), [Gesture::Press]);
+
+    bench.hold(false, 200);
+    assert_eq!(bench.gestures(), [Gesture::Press, Gesture::ShortPress]);
+}
+
+#[test]
+fn bounces_are_ignored() {
+    let mut bench = Bench::new();
+
+    // Contact bounce on press and release
+    for _ in 0..4 {
+        bench.hold(true, 5);
+        bench.hold(false, 5);
+    }
+    bench.hold(true, 100);
+    for _ in 0..4 {
+        bench.hold(false, 5);
+        bench.hold(true, 5);
+    }
+    bench.hold(false, 1000);
+
+    assert_eq!(bench.gestures(), [Gesture::Press, Gesture::ShortPress]);
+}
+
+#[test]
+fn double_click() {
+    let mut bench = Bench::new();
+    bench.hold(true, 80);
+    bench.hold(false, 150);
+    bench.hold(true, 80);
+    bench.hold(false, 1000);
+
+    // Each press is still reported first
+    assert_eq!(bench.gestures(), [Gesture::Press, Gesture::Press, Gesture::DoubleClick]);
+}
+
+#[test]
+fn long_press_reported_while_held() {
+    let mut bench = Bench::new();
+    bench.hold(true, 4000);
+    assert_eq!(bench.gestures(), [Gesture::Press, Gesture::LongPress]);
+
+    let (_, at) = bench.gestures[1];
+    assert!((3100..3200).contains(&at), "{}", at);
+
+    // Release does not add a short press
+    bench.hold(false, 1000);
+    assert_eq!(bench.gestures(), [Gesture::Press, Gesture::LongPress]);
+}
+
+#[test]
+fn hold_at_boot() {
+    let mut detector = ButtonDetector::new(ButtonConfig::default());
+    let gestures = (0..1000u64)
+        .filter_map(|i| detector.update(true, i * STEP_MS))
+        .collect::<Vec<_>>();
+    assert_eq!(gestures, [Gesture::BootHold]);
+
+    // Released early, nothing happens
+    let mut detector = ButtonDetector::new(ButtonConfig::default());
+    let gestures = (0..1000u64)
+        .filter_map(|i| detector.update(i < 100, i * STEP_MS))
+        .collect::<Vec<_>>();
+    assert!(gestures.is_empty(), "{:?}", gestures);
+}
+
+#[test]
+fn emergency_disarm_only_on_press() {
+    let map = ButtonMap::default();
+    assert_eq!(map.action(Gesture::Press), ButtonAction::EmergencyDisarm);
+
+    for gesture in [Gesture::ShortPress, Gesture::DoubleClick, Gesture::LongPress, Gesture::BootHold] {
+        assert_ne!(map.action(gesture), ButtonAction::EmergencyDisarm, "{:?}", gesture);
+    }
+}
+
+#[test]
+fn actions_configurable_by_parameter() {
+    let mut params = Parameters::default();
+    assert_eq!(params.button_map, ButtonMap::default());
+
+    params.set(Parameter::Button(Gesture::ShortPress, ButtonAction::Calibrate));
+    assert_eq!(params.button_map.action(Gesture::ShortPress), ButtonAction::Calibrate);
+    assert_eq!(params.button_map.action(Gesture::DoubleClick), ButtonAction::Calibrate);
+}
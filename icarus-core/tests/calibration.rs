@@ -0,0 +1,158 @@
+//
+// calibration.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::{
+    calibration::{
+        CalibrationConfig, CalibrationError, CalibrationKind, CalibrationStage, Calibrator, Face, ImuCalibration,
+        RunningStats, StillnessDetector,
+    },
+    data::{AccelerometerData, GyroscopeData},
+};
+
+/// Gravity on each face, in calibration order
+const FACES: [[f32; 3]; 6] = [
+    [1.0, 0.0, 0.0],
+    [-1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.0, -1.0],
+];
+
+/// Long enough to settle and sample each face
+const SAMPLES_PER_FACE: usize = 500;
+
+fn accel(x: f32, y: f32, z: f32) -> AccelerometerData {
+    AccelerometerData { x, y, z }
+}
+
+fn gyro(x: f32, y: f32, z: f32) -> GyroscopeData {
+    GyroscopeData { x, y, z }
+}
+
+/// Raw reading for a true acceleration under a calibration
+fn raw(cal: &ImuCalibration, g: [f32; 3]) -> AccelerometerData {
+    let axis = |i: usize| g[i] / cal.accel_scale[i] + cal.accel_bias[i];
+    accel(axis(0), axis(1), axis(2))
+}
+
+#[test]
+fn running_stats_mean_and_variance() {
+    let mut stats = RunningStats::default();
+    assert_eq!(stats.variance(), 0.0);
+
+    for x in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+        stats.update(x);
+    }
+
+    assert_eq!(stats.count(), 8);
+    assert!((stats.mean() - 5.0).abs() < 1e-6);
+    // Sample variance, n - 1
+    assert!((stats.variance() - 32.0 / 7.0).abs() < 1e-5);
+    assert!((stats.std_dev() - (32.0f32 / 7.0).sqrt()).abs() < 1e-5);
+
+    stats.reset();
+    assert_eq!(stats.count(), 0);
+}
+
+#[test]
+fn stillness_detector_rejects_a_bump() {
+    let config = CalibrationConfig::default();
+    let mut detector = StillnessDetector::new(&config);
+
+    let level = accel(0.0, 0.0, 1.0);
+    let zero = gyro(0.0, 0.0, 0.0);
+
+    let still = (0..config.settle_samples).map(|_| detector.update(level, zero)).last().unwrap();
+    assert!(still);
+
+    // A knock resets the settle period
+    assert!(!detector.update(accel(0.5, 0.0, 1.3), gyro(0.4, 0.0, 0.0)));
+    for _ in 0..config.settle_samples / 2 {
+        assert!(!detector.update(level, zero));
+    }
+
+    // Still again once the variance decays and the settle period passes
+    assert!((0..200).any(|_| detector.update(level, zero)));
+}
+
+#[test]
+fn face_from_accel() {
+    assert_eq!(Face::from_accel([0.0, 0.0, 1.0]), Some(Face::PosZ));
+    assert_eq!(Face::from_accel([0.02, -0.05, -0.98]), Some(Face::NegZ));
+    assert_eq!(Face::from_accel([1.0, 0.1, 0.0]), Some(Face::PosX));
+    assert_eq!(Face::from_accel([0.0, -1.02, 0.1]), Some(Face::NegY));
+
+    // Tilted between two faces
+    assert_eq!(Face::from_accel([0.7, 0.0, 0.7]), None);
+}
+
+#[test]
+fn level_calibration_needs_a_level_board() {
+    let mut calibrator = Calibrator::default();
+    calibrator.start(CalibrationKind::Level);
+
+    // On its side
+    for _ in 0..1000 {
+        calibrator.update(accel(0.0, 1.0, 0.0), gyro(0.0, 0.0, 0.0));
+    }
+
+    let stage = calibrator.status().stage;
+    assert_eq!(stage, CalibrationStage::Failed(CalibrationError::NotLevel));
+}
+
+#[test]
+fn six_position_recovers_bias_and_scale() {
+    let truth = ImuCalibration {
+        accel_bias: [0.05, -0.03, 0.08],
+        accel_scale: [1.04, 0.95, 1.1],
+        gyro_bias: [0.01, -0.02, 0.03],
+    };
+    let gyro_raw = gyro(0.01, -0.02, 0.03);
+
+    let mut calibrator = Calibrator::default();
+    calibrator.start(CalibrationKind::SixPosition);
+
+    for g in FACES {
+        // Settle, sample, then wait for the next face
+        let sample = raw(&truth, g);
+        for _ in 0..SAMPLES_PER_FACE {
+            calibrator.update(sample, gyro_raw);
+        }
+    }
+
+    assert_eq!(calibrator.status().stage, CalibrationStage::Complete);
+    assert_eq!(calibrator.status().faces, 0x3F);
+
+    let result = calibrator.take_result().unwrap();
+    for axis in 0..3 {
+        assert!((result.accel_bias[axis] - truth.accel_bias[axis]).abs() < 1e-4, "{:?}", result);
+        assert!((result.accel_scale[axis] - truth.accel_scale[axis]).abs() < 1e-4, "{:?}", result);
+        assert!((result.gyro_bias[axis] - truth.gyro_bias[axis]).abs() < 1e-4, "{:?}", result);
+    }
+
+    // Calibrated readings are back to 1g
+    let level = result.apply_accel(raw(&truth, [0.0, 0.0, 1.0]));
+    assert!((level.z - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn six_position_rejects_an_implausible_scale() {
+    let truth = ImuCalibration { accel_scale: [1.5, 1.0, 1.0], ..Default::default() };
+
+    let mut calibrator = Calibrator::default();
+    calibrator.start(CalibrationKind::SixPosition);
+
+    for g in FACES {
+        for _ in 0..SAMPLES_PER_FACE {
+            calibrator.update(raw(&truth, g), gyro(0.0, 0.0, 0.0));
+        }
+    }
+
+    assert_eq!(calibrator.status().stage, CalibrationStage::Failed(CalibrationError::BadScale));
+    assert!(calibrator.take_result().is_none());
+}
@@ -0,0 +1,78 @@
+//
+// control.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::{
+    control::{AttitudeController, AttitudeSetpoint, ControllerConfig, Pid, PidGains},
+    data::{Attitude, GyroscopeData},
+    filter::{LowPassConfig, LowPassType},
+};
+
+const RATE_HZ: f32 = 100.0;
+const DT: f32 = 1.0 / RATE_HZ;
+
+fn no_filter() -> LowPassConfig {
+    LowPassConfig { kind: LowPassType::Pt1, cutoff_hz: 0.0 }
+}
+
+#[test]
+fn integral_is_limited_and_reset() {
+    let gains = PidGains { kp: 0.0, ki: 1.0, kd: 0.0, i_limit: 0.2 };
+    let mut pid = Pid::new(gains, no_filter(), RATE_HZ);
+
+    let mut out = 0.0;
+    for _ in 0..1000 {
+        out = pid.update(1.0, 0.0, DT);
+    }
+    assert!((out - 0.2).abs() < 1e-6, "{}", out);
+
+    pid.reset();
+    assert!(pid.update(0.0, 0.0, DT).abs() < 1e-6);
+}
+
+#[test]
+fn setpoint_changes_do_not_kick_the_derivative() {
+    let gains = PidGains { kp: 0.0, ki: 0.0, kd: 1.0, i_limit: 0.0 };
+    let mut pid = Pid::new(gains, no_filter(), RATE_HZ);
+
+    pid.update(0.0, 0.0, DT);
+    assert_eq!(pid.update(10.0, 0.0, DT), 0.0);
+
+    // Measurement rising damps
+    assert!(pid.update(10.0, 0.1, DT) < 0.0);
+}
+
+#[test]
+fn angle_error_becomes_a_limited_rate() {
+    let config = ControllerConfig::default();
+    let controller = AttitudeController::new(config, RATE_HZ);
+
+    let level = Attitude::default();
+    let setpoint = AttitudeSetpoint { roll: 0.1, pitch: -0.1, yaw_rate: 100.0, thrust: 0.5 };
+    let rate = controller.angle_to_rate(setpoint, level);
+
+    assert!((rate.roll - config.angle_p * 0.1).abs() < 1e-6);
+    assert!((rate.pitch + config.angle_p * 0.1).abs() < 1e-6);
+    assert_eq!(rate.yaw, config.max_rate);
+    assert_eq!(rate.thrust, 0.5);
+
+    let setpoint = AttitudeSetpoint { roll: 10.0, ..setpoint };
+    assert_eq!(controller.angle_to_rate(setpoint, level).roll, config.max_rate);
+}
+
+#[test]
+fn rate_loop_opposes_the_error() {
+    let mut controller = AttitudeController::new(ControllerConfig::default(), RATE_HZ);
+
+    let setpoint = AttitudeSetpoint { roll: 0.2, pitch: 0.0, yaw_rate: 0.0, thrust: 0.5 };
+    let gyro = GyroscopeData { x: 0.0, y: 0.5, z: -0.5 };
+    let output = controller.update(setpoint, Attitude::default(), gyro, DT);
+
+    assert!(output.roll > 0.0);
+    assert!(output.pitch < 0.0);
+    assert!(output.yaw > 0.0);
+    assert_eq!(output.thrust, 0.5);
+}
@@ -0,0 +1,270 @@
+//
+// dynamic_notch.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 06 2022
+//
+
+use icarus_core::{
+    data::{AccelerometerData, GyroscopeData},
+    dynamic_notch::{DynamicNotch, DynamicNotchConfig, PeakTracker},
+    fft::Fft,
+    filter::SignalFilter,
+    EstimatorConfig, EstimatorInput, StateEstimator,
+};
+
+use std::f32::consts::PI;
+
+const SAMPLE_RATE: f32 = 1000.0;
+
+/// Deterministic noise source so the tests are repeatable
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self, amplitude: f32) -> f32 {
+        self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+        ((self.0 >> 8) as f32 / (1 << 24) as f32 - 0.5) * 2.0 * amplitude
+    }
+}
+
+/// Tone whose frequency can change between samples without phase jumps
+struct Tone {
+    phase: f32,
+    amplitude: f32,
+}
+
+impl Tone {
+    fn new(amplitude: f32) -> Self {
+        Self { phase: 0.0, amplitude }
+    }
+
+    fn next(&mut self, freq: f32) -> f32 {
+        self.phase = (self.phase + 2.0 * PI * freq / SAMPLE_RATE) % (2.0 * PI);
+        self.amplitude * self.phase.sin()
+    }
+}
+
+/// Slow "real" motion the filter must leave untouched
+fn motion(i: usize) -> f32 {
+    0.5 * (2.0 * PI * 2.0 * i as f32 / SAMPLE_RATE).sin()
+}
+
+fn rms(values: &[f32]) -> f32 {
+    (values.iter().map(|v| v * v).sum::<f32>() / values.len() as f32).sqrt()
+}
+
+#[test]
+fn fft_peak_in_tone_bin() {
+    let fft = Fft::<64>::new();
+
+    let mut samples = [0.0; 64];
+    for (i, s) in samples.iter_mut().enumerate() {
+        *s = (2.0 * PI * 10.0 * i as f32 / 64.0).sin();
+    }
+    let mut scratch = [0.0; 64];
+    fft.power_spectrum(&mut samples, &mut scratch);
+
+    let peak = samples[..=32]
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .unwrap();
+
+    assert_eq!(peak, 10);
+}
+
+#[test]
+fn tracker_locks_onto_tone() {
+    let mut tracker = PeakTracker::<64, 1>::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+    let mut tone = Tone::new(1.0);
+    let mut noise = Noise(1);
+
+    for i in 0..500 {
+        tracker.push(tone.next(180.0) + motion(i) + noise.next(0.1));
+    }
+
+    let peak = tracker.peaks()[0];
+    assert!((peak - 180.0).abs() < 5.0, "peak at {}", peak);
+}
+
+#[test]
+fn tracker_follows_moving_tone() {
+    let mut tracker = PeakTracker::<64, 1>::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+    let mut tone = Tone::new(1.0);
+    let mut noise = Noise(2);
+
+    // Motors spinning up from 120Hz to 250Hz over two seconds
+    let samples = 2000;
+    for i in 0..samples {
+        let freq = 120.0 + 130.0 * i as f32 / samples as f32;
+        tracker.push(tone.next(freq) + motion(i) + noise.next(0.1));
+
+        if i == samples / 2 {
+            let peak = tracker.peaks()[0];
+            assert!((peak - 185.0).abs() < 10.0, "peak at {} during ramp", peak);
+        }
+    }
+
+    // Hold the final speed so the estimate settles
+    for i in 0..300 {
+        tracker.push(tone.next(250.0) + motion(samples + i) + noise.next(0.1));
+    }
+
+    let peak = tracker.peaks()[0];
+    assert!((peak - 250.0).abs() < 5.0, "peak at {}", peak);
+}
+
+#[test]
+fn tracker_finds_multiple_tones() {
+    let mut tracker = PeakTracker::<128, 2>::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+    let mut tone1 = Tone::new(1.0);
+    let mut tone2 = Tone::new(0.6);
+    let mut noise = Noise(3);
+
+    for i in 0..1000 {
+        tracker.push(tone1.next(150.0) + tone2.next(300.0) + motion(i) + noise.next(0.1));
+    }
+
+    let peaks = tracker.peaks();
+    assert!((peaks[0] - 150.0).abs() < 5.0, "peaks at {:?}", peaks);
+    assert!((peaks[1] - 300.0).abs() < 5.0, "peaks at {:?}", peaks);
+}
+
+#[test]
+fn tracker_ignores_noise_only_signal() {
+    let mut filter = DynamicNotch::<64, 2>::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+    let mut noise = Noise(4);
+
+    for i in 0..2000 {
+        filter.apply(motion(i) + noise.next(0.01));
+    }
+
+    // Nothing to track, the notches stay at their pass through default
+    assert_eq!(filter.tracker().peaks(), &[0.0; 2]);
+    assert_eq!(filter.centers(), [0.0; 2]);
+}
+
+#[test]
+fn new_peak_does_not_take_a_tracked_slot() {
+    let mut tracker = PeakTracker::<128, 2>::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+    let mut tone1 = Tone::new(1.0);
+    let mut tone2 = Tone::new(0.6);
+    let mut noise = Noise(5);
+
+    for i in 0..1000 {
+        tracker.push(tone1.next(300.0) + motion(i) + noise.next(0.1));
+    }
+
+    let peaks = *tracker.peaks();
+    assert!((peaks[0] - 300.0).abs() < 5.0, "peaks at {:?}", peaks);
+    assert_eq!(peaks[1], 0.0);
+
+    // A lower peak shows up. It gets the free slot rather than pulling the first one down
+    for i in 1000..1064 {
+        tracker.push(tone1.next(300.0) + tone2.next(150.0) + motion(i) + noise.next(0.1));
+        assert!((tracker.peaks()[0] - 300.0).abs() < 5.0, "peaks at {:?}", tracker.peaks());
+    }
+
+    for i in 1064..2000 {
+        tracker.push(tone1.next(300.0) + tone2.next(150.0) + motion(i) + noise.next(0.1));
+    }
+
+    let peaks = tracker.peaks();
+    assert!((peaks[0] - 300.0).abs() < 5.0, "peaks at {:?}", peaks);
+    assert!((peaks[1] - 150.0).abs() < 5.0, "peaks at {:?}", peaks);
+}
+
+#[test]
+fn quiet_peaks_are_not_tracked() {
+    let config = DynamicNotchConfig { min_amplitude: 0.5, ..Default::default() };
+    let mut tracker = PeakTracker::<64, 1>::new(config, SAMPLE_RATE);
+    let mut tone = Tone::new(0.2);
+
+    for i in 0..500 {
+        tracker.push(tone.next(180.0) + motion(i));
+    }
+
+    assert_eq!(tracker.peaks()[0], 0.0);
+}
+
+#[test]
+fn band_is_limited_to_nyquist() {
+    let config = DynamicNotchConfig::default();
+
+    // 400 Hz is above Nyquist at 500 Hz, the search stops at the last bin below it
+    let tracker = PeakTracker::<64, 2>::new(config, 500.0);
+    assert_eq!(tracker.band(), Some((11, 31)));
+
+    // Nothing between 80 and 400 Hz at 50 Hz
+    let tracker = PeakTracker::<64, 2>::new(config, 50.0);
+    assert_eq!(tracker.band(), None);
+
+    let mut notch = DynamicNotch::<64, 2>::new(config, 50.0);
+    assert!(!notch.is_active());
+
+    let mut tone = Tone::new(0.5);
+    for _ in 0..500 {
+        let x = tone.next(20.0);
+        assert_eq!(notch.apply(x), x);
+    }
+    assert_eq!(notch.tracker().peaks(), &[0.0; 2]);
+}
+
+#[test]
+fn notch_removes_moving_vibration() {
+    let mut filter = DynamicNotch::<64, 1>::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+    let mut tone = Tone::new(1.0);
+
+    let samples = 3000;
+    let mut errors = Vec::new();
+    let mut vibration = Vec::new();
+
+    for i in 0..samples {
+        let freq = 150.0 + 60.0 * (2.0 * PI * 0.25 * i as f32 / SAMPLE_RATE).sin();
+        let v = tone.next(freq);
+        let y = filter.apply(v + motion(i));
+
+        // Skip the initial lock-on
+        if i > 500 {
+            errors.push(y - motion(i));
+            vibration.push(v);
+        }
+    }
+
+    let attenuation = rms(&errors) / rms(&vibration);
+    assert!(attenuation < 0.3, "residual vibration ratio {}", attenuation);
+
+    let center = filter.centers()[0];
+    assert!((90.0..=210.0).contains(&center), "center at {}", center);
+}
+
+#[test]
+fn estimator_notches_gyro_vibration() {
+    let config = EstimatorConfig { sample_period: 1.0 / SAMPLE_RATE, ..Default::default() };
+    let mut estimator = StateEstimator::new(config);
+    let mut tone = Tone::new(0.5);
+
+    for _ in 0..1000 {
+        let input = EstimatorInput {
+            accel: AccelerometerData { x: 0.0, y: 0.0, z: 1.0 },
+            gyro: GyroscopeData { x: tone.next(170.0), y: 0.0, z: 0.0 },
+            altitude: 0.0,
+        };
+        assert!(estimator.update(input, 1.0 / SAMPLE_RATE).is_ok());
+    }
+
+    let [x, y, z] = estimator.gyro_notch_centers().unwrap();
+    assert!((x[0] - 170.0).abs() < 5.0, "x notches at {:?}", x);
+    assert_eq!(y, [0.0; 2]);
+    assert_eq!(z, [0.0; 2]);
+
+    let disabled = StateEstimator::new(EstimatorConfig { gyro_notch: None, ..config });
+    assert!(!disabled.has_gyro_notch());
+    assert!(disabled.gyro_notch_centers().is_none());
+
+    // Default 50 Hz sample period, the notch band is above Nyquist
+    let slow = StateEstimator::new(EstimatorConfig::default());
+    assert!(estimator.has_gyro_notch());
+    assert!(!slow.has_gyro_notch());
+}
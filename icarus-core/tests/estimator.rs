@@ -0,0 +1,101 @@
+//
+// estimator.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::{
+    data::{AccelerometerData, Attitude, GyroscopeData},
+    EstimatorConfig, EstimatorInput, StateEstimator,
+};
+
+const DT: f32 = 0.01;
+
+fn estimator() -> StateEstimator {
+    StateEstimator::new(EstimatorConfig { sample_period: DT, gyro_notch: None, ..Default::default() })
+}
+
+/// Run the estimator on a constant input and return the final attitude
+fn run(estimator: &mut StateEstimator, accel: (f32, f32, f32), gyro: (f32, f32, f32), samples: usize) -> Attitude {
+    let input = EstimatorInput {
+        accel: AccelerometerData { x: accel.0, y: accel.1, z: accel.2 },
+        gyro: GyroscopeData { x: gyro.0, y: gyro.1, z: gyro.2 },
+        altitude: 0.0,
+    };
+
+    let mut attitude = Attitude::default();
+    for _ in 0..samples {
+        attitude = estimator.update(input, DT).ok().expect("estimator failed").attitude;
+        assert!(attitude.roll.is_finite() && attitude.pitch.is_finite() && attitude.yaw.is_finite());
+    }
+
+    attitude
+}
+
+/// Specific force measured when level and rolled by `roll` radians
+fn rolled(roll: f32) -> (f32, f32, f32) {
+    (0.0, roll.sin(), roll.cos())
+}
+
+#[test]
+fn level_and_still_stays_level() {
+    // The accelerometer agrees exactly with the estimate. There is nothing to correct
+    let attitude = run(&mut estimator(), (0.0, 0.0, 1.0), (0.0, 0.0, 0.0), 1000);
+
+    assert_eq!(attitude.roll, 0.0);
+    assert_eq!(attitude.pitch, 0.0);
+}
+
+#[test]
+fn accelerometer_corrects_the_attitude_near_1g() {
+    let roll = 20f32.to_radians();
+    let attitude = run(&mut estimator(), rolled(roll), (0.0, 0.0, 0.0), 2000);
+
+    assert!((attitude.roll.abs() - roll).abs() < 0.5f32.to_radians(), "roll {}", attitude.roll.to_degrees());
+}
+
+#[test]
+fn free_fall_integrates_the_gyro_alone() {
+    // A half radian roll in free fall. Any accelerometer correction would pull it back
+    let attitude = run(&mut estimator(), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 50);
+
+    assert!((attitude.roll.abs() - 0.5).abs() < 1e-3, "roll {}", attitude.roll);
+    assert!(attitude.pitch.abs() < 1e-3);
+}
+
+#[test]
+fn hard_acceleration_is_not_trusted() {
+    // 2g sideways push while level. Trusting it would tilt the estimate by about 60 degrees
+    let mut estimator = estimator();
+    let attitude = run(&mut estimator, (0.0, 1.7, 1.0), (0.0, 0.0, 0.0), 500);
+    assert_eq!(attitude.roll, 0.0);
+
+    // Within the band it is used
+    let config = EstimatorConfig { sample_period: DT, accel_rejection: 2.0, gyro_notch: None, ..Default::default() };
+    let attitude = run(&mut StateEstimator::new(config), (0.0, 1.7, 1.0), (0.0, 0.0, 0.0), 500);
+    assert!(attitude.roll.abs() > 0.5, "roll {}", attitude.roll);
+}
+
+#[test]
+fn degenerate_readings_are_skipped() {
+    // Even with the rejection band wide open a zero or invalid reading has no direction to correct towards
+    let config = EstimatorConfig {
+        sample_period: DT,
+        accel_rejection: f32::INFINITY,
+        gyro_notch: None,
+        ..Default::default()
+    };
+    let mut estimator = StateEstimator::new(config);
+
+    let attitude = run(&mut estimator, (0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 10);
+    assert_eq!(attitude.roll, 0.0);
+
+    let attitude = run(&mut estimator, (f32::NAN, 0.0, 1.0), (0.0, 0.0, 0.0), 10);
+    assert_eq!(attitude.roll, 0.0);
+
+    // And the estimator keeps working afterwards
+    let roll = 10f32.to_radians();
+    let attitude = run(&mut estimator, rolled(roll), (0.0, 0.0, 0.0), 2000);
+    assert!((attitude.roll.abs() - roll).abs() < 0.5f32.to_radians(), "roll {}", attitude.roll.to_degrees());
+}
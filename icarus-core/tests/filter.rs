@@ -0,0 +1,189 @@
+//
+// filter.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::filter::{
+    notch_q, BiquadFilter, FrequencyFilter, LowPassConfig, LowPassFilter, LowPassType, MovingAverage, Pt1Filter,
+    Pt2Filter, Pt3Filter, SignalFilter, TriAxialFilter,
+};
+
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+const SAMPLE_RATE: f32 = 2000.0;
+const CUTOFF: f32 = 50.0;
+
+/// Steady state gain of a filter for a sine at `freq`
+fn gain<F: SignalFilter>(filter: &mut F, freq: f32) -> f32 {
+    filter.reset();
+
+    // Let the filter settle for a second, then compare the RMS of input and output over the next. Both cover a whole
+    // number of cycles
+    let samples = SAMPLE_RATE as usize;
+    let (mut input, mut output) = (0.0, 0.0);
+    for i in 0..2 * samples {
+        let x = (2.0 * PI * freq * i as f32 / SAMPLE_RATE).sin();
+        let y = filter.apply(x);
+        if i >= samples {
+            input += x * x;
+            output += y * y;
+        }
+    }
+
+    (output / input).sqrt()
+}
+
+/// Output after settling on a constant input
+fn dc_gain<F: SignalFilter>(filter: &mut F) -> f32 {
+    filter.reset();
+    (0..SAMPLE_RATE as usize).map(|_| filter.apply(1.0)).last().unwrap()
+}
+
+fn assert_close(actual: f32, expected: f32, tolerance: f32, what: &str) {
+    assert!((actual - expected).abs() < tolerance, "{}: expected {}, got {}", what, expected, actual);
+}
+
+#[test]
+fn moving_average_of_last_samples() {
+    let mut filter = MovingAverage::<3>::default();
+    assert_eq!(filter.value(), 0.0);
+
+    assert_eq!(filter.apply(3.0), 3.0);
+    assert_eq!(filter.apply(6.0), 4.5);
+    assert_eq!(filter.apply(9.0), 6.0);
+    assert_eq!(filter.apply(0.0), 5.0);
+}
+
+#[test]
+fn pt_filters_unity_dc_gain() {
+    assert_close(dc_gain(&mut Pt1Filter::new(CUTOFF, SAMPLE_RATE)), 1.0, 1e-4, "PT1");
+    assert_close(dc_gain(&mut Pt2Filter::new(CUTOFF, SAMPLE_RATE)), 1.0, 1e-4, "PT2");
+    assert_close(dc_gain(&mut Pt3Filter::new(CUTOFF, SAMPLE_RATE)), 1.0, 1e-4, "PT3");
+}
+
+#[test]
+fn pt_filters_3db_at_cutoff() {
+    assert_close(gain(&mut Pt1Filter::new(CUTOFF, SAMPLE_RATE), CUTOFF), FRAC_1_SQRT_2, 0.01, "PT1");
+    assert_close(gain(&mut Pt2Filter::new(CUTOFF, SAMPLE_RATE), CUTOFF), FRAC_1_SQRT_2, 0.01, "PT2");
+    assert_close(gain(&mut Pt3Filter::new(CUTOFF, SAMPLE_RATE), CUTOFF), FRAC_1_SQRT_2, 0.01, "PT3");
+}
+
+#[test]
+fn higher_order_pt_filters_roll_off_faster() {
+    let pt1 = gain(&mut Pt1Filter::new(CUTOFF, SAMPLE_RATE), 4.0 * CUTOFF);
+    let pt2 = gain(&mut Pt2Filter::new(CUTOFF, SAMPLE_RATE), 4.0 * CUTOFF);
+    let pt3 = gain(&mut Pt3Filter::new(CUTOFF, SAMPLE_RATE), 4.0 * CUTOFF);
+
+    assert!(pt1 > pt2 && pt2 > pt3, "gains {} {} {}", pt1, pt2, pt3);
+}
+
+#[test]
+fn pt_filter_corner_holds_near_nyquist() {
+    // The corner of an RC step drifts as the cutoff approaches the sample rate
+    let cutoff = SAMPLE_RATE / 8.0;
+    assert_close(gain(&mut Pt1Filter::new(cutoff, SAMPLE_RATE), cutoff), FRAC_1_SQRT_2, 0.01, "PT1");
+    assert_close(gain(&mut Pt3Filter::new(cutoff, SAMPLE_RATE), cutoff), FRAC_1_SQRT_2, 0.01, "PT3");
+
+    // Past Nyquist the filter still smooths rather than blowing up
+    let mut filter = Pt1Filter::new(SAMPLE_RATE, SAMPLE_RATE);
+    let y = filter.apply(1.0);
+    assert!(y > 0.5 && y < 1.0, "{}", y);
+}
+
+#[test]
+fn pt_filter_without_cutoff_passes_through() {
+    let mut filter = Pt2Filter::new(0.0, SAMPLE_RATE);
+    assert_close(filter.apply(0.7), 0.7, 1e-6, "first");
+    assert_close(filter.apply(-0.2), -0.2, 1e-6, "second");
+
+    filter.reset_to(0.5);
+    filter.set_cutoff(CUTOFF);
+    assert_eq!(filter.value(), 0.5);
+    assert_close(filter.apply(0.5), 0.5, 1e-6, "settled");
+}
+
+#[test]
+fn biquad_low_pass_response() {
+    let mut filter = BiquadFilter::low_pass(CUTOFF, SAMPLE_RATE);
+
+    assert_close(dc_gain(&mut filter), 1.0, 1e-4, "DC");
+    assert_close(gain(&mut filter, CUTOFF), FRAC_1_SQRT_2, 0.01, "cutoff");
+    // Second order, -12dB per octave well above the cutoff
+    assert!(gain(&mut filter, 8.0 * CUTOFF) < 0.02);
+}
+
+#[test]
+fn notch_attenuates_the_center_only() {
+    let q = notch_q(200.0, 150.0).unwrap();
+    let mut filter = BiquadFilter::notch(200.0, q, SAMPLE_RATE);
+
+    assert!(gain(&mut filter, 200.0) < 0.01);
+    assert_close(dc_gain(&mut filter), 1.0, 1e-4, "DC");
+    assert_close(gain(&mut filter, 20.0), 1.0, 0.01, "far below");
+
+    // Moving the notch keeps it working
+    filter.set_notch(300.0, q);
+    assert!(gain(&mut filter, 300.0) < 0.01);
+    assert!(gain(&mut filter, 200.0) > 0.5);
+}
+
+#[test]
+fn notch_q_needs_a_cutoff_below_the_center() {
+    assert_close(notch_q(200.0, 150.0).unwrap(), 200.0 * 150.0 / (200.0 * 200.0 - 150.0 * 150.0), 1e-6, "Q");
+
+    assert_eq!(notch_q(200.0, 200.0), None);
+    assert_eq!(notch_q(200.0, 250.0), None);
+    assert_eq!(notch_q(200.0, 0.0), None);
+    assert_eq!(notch_q(200.0, f32::NAN), None);
+}
+
+#[test]
+fn biquad_out_of_range_passes_through() {
+    let mut filter = BiquadFilter::low_pass(SAMPLE_RATE, SAMPLE_RATE);
+    assert_eq!(filter.apply(0.3), 0.3);
+
+    let mut filter = BiquadFilter::notch(100.0, 0.0, SAMPLE_RATE);
+    assert_eq!(filter.apply(0.3), 0.3);
+}
+
+#[test]
+fn low_pass_filter_matches_its_kind() {
+    for kind in [LowPassType::Pt1, LowPassType::Pt2, LowPassType::Pt3, LowPassType::Biquad] {
+        let mut filter = LowPassFilter::new(LowPassConfig { kind, cutoff_hz: CUTOFF }, SAMPLE_RATE);
+        assert_eq!(filter.kind(), kind);
+
+        assert_close(dc_gain(&mut filter), 1.0, 1e-4, "DC");
+        assert_close(gain(&mut filter, CUTOFF), FRAC_1_SQRT_2, 0.01, "cutoff");
+
+        // Same filter at a new cutoff
+        filter.set_cutoff(2.0 * CUTOFF);
+        assert_close(gain(&mut filter, 2.0 * CUTOFF), FRAC_1_SQRT_2, 0.01, "new cutoff");
+    }
+
+    let mut disabled = LowPassFilter::default();
+    assert_eq!(disabled.apply(0.4), 0.4);
+}
+
+#[test]
+fn tri_axial_filter_keeps_axes_separate() {
+    let mut filter = TriAxialFilter::new(Pt1Filter::new(CUTOFF, SAMPLE_RATE));
+
+    for _ in 0..SAMPLE_RATE as usize {
+        filter.apply((1.0, -2.0, 0.0));
+    }
+
+    let (x, y, z) = filter.value();
+    assert_close(x, 1.0, 1e-4, "x");
+    assert_close(y, -2.0, 1e-4, "y");
+    assert_eq!(z, 0.0);
+    assert_eq!(filter.get_y(), y);
+
+    filter.reset();
+    assert_eq!(filter.value(), (0.0, 0.0, 0.0));
+
+    let mut average = TriAxialFilter::<MovingAverage<2>>::default();
+    average.apply((2.0, 4.0, 6.0));
+    assert_eq!(average.apply((0.0, 0.0, 0.0)), (1.0, 2.0, 3.0));
+}
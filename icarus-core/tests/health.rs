@@ -0,0 +1,208 @@
+//
+// health.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::{
+    data::{AccelerometerData, GyroscopeData},
+    health::{HealthConfig, HealthFlags, ImuSensor, SensorHealthMonitor},
+};
+
+const DT: f32 = 0.02;
+
+fn accel(x: f32, y: f32, z: f32) -> AccelerometerData {
+    AccelerometerData { x, y, z }
+}
+
+fn gyro(x: f32, y: f32, z: f32) -> GyroscopeData {
+    GyroscopeData { x, y, z }
+}
+
+/// Slightly different reading each sample, like a real sensor
+fn sample(i: usize) -> (AccelerometerData, GyroscopeData) {
+    let noise = (i % 7) as f32 * 0.001;
+    (accel(noise, -noise, 1.0 + noise), gyro(noise, 0.0, -noise))
+}
+
+fn run(monitor: &mut SensorHealthMonitor, samples: usize, delta: f32) {
+    for i in 0..samples {
+        let (a, g) = sample(i);
+        monitor.record_sample(a, g, delta);
+    }
+}
+
+#[test]
+fn healthy_sensor_has_no_faults() {
+    let mut monitor = SensorHealthMonitor::default();
+    run(&mut monitor, 500, DT);
+
+    assert!(monitor.flags().is_empty(), "{:?}", monitor.flags());
+    assert!(monitor.is_healthy());
+    assert!((monitor.report().sample_rate - 50.0).abs() < 0.5);
+}
+
+#[test]
+fn repeated_values_are_stuck() {
+    let config = HealthConfig::default();
+    let mut monitor = SensorHealthMonitor::new(config);
+
+    // Gyro frozen, accelerometer still changing
+    for i in 0..=config.stuck_samples as usize {
+        assert!(!monitor.flags().contains(HealthFlags::GYRO_STUCK));
+        monitor.record_sample(sample(i).0, gyro(0.1, 0.2, 0.3), DT);
+    }
+
+    assert!(monitor.flags().contains(HealthFlags::GYRO_STUCK));
+    assert!(!monitor.flags().contains(HealthFlags::ACCEL_STUCK));
+    assert!(!monitor.is_healthy());
+
+    // One new value clears it
+    monitor.record_sample(sample(0).0, gyro(0.1, 0.2, 0.31), DT);
+    assert!(!monitor.flags().contains(HealthFlags::GYRO_STUCK));
+    assert!(monitor.is_healthy());
+}
+
+#[test]
+fn readings_at_full_scale_are_clipping() {
+    let config = HealthConfig::default();
+    let mut monitor = SensorHealthMonitor::new(config);
+
+    monitor.record_sample(accel(0.0, 0.0, config.accel_range), sample(0).1, DT);
+    assert!(monitor.flags().contains(HealthFlags::ACCEL_CLIPPING));
+    assert!(!monitor.flags().contains(HealthFlags::ACCEL_OUT_OF_RANGE));
+
+    monitor.record_sample(sample(1).0, gyro(-config.gyro_range, 0.0, 0.0), DT);
+    assert!(monitor.flags().contains(HealthFlags::GYRO_CLIPPING));
+    assert!(!monitor.flags().contains(HealthFlags::ACCEL_CLIPPING));
+
+    // Clipping alone is not critical
+    assert!(monitor.is_healthy());
+}
+
+#[test]
+fn impossible_readings_are_out_of_range() {
+    let config = HealthConfig::default();
+    let mut monitor = SensorHealthMonitor::new(config);
+
+    monitor.record_sample(accel(0.0, 0.0, config.accel_range * 1.5), sample(0).1, DT);
+    assert!(monitor.flags().contains(HealthFlags::ACCEL_OUT_OF_RANGE));
+    assert!(!monitor.is_healthy());
+
+    monitor.record_sample(sample(1).0, gyro(f32::NAN, 0.0, 0.0), DT);
+    assert!(monitor.flags().contains(HealthFlags::GYRO_OUT_OF_RANGE));
+    assert!(!monitor.flags().contains(HealthFlags::ACCEL_OUT_OF_RANGE));
+
+    monitor.record_sample(sample(2).0, sample(2).1, DT);
+    assert!(monitor.is_healthy());
+}
+
+#[test]
+fn sample_rate_drop_is_flagged_and_recovers() {
+    let mut monitor = SensorHealthMonitor::default();
+    run(&mut monitor, 100, DT);
+    assert!(!monitor.flags().contains(HealthFlags::SAMPLE_RATE));
+
+    // Half rate. The measured rate is smoothed so it takes a moment to show up
+    run(&mut monitor, 5, 2.0 * DT);
+    assert!(!monitor.flags().contains(HealthFlags::SAMPLE_RATE));
+    run(&mut monitor, 100, 2.0 * DT);
+    assert!(monitor.flags().contains(HealthFlags::SAMPLE_RATE));
+    assert!((monitor.report().sample_rate - 25.0).abs() < 1.0);
+
+    // Not a critical fault on its own
+    assert!(monitor.is_healthy());
+
+    run(&mut monitor, 300, DT);
+    assert!(!monitor.flags().contains(HealthFlags::SAMPLE_RATE));
+}
+
+#[test]
+fn consecutive_failures_lose_the_sensor() {
+    let config = HealthConfig::default();
+    let mut monitor = SensorHealthMonitor::new(config);
+
+    monitor.record_failure(ImuSensor::Accelerometer);
+    assert!(monitor.flags().contains(HealthFlags::ACCEL_READ_FAILURE));
+    assert!(!monitor.needs_reinit());
+
+    for _ in 1..config.max_consecutive_failures {
+        monitor.record_failure(ImuSensor::Gyroscope);
+    }
+
+    assert_eq!(monitor.consecutive_failures(), config.max_consecutive_failures);
+    assert!(monitor.flags().contains(HealthFlags::SENSOR_LOST | HealthFlags::GYRO_READ_FAILURE));
+    assert!(monitor.needs_reinit());
+    assert!(!monitor.is_healthy());
+
+    let report = monitor.report();
+    assert_eq!(report.accel_failures, 1);
+    assert_eq!(report.gyro_failures, config.max_consecutive_failures as u32 - 1);
+}
+
+#[test]
+fn failed_imu_read_counts_once() {
+    let config = HealthConfig::default();
+    let mut monitor = SensorHealthMonitor::new(config);
+
+    for _ in 1..config.max_consecutive_failures {
+        monitor.record_imu_failure();
+    }
+    assert!(monitor.flags().contains(HealthFlags::ACCEL_READ_FAILURE | HealthFlags::GYRO_READ_FAILURE));
+    assert!(!monitor.flags().contains(HealthFlags::SENSOR_LOST));
+
+    monitor.record_imu_failure();
+    assert_eq!(monitor.consecutive_failures(), config.max_consecutive_failures);
+    assert!(monitor.flags().contains(HealthFlags::SENSOR_LOST));
+
+    // Each sensor still counts its own failures
+    let report = monitor.report();
+    assert_eq!(report.accel_failures, config.max_consecutive_failures as u32);
+    assert_eq!(report.gyro_failures, config.max_consecutive_failures as u32);
+}
+
+#[test]
+fn lost_sensor_recovers_after_reinit() {
+    let config = HealthConfig::default();
+    let mut monitor = SensorHealthMonitor::new(config);
+
+    // Stuck before it was lost
+    for _ in 0..=config.stuck_samples {
+        monitor.record_sample(sample(0).0, sample(0).1, DT);
+    }
+    assert!(monitor.flags().contains(HealthFlags::ACCEL_STUCK | HealthFlags::GYRO_STUCK));
+
+    for _ in 0..config.max_consecutive_failures {
+        monitor.record_failure(ImuSensor::Accelerometer);
+    }
+    assert!(monitor.needs_reinit());
+
+    monitor.record_reinit();
+    assert!(!monitor.needs_reinit());
+    assert_eq!(monitor.consecutive_failures(), 0);
+    assert_eq!(monitor.report().reinit_count, 1);
+
+    // The same reading after a reinit starts the stuck count over
+    monitor.record_sample(sample(0).0, sample(0).1, DT);
+    assert!(!monitor.flags().intersects(HealthFlags::ACCEL_STUCK | HealthFlags::GYRO_STUCK));
+
+    // A good read clears the read failure
+    assert!(!monitor.flags().contains(HealthFlags::ACCEL_READ_FAILURE));
+    assert!(monitor.is_healthy());
+}
+
+#[test]
+fn a_good_sample_clears_a_failure_streak() {
+    let config = HealthConfig::default();
+    let mut monitor = SensorHealthMonitor::new(config);
+
+    for _ in 0..config.max_consecutive_failures - 1 {
+        monitor.record_failure(ImuSensor::Gyroscope);
+    }
+    run(&mut monitor, 1, DT);
+    monitor.record_failure(ImuSensor::Gyroscope);
+
+    assert_eq!(monitor.consecutive_failures(), 1);
+    assert!(!monitor.needs_reinit());
+}
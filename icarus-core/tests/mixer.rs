@@ -0,0 +1,82 @@
+//
+// mixer.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::{
+    control::ControlOutput,
+    mixer::{Mixer, NUM_MOTORS},
+};
+
+fn output(roll: f32, pitch: f32, yaw: f32, thrust: f32) -> ControlOutput {
+    ControlOutput { roll, pitch, yaw, thrust }
+}
+
+fn assert_in_range(motors: &[f32; NUM_MOTORS]) {
+    assert!(motors.iter().all(|m| (0.0..=1.0).contains(m)), "{:?}", motors);
+}
+
+fn spread(motors: &[f32; NUM_MOTORS]) -> f32 {
+    motors.iter().copied().fold(f32::MIN, f32::max) - motors.iter().copied().fold(f32::MAX, f32::min)
+}
+
+#[test]
+fn thrust_only_drives_every_motor_equally() {
+    let mixer = Mixer::default();
+
+    assert_eq!(mixer.mix(output(0.0, 0.0, 0.0, 0.4)), [0.4; NUM_MOTORS]);
+    assert_eq!(mixer.mix(output(0.0, 0.0, 0.0, 1.5)), [1.0; NUM_MOTORS]);
+    assert_eq!(mixer.mix(output(0.3, 0.0, 0.0, 0.0)), [0.0; NUM_MOTORS]);
+}
+
+#[test]
+fn torque_has_priority_over_thrust() {
+    let mixer = Mixer::default();
+
+    // Full roll near full thrust. The thrust gives way so the roll torque is kept
+    let motors = mixer.mix(output(0.4, 0.0, 0.0, 0.9));
+    assert_in_range(&motors);
+    assert!((spread(&motors) - 0.8).abs() < 1e-6, "{:?}", motors);
+
+    // Same at low thrust
+    let motors = mixer.mix(output(0.4, 0.0, 0.0, 0.1));
+    assert_in_range(&motors);
+    assert!((spread(&motors) - 0.8).abs() < 1e-6, "{:?}", motors);
+
+    // More torque than the motors can give is scaled down to the full range
+    let motors = mixer.mix(output(1.0, 1.0, 1.0, 0.5));
+    assert_in_range(&motors);
+    assert!((spread(&motors) - 1.0).abs() < 1e-6, "{:?}", motors);
+}
+
+#[test]
+fn saturation_never_panics() {
+    let mixer = Mixer::default();
+
+    // Rounding after scaling used to cross the thrust bounds
+    assert_in_range(&mixer.mix(output(0.554, 0.634, 0.797, 0.029)));
+
+    let steps = (-20..=20).map(|i| i as f32 * 0.137);
+    for roll in steps.clone() {
+        for pitch in steps.clone() {
+            for yaw in steps.clone() {
+                for thrust in [0.001, 0.029, 0.5, 0.97, 2.0] {
+                    assert_in_range(&mixer.mix(output(roll, pitch, yaw, thrust)));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn invalid_demand() {
+    let mixer = Mixer::default();
+
+    assert_eq!(mixer.mix(output(0.0, 0.0, 0.0, f32::NAN)), [0.0; NUM_MOTORS]);
+
+    // Torque is dropped, thrust is kept
+    assert_eq!(mixer.mix(output(f32::NAN, 0.1, 0.0, 0.5)), [0.5; NUM_MOTORS]);
+    assert_eq!(mixer.mix(output(0.0, f32::INFINITY, 0.0, 0.5)), [0.5; NUM_MOTORS]);
+}
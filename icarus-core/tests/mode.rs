@@ -0,0 +1,152 @@
+//
+// mode.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_core::{
+    control::{AttitudeSetpoint, MAX_TILT},
+    mode::{FlightMode, ModeConfig, ModeError, ModeLimits, ModeManager, ModeTarget, PilotInput},
+};
+
+const RATE_HZ: f32 = 100.0;
+const DT: f32 = 1.0 / RATE_HZ;
+
+fn assert_close(a: f32, b: f32, tolerance: f32) {
+    assert!((a - b).abs() <= tolerance, "{} != {} (+/- {})", a, b, tolerance);
+}
+
+fn sticks(roll: f32, pitch: f32, yaw: f32, thrust: f32) -> PilotInput {
+    PilotInput { roll, pitch, yaw, thrust }
+}
+
+/// Manager with a barometer reading `altitude`
+fn with_altitude(altitude: f32) -> ModeManager {
+    let mut modes = ModeManager::new(ModeConfig::default(), RATE_HZ);
+    for _ in 0..10 {
+        modes.update_altitude(Some(altitude), DT);
+    }
+    modes
+}
+
+/// Run `n` iterations at a fixed altitude, returning the last thrust
+fn fly(modes: &mut ModeManager, altitude: f32, n: usize) -> f32 {
+    let mut thrust = 0.0;
+    for _ in 0..n {
+        modes.update_altitude(Some(altitude), DT);
+        thrust = modes.target(DT).thrust();
+    }
+    thrust
+}
+
+#[test]
+fn sticks_scale_to_the_mode_limits() {
+    let limits = ModeLimits::default();
+    let mut modes = ModeManager::new(ModeConfig::default(), RATE_HZ);
+    modes.set_input(sticks(1.0, -0.5, 0.5, 0.4));
+
+    match modes.target(DT) {
+        ModeTarget::Attitude(setpoint) => {
+            assert_close(setpoint.roll, limits.max_angle, 1e-6);
+            assert_close(setpoint.pitch, -0.5 * limits.max_angle, 1e-6);
+            assert_close(setpoint.yaw_rate, 0.5 * limits.max_yaw_rate, 1e-6);
+            assert_close(setpoint.thrust, 0.4, 1e-6);
+        }
+        target => panic!("{:?}", target),
+    }
+
+    modes.set_mode(FlightMode::Rate).unwrap();
+    modes.set_input(sticks(1.0, 0.0, -1.0, 0.4));
+    match modes.target(DT) {
+        ModeTarget::Rate(setpoint) => {
+            assert_close(setpoint.roll, limits.max_rate, 1e-6);
+            assert_close(setpoint.yaw, -limits.max_yaw_rate, 1e-6);
+        }
+        target => panic!("{:?}", target),
+    }
+
+    // Physical setpoints beyond the limits end up at full stick
+    let attitude = AttitudeSetpoint { roll: 2.0, pitch: 0.0, yaw_rate: 0.0, thrust: 2.0 };
+    assert_eq!(PilotInput::from_attitude(attitude, &limits), sticks(1.0, 0.0, 0.0, 1.0));
+
+    let limits = ModeLimits { max_angle: 2.0, max_rate: 100.0, ..limits }.clamped();
+    assert_close(limits.max_angle, MAX_TILT, 1e-6);
+    assert_close(limits.max_rate, ModeLimits::MAX_RATE, 1e-6);
+}
+
+#[test]
+fn mode_changes_keep_thrust_and_centre_the_sticks() {
+    let mut modes = with_altitude(10.0);
+    modes.set_input(sticks(0.5, 0.5, 0.5, 0.45));
+    fly(&mut modes, 10.0, 1);
+
+    modes.set_mode(FlightMode::Rate).unwrap();
+    assert_eq!(*modes.input(), sticks(0.0, 0.0, 0.0, 0.45));
+
+    // Altitude hold starts at the thrust it was entered with
+    modes.set_mode(FlightMode::AltHold).unwrap();
+    assert_close(fly(&mut modes, 10.0, 1), 0.45, 1e-3);
+    assert_close(fly(&mut modes, 10.0, 100), 0.45, 1e-3);
+
+    // Thrust stick takes over at the thrust altitude hold was flying at
+    modes.set_input(sticks(0.0, 0.0, 0.0, 0.8));
+    let thrust = fly(&mut modes, 10.0, 20);
+    modes.set_mode(FlightMode::Angle).unwrap();
+    assert_close(modes.input().thrust, thrust, 1e-6);
+    assert_close(modes.target(DT).thrust(), thrust, 1e-6);
+}
+
+#[test]
+fn altitude_hold_needs_the_barometer() {
+    let mut modes = ModeManager::new(ModeConfig::default(), RATE_HZ);
+    assert_eq!(modes.set_mode(FlightMode::AltHold), Err(ModeError::NoAltitude));
+
+    let mut modes = with_altitude(5.0);
+    modes.set_input(sticks(0.0, 0.0, 0.0, 0.5));
+    modes.set_mode(FlightMode::AltHold).unwrap();
+    fly(&mut modes, 5.0, 10);
+
+    // Short gaps are tolerated
+    for _ in 0..10 {
+        modes.update_altitude(None, DT);
+    }
+    assert_eq!(modes.mode(), FlightMode::AltHold);
+
+    // Lost for longer than the timeout
+    for _ in 0..100 {
+        modes.update_altitude(None, DT);
+    }
+    assert!(!modes.has_altitude());
+    assert_eq!(modes.mode(), FlightMode::Angle);
+    assert_close(modes.input().thrust, 0.5, 1e-3);
+    assert_eq!(modes.set_mode(FlightMode::AltHold), Err(ModeError::NoAltitude));
+}
+
+#[test]
+fn altitude_hold_corrects_towards_the_held_altitude() {
+    let mut modes = with_altitude(5.0);
+    modes.set_input(sticks(0.0, 0.0, 0.0, 0.5));
+    modes.set_mode(FlightMode::AltHold).unwrap();
+
+    // Sinking below the held altitude adds thrust, rising above it takes thrust away
+    assert!(fly(&mut modes, 4.0, 200) > 0.5);
+
+    let mut modes = with_altitude(5.0);
+    modes.set_input(sticks(0.0, 0.0, 0.0, 0.5));
+    modes.set_mode(FlightMode::AltHold).unwrap();
+    assert!(fly(&mut modes, 6.0, 200) < 0.5);
+
+    // Stick up asks for a climb, within the deadband holds
+    let mut modes = with_altitude(5.0);
+    modes.set_input(sticks(0.0, 0.0, 0.0, 0.5));
+    modes.set_mode(FlightMode::AltHold).unwrap();
+    modes.set_input(sticks(0.0, 0.0, 0.0, 1.0));
+    assert!(fly(&mut modes, 5.0, 100) > 0.5);
+
+    let mut modes = with_altitude(5.0);
+    modes.set_input(sticks(0.0, 0.0, 0.0, 0.5));
+    modes.set_mode(FlightMode::AltHold).unwrap();
+    modes.set_input(sticks(0.0, 0.0, 0.0, 0.55));
+    assert_close(fly(&mut modes, 5.0, 100), 0.5, 1e-3);
+}
@@ -0,0 +1,95 @@
+//
+// motor.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 05 2022
+//
+
+use icarus_core::motor::{MotorCurve, ThrustSample};
+
+fn assert_close(a: f32, b: f32, tolerance: f32) {
+    assert!((a - b).abs() <= tolerance, "{} != {} (+/- {})", a, b, tolerance);
+}
+
+#[test]
+fn default_curve_passes_through() {
+    let curve = MotorCurve::default();
+
+    for thrust in [0.0, 0.1, 0.5, 1.0] {
+        assert_close(curve.duty(thrust, Some(3.7)), thrust, 1e-6);
+    }
+    assert_eq!(curve.duty(2.0, None), 1.0);
+}
+
+#[test]
+fn expo_inverts_the_thrust_model() {
+    let curve = MotorCurve { expo: 0.6, min_output: 0.1, max_output: 0.9, nominal_voltage: 0.0 };
+
+    // Stopped motors stay stopped, any thrust demand starts at the minimum output
+    assert_eq!(curve.duty(0.0, None), 0.0);
+    assert_close(curve.duty(1e-6, None), 0.1, 1e-3);
+    assert_close(curve.duty(1.0, None), 0.9, 1e-6);
+
+    for thrust in [0.1, 0.25, 0.5, 0.75] {
+        assert_close(curve.thrust(curve.duty(thrust, None)), thrust, 1e-4);
+    }
+
+    // Half thrust needs more than half of the duty range with a squared response
+    assert!(curve.duty(0.5, None) > 0.5);
+}
+
+#[test]
+fn low_battery_raises_duty() {
+    let curve = MotorCurve { nominal_voltage: 3.8, ..MotorCurve::default() };
+
+    assert_close(curve.duty(0.5, Some(3.8)), 0.5, 1e-6);
+    assert_close(curve.duty(0.5, Some(3.4)), 0.5 * 3.8 / 3.4, 1e-5);
+    assert!(curve.duty(0.5, Some(4.2)) < 0.5);
+
+    // Compensation never exceeds the maximum output and ignores a missing reading
+    assert_eq!(curve.duty(0.9, Some(3.0)), 1.0);
+    assert_eq!(curve.duty(0.5, None), 0.5);
+
+    let outputs = curve.apply(&[0.0, 0.5, 0.5, 0.0], Some(3.4));
+    assert_eq!(outputs[0], 0.0);
+    assert!(outputs[1] > 0.5);
+
+    // A high battery never pulls a spinning motor below its minimum output
+    let curve = MotorCurve { min_output: 0.1, max_output: 0.9, nominal_voltage: 3.2, ..MotorCurve::default() };
+    assert_close(curve.duty(0.001, Some(4.2)), 0.1, 1e-6);
+    assert!(curve.duty(0.3, Some(4.2)) >= 0.1);
+    assert_eq!(curve.duty(0.0, Some(4.2)), 0.0);
+}
+
+#[test]
+fn fit_recovers_bench_curve() {
+    let truth = MotorCurve { expo: 0.7, min_output: 0.1, max_output: 1.0, nominal_voltage: 0.0 };
+
+    // Steps of 5% duty, thrust in grams. The motor does not turn below 10%
+    let samples = (0..=20)
+        .map(|i| {
+            let duty = i as f32 * 0.05;
+            ThrustSample { duty, thrust: truth.thrust(duty) * 120.0, voltage: 3.9 }
+        })
+        .collect::<Vec<_>>();
+
+    let fit = MotorCurve::fit(&samples).unwrap();
+    assert_close(fit.expo, 0.7, 0.05);
+    assert_close(fit.min_output, 0.1, 1e-6);
+    assert_close(fit.max_output, 1.0, 1e-6);
+    assert_close(fit.nominal_voltage, 3.9, 1e-5);
+}
+
+#[test]
+fn fit_needs_spinning_samples() {
+    assert!(MotorCurve::fit(&[]).is_none());
+
+    let stopped = [ThrustSample { duty: 0.2, thrust: 0.0, voltage: 3.9 }; 5];
+    assert!(MotorCurve::fit(&stopped).is_none());
+
+    let two = [
+        ThrustSample { duty: 0.5, thrust: 10.0, voltage: 3.9 },
+        ThrustSample { duty: 1.0, thrust: 40.0, voltage: 3.9 },
+    ];
+    assert!(MotorCurve::fit(&two).is_none());
+}
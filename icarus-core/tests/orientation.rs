@@ -0,0 +1,141 @@
+//
+// orientation.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 16 2022
+//
+
+use icarus_core::{
+    data::{AccelerometerData, GyroscopeData},
+    orientation::{BoardAlignment, BoardRotation, SensorAlignment},
+    EstimatorInput, StateEstimator,
+};
+
+use nalgebra::{Rotation3, Vector3};
+
+const TOLERANCE_DEG: f32 = 1.0;
+
+/// Body attitudes to test each mounting with (roll, pitch) in degrees
+const ATTITUDES: [(f32, f32); 4] = [(0.0, 0.0), (20.0, 0.0), (0.0, -15.0), (-10.0, 25.0)];
+
+/// Accelerometer reading in the sensor frame for a stationary body at the given attitude
+fn sensor_accel(alignment: &BoardAlignment, roll: f32, pitch: f32) -> AccelerometerData {
+    let body = Rotation3::from_euler_angles(roll.to_radians(), pitch.to_radians(), 0.0);
+    // Specific force in the body frame, +1g up when level
+    let accel_body = body.inverse() * Vector3::new(0.0, 0.0, 1.0);
+    let accel_sensor = alignment.rotation().inverse() * accel_body;
+
+    AccelerometerData { x: accel_sensor.x, y: accel_sensor.y, z: accel_sensor.z }
+}
+
+/// Run the estimator on a stationary board until it converges
+fn estimate(alignment: BoardAlignment, accel: AccelerometerData) -> (f32, f32) {
+    let mut estimator = StateEstimator::default();
+    estimator.set_alignment(alignment);
+
+    let input = EstimatorInput {
+        accel,
+        gyro: GyroscopeData::default(),
+        altitude: 0.0,
+    };
+
+    let mut attitude = None;
+    for _ in 0..3000 {
+        attitude = estimator.update(input, 0.02).ok().map(|s| s.attitude);
+    }
+
+    let attitude = attitude.expect("estimator failed");
+    (attitude.roll.to_degrees(), attitude.pitch.to_degrees())
+}
+
+fn assert_close(actual: f32, expected: f32, what: &str) {
+    assert!((actual - expected).abs() < TOLERANCE_DEG, "{}: expected {}, got {}", what, expected, actual);
+}
+
+#[test]
+fn attitude_correct_for_each_rotation() {
+    for rotation in BoardRotation::ALL {
+        let alignment = BoardAlignment { rotation, ..Default::default() };
+
+        for (roll, pitch) in ATTITUDES {
+            let (est_roll, est_pitch) = estimate(alignment, sensor_accel(&alignment, roll, pitch));
+
+            let what = format!("{:?} at roll={} pitch={}", rotation, roll, pitch);
+            assert_close(est_roll, roll, &what);
+            assert_close(est_pitch, pitch, &what);
+        }
+    }
+}
+
+#[test]
+fn attitude_correct_with_trim() {
+    let alignments = [
+        BoardAlignment { rotation: BoardRotation::Cw0, roll_trim: 3.0, pitch_trim: -2.0, yaw_trim: 0.0 },
+        BoardAlignment { rotation: BoardRotation::Cw90, roll_trim: 0.0, pitch_trim: 5.0, yaw_trim: 10.0 },
+        BoardAlignment { rotation: BoardRotation::Cw180Flip, roll_trim: -4.0, pitch_trim: 1.5, yaw_trim: -30.0 },
+    ];
+
+    for alignment in alignments {
+        for (roll, pitch) in ATTITUDES {
+            let (est_roll, est_pitch) = estimate(alignment, sensor_accel(&alignment, roll, pitch));
+
+            let what = format!("{:?} at roll={} pitch={}", alignment, roll, pitch);
+            assert_close(est_roll, roll, &what);
+            assert_close(est_pitch, pitch, &what);
+        }
+    }
+}
+
+#[test]
+fn unaligned_estimator_is_wrong_for_rotated_board() {
+    // Sanity check that the alignment is what makes the tests above pass
+    let alignment = BoardAlignment { rotation: BoardRotation::Cw90, ..Default::default() };
+    let (est_roll, est_pitch) = estimate(BoardAlignment::default(), sensor_accel(&alignment, 20.0, 0.0));
+
+    assert!((est_roll - 20.0).abs() > 10.0 || est_pitch.abs() > 10.0);
+}
+
+#[test]
+fn preset_axis_mapping() {
+    let x = AccelerometerData { x: 1.0, y: 0.0, z: 0.0 };
+    let z = AccelerometerData { x: 0.0, y: 0.0, z: 1.0 };
+
+    // (rotation, sensor +X in body frame, sensor +Z in body frame)
+    let cases = [
+        (BoardRotation::Cw0, (1.0, 0.0, 0.0), (0.0, 0.0, 1.0)),
+        (BoardRotation::Cw90, (0.0, -1.0, 0.0), (0.0, 0.0, 1.0)),
+        (BoardRotation::Cw180, (-1.0, 0.0, 0.0), (0.0, 0.0, 1.0)),
+        (BoardRotation::Cw270, (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)),
+        (BoardRotation::Cw0Flip, (-1.0, 0.0, 0.0), (0.0, 0.0, -1.0)),
+        (BoardRotation::Cw90Flip, (0.0, 1.0, 0.0), (0.0, 0.0, -1.0)),
+        (BoardRotation::Cw180Flip, (1.0, 0.0, 0.0), (0.0, 0.0, -1.0)),
+        (BoardRotation::Cw270Flip, (0.0, -1.0, 0.0), (0.0, 0.0, -1.0)),
+    ];
+
+    for (rotation, expected_x, expected_z) in cases {
+        let alignment = SensorAlignment::from(BoardAlignment { rotation, ..Default::default() });
+
+        for (input, expected) in [(x, expected_x), (z, expected_z)] {
+            let actual: (f32, f32, f32) = alignment.accel(input).into();
+            assert!(
+                (actual.0 - expected.0).abs() < 1e-5
+                    && (actual.1 - expected.1).abs() < 1e-5
+                    && (actual.2 - expected.2).abs() < 1e-5,
+                "{:?}: {:?} -> {:?}, expected {:?}", rotation, input, actual, expected
+            );
+        }
+    }
+}
+
+#[test]
+fn gyro_rotated_into_body_frame() {
+    let alignment = BoardAlignment { rotation: BoardRotation::Cw270Flip, roll_trim: 2.0, pitch_trim: 0.0, yaw_trim: 0.0 };
+
+    // Pure yaw rate in the body frame
+    let body = Vector3::new(0.0, 0.0, 1.0);
+    let sensor = alignment.rotation().inverse() * body;
+
+    let gyro = SensorAlignment::from(alignment).gyro(GyroscopeData { x: sensor.x, y: sensor.y, z: sensor.z });
+
+    assert!(gyro.x.abs() < 1e-5 && gyro.y.abs() < 1e-5 && (gyro.z - 1.0).abs() < 1e-5, "{:?}", gyro);
+}
@@ -0,0 +1,305 @@
+//
+// scenarios.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 25 2022
+//
+
+use icarus_core::{
+    data::{AccelerometerData, GyroscopeData},
+    EstimatorInput, StateEstimator,
+};
+
+use nalgebra::{Rotation3, Vector3};
+
+use std::f32::consts::PI;
+
+/// Firmware control loop rate
+const SAMPLE_RATE: f32 = 50.0;
+
+/// Vehicle motion, in degrees and seconds
+#[derive(Debug, Clone, Copy)]
+enum Motion {
+    /// Held still at a fixed attitude
+    Stationary { roll: f32, pitch: f32 },
+    /// Constant body rate (deg/s) starting level
+    ConstantRotation { rate: [f32; 3] },
+    /// Roll and pitch oscillating 90 degrees out of phase
+    Wobble { amplitude: f32, frequency: f32 },
+    /// Held level, then dropped at `start`. The accelerometer reads zero while falling
+    FreeFall { start: f32, rate: [f32; 3] },
+}
+
+/// Sensor error model
+#[derive(Debug, Clone, Copy)]
+struct Noise {
+    /// Accelerometer white noise (g)
+    accel: f32,
+    /// Gyroscope white noise (rad/s)
+    gyro: f32,
+    /// Frame vibration on every accelerometer axis as (amplitude in g, frequency in Hz)
+    vibration: Option<(f32, f32)>,
+}
+
+impl Noise {
+    const NONE: Noise = Noise { accel: 0.0, gyro: 0.0, vibration: None };
+    /// Roughly the MPU6050 at its default ranges
+    const MPU6050: Noise = Noise { accel: 0.003, gyro: 0.006, vibration: None };
+}
+
+/// What the estimator must achieve
+#[derive(Debug, Clone, Copy)]
+struct Expect {
+    /// Tilt error must drop below `max_error` by this time (s) and stay there
+    convergence_time: f32,
+    /// Maximum tilt error after convergence (deg)
+    max_error: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Scenario {
+    name: &'static str,
+    motion: Motion,
+    noise: Noise,
+    duration: f32,
+    expect: Expect,
+}
+
+#[derive(Debug)]
+struct Outcome {
+    /// First time after which the error stayed inside the bound. None if it never settled
+    converged_at: Option<f32>,
+    /// Largest error after convergence (deg)
+    max_error: f32,
+}
+
+/// Small deterministic generator so scenarios are repeatable
+struct Rng(u64);
+
+impl Rng {
+    fn uniform(&mut self) -> f32 {
+        // xorshift64*
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let x = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        ((x >> 40) as f32 + 0.5) / (1u64 << 24) as f32
+    }
+
+    fn normal(&mut self, std_dev: f32) -> f32 {
+        // Box-Muller
+        let (u1, u2) = (self.uniform(), self.uniform());
+        std_dev * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
+
+fn rate_rad(rate: [f32; 3]) -> Vector3<f32> {
+    Vector3::new(rate[0], rate[1], rate[2]).map(f32::to_radians)
+}
+
+impl Motion {
+    /// True body to world rotation at time `t`
+    fn orientation(&self, t: f32) -> Rotation3<f32> {
+        match *self {
+            Motion::Stationary { roll, pitch } => {
+                Rotation3::from_euler_angles(roll.to_radians(), pitch.to_radians(), 0.0)
+            }
+            Motion::ConstantRotation { rate } => Rotation3::from_scaled_axis(rate_rad(rate) * t),
+            Motion::Wobble { amplitude, frequency } => {
+                let phase = 2.0 * PI * frequency * t;
+                let amplitude = amplitude.to_radians();
+                Rotation3::from_euler_angles(amplitude * phase.sin(), amplitude * phase.cos(), 0.0)
+            }
+            Motion::FreeFall { start, rate } => Rotation3::from_scaled_axis(rate_rad(rate) * (t - start).max(0.0)),
+        }
+    }
+
+    /// Body rate (rad/s) at time `t`
+    fn body_rate(&self, t: f32) -> Vector3<f32> {
+        match *self {
+            Motion::Stationary { .. } => Vector3::zeros(),
+            Motion::ConstantRotation { rate } => rate_rad(rate),
+            Motion::FreeFall { start, rate } => if t >= start { rate_rad(rate) } else { Vector3::zeros() },
+            Motion::Wobble { .. } => {
+                // Central difference of the orientation
+                let h = 1e-3;
+                let delta = self.orientation(t - h).inverse() * self.orientation(t + h);
+                delta.scaled_axis() / (2.0 * h)
+            }
+        }
+    }
+
+    /// Specific force in the body frame (g)
+    fn specific_force(&self, t: f32) -> Vector3<f32> {
+        match *self {
+            Motion::FreeFall { start, .. } if t >= start => Vector3::zeros(),
+            // Attitude only motion. The body does not translate so the accelerometer sees gravity alone
+            _ => self.orientation(t).inverse() * Vector3::z(),
+        }
+    }
+}
+
+/// Angle between the true and estimated up vectors (deg). Heading is not observable without a magnetometer
+fn tilt_error(truth: &Rotation3<f32>, estimate: &Rotation3<f32>) -> f32 {
+    let up_truth = truth.inverse() * Vector3::z();
+    let up_estimate = estimate.inverse() * Vector3::z();
+
+    up_truth.angle(&up_estimate).to_degrees()
+}
+
+fn run(scenario: &Scenario) -> Outcome {
+    let mut estimator = StateEstimator::default();
+    let mut rng = Rng(0x001c_a205);
+
+    let dt = 1.0 / SAMPLE_RATE;
+    let steps = (scenario.duration * SAMPLE_RATE) as usize;
+    let noise = scenario.noise;
+
+    let mut errors = Vec::with_capacity(steps);
+
+    for i in 0..steps {
+        let t = i as f32 * dt;
+
+        let mut accel = scenario.motion.specific_force(t);
+        let mut gyro = scenario.motion.body_rate(t);
+
+        if let Some((amplitude, frequency)) = noise.vibration {
+            let phase = 2.0 * PI * frequency * t;
+            accel += Vector3::new(phase.sin(), (phase + 1.0).sin(), (phase + 2.0).sin()) * amplitude;
+        }
+        accel = accel.map(|a| a + rng.normal(noise.accel));
+        gyro = gyro.map(|g| g + rng.normal(noise.gyro));
+
+        let input = EstimatorInput {
+            accel: AccelerometerData { x: accel.x, y: accel.y, z: accel.z },
+            gyro: GyroscopeData { x: gyro.x, y: gyro.y, z: gyro.z },
+            altitude: 0.0,
+        };
+
+        let state = estimator
+            .update(input, dt)
+            .unwrap_or_else(|_| panic!("{}: estimator failed at t={}", scenario.name, t));
+
+        let attitude = state.attitude;
+        assert!(
+            attitude.roll.is_finite() && attitude.pitch.is_finite() && attitude.yaw.is_finite(),
+            "{}: non-finite attitude at t={}",
+            scenario.name,
+            t,
+        );
+
+        // Sensors are sampled at the end of the period the estimator integrates over
+        let truth = scenario.motion.orientation(t + dt);
+        let estimate = Rotation3::from_euler_angles(attitude.roll, attitude.pitch, attitude.yaw);
+        errors.push((t, tilt_error(&truth, &estimate)));
+    }
+
+    let bound = scenario.expect.max_error;
+    let settle_index = errors
+        .iter()
+        .rposition(|(_, e)| *e > bound)
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    Outcome {
+        converged_at: errors.get(settle_index).map(|(t, _)| *t),
+        max_error: errors[settle_index..].iter().map(|(_, e)| *e).fold(0.0, f32::max),
+    }
+}
+
+fn check(scenario: Scenario) {
+    let outcome = run(&scenario);
+
+    let converged_at = outcome
+        .converged_at
+        .unwrap_or_else(|| panic!("{}: never converged within {} deg", scenario.name, scenario.expect.max_error));
+
+    assert!(
+        converged_at <= scenario.expect.convergence_time,
+        "{}: converged at {:.2}s, expected within {:.2}s",
+        scenario.name,
+        converged_at,
+        scenario.expect.convergence_time,
+    );
+    assert!(outcome.max_error <= scenario.expect.max_error, "{}: {:?}", scenario.name, outcome);
+}
+
+#[test]
+fn stationary_level() {
+    check(Scenario {
+        name: "stationary level",
+        motion: Motion::Stationary { roll: 0.0, pitch: 0.0 },
+        noise: Noise::MPU6050,
+        duration: 10.0,
+        expect: Expect { convergence_time: 0.0, max_error: 1.0 },
+    });
+}
+
+#[test]
+fn stationary_tilted() {
+    check(Scenario {
+        name: "stationary tilted",
+        motion: Motion::Stationary { roll: 25.0, pitch: -15.0 },
+        noise: Noise::MPU6050,
+        duration: 15.0,
+        expect: Expect { convergence_time: 4.0, max_error: 1.0 },
+    });
+}
+
+#[test]
+fn constant_yaw_rotation() {
+    check(Scenario {
+        name: "constant yaw rotation",
+        motion: Motion::ConstantRotation { rate: [0.0, 0.0, 90.0] },
+        noise: Noise::MPU6050,
+        duration: 10.0,
+        expect: Expect { convergence_time: 0.0, max_error: 2.0 },
+    });
+}
+
+#[test]
+fn constant_roll_rotation() {
+    // Flipping continuously. The accelerometer correction lags the rotation, bounded error rather than convergence
+    check(Scenario {
+        name: "constant roll rotation",
+        motion: Motion::ConstantRotation { rate: [45.0, 0.0, 0.0] },
+        noise: Noise::NONE,
+        duration: 16.0,
+        expect: Expect { convergence_time: 0.0, max_error: 3.0 },
+    });
+}
+
+#[test]
+fn wobble() {
+    check(Scenario {
+        name: "wobble",
+        motion: Motion::Wobble { amplitude: 15.0, frequency: 0.5 },
+        noise: Noise::MPU6050,
+        duration: 10.0,
+        expect: Expect { convergence_time: 1.5, max_error: 5.5 },
+    });
+}
+
+#[test]
+fn free_fall() {
+    // Tumbling while falling. Only the gyro is usable until the fall ends
+    check(Scenario {
+        name: "free fall",
+        motion: Motion::FreeFall { start: 2.0, rate: [30.0, -20.0, 60.0] },
+        noise: Noise { accel: 0.02, ..Noise::MPU6050 },
+        duration: 4.0,
+        expect: Expect { convergence_time: 0.0, max_error: 2.0 },
+    });
+}
+
+#[test]
+fn vibration() {
+    // Motor vibration well above the sample rate aliases into the accelerometer
+    check(Scenario {
+        name: "vibration",
+        motion: Motion::Stationary { roll: 10.0, pitch: 5.0 },
+        noise: Noise { vibration: Some((0.3, 183.0)), ..Noise::MPU6050 },
+        duration: 15.0,
+        expect: Expect { convergence_time: 2.0, max_error: 3.5 },
+    });
+}
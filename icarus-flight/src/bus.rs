@@ -0,0 +1,256 @@
+//
+// bus.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 28 2022
+//
+
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+pub use shared_bus::{BusMutex, NullMutex};
+
+/// Number of devices tracked individually in [`BusStats`]
+pub const MAX_DEVICES: usize = 4;
+/// SCL pulses needed to finish any byte a device could be stuck in
+pub const RECOVERY_CLOCKS: usize = 9;
+
+/// Direct control of the SDA and SCL lines, used to recover a stuck bus
+pub trait I2cLines {
+    /// Take the lines from the I2C peripheral and drive them as open drain outputs
+    fn acquire(&mut self);
+    /// Hand the lines back to the I2C peripheral
+    fn release(&mut self);
+    fn set_scl(&mut self, high: bool);
+    fn set_sda(&mut self, high: bool);
+    fn sda_is_high(&self) -> bool;
+    /// Wait half a clock period
+    fn delay(&mut self);
+}
+
+/// Free a device holding SDA low by clocking SCL until it lets go, then issue a STOP. Returns true if SDA was released
+pub fn recover_bus<L: I2cLines>(lines: &mut L) -> bool {
+    lines.acquire();
+
+    lines.set_sda(true);
+    lines.set_scl(true);
+    lines.delay();
+
+    for _ in 0..RECOVERY_CLOCKS {
+        if lines.sda_is_high() {
+            break;
+        }
+
+        lines.set_scl(false);
+        lines.delay();
+        lines.set_scl(true);
+        lines.delay();
+    }
+
+    let released = lines.sda_is_high();
+
+    // STOP, SDA rises while SCL is high
+    lines.set_scl(false);
+    lines.delay();
+    lines.set_sda(false);
+    lines.delay();
+    lines.set_scl(true);
+    lines.delay();
+    lines.set_sda(true);
+    lines.delay();
+
+    lines.release();
+
+    released
+}
+
+/// Transaction counts for a single device
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceStats {
+    pub address: u8,
+    pub transactions: u32,
+    pub errors: u32,
+}
+
+/// Transaction counts for the whole bus
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BusStats {
+    pub transactions: u32,
+    pub errors: u32,
+    /// Recoveries that released SDA
+    pub recoveries: u32,
+    /// Recoveries where SDA was still held low
+    pub failed_recoveries: u32,
+    /// Per device counts, in order of first use
+    pub devices: [Option<DeviceStats>; MAX_DEVICES],
+}
+
+impl BusStats {
+    /// Counts for the device at `address`, if it has been used
+    pub fn device(&self, address: u8) -> Option<DeviceStats> {
+        self.devices.iter().flatten().find(|d| d.address == address).copied()
+    }
+
+    fn record(&mut self, address: u8, ok: bool) {
+        let errors = if ok { 0 } else { 1 };
+
+        self.transactions = self.transactions.saturating_add(1);
+        self.errors = self.errors.saturating_add(errors);
+
+        let slot = self.devices.iter_mut().find(|d| d.map(|d| d.address == address).unwrap_or(true));
+
+        // Past MAX_DEVICES only the bus totals are kept
+        if let Some(slot) = slot {
+            let device = slot.get_or_insert(DeviceStats { address, ..Default::default() });
+            device.transactions = device.transactions.saturating_add(1);
+            device.errors = device.errors.saturating_add(errors);
+        }
+    }
+}
+
+/// I2C bus with error accounting and stuck bus recovery
+///
+/// After a failed transaction the bus is checked for a device holding SDA low, which would fail every transaction
+/// that follows. If so, the bus is recovered before the error is returned.
+pub struct ManagedI2c<I, L> {
+    i2c: I,
+    lines: L,
+    stats: BusStats,
+}
+
+impl<I, L: I2cLines> ManagedI2c<I, L> {
+    pub fn new(i2c: I, lines: L) -> Self {
+        Self { i2c, lines, stats: BusStats::default() }
+    }
+
+    pub fn stats(&self) -> BusStats {
+        self.stats
+    }
+
+    /// Recover the bus regardless of its state
+    pub fn recover(&mut self) -> bool {
+        let released = recover_bus(&mut self.lines);
+
+        if released {
+            self.stats.recoveries = self.stats.recoveries.saturating_add(1);
+        }
+        else {
+            self.stats.failed_recoveries = self.stats.failed_recoveries.saturating_add(1);
+        }
+
+        released
+    }
+
+    fn record<T, E>(&mut self, address: u8, result: Result<T, E>) -> Result<T, E> {
+        self.stats.record(address, result.is_ok());
+
+        if result.is_err() && !self.lines.sda_is_high() {
+            self.recover();
+        }
+
+        result
+    }
+}
+
+impl<I: Write, L: I2cLines> Write for ManagedI2c<I, L> {
+    type Error = I::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        let result = self.i2c.write(address, bytes);
+        self.record(address, result)
+    }
+}
+
+impl<I: Read, L: I2cLines> Read for ManagedI2c<I, L> {
+    type Error = I::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let result = self.i2c.read(address, buffer);
+        self.record(address, result)
+    }
+}
+
+impl<I: WriteRead, L: I2cLines> WriteRead for ManagedI2c<I, L> {
+    type Error = I::Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let result = self.i2c.write_read(address, bytes, buffer);
+        self.record(address, result)
+    }
+}
+
+/// Shares one [`ManagedI2c`] between every sensor on the bus
+///
+/// `M` decides how access is synchronized, `NullMutex` within a single task or `std::sync::Mutex` across threads.
+pub struct I2cBus<M> {
+    mutex: M,
+}
+
+impl<M, I, L> I2cBus<M>
+where
+    M: BusMutex<Bus = ManagedI2c<I, L>>,
+    L: I2cLines,
+{
+    pub fn new(i2c: I, lines: L) -> Self {
+        Self { mutex: M::create(ManagedI2c::new(i2c, lines)) }
+    }
+
+    /// Handle for one device on the bus
+    pub fn acquire(&self) -> I2cProxy<'_, M> {
+        I2cProxy { mutex: &self.mutex }
+    }
+
+    pub fn stats(&self) -> BusStats {
+        self.mutex.lock(|bus| bus.stats())
+    }
+
+    pub fn recover(&self) -> bool {
+        self.mutex.lock(|bus| bus.recover())
+    }
+}
+
+/// A device's handle to an [`I2cBus`]
+pub struct I2cProxy<'a, M> {
+    mutex: &'a M,
+}
+
+impl<'a, M> Clone for I2cProxy<'a, M> {
+    fn clone(&self) -> Self {
+        Self { mutex: self.mutex }
+    }
+}
+
+impl<'a, M> Write for I2cProxy<'a, M>
+where
+    M: BusMutex,
+    M::Bus: Write,
+{
+    type Error = <M::Bus as Write>::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.mutex.lock(|bus| bus.write(address, bytes))
+    }
+}
+
+impl<'a, M> Read for I2cProxy<'a, M>
+where
+    M: BusMutex,
+    M::Bus: Read,
+{
+    type Error = <M::Bus as Read>::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.mutex.lock(|bus| bus.read(address, buffer))
+    }
+}
+
+impl<'a, M> WriteRead for I2cProxy<'a, M>
+where
+    M: BusMutex,
+    M::Bus: WriteRead,
+{
+    type Error = <M::Bus as WriteRead>::Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.mutex.lock(|bus| bus.write_read(address, bytes, buffer))
+    }
+}
@@ -0,0 +1,106 @@
+//
+// bmp388.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 29 2022
+//
+
+use crate::hal::Barometer;
+
+use icarus_wire::BarometerRaw;
+
+use bmp388::{Filter, Oversampling, OversamplingConfig, PowerControl, PowerMode, SamplingRate, BMP388};
+
+use embedded_hal::blocking::i2c::WriteRead;
+
+/// Chip id of the BMP388
+pub const CHIP_ID: u8 = 0x50;
+/// Standard sea level pressure (Pa)
+pub const SEA_LEVEL_PRESSURE: f32 = 101_325.0;
+
+#[derive(Debug)]
+pub enum Bmp388Error<E> {
+    I2c(E),
+    /// The chip id did not match a BMP388
+    WrongDevice(u8),
+}
+
+impl<E> From<E> for Bmp388Error<E> {
+    fn from(e: E) -> Self {
+        Bmp388Error::I2c(e)
+    }
+}
+
+/// Pressure altitude (m) using the international barometric formula
+pub fn pressure_to_altitude(pressure: f32, sea_level: f32) -> f32 {
+    44_330.0 * (1.0 - libm::powf(pressure / sea_level, 1.0 / 5.255))
+}
+
+/// BMP388 pressure sensor
+///
+/// The sensor is set up on first use and again after any failed read, so a barometer that is missing at boot or drops
+/// off the bus recovers on its own.
+pub struct Bmp388<I: WriteRead> {
+    i2c: I,
+    sensor: Option<BMP388<I>>,
+}
+
+impl<I, E> Bmp388<I>
+where
+    I: WriteRead<Error = E> + Clone,
+{
+    pub fn new(i2c: I) -> Self {
+        Self { i2c, sensor: None }
+    }
+
+    /// Reset, load calibration and start continuous conversion at 50 Hz
+    pub fn init(&mut self) -> Result<(), Bmp388Error<E>> {
+        self.sensor = None;
+
+        let mut sensor = BMP388::new(self.i2c.clone())?;
+
+        let id = sensor.id()?;
+        if id != CHIP_ID {
+            return Err(Bmp388Error::WrongDevice(id));
+        }
+
+        sensor.set_oversampling(OversamplingConfig { osr_p: Oversampling::x2, osr4_t: Oversampling::x1 })?;
+        sensor.set_sampling_rate(SamplingRate::ms20)?;
+        sensor.set_filter(Filter::c3)?;
+        sensor.set_power_control(PowerControl {
+            pressure_enable: true,
+            temperature_enable: true,
+            mode: PowerMode::Normal,
+        })?;
+
+        self.sensor = Some(sensor);
+
+        Ok(())
+    }
+}
+
+impl<I, E> Barometer for Bmp388<I>
+where
+    I: WriteRead<Error = E> + Clone,
+{
+    type Error = Bmp388Error<E>;
+
+    fn read(&mut self) -> Result<BarometerRaw, Self::Error> {
+        if self.sensor.is_none() {
+            self.init()?;
+        }
+
+        let sensor = self.sensor.as_mut().expect("initialized above");
+
+        match sensor.sensor_values() {
+            Ok(values) => Ok(BarometerRaw {
+                altitude: pressure_to_altitude(values.pressure as f32, SEA_LEVEL_PRESSURE),
+                temp: values.temperature as f32,
+            }),
+            Err(e) => {
+                self.sensor = None;
+                Err(e.into())
+            }
+        }
+    }
+}
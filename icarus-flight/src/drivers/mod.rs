@@ -0,0 +1,8 @@
+//
+// mod.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 27 2022
+//
+pub mod bmp388;
+pub mod mpu6050;
@@ -0,0 +1,378 @@
+//
+// mpu6050.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 27 2022
+//
+
+use crate::hal::{Clock, DataReady, Imu, ImuSample};
+
+use icarus_core::data::{AccelerometerData, GyroscopeData};
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+/// Default I2C address (AD0 low)
+pub const DEFAULT_ADDRESS: u8 = 0x68;
+
+/// Register map
+pub mod regs {
+    pub const SMPLRT_DIV: u8 = 0x19;
+    pub const CONFIG: u8 = 0x1A;
+    pub const GYRO_CONFIG: u8 = 0x1B;
+    pub const ACCEL_CONFIG: u8 = 0x1C;
+    pub const FIFO_EN: u8 = 0x23;
+    pub const INT_PIN_CFG: u8 = 0x37;
+    pub const INT_ENABLE: u8 = 0x38;
+    pub const INT_STATUS: u8 = 0x3A;
+    pub const ACCEL_XOUT_H: u8 = 0x3B;
+    pub const USER_CTRL: u8 = 0x6A;
+    pub const PWR_MGMT_1: u8 = 0x6B;
+    pub const FIFO_COUNT_H: u8 = 0x72;
+    pub const FIFO_R_W: u8 = 0x74;
+    pub const WHO_AM_I: u8 = 0x75;
+
+    /// FIFO_EN: gyroscope X, Y, Z and accelerometer
+    pub const FIFO_EN_GYRO_ACCEL: u8 = 0x78;
+    /// INT_PIN_CFG: clear the interrupt status on any read. Not used, a data or FIFO read would drop pending status
+    pub const INT_RD_CLEAR: u8 = 0x10;
+    /// INT_ENABLE / INT_STATUS
+    pub const FIFO_OFLOW: u8 = 0x10;
+    pub const DATA_RDY: u8 = 0x01;
+    /// USER_CTRL
+    pub const USER_FIFO_EN: u8 = 0x40;
+    pub const USER_FIFO_RESET: u8 = 0x04;
+    /// PWR_MGMT_1: wake up, clocked from the X gyro PLL
+    pub const CLKSEL_PLL_X: u8 = 0x01;
+
+    pub const WHO_AM_I_VALUE: u8 = 0x68;
+}
+
+/// Bytes per FIFO sample, accelerometer then gyroscope
+pub const FIFO_SAMPLE_SIZE: usize = 12;
+/// Hardware FIFO size (bytes)
+pub const FIFO_SIZE: usize = 1024;
+/// Samples read from the FIFO per I2C transaction
+const FIFO_CHUNK_SAMPLES: usize = 8;
+
+/// Digital low pass filter bandwidth (accel / gyro)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dlpf {
+    /// Filter off, gyro sampled at 8 kHz
+    Off = 0,
+    Hz184 = 1,
+    Hz94 = 2,
+    Hz44 = 3,
+    Hz21 = 4,
+    Hz10 = 5,
+    Hz5 = 6,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccelRange {
+    G2 = 0,
+    G4 = 1,
+    G8 = 2,
+    G16 = 3,
+}
+
+impl AccelRange {
+    /// LSB per g
+    pub fn sensitivity(&self) -> f32 {
+        16384.0 / (1 << *self as u8) as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GyroRange {
+    Deg250 = 0,
+    Deg500 = 1,
+    Deg1000 = 2,
+    Deg2000 = 3,
+}
+
+impl GyroRange {
+    /// LSB per deg/s
+    pub fn sensitivity(&self) -> f32 {
+        131.0 / (1 << *self as u8) as f32
+    }
+}
+
+/// How samples are collected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Read the latest sample from the data registers
+    Burst,
+    /// Drain the hardware FIFO and average every sample since the last read
+    Fifo,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Mpu6050Config {
+    /// Output rate is the gyro rate (8 kHz with the DLPF off, 1 kHz otherwise) / (1 + divider)
+    pub sample_rate_divider: u8,
+    pub dlpf: Dlpf,
+    pub accel_range: AccelRange,
+    pub gyro_range: GyroRange,
+    pub mode: ReadMode,
+}
+
+impl Default for Mpu6050Config {
+    fn default() -> Self {
+        // 1 kHz with 94 Hz bandwidth, keeps motor vibration out of the estimator at any supported loop rate
+        Self {
+            sample_rate_divider: 0,
+            dlpf: Dlpf::Hz94,
+            accel_range: AccelRange::G2,
+            gyro_range: GyroRange::Deg250,
+            mode: ReadMode::Fifo,
+        }
+    }
+}
+
+impl Mpu6050Config {
+    /// Sensor output data rate (Hz)
+    pub fn sample_rate(&self) -> f32 {
+        let gyro_rate = if self.dlpf == Dlpf::Off { 8000.0 } else { 1000.0 };
+        gyro_rate / (1.0 + self.sample_rate_divider as f32)
+    }
+}
+
+#[derive(Debug)]
+pub enum Mpu6050Error<E> {
+    I2c(E),
+    /// WHO_AM_I returned something other than an MPU6050
+    WrongDevice(u8),
+}
+
+impl<E> From<E> for Mpu6050Error<E> {
+    fn from(e: E) -> Self {
+        Mpu6050Error::I2c(e)
+    }
+}
+
+/// MPU6050 accelerometer and gyroscope on I2C
+///
+/// Every sample is read in a single transaction so the accelerometer and gyroscope always come from the same instant.
+/// Samples are timestamped with the data ready interrupt when one is wired, otherwise with the time they were read.
+pub struct Mpu6050<I, C, D> {
+    i2c: I,
+    addr: u8,
+    config: Mpu6050Config,
+    clock: C,
+    data_ready: D,
+    fifo_overflows: u32,
+    last_fifo_samples: usize,
+}
+
+impl<I, C, D, E> Mpu6050<I, C, D>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+    C: Clock,
+    D: DataReady,
+{
+    pub fn new(i2c: I, addr: u8, config: Mpu6050Config, clock: C, data_ready: D) -> Self {
+        Self {
+            i2c,
+            addr,
+            config,
+            clock,
+            data_ready,
+            fifo_overflows: 0,
+            last_fifo_samples: 0,
+        }
+    }
+
+    /// Check the device and write the configuration
+    pub fn init(&mut self) -> Result<(), Mpu6050Error<E>> {
+        let id = self.read_register(regs::WHO_AM_I)?;
+        if id != regs::WHO_AM_I_VALUE {
+            return Err(Mpu6050Error::WrongDevice(id));
+        }
+
+        self.write_register(regs::PWR_MGMT_1, regs::CLKSEL_PLL_X)?;
+        self.write_register(regs::SMPLRT_DIV, self.config.sample_rate_divider)?;
+        self.write_register(regs::CONFIG, self.config.dlpf as u8)?;
+        self.write_register(regs::GYRO_CONFIG, (self.config.gyro_range as u8) << 3)?;
+        self.write_register(regs::ACCEL_CONFIG, (self.config.accel_range as u8) << 3)?;
+
+        // Data ready pulses the INT pin on every new sample. Status bits stay set until INT_STATUS itself is read, so an
+        // overflow is not lost to a data read in between
+        self.write_register(regs::INT_PIN_CFG, 0)?;
+        self.write_register(regs::INT_ENABLE, regs::DATA_RDY | regs::FIFO_OFLOW)?;
+
+        match self.config.mode {
+            ReadMode::Fifo => {
+                self.write_register(regs::FIFO_EN, regs::FIFO_EN_GYRO_ACCEL)?;
+                self.reset_fifo()?;
+            }
+            ReadMode::Burst => {
+                self.write_register(regs::FIFO_EN, 0)?;
+                self.write_register(regs::USER_CTRL, 0)?;
+            }
+        }
+
+        // Anything already latched is stale
+        self.data_ready.take();
+
+        Ok(())
+    }
+
+    pub fn bus_mut(&mut self) -> &mut I {
+        &mut self.i2c
+    }
+
+    pub fn config(&self) -> &Mpu6050Config {
+        &self.config
+    }
+
+    /// Number of times the FIFO overflowed and was discarded
+    pub fn fifo_overflows(&self) -> u32 {
+        self.fifo_overflows
+    }
+
+    /// Number of FIFO samples averaged into the last reading
+    pub fn last_fifo_samples(&self) -> usize {
+        self.last_fifo_samples
+    }
+
+    /// Poll the data ready status, for boards without the interrupt line
+    pub fn is_data_ready(&mut self) -> Result<bool, Mpu6050Error<E>> {
+        Ok(self.read_register(regs::INT_STATUS)? & regs::DATA_RDY != 0)
+    }
+
+    /// Read the latest sample from the data registers in one transaction
+    pub fn read_burst(&mut self) -> Result<ImuSample, Mpu6050Error<E>> {
+        let mut buf = [0u8; 14];
+        self.i2c.write_read(self.addr, &[regs::ACCEL_XOUT_H], &mut buf)?;
+
+        let timestamp_us = self.data_ready.take().unwrap_or_else(|| self.clock.now_us());
+
+        // Temperature sits between the accelerometer and gyroscope
+        let (accel, gyro) = self.convert(&buf[0..6], &buf[8..14]);
+        Ok(ImuSample { accel, gyro, timestamp_us })
+    }
+
+    /// Drain the FIFO, passing each sample to `f`. Returns the number of samples read
+    ///
+    /// The newest sample is stamped with the data ready time, earlier ones are spaced one sample period apart. On
+    /// overflow the FIFO contents are misaligned and are discarded.
+    pub fn read_fifo<F: FnMut(ImuSample)>(&mut self, mut f: F) -> Result<usize, Mpu6050Error<E>> {
+        let status = self.read_register(regs::INT_STATUS)?;
+        let now = self.data_ready.take().unwrap_or_else(|| self.clock.now_us());
+
+        if status & regs::FIFO_OFLOW != 0 {
+            self.fifo_overflows = self.fifo_overflows.saturating_add(1);
+            self.reset_fifo()?;
+            return Ok(0);
+        }
+
+        let mut count = [0u8; 2];
+        self.i2c.write_read(self.addr, &[regs::FIFO_COUNT_H], &mut count)?;
+        let available = u16::from_be_bytes(count) as usize / FIFO_SAMPLE_SIZE;
+
+        let period_us = (1e6 / self.config.sample_rate()) as u64;
+        let mut buf = [0u8; FIFO_SAMPLE_SIZE * FIFO_CHUNK_SAMPLES];
+        let mut read = 0;
+
+        while read < available {
+            let n = (available - read).min(FIFO_CHUNK_SAMPLES);
+            let chunk = &mut buf[..n * FIFO_SAMPLE_SIZE];
+            self.i2c.write_read(self.addr, &[regs::FIFO_R_W], chunk)?;
+
+            for raw in chunk.chunks_exact(FIFO_SAMPLE_SIZE) {
+                let (accel, gyro) = self.convert(&raw[0..6], &raw[6..12]);
+                let age = (available - 1 - read) as u64;
+                read += 1;
+
+                f(ImuSample { accel, gyro, timestamp_us: now.saturating_sub(age * period_us) });
+            }
+        }
+
+        Ok(read)
+    }
+
+    fn reset_fifo(&mut self) -> Result<(), Mpu6050Error<E>> {
+        self.write_register(regs::USER_CTRL, regs::USER_FIFO_RESET)?;
+        self.write_register(regs::USER_CTRL, regs::USER_FIFO_EN)?;
+        Ok(())
+    }
+
+    fn convert(&self, accel: &[u8], gyro: &[u8]) -> (AccelerometerData, GyroscopeData) {
+        let a = self.config.accel_range.sensitivity();
+        let g = self.config.gyro_range.sensitivity() * 180.0 / core::f32::consts::PI;
+
+        let accel = AccelerometerData {
+            x: axis(accel, 0) / a,
+            y: axis(accel, 1) / a,
+            z: axis(accel, 2) / a,
+        };
+        let gyro = GyroscopeData {
+            x: axis(gyro, 0) / g,
+            y: axis(gyro, 1) / g,
+            z: axis(gyro, 2) / g,
+        };
+
+        (accel, gyro)
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u8, E> {
+        let mut buf = [0u8; 1];
+        self.i2c.write_read(self.addr, &[reg], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) -> Result<(), E> {
+        self.i2c.write(self.addr, &[reg, value])
+    }
+}
+
+impl<I, C, D, E> Imu for Mpu6050<I, C, D>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+    C: Clock,
+    D: DataReady,
+{
+    type Error = Mpu6050Error<E>;
+
+    fn read(&mut self) -> Result<ImuSample, Self::Error> {
+        if self.config.mode == ReadMode::Burst {
+            return self.read_burst();
+        }
+
+        // Averaging everything since the last read acts as an anti-aliasing filter when the loop runs slower than the
+        // sensor
+        let mut sum = [0.0f32; 6];
+        let mut newest = 0;
+        let n = self.read_fifo(|s| {
+            sum[0] += s.accel.x;
+            sum[1] += s.accel.y;
+            sum[2] += s.accel.z;
+            sum[3] += s.gyro.x;
+            sum[4] += s.gyro.y;
+            sum[5] += s.gyro.z;
+            newest = s.timestamp_us;
+        })?;
+        self.last_fifo_samples = n;
+
+        if n == 0 {
+            // Nothing new or the FIFO was just reset, the data registers still hold the latest sample
+            return self.read_burst();
+        }
+
+        let s = sum.map(|v| v / n as f32);
+        Ok(ImuSample {
+            accel: AccelerometerData { x: s[0], y: s[1], z: s[2] },
+            gyro: GyroscopeData { x: s[3], y: s[4], z: s[5] },
+            timestamp_us: newest,
+        })
+    }
+
+    fn reinit(&mut self) -> Result<(), Self::Error> {
+        self.init()
+    }
+}
+
+/// Big endian signed axis `i` from a block of three
+fn axis(buf: &[u8], i: usize) -> f32 {
+    i16::from_be_bytes([buf[2 * i], buf[2 * i + 1]]) as f32
+}
@@ -0,0 +1,184 @@
+//
+// esc.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 04 2022
+//
+
+use crate::hal::Motors;
+
+use icarus_core::{
+    mixer::NUM_MOTORS,
+    motor::{DShotSpeed, MotorProtocol},
+};
+
+/// Highest duty resolution of the LEDC timers
+pub const MAX_DUTY_BITS: u32 = 14;
+
+/// Pulse width modulated output timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PwmTiming {
+    pub frequency_hz: u32,
+    /// Pulse width at zero output (ns)
+    pub min_pulse_ns: u32,
+    /// Pulse width at full output (ns)
+    pub max_pulse_ns: u32,
+}
+
+impl PwmTiming {
+    /// Servo style pulses at 50 Hz
+    pub const STANDARD: PwmTiming = PwmTiming { frequency_hz: 50, min_pulse_ns: 1_000_000, max_pulse_ns: 2_000_000 };
+    pub const ONESHOT125: PwmTiming = PwmTiming { frequency_hz: 2000, min_pulse_ns: 125_000, max_pulse_ns: 250_000 };
+    pub const MULTISHOT: PwmTiming = PwmTiming { frequency_hz: 16_000, min_pulse_ns: 5_000, max_pulse_ns: 25_000 };
+
+    /// Duty cycle from 0 to a full period
+    pub fn brushed(frequency_hz: u32) -> Self {
+        let frequency_hz = frequency_hz.max(1);
+        Self { frequency_hz, min_pulse_ns: 0, max_pulse_ns: 1_000_000_000 / frequency_hz }
+    }
+
+    /// Period of one PWM cycle (ns)
+    pub fn period_ns(&self) -> u32 {
+        1_000_000_000 / self.frequency_hz.max(1)
+    }
+
+    /// Highest duty resolution a timer clocked at `source_hz` can run at this frequency
+    pub fn duty_bits(&self, source_hz: u32) -> u32 {
+        let divisions = source_hz / self.frequency_hz.max(1);
+        if divisions < 2 {
+            1
+        }
+        else {
+            (31 - divisions.leading_zeros()).min(MAX_DUTY_BITS)
+        }
+    }
+
+    /// Pulse width for an output in the range 0 - 1 (ns)
+    pub fn pulse_ns(&self, output: f32) -> u32 {
+        let span = (self.max_pulse_ns - self.min_pulse_ns) as f32;
+        self.min_pulse_ns + (output.clamp(0.0, 1.0) * span) as u32
+    }
+
+    /// Duty cycle for an output in the range 0 - 1, where `max_duty` is a full period
+    pub fn duty(&self, output: f32, max_duty: u32) -> u32 {
+        let duty = self.pulse_ns(output) as u64 * max_duty as u64 / self.period_ns() as u64;
+        (duty as u32).min(max_duty)
+    }
+}
+
+/// Output stage needed for a motor protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotorOutput {
+    Pwm(PwmTiming),
+    DShot(DShotTiming),
+}
+
+impl MotorOutput {
+    pub fn from_protocol(protocol: MotorProtocol) -> Self {
+        match protocol.clamped() {
+            MotorProtocol::Brushed { frequency_hz } => MotorOutput::Pwm(PwmTiming::brushed(frequency_hz)),
+            MotorProtocol::Standard => MotorOutput::Pwm(PwmTiming::STANDARD),
+            MotorProtocol::OneShot125 => MotorOutput::Pwm(PwmTiming::ONESHOT125),
+            MotorProtocol::Multishot => MotorOutput::Pwm(PwmTiming::MULTISHOT),
+            MotorProtocol::DShot(speed) => MotorOutput::DShot(DShotTiming::from_speed(speed)),
+        }
+    }
+}
+
+/// Throttle value that stops the motor
+pub const DSHOT_STOP: u16 = 0;
+/// Lowest throttle value. Values below are special commands
+pub const DSHOT_MIN_THROTTLE: u16 = 48;
+pub const DSHOT_MAX_THROTTLE: u16 = 2047;
+/// Bits in a DShot frame
+pub const DSHOT_FRAME_BITS: usize = 16;
+
+/// DShot throttle value for an output in the range 0 - 1. Zero output stops the motor
+pub fn dshot_throttle(output: f32) -> u16 {
+    if output <= 0.0 {
+        DSHOT_STOP
+    }
+    else {
+        let span = (DSHOT_MAX_THROTTLE - DSHOT_MIN_THROTTLE) as f32;
+        DSHOT_MIN_THROTTLE + (output.min(1.0) * span) as u16
+    }
+}
+
+/// Checksum of the upper 12 bits of a frame
+pub fn dshot_crc(packet: u16) -> u16 {
+    (packet ^ (packet >> 4) ^ (packet >> 8)) & 0x0F
+}
+
+/// 16 bit frame: 11 bit value, telemetry request bit and a 4 bit checksum. Sent MSB first
+pub fn dshot_frame(value: u16, telemetry: bool) -> u16 {
+    let packet = ((value & 0x07FF) << 1) | telemetry as u16;
+    (packet << 4) | dshot_crc(packet)
+}
+
+/// Bit timing for a DShot speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DShotTiming {
+    /// Length of one bit (ns)
+    pub bit_ns: u32,
+    /// High time of a zero (ns)
+    pub t0h_ns: u32,
+    /// High time of a one (ns)
+    pub t1h_ns: u32,
+}
+
+impl DShotTiming {
+    pub fn from_speed(speed: DShotSpeed) -> Self {
+        match speed {
+            DShotSpeed::DShot150 => Self { bit_ns: 6667, t0h_ns: 2500, t1h_ns: 5000 },
+            DShotSpeed::DShot300 => Self { bit_ns: 3333, t0h_ns: 1250, t1h_ns: 2500 },
+            DShotSpeed::DShot600 => Self { bit_ns: 1667, t0h_ns: 625, t1h_ns: 1250 },
+        }
+    }
+
+    /// High and low time of each bit in a frame (ns), in transmit order
+    pub fn pulses(&self, frame: u16) -> [(u32, u32); DSHOT_FRAME_BITS] {
+        let mut pulses = [(0, 0); DSHOT_FRAME_BITS];
+
+        for (i, pulse) in pulses.iter_mut().enumerate() {
+            let one = frame & (0x8000 >> i) != 0;
+            let high = if one { self.t1h_ns } else { self.t0h_ns };
+            *pulse = (high, self.bit_ns - high);
+        }
+
+        pulses
+    }
+}
+
+/// Sends a DShot frame to one motor
+pub trait DShotTx {
+    type Error;
+
+    fn send(&mut self, motor: usize, frame: u16) -> Result<(), Self::Error>;
+}
+
+/// Motor outputs as DShot frames
+pub struct DShotMotors<T: DShotTx> {
+    tx: T,
+}
+
+impl<T: DShotTx> DShotMotors<T> {
+    pub fn new(tx: T) -> Self {
+        Self { tx }
+    }
+
+    pub fn tx(&self) -> &T {
+        &self.tx
+    }
+}
+
+impl<T: DShotTx> Motors for DShotMotors<T> {
+    type Error = T::Error;
+
+    fn set(&mut self, outputs: &[f32; NUM_MOTORS]) -> Result<(), Self::Error> {
+        for (motor, output) in outputs.iter().enumerate() {
+            self.tx.send(motor, dshot_frame(dshot_throttle(*output), false))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//
+// hal.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 22 2022
+//
+
+use crate::led::Rgb;
+
+use icarus_core::{
+    data::{AccelerometerData, GyroscopeData},
+    mixer::NUM_MOTORS,
+};
+use icarus_wire::BarometerRaw;
+
+/// One accelerometer and gyroscope sample, both taken at the same instant
+#[derive(Debug, Clone, Copy)]
+pub struct ImuSample {
+    /// Acceleration in g, sensor frame
+    pub accel: AccelerometerData,
+    /// Angular rate in rad/s, sensor frame
+    pub gyro: GyroscopeData,
+    /// Time the sample was taken (us), same time base as the [`Clock`]
+    pub timestamp_us: u64,
+}
+
+/// Accelerometer and gyroscope
+pub trait Imu {
+    type Error;
+
+    /// Read the latest sample
+    fn read(&mut self) -> Result<ImuSample, Self::Error>;
+    /// Re-initialize the sensor after it has stopped responding
+    fn reinit(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Pressure altitude
+pub trait Barometer {
+    type Error;
+
+    fn read(&mut self) -> Result<BarometerRaw, Self::Error>;
+}
+
+/// Battery voltage sense and charger status
+pub trait BatterySense {
+    type Error;
+
+    /// Raw ADC reading of the divided battery voltage
+    fn read_raw(&mut self) -> Result<u16, Self::Error>;
+    /// True once the charger reports the battery is full
+    fn charge_complete(&mut self) -> bool;
+}
+
+/// Motor outputs
+pub trait Motors {
+    type Error;
+
+    /// Set each motor output in the range 0 - 1
+    fn set(&mut self, outputs: &[f32; NUM_MOTORS]) -> Result<(), Self::Error>;
+}
+
+/// Monotonic time source
+pub trait Clock {
+    /// Time since an arbitrary fixed point in microseconds
+    fn now_us(&self) -> u64;
+}
+
+/// Sensor data ready interrupt
+pub trait DataReady {
+    /// Time (us) of the latest interrupt since the last call, if one has fired
+    fn take(&mut self) -> Option<u64>;
+}
+
+/// Placeholder for sensors without an interrupt line. Samples are timestamped when they are read
+pub struct NoInterrupt;
+
+impl DataReady for NoInterrupt {
+    fn take(&mut self) -> Option<u64> {
+        None
+    }
+}
+
+/// Status LED
+pub trait Led {
+    type Error;
+
+    fn set(&mut self, color: Rgb) -> Result<(), Self::Error>;
+}
+
+/// Placeholder for boards without a barometer. Every read fails
+pub struct NoBarometer;
+
+impl Barometer for NoBarometer {
+    type Error = ();
+
+    fn read(&mut self) -> Result<BarometerRaw, Self::Error> {
+        Err(())
+    }
+}
+
+/// Placeholder for boards without battery sensing. Every read fails
+pub struct NoBattery;
+
+impl BatterySense for NoBattery {
+    type Error = ();
+
+    fn read_raw(&mut self) -> Result<u16, Self::Error> {
+        Err(())
+    }
+
+    fn charge_complete(&mut self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,218 @@
+//
+// led.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 01 2022
+//
+
+/// LED color at full brightness. Platforms apply their own brightness limit
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+    pub const RED: Rgb = Rgb::new(255, 0, 0);
+    pub const GREEN: Rgb = Rgb::new(0, 255, 0);
+    pub const BLUE: Rgb = Rgb::new(0, 0, 255);
+    pub const YELLOW: Rgb = Rgb::new(255, 255, 0);
+    pub const ORANGE: Rgb = Rgb::new(255, 96, 0);
+    pub const CYAN: Rgb = Rgb::new(0, 255, 255);
+    pub const PURPLE: Rgb = Rgb::new(160, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Fully saturated color at `hue` on a 0 - 255 color wheel
+    pub fn wheel(hue: u8) -> Self {
+        let sector = hue / 86;
+        let rise = (hue % 86) as u16 * 3;
+        let rise = rise.min(255) as u8;
+        let fall = 255 - rise;
+
+        match sector {
+            0 => Rgb::new(fall, rise, 0),
+            1 => Rgb::new(0, fall, rise),
+            _ => Rgb::new(rise, 0, fall),
+        }
+    }
+
+    /// Scale each channel by `level` / 255
+    pub fn scale(self, level: u8) -> Self {
+        let scale = |c: u8| ((c as u16 * level as u16) / 255) as u8;
+        Rgb::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+}
+
+/// Time varying LED output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Solid(Rgb),
+    /// On for the first half of the period
+    Blink { color: Rgb, period_ms: u32 },
+    /// Two short flashes each period
+    DoubleBlink { color: Rgb, period_ms: u32 },
+    /// Fade in and out
+    Breathe { color: Rgb, period_ms: u32 },
+    /// Cycle through the color wheel
+    Rainbow { period_ms: u32 },
+    /// Step through a list of colors
+    Sequence { colors: &'static [Rgb], step_ms: u32 },
+    /// `code` flashes followed by a pause
+    ErrorCode { color: Rgb, code: u8 },
+}
+
+/// Length of each error code flash and the gap after it
+const ERROR_FLASH_MS: u32 = 200;
+/// Pause between repeats of an error code
+const ERROR_PAUSE_MS: u32 = 1000;
+
+impl Pattern {
+    /// Color `elapsed_ms` after the pattern started
+    pub fn color_at(&self, elapsed_ms: u32) -> Rgb {
+        match *self {
+            Pattern::Solid(color) => color,
+            Pattern::Blink { color, period_ms } => {
+                let period_ms = period_ms.max(1);
+                if elapsed_ms % period_ms < period_ms / 2 { color } else { Rgb::BLACK }
+            }
+            Pattern::DoubleBlink { color, period_ms } => {
+                // Flashes in the first and third of eight slots
+                let slot = (elapsed_ms % period_ms.max(8)) * 8 / period_ms.max(8);
+                if slot == 0 || slot == 2 { color } else { Rgb::BLACK }
+            }
+            Pattern::Breathe { color, period_ms } => {
+                let period_ms = period_ms.max(2);
+                let phase = elapsed_ms % period_ms;
+                let half = period_ms / 2;
+                let level = if phase < half { phase * 255 / half } else { (period_ms - phase) * 255 / half };
+                color.scale(level.min(255) as u8)
+            }
+            Pattern::Rainbow { period_ms } => {
+                let period_ms = period_ms.max(1);
+                Rgb::wheel(((elapsed_ms % period_ms) * 256 / period_ms) as u8)
+            }
+            Pattern::Sequence { colors, step_ms } => {
+                if colors.is_empty() {
+                    Rgb::BLACK
+                }
+                else {
+                    colors[(elapsed_ms / step_ms.max(1)) as usize % colors.len()]
+                }
+            }
+            Pattern::ErrorCode { color, code } => {
+                let flashes = code as u32 * 2 * ERROR_FLASH_MS;
+                let phase = elapsed_ms % (flashes + ERROR_PAUSE_MS);
+                if phase < flashes && (phase / ERROR_FLASH_MS) & 1 == 0 { color } else { Rgb::BLACK }
+            }
+        }
+    }
+}
+
+/// Active status sources. Combined by the platform from the flight task and its own state
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Indications(pub u16);
+
+impl Indications {
+    /// Firmware is starting up
+    pub const BOOT: Indications = Indications(1 << 0);
+    /// Host link is up
+    pub const LINK: Indications = Indications(1 << 1);
+    /// Motors are armed
+    pub const ARMED: Indications = Indications(1 << 2);
+    /// IMU calibration in progress
+    pub const CALIBRATING: Indications = Indications(1 << 3);
+    /// Battery warning
+    pub const LOW_BATTERY: Indications = Indications(1 << 4);
+    /// Forced landing or disarm, e.g. on a critical battery
+    pub const FAILSAFE: Indications = Indications(1 << 5);
+    /// IMU fault. Error code 1
+    pub const SENSOR_FAULT: Indications = Indications(1 << 6);
+    /// Parameters could not be loaded or saved. Error code 2
+    pub const PARAMETER_FAULT: Indications = Indications(1 << 7);
+
+    pub const fn empty() -> Self {
+        Indications(0)
+    }
+
+    pub const fn contains(&self, other: Indications) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn set(&mut self, other: Indications, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+impl core::ops::BitOr for Indications {
+    type Output = Indications;
+
+    fn bitor(self, rhs: Indications) -> Indications {
+        Indications(self.0 | rhs.0)
+    }
+}
+
+const BOOT_COLORS: [Rgb; 3] = [Rgb::RED, Rgb::GREEN, Rgb::BLUE];
+
+/// Indications with their patterns, highest priority first
+const PRIORITIES: [(Indications, Pattern); 8] = [
+    (Indications::SENSOR_FAULT, Pattern::ErrorCode { color: Rgb::RED, code: 1 }),
+    (Indications::PARAMETER_FAULT, Pattern::ErrorCode { color: Rgb::RED, code: 2 }),
+    (Indications::FAILSAFE, Pattern::Blink { color: Rgb::RED, period_ms: 200 }),
+    (Indications::LOW_BATTERY, Pattern::DoubleBlink { color: Rgb::ORANGE, period_ms: 1000 }),
+    (Indications::CALIBRATING, Pattern::Breathe { color: Rgb::BLUE, period_ms: 1000 }),
+    (Indications::ARMED, Pattern::Solid(Rgb::GREEN)),
+    (Indications::BOOT, Pattern::Sequence { colors: &BOOT_COLORS, step_ms: 150 }),
+    (Indications::LINK, Pattern::Blink { color: Rgb::GREEN, period_ms: 2000 }),
+];
+
+/// Shown when nothing else is active: waiting for the host
+const IDLE_PATTERN: Pattern = Pattern::Blink { color: Rgb::RED, period_ms: 600 };
+
+/// Picks the pattern for the highest priority active indication and renders it
+///
+/// Patterns restart from the beginning when the selected indication changes.
+pub struct LedEngine {
+    pattern: Pattern,
+    start_ms: u64,
+    started: bool,
+}
+
+impl Default for LedEngine {
+    fn default() -> Self {
+        Self { pattern: IDLE_PATTERN, start_ms: 0, started: false }
+    }
+}
+
+impl LedEngine {
+    /// Pattern shown for a set of indications
+    pub fn pattern(indications: Indications) -> Pattern {
+        PRIORITIES
+            .iter()
+            .find(|(indication, _)| indications.contains(*indication))
+            .map(|(_, pattern)| *pattern)
+            .unwrap_or(IDLE_PATTERN)
+    }
+
+    /// LED color at `now_ms`
+    pub fn update(&mut self, indications: Indications, now_ms: u64) -> Rgb {
+        let pattern = Self::pattern(indications);
+
+        if !self.started || pattern != self.pattern {
+            self.pattern = pattern;
+            self.start_ms = now_ms;
+            self.started = true;
+        }
+
+        self.pattern.color_at(now_ms.saturating_sub(self.start_ms) as u32)
+    }
+}
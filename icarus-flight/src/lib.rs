@@ -0,0 +1,17 @@
+//
+// lib.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 22 2022
+//
+#![no_std]
+pub mod bus;
+pub mod drivers;
+pub mod esc;
+pub mod hal;
+pub mod led;
+pub mod scheduler;
+pub mod task;
+pub mod telemetry;
+
+pub use task::{CommandError, FlightTask};
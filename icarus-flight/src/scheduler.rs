@@ -0,0 +1,160 @@
+//
+// scheduler.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 26 2022
+//
+
+use icarus_wire::{LoopStats, TimingStats};
+
+/// Timed sections of the control loop
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    Sensors,
+    Estimation,
+    Control,
+    Output,
+}
+
+const NUM_STAGES: usize = 4;
+
+/// Accumulates a duration for a report window
+#[derive(Debug, Default, Clone, Copy)]
+struct TimingAccumulator {
+    sum: u64,
+    max: u64,
+    count: u32,
+}
+
+impl TimingAccumulator {
+    fn add(&mut self, us: u64) {
+        self.sum += us;
+        self.max = self.max.max(us);
+        self.count += 1;
+    }
+
+    fn stats(&self) -> TimingStats {
+        let mean = if self.count > 0 { self.sum / self.count as u64 } else { 0 };
+
+        TimingStats {
+            mean_us: mean.min(u32::MAX as u64) as u32,
+            max_us: self.max.min(u32::MAX as u64) as u32,
+        }
+    }
+}
+
+/// Deadline based fixed rate scheduler
+///
+/// Iterations are due every period measured from the first iteration rather than from the end of the previous one, so
+/// the rate does not drift with the time spent in the loop. If the loop falls more than a whole period behind the
+/// missed iterations are skipped instead of being run back to back.
+pub struct Scheduler {
+    rate_hz: f32,
+    period_us: u64,
+    next_deadline: Option<u64>,
+    iteration_start: u64,
+    stage_start: u64,
+
+    window_start: Option<u64>,
+    iterations: u32,
+    overruns: u32,
+    busy_us: u64,
+    jitter: TimingAccumulator,
+    stages: [TimingAccumulator; NUM_STAGES],
+}
+
+impl Scheduler {
+    pub fn new(rate_hz: f32) -> Self {
+        Self {
+            rate_hz,
+            period_us: (1e6 / rate_hz) as u64,
+            next_deadline: None,
+            iteration_start: 0,
+            stage_start: 0,
+            window_start: None,
+            iterations: 0,
+            overruns: 0,
+            busy_us: 0,
+            jitter: TimingAccumulator::default(),
+            stages: [TimingAccumulator::default(); NUM_STAGES],
+        }
+    }
+
+    pub fn rate_hz(&self) -> f32 {
+        self.rate_hz
+    }
+
+    /// Nominal period (s)
+    pub fn period(&self) -> f32 {
+        self.period_us as f32 * 1e-6
+    }
+
+    /// Time until the next iteration is due (us). Zero if it is due now
+    pub fn time_until_next(&self, now: u64) -> u64 {
+        self.next_deadline.map(|deadline| deadline.saturating_sub(now)).unwrap_or(0)
+    }
+
+    /// Start an iteration
+    pub fn begin(&mut self, now: u64) {
+        let deadline = self.next_deadline.unwrap_or(now);
+        let lateness = now.saturating_sub(deadline);
+
+        self.jitter.add(lateness);
+
+        let skipped = lateness / self.period_us;
+        self.next_deadline = Some(deadline + (skipped + 1) * self.period_us);
+
+        self.window_start.get_or_insert(now);
+        self.iteration_start = now;
+        self.stage_start = now;
+    }
+
+    /// Mark the end of a stage. The stage started at the end of the previous one or at the start of the iteration
+    pub fn end_stage(&mut self, stage: Stage, now: u64) {
+        self.stages[stage as usize].add(now.saturating_sub(self.stage_start));
+        self.stage_start = now;
+    }
+
+    /// Finish an iteration
+    pub fn end(&mut self, now: u64) {
+        self.iterations += 1;
+        self.busy_us += now.saturating_sub(self.iteration_start);
+
+        if self.next_deadline.map(|deadline| now > deadline).unwrap_or(false) {
+            self.overruns += 1;
+        }
+    }
+
+    /// Statistics since the last report. Starts a new report window
+    pub fn report(&mut self, now: u64) -> LoopStats {
+        let elapsed = self.window_start.map(|start| now.saturating_sub(start)).unwrap_or(0);
+        let seconds = elapsed as f32 * 1e-6;
+
+        let (measured_rate, load) = if elapsed > 0 {
+            (self.iterations as f32 / seconds, self.busy_us as f32 / elapsed as f32)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let stats = LoopStats {
+            rate: self.rate_hz as u16,
+            measured_rate,
+            overruns: self.overruns,
+            jitter: self.jitter.stats(),
+            sensors: self.stages[Stage::Sensors as usize].stats(),
+            estimation: self.stages[Stage::Estimation as usize].stats(),
+            control: self.stages[Stage::Control as usize].stats(),
+            output: self.stages[Stage::Output as usize].stats(),
+            load,
+        };
+
+        self.window_start = Some(now);
+        self.iterations = 0;
+        self.overruns = 0;
+        self.busy_us = 0;
+        self.jitter = TimingAccumulator::default();
+        self.stages = [TimingAccumulator::default(); NUM_STAGES];
+
+        stats
+    }
+}
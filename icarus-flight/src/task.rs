@@ -0,0 +1,831 @@
+//
+// task.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 22 2022
+//
+
+use crate::{
+    hal::{Barometer, BatterySense, Clock, Imu, ImuSample, Motors},
+    led::Indications,
+    scheduler::{Scheduler, Stage},
+    telemetry::Telemetry,
+};
+
+use icarus_core::{
+    arming::{Arming, ArmingBlockers},
+    battery::{BatteryConfig, BatteryMonitor, BatteryStatus},
+    button::ButtonAction,
+    calibration::{CalibrationKind, CalibrationStatus, Calibrator, ImuCalibration},
+    control::{AttitudeController, AttitudeSetpoint, ControllerConfig},
+    health::{HealthConfig, SensorHealthMonitor},
+    mixer::{Mixer, NUM_MOTORS},
+    mode::{FlightMode, ModeConfig, ModeError, ModeManager, ModeTarget, PilotInput},
+    params::Parameters,
+    EstimatedState, EstimatorConfig, EstimatorInput, StateEstimator,
+};
+use icarus_wire::{
+    BatteryState, CommandRequest, IcarusCommand, IcarusState, MessageCounts, MotorTest, NackReason, TelemetryChannel,
+    ThrustStep,
+};
+
+/// Minimum time between attempts to re-initialize a lost IMU
+const IMU_REINIT_BACKOFF_US: u64 = 500_000;
+/// Rate the calibrator is fed at. Sample counts in the calibration config are tuned for this rate
+const CALIBRATION_RATE_HZ: f32 = 50.0;
+/// Rate the battery is sampled at
+const BATTERY_RATE_HZ: f32 = 10.0;
+/// Rate thrust is reduced at during a low battery landing (1/s)
+const LAND_THRUST_RAMP: f32 = 0.1;
+/// Highest output allowed during a motor test
+const MAX_MOTOR_TEST_OUTPUT: f32 = 0.5;
+/// Longest a motor test or thrust step can run for
+const MAX_MOTOR_TEST_DURATION_US: u64 = 5_000_000;
+/// A repeat of the last request id within this window is a retry and is not run again
+const RETRY_WINDOW_US: u64 = 2_000_000;
+
+/// Reasons a command was rejected
+#[derive(Debug, Clone, Copy)]
+pub enum CommandError {
+    /// Not allowed while armed
+    Armed,
+    /// Pre-arm checks failed
+    ArmingBlocked(ArmingBlockers),
+    /// Not allowed while the IMU is being calibrated
+    Calibrating,
+    /// Motor tests require the propellers to be removed
+    PropsOn,
+    /// No such motor
+    InvalidMotor,
+    /// Setpoint does not match the current flight mode
+    WrongMode,
+    /// Altitude hold needs a valid barometer
+    NoAltitude,
+    /// Altitude hold can only be entered while flying
+    NotArmed,
+    /// Thrust steps must confirm the rotor is on a thrust stand
+    NoThrustStand,
+    /// Duty is above the bench limit
+    DutyLimit,
+}
+
+impl From<CommandError> for NackReason {
+    fn from(e: CommandError) -> Self {
+        match e {
+            CommandError::Armed => NackReason::Armed,
+            CommandError::ArmingBlocked(blockers) => NackReason::ArmingBlocked(blockers),
+            CommandError::Calibrating => NackReason::Calibrating,
+            CommandError::PropsOn => NackReason::PropsOn,
+            CommandError::InvalidMotor => NackReason::InvalidMotor,
+            CommandError::WrongMode => NackReason::WrongMode,
+            CommandError::NoAltitude => NackReason::NoAltitude,
+            CommandError::NotArmed => NackReason::NotArmed,
+            CommandError::NoThrustStand => NackReason::NoThrustStand,
+            CommandError::DutyLimit => NackReason::DutyLimit,
+        }
+    }
+}
+
+/// Outcome of the last tagged command, replayed to retries
+#[derive(Debug, Clone, Copy)]
+struct LastRequest {
+    id: u16,
+    received_us: u64,
+    result: Result<(), NackReason>,
+}
+
+/// Motor test or thrust step in progress
+#[derive(Debug, Clone, Copy)]
+struct ActiveMotorTest {
+    motor: usize,
+    output: f32,
+    end_us: u64,
+}
+
+/// Passes one in every `factor` iterations
+struct Decimator {
+    factor: u32,
+    count: u32,
+}
+
+impl Decimator {
+    fn new(factor: u32) -> Self {
+        Self { factor, count: 0 }
+    }
+
+    fn tick(&mut self) -> bool {
+        let pass = self.count == 0;
+        self.count = if self.count + 1 >= self.factor { 0 } else { self.count + 1 };
+        pass
+    }
+}
+
+/// State estimator sampled at the loop rate
+fn new_estimator(rate_hz: f32, params: &Parameters) -> StateEstimator {
+    let mut estimator = StateEstimator::new(EstimatorConfig { sample_period: 1.0 / rate_hz, ..Default::default() });
+    estimator.set_alignment(params.board_alignment);
+    estimator
+}
+
+/// Platform independent control loop
+///
+/// Runs at `Parameters::loop_rate`. Call `update` whenever `time_until_next_us` reaches zero.
+///
+/// 1. Get sensor input
+/// 2. Pass sensor data to the state estimator
+/// 3. Use estimated state in PID control loop
+/// 4. 'Mix' motor output
+///
+/// Setpoints must match the flight mode: `Attitude` in angle mode and altitude hold, `Rate` in rate mode. `Throttle`
+/// is an attitude setpoint in degrees and percent. `Sticks` are accepted in any mode. A positive thrust arms the
+/// vehicle if the pre-arm checks pass, zero thrust disarms it outside of altitude hold.
+///
+/// Altitude hold is entered from flight and needs the barometer. Losing the barometer or disarming falls back to
+/// angle mode.
+///
+/// A low battery limits thrust to a slowly decreasing ceiling until the vehicle lands and disarms. A critical battery
+/// disarms immediately.
+///
+/// A single rotor can be spun for a bounded time while disarmed with the propellers removed. Arming ends the test.
+///
+/// Periodic telemetry is sent at the rates the host subscribed to. Battery status changes are reported immediately
+/// unless the battery channel is off.
+pub struct FlightTask<I, B, P, M, C> {
+    imu: I,
+    barometer: B,
+    battery: P,
+    motors: M,
+    clock: C,
+
+    params: Parameters,
+    calibration: ImuCalibration,
+    calibrator: Calibrator,
+    /// The calibration running is the one started on boot
+    boot_calibration: bool,
+    /// Parameters were changed by the task itself and need saving
+    params_changed: bool,
+    last_calibration_status: Option<CalibrationStatus>,
+    estimator: StateEstimator,
+    estimated_state: EstimatedState,
+    health: SensorHealthMonitor,
+    battery_monitor: BatteryMonitor,
+    /// Thrust ceiling while landing on a low battery
+    land_thrust: Option<f32>,
+    arming: Arming,
+    controller: AttitudeController,
+    mixer: Mixer,
+    modes: ModeManager,
+    outputs: [f32; NUM_MOTORS],
+    motor_test: Option<ActiveMotorTest>,
+
+    scheduler: Scheduler,
+    telemetry: Telemetry,
+    calibration_decimation: Decimator,
+    battery_decimation: Decimator,
+    last_update: Option<u64>,
+    last_sample_us: Option<u64>,
+    last_reinit_attempt: u64,
+    last_request: Option<LastRequest>,
+}
+
+impl<I, B, P, M, C> FlightTask<I, B, P, M, C>
+where
+    I: Imu,
+    B: Barometer,
+    P: BatterySense,
+    M: Motors,
+    C: Clock,
+{
+    /// Create the flight task
+    ///
+    /// A level calibration is started immediately, same as on boot. If a calibration is stored in the parameters it is
+    /// used as is and the boot calibration only refreshes the gyro bias.
+    pub fn new(imu: I, barometer: B, battery: P, motors: M, clock: C, params: Parameters) -> Self {
+        let rate_hz = params.loop_rate.clamp(Parameters::MIN_LOOP_RATE, Parameters::MAX_LOOP_RATE) as f32;
+        let decimation = |rate: f32| Decimator::new(libm::roundf(rate_hz / rate).max(1.0) as u32);
+
+        let mut calibrator = Calibrator::default();
+        calibrator.start(CalibrationKind::Level);
+
+        let health_config = HealthConfig::default();
+        let health = SensorHealthMonitor::new(HealthConfig {
+            expected_rate: rate_hz,
+            // Keep the stuck detection window the same length in time
+            stuck_samples: (health_config.stuck_samples as f32 * rate_hz / health_config.expected_rate) as u16,
+            ..health_config
+        });
+
+        let battery_config = BatteryConfig { divider: params.battery_divider, ..Default::default() };
+
+        let now = clock.now_us();
+
+        Self {
+            imu,
+            barometer,
+            battery,
+            motors,
+            clock,
+            params,
+            calibration: params.imu_calibration.unwrap_or_default(),
+            calibrator,
+            boot_calibration: true,
+            params_changed: false,
+            last_calibration_status: None,
+            estimator: new_estimator(rate_hz, &params),
+            estimated_state: EstimatedState::default(),
+            health,
+            battery_monitor: BatteryMonitor::new(battery_config, BATTERY_RATE_HZ),
+            land_thrust: None,
+            arming: Arming::default(),
+            controller: AttitudeController::new(ControllerConfig::default(), rate_hz),
+            mixer: Mixer::default(),
+            modes: ModeManager::new(ModeConfig { limits: params.mode_limits, ..Default::default() }, rate_hz),
+            outputs: [0.0; NUM_MOTORS],
+            motor_test: None,
+            scheduler: Scheduler::new(rate_hz),
+            telemetry: Telemetry::new(rate_hz, now),
+            calibration_decimation: decimation(CALIBRATION_RATE_HZ),
+            battery_decimation: decimation(BATTERY_RATE_HZ),
+            last_update: None,
+            last_sample_us: None,
+            last_reinit_attempt: now,
+            last_request: None,
+        }
+    }
+
+    pub fn imu_mut(&mut self) -> &mut I {
+        &mut self.imu
+    }
+
+    pub fn barometer_mut(&mut self) -> &mut B {
+        &mut self.barometer
+    }
+
+    pub fn battery_mut(&mut self) -> &mut P {
+        &mut self.battery
+    }
+
+    pub fn motors(&self) -> &M {
+        &self.motors
+    }
+
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    /// Time until the next iteration is due (us)
+    pub fn time_until_next_us(&self) -> u64 {
+        self.scheduler.time_until_next(self.clock.now_us())
+    }
+
+    /// Loop rate (Hz)
+    pub fn rate_hz(&self) -> f32 {
+        self.scheduler.rate_hz()
+    }
+
+    pub fn params(&self) -> &Parameters {
+        &self.params
+    }
+
+    pub fn battery(&self) -> &BatteryMonitor {
+        &self.battery_monitor
+    }
+
+    pub fn arming(&self) -> &Arming {
+        &self.arming
+    }
+
+    pub fn health(&self) -> &SensorHealthMonitor {
+        &self.health
+    }
+
+    pub fn is_calibrating(&self) -> bool {
+        self.calibrator.is_active()
+    }
+
+    /// Telemetry rates and dropped message counts
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    /// Count telemetry the platform dropped because the link could not keep up. Reported on the stats channel
+    pub fn record_dropped(&mut self, dropped: &MessageCounts) {
+        self.telemetry.record_dropped(dropped);
+    }
+
+    /// Count command frames the platform dropped because they failed the integrity check. Reported on the stats channel
+    pub fn record_corrupt_frames(&mut self, n: u32) {
+        self.telemetry.record_corrupt_frames(n);
+    }
+
+    /// Status to show on the LED. The platform adds boot and link state
+    pub fn indications(&self) -> Indications {
+        let mut indications = Indications::empty();
+        let battery = self.battery_monitor.status();
+
+        indications.set(Indications::ARMED, self.arming.is_armed());
+        indications.set(Indications::CALIBRATING, self.calibrator.is_active());
+        indications.set(Indications::LOW_BATTERY, battery >= BatteryStatus::Warning);
+        indications.set(Indications::FAILSAFE, battery >= BatteryStatus::Critical || self.land_thrust.is_some());
+        indications.set(Indications::SENSOR_FAULT, !self.health.is_healthy());
+
+        indications
+    }
+
+    /// Latest output of the state estimator
+    pub fn estimated_state(&self) -> &EstimatedState {
+        &self.estimated_state
+    }
+
+    /// Latest motor outputs (0 - 1)
+    pub fn outputs(&self) -> &[f32; NUM_MOTORS] {
+        &self.outputs
+    }
+
+    /// Apply a tagged command from the host and answer it through `emit`
+    ///
+    /// A retry of the last request is answered again without running the command twice. Returns the result of the
+    /// command when it was run, `None` for a retry.
+    pub fn handle_request<F: FnMut(IcarusState)>(
+        &mut self,
+        request: CommandRequest,
+        mut emit: F,
+    ) -> Option<Result<(), CommandError>> {
+        let now = self.clock.now_us();
+
+        let (result, outcome) = match self.last_request {
+            Some(last) if last.id == request.id && now.saturating_sub(last.received_us) < RETRY_WINDOW_US => {
+                (last.result, None)
+            }
+            _ => {
+                let outcome = self.handle_command(request.command);
+                (outcome.map_err(NackReason::from), Some(outcome))
+            }
+        };
+
+        self.last_request = Some(LastRequest { id: request.id, received_us: now, result });
+
+        emit(match result {
+            Ok(()) => IcarusState::Ack(request.id),
+            Err(reason) => IcarusState::Nack(request.id, reason),
+        });
+
+        outcome
+    }
+
+    /// Apply a command from the host
+    ///
+    /// Calibration and parameter changes are rejected while armed. The caller is responsible for persisting
+    /// parameters after a successful `SetParameter` and for restarting after a successful `Reboot`.
+    pub fn handle_command(&mut self, cmd: IcarusCommand) -> Result<(), CommandError> {
+        match cmd {
+            IcarusCommand::Throttle(roll, pitch, thrust) => {
+                let setpoint = AttitudeSetpoint {
+                    roll: (roll as f32).to_radians(),
+                    pitch: (pitch as f32).to_radians(),
+                    yaw_rate: 0.0,
+                    thrust: thrust as f32 / 100.0,
+                };
+                self.set_attitude(setpoint)?;
+            }
+            IcarusCommand::Attitude(setpoint) => self.set_attitude(setpoint)?,
+            IcarusCommand::Rate(setpoint) => {
+                if self.modes.mode() != FlightMode::Rate {
+                    return Err(CommandError::WrongMode);
+                }
+
+                self.set_input(PilotInput::from_rate(setpoint, self.modes.limits()))?;
+            }
+            IcarusCommand::Sticks(input) => self.set_input(input)?,
+            IcarusCommand::Subscribe(channel, rate) => self.telemetry.subscribe(channel, rate, self.clock.now_us()),
+            IcarusCommand::SetMode(mode) => self.set_mode(mode)?,
+            IcarusCommand::Reboot => self.ensure_disarmed()?,
+            IcarusCommand::MotorStop => self.stop_motors(),
+            IcarusCommand::Calibrate(kind) => {
+                self.ensure_disarmed()?;
+                self.calibrator.start(kind);
+                self.boot_calibration = false;
+            }
+            IcarusCommand::SetParameter(param) => {
+                self.ensure_disarmed()?;
+                self.params.set(param);
+                self.apply_params();
+            }
+            IcarusCommand::MotorTest(test) => self.start_motor_test(test)?,
+            IcarusCommand::ThrustStep(step) => self.start_thrust_step(step)?,
+        }
+
+        Ok(())
+    }
+
+    /// Current flight mode
+    pub fn mode(&self) -> FlightMode {
+        self.modes.mode()
+    }
+
+    /// Flight mode manager
+    pub fn modes(&self) -> &ModeManager {
+        &self.modes
+    }
+
+    fn set_attitude(&mut self, setpoint: AttitudeSetpoint) -> Result<(), CommandError> {
+        if self.modes.mode() == FlightMode::Rate {
+            return Err(CommandError::WrongMode);
+        }
+
+        self.set_input(PilotInput::from_attitude(setpoint, self.modes.limits()))
+    }
+
+    fn set_input(&mut self, input: PilotInput) -> Result<(), CommandError> {
+        self.modes.set_input(input);
+
+        // Low thrust is a descent in altitude hold, not a disarm
+        if self.modes.mode() == FlightMode::AltHold {
+            return Ok(());
+        }
+
+        self.update_arming(self.modes.input().thrust)
+    }
+
+    /// A positive thrust arms, zero thrust disarms
+    fn update_arming(&mut self, thrust: f32) -> Result<(), CommandError> {
+        if thrust <= 0.0 {
+            self.arming.disarm();
+        }
+        else if !self.arming.is_armed() {
+            self.arming.arm().map_err(CommandError::ArmingBlocked)?;
+            self.motor_test = None;
+        }
+
+        Ok(())
+    }
+
+    /// Switch flight mode. The collective thrust carries over, the sticks start centered
+    fn set_mode(&mut self, mode: FlightMode) -> Result<(), CommandError> {
+        if mode == FlightMode::AltHold && !self.arming.is_armed() {
+            return Err(CommandError::NotArmed);
+        }
+
+        self.modes.set_mode(mode).map_err(|e| match e {
+            ModeError::NoAltitude => CommandError::NoAltitude,
+        })
+    }
+
+    /// Disarm, clear the setpoints and end any motor test
+    fn stop_motors(&mut self) {
+        self.arming.disarm();
+        self.modes.reset();
+        self.motor_test = None;
+    }
+
+    /// True while a motor test is spinning a rotor
+    pub fn is_motor_test_active(&self) -> bool {
+        self.motor_test.is_some()
+    }
+
+    fn start_motor_test(&mut self, test: MotorTest) -> Result<(), CommandError> {
+        if !test.props_off {
+            return Err(CommandError::PropsOn);
+        }
+
+        let output = (test.duty as f32 / 100.0).min(MAX_MOTOR_TEST_OUTPUT);
+        self.start_test(test.motor, output, test.duration_ms)
+    }
+
+    /// Thrust steps run with the propeller on, so they must be confirmed and stay within the bench limit
+    fn start_thrust_step(&mut self, step: ThrustStep) -> Result<(), CommandError> {
+        if !step.thrust_stand {
+            return Err(CommandError::NoThrustStand);
+        }
+        if step.duty > self.params.bench_max_duty {
+            return Err(CommandError::DutyLimit);
+        }
+
+        let output = step.duty as f32 / 100.0;
+        self.start_test(step.motor, output, step.duration_ms)
+    }
+
+    fn start_test(&mut self, motor: u8, output: f32, duration_ms: u16) -> Result<(), CommandError> {
+        self.ensure_disarmed()?;
+
+        // Vibration would spoil the calibration
+        if self.calibrator.is_active() {
+            return Err(CommandError::Calibrating);
+        }
+        if motor as usize >= NUM_MOTORS {
+            return Err(CommandError::InvalidMotor);
+        }
+
+        let duration_us = (duration_ms as u64 * 1000).min(MAX_MOTOR_TEST_DURATION_US);
+
+        self.motor_test = if duration_us > 0 && output > 0.0 {
+            Some(ActiveMotorTest { motor: motor as usize, output, end_us: self.clock.now_us() + duration_us })
+        }
+        else {
+            None
+        };
+
+        Ok(())
+    }
+
+    /// Apply a user button action
+    ///
+    /// Network actions are left to the platform. The caller is responsible for persisting parameters after a
+    /// successful `FactoryReset`.
+    pub fn handle_action(&mut self, action: ButtonAction) -> Result<(), CommandError> {
+        match action {
+            ButtonAction::Calibrate => self.handle_command(IcarusCommand::Calibrate(CalibrationKind::Level))?,
+            ButtonAction::EmergencyDisarm => self.stop_motors(),
+            ButtonAction::FactoryReset => {
+                self.ensure_disarmed()?;
+                self.params = Parameters::default();
+                self.apply_params();
+            }
+            ButtonAction::ToggleAccessPoint | ButtonAction::None => {}
+        }
+
+        Ok(())
+    }
+
+    /// Run one iteration of the control loop. Telemetry for the host is passed to `emit`
+    pub fn update<F: FnMut(IcarusState)>(&mut self, mut emit: F) {
+        let now = self.clock.now_us();
+        self.scheduler.begin(now);
+
+        // Health checks use the measured sample period. Estimation and control use the nominal period so jitter does
+        // not leak into the filters
+        let measured_dt = match self.last_update {
+            Some(last) => now.saturating_sub(last) as f32 * 1e-6,
+            None => self.scheduler.period(),
+        };
+        let delta_time = self.scheduler.period();
+        self.last_update = Some(now);
+
+        let send_sensors = self.telemetry.due(TelemetryChannel::Sensors, now);
+        let send_state = self.telemetry.due(TelemetryChannel::State, now);
+        let feed_calibrator = self.calibration_decimation.tick();
+        let sample_battery = self.battery_decimation.tick();
+
+        // Read IMU data
+        let sample = self.imu.read();
+
+        // Barometer is optional
+        let altitude = self.barometer.read().ok().map(|baro| baro.altitude);
+        self.modes.update_altitude(altitude, delta_time);
+
+        self.scheduler.end_stage(Stage::Sensors, self.clock.now_us());
+
+        let mut body_gyro = None;
+
+        match sample {
+            Ok(ImuSample { accel, gyro, timestamp_us }) => {
+                // Time between the samples themselves, falls back to the loop period when timestamps are not usable
+                let measured_dt = match self.last_sample_us {
+                    Some(last) if timestamp_us > last => (timestamp_us - last) as f32 * 1e-6,
+                    _ => measured_dt,
+                };
+                self.last_sample_us = Some(timestamp_us);
+
+                // Range and stuck checks are done against the raw readings
+                self.health.record_sample(accel, gyro, measured_dt);
+
+                if self.calibrator.is_active() {
+                    if feed_calibrator {
+                        self.calibrator.update(accel, gyro);
+                    }
+
+                    if let Some(result) = self.calibrator.take_result() {
+                        self.apply_calibration(result);
+                        self.estimator = new_estimator(self.rate_hz(), &self.params);
+                    }
+
+                    // Report progress in 10% steps and on every stage change
+                    let status = self.calibrator.status();
+                    let changed = self.last_calibration_status
+                        .map(|last| {
+                            last.stage != status.stage
+                                || last.faces != status.faces
+                                || last.progress / 10 != status.progress / 10
+                        })
+                        .unwrap_or(true);
+
+                    if changed {
+                        self.last_calibration_status = Some(status);
+                        emit(IcarusState::Calibration(status));
+                    }
+                }
+                else {
+                    let input = EstimatorInput {
+                        accel: self.calibration.apply_accel(accel),
+                        gyro: self.calibration.apply_gyro(gyro),
+                        altitude: altitude.unwrap_or(0.0),
+                    };
+
+                    if send_sensors {
+                        emit(IcarusState::Sensors(input));
+                    }
+
+                    // Don't feed garbage into the estimator
+                    if self.health.is_healthy() {
+                        if let Ok(estimated_state) = self.estimator.update(input, delta_time) {
+                            self.estimated_state = estimated_state;
+
+                            if send_state {
+                                emit(IcarusState::EstimatedState(estimated_state));
+                            }
+                        }
+
+                        // Body frame with motor vibration notched out, so it doesn't feed back through the motors
+                        body_gyro = Some(self.estimator.body_gyro());
+                    }
+                }
+            }
+            Err(_) => {
+                // Both come from the same transaction
+                self.health.record_imu_failure();
+            }
+        }
+
+        // Check health before attempting recovery so a lost sensor always disarms
+        self.arming.update_health(self.health.flags());
+        self.arming.set_calibrating(self.calibrator.is_active());
+
+        // Attempt to recover the IMU after too many failed reads
+        if self.health.needs_reinit() && now.saturating_sub(self.last_reinit_attempt) >= IMU_REINIT_BACKOFF_US {
+            self.last_reinit_attempt = now;
+
+            if self.imu.reinit().is_ok() {
+                self.health.record_reinit();
+                self.last_sample_us = None;
+            }
+        }
+
+        if sample_battery {
+            self.update_battery(&mut emit);
+        }
+
+        self.scheduler.end_stage(Stage::Estimation, self.clock.now_us());
+
+        // Ramp thrust down while landing on a low battery. Disarm once it reaches zero
+        if let Some(limit) = self.land_thrust {
+            let limit = (limit.min(self.modes.thrust()) - LAND_THRUST_RAMP * delta_time).max(0.0);
+            self.land_thrust = Some(limit);
+
+            if limit <= 0.0 {
+                self.arming.disarm();
+            }
+        }
+
+        // Hold the last output if this iteration had no usable gyro sample. A critical fault disarms above
+        self.outputs = match (self.arming.is_armed(), body_gyro) {
+            (true, Some(gyro)) => {
+                let target = self.modes.target(delta_time);
+                let target = self.land_thrust.map(|limit| target.limit_thrust(limit)).unwrap_or(target);
+
+                let output = match target {
+                    ModeTarget::Rate(setpoint) => self.controller.update_rate(setpoint, gyro, delta_time),
+                    ModeTarget::Attitude(setpoint) => {
+                        self.controller.update(setpoint, self.estimated_state.attitude, gyro, delta_time)
+                    }
+                };
+                self.params.motor_curve.apply(&self.mixer.mix(output), self.battery_voltage())
+            }
+            (true, None) => self.outputs,
+            (false, _) => {
+                self.controller.reset();
+
+                if self.modes.mode() == FlightMode::AltHold {
+                    self.modes.reset();
+                }
+
+                // Motor test stops at its deadline
+                self.motor_test = self.motor_test.filter(|test| now < test.end_us);
+
+                let mut outputs = [0.0; NUM_MOTORS];
+                if let Some(test) = self.motor_test {
+                    outputs[test.motor] = test.output;
+                }
+                outputs
+            }
+        };
+
+        self.scheduler.end_stage(Stage::Control, self.clock.now_us());
+
+        self.motors.set(&self.outputs).ok();
+
+        let now = self.clock.now_us();
+        self.scheduler.end_stage(Stage::Output, now);
+        self.scheduler.end(now);
+
+        if self.telemetry.due(TelemetryChannel::Health, now) {
+            emit(IcarusState::Health(self.health.report()));
+        }
+        if self.telemetry.due(TelemetryChannel::LoopStats, now) {
+            emit(IcarusState::LoopStats(self.scheduler.report(now)));
+        }
+        // Boards without battery sensing never produce a reading
+        if self.telemetry.due(TelemetryChannel::Battery, now) && self.battery_monitor.adc_raw() != 0 {
+            emit(IcarusState::Battery(self.battery_state()));
+        }
+        if self.telemetry.due(TelemetryChannel::Stats, now) {
+            emit(IcarusState::TelemetryStats(self.telemetry.stats()));
+        }
+    }
+
+    /// Sample the battery and act on its status. Reported immediately when the status changes
+    fn update_battery<F: FnMut(IcarusState)>(&mut self, emit: &mut F) {
+        let adc_raw = match self.battery.read_raw() {
+            Ok(adc_raw) => adc_raw,
+            Err(_) => return,
+        };
+        let charge_complete = self.battery.charge_complete();
+
+        // Mean motor output approximates the current draw
+        let load = self.outputs.iter().sum::<f32>() / NUM_MOTORS as f32;
+        let armed = self.arming.is_armed();
+
+        let previous = self.battery_monitor.status();
+        let status = self.battery_monitor.update(adc_raw, load, charge_complete, armed);
+
+        self.arming.update_battery(status);
+
+        match status {
+            BatteryStatus::Critical => {
+                self.arming.disarm();
+                self.motor_test = None;
+            }
+            BatteryStatus::Land if armed && self.land_thrust.is_none() => self.land_thrust = Some(self.modes.thrust()),
+            _ => {}
+        }
+
+        if !self.arming.is_armed() {
+            self.land_thrust = None;
+        }
+
+        if status != previous && self.telemetry.is_enabled(TelemetryChannel::Battery) {
+            emit(IcarusState::Battery(self.battery_state()));
+        }
+    }
+
+    /// Measured battery voltage, if the board has battery sensing
+    fn battery_voltage(&self) -> Option<f32> {
+        if self.battery_monitor.adc_raw() != 0 { Some(self.battery_monitor.voltage()) } else { None }
+    }
+
+    fn battery_state(&self) -> BatteryState {
+        let millivolts = |v: f32| (v * 1000.0) as u16;
+
+        BatteryState {
+            voltage: millivolts(self.battery_monitor.voltage()),
+            adc_raw: self.battery_monitor.adc_raw(),
+            charge_complete: self.battery_monitor.charge_complete(),
+            compensated_voltage: millivolts(self.battery_monitor.compensated_voltage()),
+            sag: millivolts(self.battery_monitor.sag()),
+            state_of_charge: self.battery_monitor.state_of_charge(),
+            status: self.battery_monitor.status(),
+        }
+    }
+
+    /// Use a calibration result. Requested calibrations are stored, the boot calibration keeps a stored accelerometer
+    /// calibration and only takes the gyro bias, which drifts between power cycles
+    fn apply_calibration(&mut self, result: ImuCalibration) {
+        match (self.boot_calibration, self.params.imu_calibration) {
+            (true, Some(stored)) => self.calibration = ImuCalibration { gyro_bias: result.gyro_bias, ..stored },
+            (true, None) => self.calibration = result,
+            (false, _) => {
+                self.calibration = result;
+                self.params.imu_calibration = Some(result);
+                self.params_changed = true;
+            }
+        }
+
+        self.boot_calibration = false;
+    }
+
+    /// Calibration applied to the IMU readings
+    pub fn calibration(&self) -> &ImuCalibration {
+        &self.calibration
+    }
+
+    /// True once after the task changed the parameters itself, such as storing a calibration. The caller is
+    /// responsible for persisting them
+    pub fn take_params_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.params_changed, false)
+    }
+
+    /// Apply parameters that can change at runtime
+    fn apply_params(&mut self) {
+        self.estimator.set_alignment(self.params.board_alignment);
+        self.battery_monitor.set_divider(self.params.battery_divider);
+        self.modes.set_limits(self.params.mode_limits);
+    }
+
+    fn ensure_disarmed(&self) -> Result<(), CommandError> {
+        if self.arming.is_armed() {
+            Err(CommandError::Armed)
+        }
+        else {
+            Ok(())
+        }
+    }
+}
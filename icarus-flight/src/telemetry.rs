@@ -0,0 +1,103 @@
+//
+// telemetry.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_wire::{MessageCounts, TelemetryChannel, TelemetryStats};
+
+/// Rate each channel starts at (Hz)
+pub fn default_rate(channel: TelemetryChannel) -> u16 {
+    match channel {
+        TelemetryChannel::Sensors | TelemetryChannel::State => 50,
+        TelemetryChannel::Battery
+        | TelemetryChannel::Health
+        | TelemetryChannel::LoopStats
+        | TelemetryChannel::Stats => 1,
+    }
+}
+
+/// Per channel telemetry rates
+///
+/// Each channel is sent at most once per its period, measured from the first time it was due so the average rate holds
+/// when the period is not a multiple of the loop period. Subscriptions last until reboot.
+pub struct Telemetry {
+    max_rate: u16,
+    rates: [u16; TelemetryChannel::COUNT],
+    next_due: [u64; TelemetryChannel::COUNT],
+    dropped: MessageCounts,
+    corrupt_frames: u32,
+}
+
+impl Telemetry {
+    /// Channels start at their default rates and are first due one period after `now_us`
+    pub fn new(loop_rate: f32, now_us: u64) -> Self {
+        let max_rate = loop_rate as u16;
+
+        let mut telemetry = Self {
+            max_rate,
+            rates: [0; TelemetryChannel::COUNT],
+            next_due: [0; TelemetryChannel::COUNT],
+            dropped: MessageCounts::default(),
+            corrupt_frames: 0,
+        };
+
+        for channel in TelemetryChannel::ALL {
+            let rate = default_rate(channel).min(max_rate);
+            telemetry.rates[channel.index()] = rate;
+            telemetry.next_due[channel.index()] = now_us + period_us(rate);
+        }
+
+        telemetry
+    }
+
+    /// Change the rate of a channel. Zero turns it off. The first message at the new rate is due immediately
+    pub fn subscribe(&mut self, channel: TelemetryChannel, rate: u16, now_us: u64) {
+        self.rates[channel.index()] = rate.min(self.max_rate);
+        self.next_due[channel.index()] = now_us;
+    }
+
+    /// Channel rate (Hz)
+    pub fn rate(&self, channel: TelemetryChannel) -> u16 {
+        self.rates[channel.index()]
+    }
+
+    pub fn is_enabled(&self, channel: TelemetryChannel) -> bool {
+        self.rate(channel) > 0
+    }
+
+    /// True if a message on `channel` should be sent now
+    pub fn due(&mut self, channel: TelemetryChannel, now_us: u64) -> bool {
+        let rate = self.rate(channel);
+        let next_due = &mut self.next_due[channel.index()];
+
+        if rate == 0 || now_us < *next_due {
+            return false;
+        }
+
+        // Skip missed periods rather than sending them back to back
+        let period = period_us(rate);
+        *next_due = if now_us - *next_due >= period { now_us + period } else { *next_due + period };
+
+        true
+    }
+
+    /// Count messages the platform could not deliver
+    pub fn record_dropped(&mut self, dropped: &MessageCounts) {
+        self.dropped.add(dropped);
+    }
+
+    /// Count command frames the platform received corrupted
+    pub fn record_corrupt_frames(&mut self, n: u32) {
+        self.corrupt_frames = self.corrupt_frames.wrapping_add(n);
+    }
+
+    pub fn stats(&self) -> TelemetryStats {
+        TelemetryStats { rates: self.rates, dropped: self.dropped, corrupt_frames: self.corrupt_frames }
+    }
+}
+
+fn period_us(rate: u16) -> u64 {
+    if rate > 0 { 1_000_000 / rate as u64 } else { 0 }
+}
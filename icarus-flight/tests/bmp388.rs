@@ -0,0 +1,56 @@
+//
+// bmp388.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 29 2022
+//
+
+use icarus_flight::{
+    drivers::bmp388::{pressure_to_altitude, Bmp388, Bmp388Error, SEA_LEVEL_PRESSURE},
+    hal::Barometer,
+};
+
+use embedded_hal::blocking::i2c::WriteRead;
+
+use std::{cell::Cell, rc::Rc};
+
+/// Answers every read with zeros except the chip id
+#[derive(Clone)]
+struct FakeBus {
+    id: u8,
+    id_reads: Rc<Cell<usize>>,
+}
+
+impl WriteRead for FakeBus {
+    type Error = ();
+
+    fn write_read(&mut self, _: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+        buffer.iter_mut().for_each(|b| *b = 0);
+        if bytes[0] == 0x00 {
+            self.id_reads.set(self.id_reads.get() + 1);
+            buffer[0] = self.id;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn pressure_altitude() {
+    assert!(pressure_to_altitude(SEA_LEVEL_PRESSURE, SEA_LEVEL_PRESSURE).abs() < 1e-3);
+
+    // Standard atmosphere at 1000 m
+    let altitude = pressure_to_altitude(89_874.6, SEA_LEVEL_PRESSURE);
+    assert!((altitude - 1000.0).abs() < 1.0, "{}", altitude);
+}
+
+#[test]
+fn wrong_device_retried_on_next_read() {
+    let id_reads = Rc::new(Cell::new(0));
+    let mut baro = Bmp388::new(FakeBus { id: 0x60, id_reads: id_reads.clone() });
+
+    assert!(matches!(baro.read(), Err(Bmp388Error::WrongDevice(0x60))));
+    let first = id_reads.get();
+
+    assert!(baro.read().is_err());
+    assert!(id_reads.get() > first);
+}
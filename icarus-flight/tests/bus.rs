@@ -0,0 +1,180 @@
+//
+// bus.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 28 2022
+//
+
+use icarus_flight::bus::{recover_bus, I2cBus, I2cLines, ManagedI2c, NullMutex, RECOVERY_CLOCKS};
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use std::{cell::Cell, rc::Rc};
+
+/// A device that holds SDA low until it has seen `stuck_clocks` SCL pulses
+struct FakeLines {
+    stuck_clocks: Rc<Cell<usize>>,
+    scl: bool,
+    sda: bool,
+    acquired: bool,
+    clocks: usize,
+    /// SDA rose while SCL was high
+    stops: usize,
+    releases: usize,
+}
+
+impl FakeLines {
+    /// Idle bus, both lines pulled up
+    fn new(stuck_clocks: Rc<Cell<usize>>) -> Self {
+        Self { stuck_clocks, scl: true, sda: true, acquired: false, clocks: 0, stops: 0, releases: 0 }
+    }
+}
+
+impl I2cLines for FakeLines {
+    fn acquire(&mut self) {
+        self.acquired = true;
+    }
+
+    fn release(&mut self) {
+        self.acquired = false;
+        self.releases += 1;
+    }
+
+    fn set_scl(&mut self, high: bool) {
+        assert!(self.acquired);
+        if high && !self.scl {
+            self.clocks += 1;
+            let stuck = self.stuck_clocks.get();
+            self.stuck_clocks.set(stuck.saturating_sub(1));
+        }
+        self.scl = high;
+    }
+
+    fn set_sda(&mut self, high: bool) {
+        assert!(self.acquired);
+        if high && !self.sda && self.scl {
+            self.stops += 1;
+        }
+        self.sda = high;
+    }
+
+    fn sda_is_high(&self) -> bool {
+        self.stuck_clocks.get() == 0
+    }
+
+    fn delay(&mut self) {}
+}
+
+#[derive(Debug, PartialEq)]
+struct BusError;
+
+/// Every transaction fails while `failing` or while a device is holding SDA
+struct FakeI2c {
+    stuck_clocks: Rc<Cell<usize>>,
+    failing: Rc<Cell<bool>>,
+}
+
+impl FakeI2c {
+    fn result(&self) -> Result<(), BusError> {
+        if self.failing.get() || self.stuck_clocks.get() > 0 { Err(BusError) } else { Ok(()) }
+    }
+}
+
+impl Write for FakeI2c {
+    type Error = BusError;
+
+    fn write(&mut self, _: u8, _: &[u8]) -> Result<(), BusError> {
+        self.result()
+    }
+}
+
+impl WriteRead for FakeI2c {
+    type Error = BusError;
+
+    fn write_read(&mut self, _: u8, _: &[u8], _: &mut [u8]) -> Result<(), BusError> {
+        self.result()
+    }
+}
+
+type Bus = I2cBus<NullMutex<ManagedI2c<FakeI2c, FakeLines>>>;
+
+fn new_bus() -> (Bus, Rc<Cell<usize>>, Rc<Cell<bool>>) {
+    let stuck = Rc::new(Cell::new(0));
+    let failing = Rc::new(Cell::new(false));
+
+    let i2c = FakeI2c { stuck_clocks: stuck.clone(), failing: failing.clone() };
+    let lines = FakeLines::new(stuck.clone());
+
+    (I2cBus::new(i2c, lines), stuck, failing)
+}
+
+#[test]
+fn recovery_clocks_until_sda_released() {
+    let stuck = Rc::new(Cell::new(4));
+    let mut lines = FakeLines::new(stuck.clone());
+
+    assert!(recover_bus(&mut lines));
+    // Four pulses free the device, one more for the STOP
+    assert_eq!(lines.clocks, 4 + 1);
+    assert_eq!(lines.stops, 1);
+    assert_eq!(lines.releases, 1);
+}
+
+#[test]
+fn recovery_gives_up_after_nine_clocks() {
+    let stuck = Rc::new(Cell::new(100));
+    let mut lines = FakeLines::new(stuck);
+
+    assert!(!recover_bus(&mut lines));
+    assert_eq!(lines.clocks, RECOVERY_CLOCKS + 1);
+    assert_eq!(lines.releases, 1);
+}
+
+#[test]
+fn transactions_counted_per_device() {
+    let (bus, _, failing) = new_bus();
+    let mut imu = bus.acquire();
+    let mut baro = bus.acquire();
+
+    imu.write(0x68, &[0]).unwrap();
+    imu.write_read(0x68, &[0], &mut [0]).unwrap();
+    failing.set(true);
+    assert_eq!(baro.write(0x76, &[0]), Err(BusError));
+
+    let stats = bus.stats();
+    assert_eq!(stats.transactions, 3);
+    assert_eq!(stats.errors, 1);
+    assert_eq!(stats.device(0x68).unwrap().transactions, 2);
+    assert_eq!(stats.device(0x68).unwrap().errors, 0);
+    assert_eq!(stats.device(0x76).unwrap().errors, 1);
+
+    // SDA was not held, a NACK does not need recovery
+    assert_eq!(stats.recoveries, 0);
+}
+
+#[test]
+fn stuck_bus_recovered_after_failure() {
+    let (bus, stuck, _) = new_bus();
+    let mut imu = bus.acquire();
+
+    stuck.set(3);
+    assert_eq!(imu.write(0x68, &[0]), Err(BusError));
+    assert_eq!(bus.stats().recoveries, 1);
+
+    // The next transaction goes through
+    imu.write(0x68, &[0]).unwrap();
+    assert_eq!(bus.stats().device(0x68).unwrap().errors, 1);
+}
+
+#[test]
+fn failed_recovery_is_counted() {
+    let (bus, stuck, _) = new_bus();
+    let mut imu = bus.acquire();
+
+    stuck.set(100);
+    assert!(imu.write(0x68, &[0]).is_err());
+
+    let stats = bus.stats();
+    assert_eq!(stats.recoveries, 0);
+    assert_eq!(stats.failed_recoveries, 1);
+}
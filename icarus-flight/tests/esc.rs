@@ -0,0 +1,126 @@
+//
+// esc.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 04 2022
+//
+
+use icarus_flight::{
+    esc::{self, DShotMotors, DShotTiming, DShotTx, MotorOutput, PwmTiming},
+    hal::Motors,
+};
+use icarus_core::motor::{DShotSpeed, MotorProtocol};
+
+/// APB clock feeding the LEDC timers
+const APB_HZ: u32 = 80_000_000;
+
+fn pwm(protocol: MotorProtocol) -> PwmTiming {
+    match MotorOutput::from_protocol(protocol) {
+        MotorOutput::Pwm(timing) => timing,
+        MotorOutput::DShot(_) => panic!("{:?} is not a PWM protocol", protocol),
+    }
+}
+
+#[test]
+fn brushed_duty_spans_the_full_period() {
+    let timing = pwm(MotorProtocol::Brushed { frequency_hz: 20_000 });
+    assert_eq!(timing.duty_bits(APB_HZ), 11);
+
+    let max_duty = (1 << 11) - 1;
+    assert_eq!(timing.duty(0.0, max_duty), 0);
+    assert_eq!(timing.duty(0.5, max_duty), max_duty / 2);
+    assert_eq!(timing.duty(1.0, max_duty), max_duty);
+    assert_eq!(timing.duty(2.0, max_duty), max_duty);
+
+    // Out of range frequencies are clamped
+    let timing = pwm(MotorProtocol::Brushed { frequency_hz: 1 });
+    assert_eq!(timing.frequency_hz, MotorProtocol::MIN_BRUSHED_FREQUENCY);
+}
+
+#[test]
+fn esc_pulse_widths() {
+    let standard = pwm(MotorProtocol::Standard);
+    assert_eq!(standard.pulse_ns(0.0), 1_000_000);
+    assert_eq!(standard.pulse_ns(0.5), 1_500_000);
+    assert_eq!(standard.pulse_ns(1.0), 2_000_000);
+    assert_eq!(standard.duty_bits(APB_HZ), esc::MAX_DUTY_BITS);
+
+    // 1 ms of a 20 ms period
+    let max_duty = (1 << esc::MAX_DUTY_BITS) - 1;
+    assert_eq!(standard.duty(0.0, max_duty), max_duty / 20);
+
+    let oneshot = pwm(MotorProtocol::OneShot125);
+    assert_eq!(oneshot.pulse_ns(0.0), 125_000);
+    assert_eq!(oneshot.pulse_ns(1.0), 250_000);
+    assert!(oneshot.max_pulse_ns < oneshot.period_ns());
+
+    let multishot = pwm(MotorProtocol::Multishot);
+    assert_eq!(multishot.pulse_ns(0.0), 5_000);
+    assert_eq!(multishot.pulse_ns(1.0), 25_000);
+    assert!(multishot.max_pulse_ns < multishot.period_ns());
+
+    assert_eq!(
+        MotorOutput::from_protocol(MotorProtocol::DShot(DShotSpeed::DShot300)),
+        MotorOutput::DShot(DShotTiming::from_speed(DShotSpeed::DShot300))
+    );
+}
+
+#[test]
+fn dshot_frames() {
+    assert_eq!(esc::dshot_throttle(0.0), esc::DSHOT_STOP);
+    assert_eq!(esc::dshot_throttle(-1.0), esc::DSHOT_STOP);
+    assert_eq!(esc::dshot_throttle(0.0001), esc::DSHOT_MIN_THROTTLE);
+    assert_eq!(esc::dshot_throttle(1.0), esc::DSHOT_MAX_THROTTLE);
+    assert_eq!(esc::dshot_throttle(5.0), esc::DSHOT_MAX_THROTTLE);
+
+    // Reference frame for throttle 1046
+    assert_eq!(esc::dshot_frame(1046, false), 0b1000_0010_1100_0110);
+    assert_eq!(esc::dshot_frame(0, false), 0);
+
+    // The checksum covers the telemetry bit
+    let frame = esc::dshot_frame(1046, true);
+    assert_eq!(frame >> 4, (1046 << 1) | 1);
+    assert_eq!(frame & 0x0F, esc::dshot_crc(frame >> 4));
+}
+
+#[test]
+fn dshot_bit_timing() {
+    let timing = DShotTiming::from_speed(DShotSpeed::DShot600);
+    let pulses = timing.pulses(0b1000_0000_0000_0001);
+
+    assert_eq!(pulses[0], (1250, 417));
+    assert_eq!(pulses[1], (625, 1042));
+    assert_eq!(pulses[15], (1250, 417));
+
+    for speed in [DShotSpeed::DShot150, DShotSpeed::DShot300, DShotSpeed::DShot600] {
+        let timing = DShotTiming::from_speed(speed);
+        assert!(timing.pulses(0xFFFF).iter().chain(timing.pulses(0).iter()).all(|(h, l)| h + l == timing.bit_ns));
+    }
+}
+
+#[derive(Default)]
+struct FakeTx {
+    frames: Vec<(usize, u16)>,
+}
+
+impl DShotTx for FakeTx {
+    type Error = ();
+
+    fn send(&mut self, motor: usize, frame: u16) -> Result<(), Self::Error> {
+        self.frames.push((motor, frame));
+        Ok(())
+    }
+}
+
+#[test]
+fn dshot_motors_send_a_frame_per_motor() {
+    let mut motors = DShotMotors::new(FakeTx::default());
+    motors.set(&[0.0, 1.0, 0.5, 0.0]).unwrap();
+
+    let frames = &motors.tx().frames;
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[0], (0, esc::dshot_frame(0, false)));
+    assert_eq!(frames[1], (1, esc::dshot_frame(2047, false)));
+    assert_eq!(frames[2], (2, esc::dshot_frame(esc::dshot_throttle(0.5), false)));
+    assert_eq!(frames[3].0, 3);
+}
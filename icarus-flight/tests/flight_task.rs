@@ -0,0 +1,771 @@
+//
+// flight_task.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 22 2022
+//
+
+use icarus_flight::{
+    hal::{Barometer, BatterySense, Clock, Imu, ImuSample, Motors},
+    led::Indications,
+    CommandError, FlightTask,
+};
+use icarus_core::{
+    battery::{BatteryConfig, BatteryStatus},
+    button::ButtonAction,
+    calibration::{CalibrationKind, ImuCalibration},
+    control::{AttitudeSetpoint, RateSetpoint},
+    data::{AccelerometerData, GyroscopeData},
+    health::HealthFlags,
+    mixer::NUM_MOTORS,
+    mode::FlightMode,
+    motor::MotorCurve,
+    params::{Parameter, Parameters},
+};
+use icarus_wire::{
+    BarometerRaw, CommandRequest, IcarusCommand, IcarusState, MessageCounts, MotorTest, NackReason, TelemetryChannel,
+    ThrustStep,
+};
+
+use std::f32::consts::PI;
+
+const RATE_HZ: u16 = 50;
+const PERIOD_US: u64 = 20_000;
+
+/// Level, stationary IMU with a little dither so it never looks stuck
+#[derive(Default)]
+struct FakeImu {
+    count: u32,
+    /// Time stamped onto samples, follows the task clock
+    now_us: u64,
+    failing: bool,
+    /// Re-initialization fails as well
+    lost: bool,
+    reinits: u32,
+    /// Motor vibration on the gyro x axis as (frequency Hz, amplitude rad/s)
+    tone: Option<(f32, f32)>,
+}
+
+impl FakeImu {
+    fn dither(&self) -> f32 {
+        if self.count & 1 == 0 { 0.001 } else { -0.001 }
+    }
+}
+
+impl Imu for FakeImu {
+    type Error = ();
+
+    fn read(&mut self) -> Result<ImuSample, ()> {
+        if self.failing {
+            return Err(());
+        }
+
+        self.count += 1;
+        let dither = self.dither();
+        let tone = self.tone
+            .map(|(freq, amplitude)| amplitude * (2.0 * PI * freq * self.now_us as f32 * 1e-6).sin())
+            .unwrap_or(0.0);
+
+        Ok(ImuSample {
+            accel: AccelerometerData { x: dither, y: -dither, z: 1.0 + dither },
+            gyro: GyroscopeData { x: dither + tone, y: dither, z: -dither },
+            timestamp_us: self.now_us,
+        })
+    }
+
+    fn reinit(&mut self) -> Result<(), ()> {
+        self.reinits += 1;
+        if self.lost {
+            return Err(());
+        }
+        self.failing = false;
+        Ok(())
+    }
+}
+
+/// Battery that sags under load. Not fitted by default
+#[derive(Default)]
+struct FakeBattery {
+    /// Resting voltage
+    voltage: Option<f32>,
+    /// Mean motor output, follows the task outputs
+    load: f32,
+}
+
+/// Voltage drop at full load
+const BATTERY_SAG: f32 = 0.4;
+
+impl BatterySense for FakeBattery {
+    type Error = ();
+
+    fn read_raw(&mut self) -> Result<u16, ()> {
+        let config = BatteryConfig::default();
+        self.voltage
+            .map(|v| v - BATTERY_SAG * self.load)
+            .map(|v| (v / config.divider / config.adc_full_scale * config.adc_max as f32) as u16)
+            .ok_or(())
+    }
+
+    fn charge_complete(&mut self) -> bool {
+        false
+    }
+}
+
+/// Barometer at a fixed altitude. Not fitted by default
+#[derive(Default)]
+struct FakeBarometer {
+    altitude: Option<f32>,
+}
+
+impl Barometer for FakeBarometer {
+    type Error = ();
+
+    fn read(&mut self) -> Result<BarometerRaw, ()> {
+        self.altitude.map(|altitude| BarometerRaw { altitude, temp: 20.0 }).ok_or(())
+    }
+}
+
+#[derive(Default)]
+struct FakeMotors {
+    outputs: [f32; NUM_MOTORS],
+}
+
+impl Motors for FakeMotors {
+    type Error = ();
+
+    fn set(&mut self, outputs: &[f32; NUM_MOTORS]) -> Result<(), ()> {
+        self.outputs = *outputs;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FakeClock {
+    now_us: u64,
+}
+
+impl Clock for FakeClock {
+    fn now_us(&self) -> u64 {
+        self.now_us
+    }
+}
+
+type Task = FlightTask<FakeImu, FakeBarometer, FakeBattery, FakeMotors, FakeClock>;
+
+fn new_task_at(loop_rate: u16) -> Task {
+    let params = Parameters { loop_rate, ..Default::default() };
+    FlightTask::new(
+        FakeImu::default(),
+        FakeBarometer::default(),
+        FakeBattery::default(),
+        FakeMotors::default(),
+        FakeClock::default(),
+        params,
+    )
+}
+
+fn new_task() -> Task {
+    new_task_at(RATE_HZ)
+}
+
+/// Step the task `n` times, collecting telemetry
+fn run(task: &mut Task, n: usize) -> Vec<IcarusState> {
+    let mut telemetry = Vec::new();
+
+    for _ in 0..n {
+        task.clock_mut().now_us += task.time_until_next_us();
+        task.imu_mut().now_us = task.clock_mut().now_us;
+        task.update(|state| telemetry.push(state));
+        task.battery_mut().load = task.motors().outputs.iter().sum::<f32>() / NUM_MOTORS as f32;
+    }
+
+    telemetry
+}
+
+fn run_until_calibrated(task: &mut Task) {
+    for _ in 0..10_000 {
+        if !task.is_calibrating() {
+            return;
+        }
+        run(task, 1);
+    }
+
+    panic!("Boot calibration did not finish");
+}
+
+#[test]
+fn boot_calibration_then_estimation() {
+    let mut task = new_task();
+    assert!(task.is_calibrating());
+
+    let telemetry = run(&mut task, 10);
+    assert!(telemetry.iter().any(|s| matches!(s, IcarusState::Calibration(_))));
+    assert!(!telemetry.iter().any(|s| matches!(s, IcarusState::Sensors(_))));
+
+    run_until_calibrated(&mut task);
+
+    let telemetry = run(&mut task, 10);
+    assert!(telemetry.iter().any(|s| matches!(s, IcarusState::Sensors(_))));
+    assert!(telemetry.iter().any(|s| matches!(s, IcarusState::EstimatedState(_))));
+}
+
+#[test]
+fn arming_blocked_while_calibrating() {
+    let mut task = new_task();
+    run(&mut task, 1);
+
+    let result = task.handle_command(IcarusCommand::Throttle(0, 0, 50));
+    assert!(matches!(result, Err(CommandError::ArmingBlocked(_))));
+    assert!(!task.arming().is_armed());
+}
+
+#[test]
+fn throttle_arms_and_drives_motors() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    assert!(task.arming().is_armed());
+
+    run(&mut task, 5);
+    for output in task.motors().outputs.iter() {
+        assert!((output - 0.5).abs() < 0.05, "{:?}", task.motors().outputs);
+    }
+
+    // Zero thrust disarms and stops the motors
+    task.handle_command(IcarusCommand::Throttle(0, 0, 0)).unwrap();
+    run(&mut task, 1);
+    assert!(!task.arming().is_armed());
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+}
+
+#[test]
+fn configuration_rejected_while_armed() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+
+    let result = task.handle_command(IcarusCommand::Calibrate(CalibrationKind::Level));
+    assert!(matches!(result, Err(CommandError::Armed)));
+    assert!(!task.is_calibrating());
+}
+
+#[test]
+fn lost_imu_disarms_and_recovers() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    run(&mut task, 5);
+
+    task.imu_mut().failing = true;
+    run(&mut task, 10);
+    assert!(!task.arming().is_armed());
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+
+    // Re-initialization is attempted after the back off
+    run(&mut task, 30);
+    assert!(task.imu_mut().reinits >= 1);
+    assert!(!task.imu_mut().failing);
+}
+
+#[test]
+fn imu_is_lost_after_the_configured_failed_reads() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    let max_failures = task.health().config().max_consecutive_failures;
+    task.imu_mut().failing = true;
+    task.imu_mut().lost = true;
+
+    run(&mut task, max_failures as usize - 1);
+    assert!(!task.health().flags().contains(HealthFlags::SENSOR_LOST));
+    assert_eq!(task.health().consecutive_failures(), max_failures - 1);
+
+    run(&mut task, 1);
+    assert!(task.health().flags().contains(HealthFlags::SENSOR_LOST));
+}
+
+#[test]
+fn health_reported_every_second() {
+    let mut task = new_task();
+
+    // First iteration runs at t = 0
+    let telemetry = run(&mut task, 151);
+    let reports = telemetry.iter().filter(|s| matches!(s, IcarusState::Health(_))).count();
+    assert_eq!(reports, 3);
+
+    let stats = telemetry
+        .iter()
+        .filter_map(|s| if let IcarusState::LoopStats(stats) = s { Some(*stats) } else { None })
+        .collect::<Vec<_>>();
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats[1].rate, RATE_HZ);
+    assert!((stats[1].measured_rate - RATE_HZ as f32).abs() < 1.0, "{:?}", stats[1]);
+    assert_eq!(stats[1].overruns, 0);
+}
+
+#[test]
+fn telemetry_decimated_at_high_loop_rates() {
+    let mut task = new_task_at(500);
+    assert_eq!(task.time_until_next_us(), 0);
+
+    run_until_calibrated(&mut task);
+
+    // One second at 500 Hz
+    let telemetry = run(&mut task, 500);
+    let sensors = telemetry.iter().filter(|s| matches!(s, IcarusState::Sensors(_))).count();
+    assert_eq!(sensors, 50);
+
+    // Next iteration is one period away
+    assert_eq!(task.time_until_next_us(), 2000);
+}
+
+#[test]
+fn boot_calibration_takes_the_same_time_at_any_rate() {
+    let mut slow = new_task_at(50);
+    let mut fast = new_task_at(500);
+
+    run_until_calibrated(&mut slow);
+    run_until_calibrated(&mut fast);
+
+    let slow_time = slow.clock_mut().now_us;
+    let fast_time = fast.clock_mut().now_us;
+    assert!(slow_time.abs_diff(fast_time) <= PERIOD_US, "{} {}", slow_time, fast_time);
+}
+
+#[test]
+fn battery_reported_with_health() {
+    let mut task = new_task();
+    task.battery_mut().voltage = Some(3.9);
+
+    let telemetry = run(&mut task, 101);
+    let battery = telemetry
+        .iter()
+        .filter_map(|s| if let IcarusState::Battery(battery) = s { Some(*battery) } else { None })
+        .collect::<Vec<_>>();
+
+    assert_eq!(battery.len(), 2);
+    assert!((battery[1].voltage as i32 - 3900).abs() < 20, "{:?}", battery[1]);
+    assert_eq!(battery[1].status, BatteryStatus::Ok);
+}
+
+#[test]
+fn low_battery_lands_then_disarms() {
+    let mut task = new_task();
+    task.battery_mut().voltage = Some(3.9);
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    run(&mut task, 5);
+
+    task.battery_mut().voltage = Some(3.35);
+    let telemetry = run(&mut task, 5 * RATE_HZ as usize);
+    assert!(telemetry.iter().any(|s| matches!(s, IcarusState::Battery(b) if b.status == BatteryStatus::Land)));
+
+    // Thrust is being reduced but the vehicle is still flying
+    assert!(task.arming().is_armed());
+    let thrust = task.motors().outputs.iter().sum::<f32>() / NUM_MOTORS as f32;
+    assert!(thrust < 0.45, "{:?}", task.motors().outputs);
+
+    run(&mut task, 10 * RATE_HZ as usize);
+    assert!(!task.arming().is_armed());
+
+    // Can't take off again
+    assert!(matches!(task.handle_command(IcarusCommand::Throttle(0, 0, 50)), Err(CommandError::ArmingBlocked(_))));
+}
+
+#[test]
+fn critical_battery_disarms() {
+    let mut task = new_task();
+    task.battery_mut().voltage = Some(3.9);
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    run(&mut task, 5);
+
+    task.battery_mut().voltage = Some(3.0);
+    run(&mut task, 4 * RATE_HZ as usize);
+    assert_eq!(task.battery().status(), BatteryStatus::Critical);
+    assert!(!task.arming().is_armed());
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+}
+
+#[test]
+fn indications_follow_state() {
+    let mut task = new_task();
+    run(&mut task, 1);
+    assert!(task.indications().contains(Indications::CALIBRATING));
+
+    run_until_calibrated(&mut task);
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    run(&mut task, 1);
+    assert_eq!(task.indications(), Indications::ARMED);
+
+    task.imu_mut().failing = true;
+    task.imu_mut().lost = true;
+    run(&mut task, 10);
+    assert!(task.indications().contains(Indications::SENSOR_FAULT));
+    assert!(!task.indications().contains(Indications::ARMED));
+}
+
+#[test]
+fn button_actions() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    run(&mut task, 5);
+
+    // Factory reset and calibration wait until disarmed
+    assert!(matches!(task.handle_action(ButtonAction::FactoryReset), Err(CommandError::Armed)));
+    assert!(matches!(task.handle_action(ButtonAction::Calibrate), Err(CommandError::Armed)));
+
+    task.handle_action(ButtonAction::EmergencyDisarm).unwrap();
+    run(&mut task, 1);
+    assert!(!task.arming().is_armed());
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+
+    task.handle_action(ButtonAction::FactoryReset).unwrap();
+    assert_eq!(task.params().loop_rate, Parameters::default().loop_rate);
+
+    task.handle_action(ButtonAction::Calibrate).unwrap();
+    assert!(task.is_calibrating());
+}
+
+fn motor_test(motor: u8, duty: u8, duration_ms: u16, props_off: bool) -> IcarusCommand {
+    IcarusCommand::MotorTest(MotorTest { motor, duty, duration_ms, props_off })
+}
+
+#[test]
+fn motor_test_spins_one_rotor_for_bounded_time() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    task.handle_command(motor_test(2, 20, 500, true)).unwrap();
+    run(&mut task, 5);
+    assert_eq!(task.motors().outputs, [0.0, 0.0, 0.2, 0.0]);
+    assert!(!task.arming().is_armed());
+
+    // Stops on its own after 500 ms
+    run(&mut task, 25);
+    assert!(!task.is_motor_test_active());
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+
+    // Output and duration are limited
+    task.handle_command(motor_test(0, 100, u16::MAX, true)).unwrap();
+    run(&mut task, 1);
+    assert_eq!(task.motors().outputs[0], 0.5);
+    run(&mut task, 5 * RATE_HZ as usize);
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+
+    // Zero duration stops a running test
+    task.handle_command(motor_test(1, 20, 1000, true)).unwrap();
+    run(&mut task, 1);
+    task.handle_command(motor_test(1, 20, 0, true)).unwrap();
+    run(&mut task, 1);
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+}
+
+#[test]
+fn motor_test_requires_props_off_and_disarmed() {
+    let mut task = new_task();
+    run(&mut task, 1);
+    assert!(matches!(task.handle_command(motor_test(0, 20, 500, true)), Err(CommandError::Calibrating)));
+
+    run_until_calibrated(&mut task);
+    assert!(matches!(task.handle_command(motor_test(0, 20, 500, false)), Err(CommandError::PropsOn)));
+    assert!(matches!(task.handle_command(motor_test(4, 20, 500, true)), Err(CommandError::InvalidMotor)));
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    assert!(matches!(task.handle_command(motor_test(0, 20, 500, true)), Err(CommandError::Armed)));
+    assert!(!task.is_motor_test_active());
+}
+
+#[test]
+fn motor_curve_applied_to_armed_outputs() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    let curve = MotorCurve { expo: 1.0, min_output: 0.1, max_output: 0.9, nominal_voltage: 0.0 };
+    task.handle_command(IcarusCommand::SetParameter(Parameter::MotorCurve(curve))).unwrap();
+
+    // Half thrust with thrust proportional to duty squared
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    run(&mut task, 5);
+    let expected = 0.1 + 0.8 * 0.5f32.sqrt();
+    for output in task.motors().outputs.iter() {
+        assert!((output - expected).abs() < 0.05, "{:?}", task.motors().outputs);
+    }
+
+    // Motor tests send the raw duty
+    task.handle_command(IcarusCommand::Throttle(0, 0, 0)).unwrap();
+    task.handle_command(motor_test(0, 20, 500, true)).unwrap();
+    run(&mut task, 1);
+    assert_eq!(task.motors().outputs, [0.2, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn thrust_step_needs_a_thrust_stand_and_stays_within_the_bench_limit() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    let step = |motor, duty, duration_ms, thrust_stand| {
+        IcarusCommand::ThrustStep(ThrustStep { motor, duty, duration_ms, thrust_stand })
+    };
+
+    // Unconfirmed or above the bench limit never spins
+    assert!(matches!(task.handle_command(step(1, 50, 1000, false)), Err(CommandError::NoThrustStand)));
+    assert!(matches!(task.handle_command(step(1, 100, 1000, true)), Err(CommandError::DutyLimit)));
+    run(&mut task, 5);
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+
+    // Confirmed steps are not held to the props off motor test limit
+    let max_duty = task.params().bench_max_duty;
+    task.handle_command(step(1, max_duty, 1000, true)).unwrap();
+    run(&mut task, 5);
+    assert_eq!(task.motors().outputs, [0.0, max_duty as f32 / 100.0, 0.0, 0.0]);
+    assert!(!task.arming().is_armed());
+
+    run(&mut task, 50);
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+
+    // The limit is a parameter
+    task.handle_command(IcarusCommand::SetParameter(Parameter::BenchMaxDuty(100))).unwrap();
+    task.handle_command(step(1, 100, 100, true)).unwrap();
+    run(&mut task, 1);
+    assert_eq!(task.motors().outputs, [0.0, 1.0, 0.0, 0.0]);
+
+    assert!(matches!(task.handle_command(step(4, 50, 1000, true)), Err(CommandError::InvalidMotor)));
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    assert!(matches!(task.handle_command(step(0, 50, 1000, true)), Err(CommandError::Armed)));
+}
+
+/// RMS of the roll and pitch torque reaching the motors over `n` steps, measured as the first motor's offset from the
+/// mean output
+fn motor_torque_rms(task: &mut Task, n: usize) -> f32 {
+    let sum = (0..n)
+        .map(|_| {
+            run(task, 1);
+            let outputs = task.motors().outputs;
+            let offset = outputs[0] - outputs.iter().sum::<f32>() / NUM_MOTORS as f32;
+            offset * offset
+        })
+        .sum::<f32>();
+
+    (sum / n as f32).sqrt()
+}
+
+#[test]
+fn gyro_vibration_is_notched_out_of_the_motors() {
+    let mut task = new_task_at(1000);
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::SetMode(FlightMode::Rate)).unwrap();
+    let hover = RateSetpoint { roll: 0.0, pitch: 0.0, yaw: 0.0, thrust: 0.5 };
+    task.handle_command(IcarusCommand::Rate(hover)).unwrap();
+    assert!(task.arming().is_armed());
+
+    task.imu_mut().tone = Some((200.0, 0.5));
+
+    // Passed straight through until the notch finds the peak
+    let before = motor_torque_rms(&mut task, 32);
+
+    run(&mut task, 500);
+    let after = motor_torque_rms(&mut task, 500);
+
+    assert!(before > 0.001, "{}", before);
+    assert!(after < before * 0.1, "before {}, after {}", before, after);
+}
+
+#[test]
+fn setpoints_must_match_the_flight_mode() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+    assert_eq!(task.mode(), FlightMode::Angle);
+
+    let rate = RateSetpoint { roll: 0.5, pitch: 0.0, yaw: 0.0, thrust: 0.4 };
+    assert!(matches!(task.handle_command(IcarusCommand::Rate(rate)), Err(CommandError::WrongMode)));
+    assert!(!task.arming().is_armed());
+
+    // Out of range angles are limited rather than rejected
+    let attitude = AttitudeSetpoint { roll: 2.0, pitch: 0.0, yaw_rate: 0.0, thrust: 0.4 };
+    task.handle_command(IcarusCommand::Attitude(attitude)).unwrap();
+    assert!(task.arming().is_armed());
+    run(&mut task, 5);
+    assert!(task.motors().outputs.iter().all(|o| *o > 0.0));
+
+    // Thrust carries over into rate mode
+    task.handle_command(IcarusCommand::SetMode(FlightMode::Rate)).unwrap();
+    assert!(task.arming().is_armed());
+    assert!(matches!(task.handle_command(IcarusCommand::Attitude(attitude)), Err(CommandError::WrongMode)));
+    task.handle_command(IcarusCommand::Rate(rate)).unwrap();
+    run(&mut task, 5);
+    assert!(task.arming().is_armed());
+
+    // No barometer fitted
+    let alt_hold = task.handle_command(IcarusCommand::SetMode(FlightMode::AltHold));
+    assert!(matches!(alt_hold, Err(CommandError::NoAltitude)));
+}
+
+#[test]
+fn altitude_hold_from_flight_with_a_barometer() {
+    let mut task = new_task();
+    task.barometer_mut().altitude = Some(100.0);
+    run_until_calibrated(&mut task);
+
+    let alt_hold = IcarusCommand::SetMode(FlightMode::AltHold);
+    assert!(matches!(task.handle_command(alt_hold), Err(CommandError::NotArmed)));
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 40)).unwrap();
+    run(&mut task, 5);
+    let outputs = task.motors().outputs;
+
+    // Holds at the thrust it was entered with, zero thrust stick descends instead of disarming
+    task.handle_command(alt_hold).unwrap();
+    run(&mut task, 5);
+    for (output, before) in task.motors().outputs.iter().zip(outputs.iter()) {
+        assert!((output - before).abs() < 0.01, "{:?} {:?}", task.motors().outputs, outputs);
+    }
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 0)).unwrap();
+    run(&mut task, 5);
+    assert!(task.arming().is_armed());
+    assert_eq!(task.mode(), FlightMode::AltHold);
+
+    // Losing the barometer falls back to angle mode
+    task.barometer_mut().altitude = None;
+    run(&mut task, 50);
+    assert_eq!(task.mode(), FlightMode::Angle);
+    assert!(task.arming().is_armed());
+
+    task.barometer_mut().altitude = Some(100.0);
+    run(&mut task, 5);
+    task.handle_command(alt_hold).unwrap();
+    task.handle_command(IcarusCommand::MotorStop).unwrap();
+    assert_eq!(task.mode(), FlightMode::Angle);
+}
+
+#[test]
+fn motor_stop_and_reboot() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Throttle(0, 0, 50)).unwrap();
+    run(&mut task, 2);
+    assert!(matches!(task.handle_command(IcarusCommand::Reboot), Err(CommandError::Armed)));
+
+    task.handle_command(IcarusCommand::MotorStop).unwrap();
+    run(&mut task, 1);
+    assert!(!task.arming().is_armed());
+    assert_eq!(task.motors().outputs, [0.0; NUM_MOTORS]);
+
+    task.handle_command(IcarusCommand::Reboot).unwrap();
+}
+
+#[test]
+fn requests_are_answered_once_per_id() {
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+
+    let request = |id, command| CommandRequest { id, command };
+    let mut responses = Vec::new();
+
+    let result = task.handle_request(request(7, IcarusCommand::Throttle(0, 0, 30)), |state| responses.push(state));
+    assert!(matches!(result, Some(Ok(()))));
+    assert!(task.arming().is_armed());
+
+    // Rejected commands are answered with the reason
+    let result = task.handle_request(request(8, IcarusCommand::Reboot), |state| responses.push(state));
+    assert!(matches!(result, Some(Err(CommandError::Armed))));
+
+    // A retry is answered again without running the command. Stopping the motors first shows it is not re-run
+    task.handle_command(IcarusCommand::MotorStop).unwrap();
+    let result = task.handle_request(request(8, IcarusCommand::Reboot), |state| responses.push(state));
+    assert!(result.is_none());
+
+    assert!(matches!(responses[0], IcarusState::Ack(7)));
+    assert!(matches!(responses[1], IcarusState::Nack(8, NackReason::Armed)));
+    assert!(matches!(responses[2], IcarusState::Nack(8, NackReason::Armed)));
+
+    // The same id long after is a new command
+    run(&mut task, 150);
+    let result = task.handle_request(request(8, IcarusCommand::Reboot), |state| responses.push(state));
+    assert!(matches!(result, Some(Ok(()))));
+    assert!(matches!(responses[3], IcarusState::Ack(8)));
+}
+
+#[test]
+fn telemetry_follows_subscriptions() {
+    let mut task = new_task();
+    task.battery_mut().voltage = Some(3.9);
+    run_until_calibrated(&mut task);
+
+    task.handle_command(IcarusCommand::Subscribe(TelemetryChannel::Sensors, 0)).unwrap();
+    task.handle_command(IcarusCommand::Subscribe(TelemetryChannel::State, 10)).unwrap();
+    task.handle_command(IcarusCommand::Subscribe(TelemetryChannel::Health, 5)).unwrap();
+
+    let count = |telemetry: &[IcarusState], channel| {
+        telemetry.iter().filter(|s| s.channel() == Some(channel)).count()
+    };
+
+    // Two seconds. New subscriptions send one message straight away
+    let telemetry = run(&mut task, 100);
+    assert_eq!(count(&telemetry, TelemetryChannel::Sensors), 0);
+    assert_eq!(count(&telemetry, TelemetryChannel::State), 21);
+    assert_eq!(count(&telemetry, TelemetryChannel::Health), 11);
+    assert_eq!(count(&telemetry, TelemetryChannel::Battery), 2);
+
+    // Drops reported by the platform come back on the stats channel
+    let mut dropped = MessageCounts::default();
+    dropped.record(&telemetry[0]);
+    task.record_dropped(&dropped);
+
+    let telemetry = run(&mut task, 50);
+    let stats = telemetry
+        .iter()
+        .find_map(|s| if let IcarusState::TelemetryStats(stats) = s { Some(*stats) } else { None })
+        .expect("no telemetry stats");
+    assert_eq!(stats.dropped.total(), 1);
+    assert_eq!(stats.rates[TelemetryChannel::State.index()], 10);
+    assert_eq!(stats.rates[TelemetryChannel::Sensors.index()], 0);
+}
+
+#[test]
+fn calibration_is_stored_and_kept_across_boots() {
+    // Nothing stored. The boot calibration is used but not saved
+    let mut task = new_task();
+    run_until_calibrated(&mut task);
+    assert!(task.params().imu_calibration.is_none());
+    assert!(!task.take_params_changed());
+
+    // A requested calibration is stored
+    task.handle_command(IcarusCommand::Calibrate(CalibrationKind::Level)).unwrap();
+    run_until_calibrated(&mut task);
+    assert_eq!(task.params().imu_calibration, Some(*task.calibration()));
+    assert!(task.take_params_changed());
+    assert!(!task.take_params_changed());
+
+    // Next boot keeps the stored accelerometer scale and bias and only refreshes the gyro bias
+    let stored = ImuCalibration { accel_bias: [0.01, -0.02, 0.03], accel_scale: [1.05, 0.97, 1.02], gyro_bias: [0.5; 3] };
+    let params = Parameters { loop_rate: RATE_HZ, imu_calibration: Some(stored), ..Default::default() };
+    let mut task = FlightTask::new(
+        FakeImu::default(),
+        FakeBarometer::default(),
+        FakeBattery::default(),
+        FakeMotors::default(),
+        FakeClock::default(),
+        params,
+    );
+    assert_eq!(*task.calibration(), stored);
+
+    run_until_calibrated(&mut task);
+    let calibration = task.calibration();
+    assert_eq!(calibration.accel_bias, stored.accel_bias);
+    assert_eq!(calibration.accel_scale, stored.accel_scale);
+    assert!(calibration.gyro_bias.iter().all(|b| b.abs() < 0.01), "{:?}", calibration);
+    assert_eq!(task.params().imu_calibration, Some(stored));
+    assert!(!task.take_params_changed());
+}
@@ -0,0 +1,88 @@
+//
+// led.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 01 2022
+//
+
+use icarus_flight::led::{Indications, LedEngine, Pattern, Rgb};
+
+#[test]
+fn blink_patterns() {
+    let blink = Pattern::Blink { color: Rgb::RED, period_ms: 1000 };
+    assert_eq!(blink.color_at(0), Rgb::RED);
+    assert_eq!(blink.color_at(600), Rgb::BLACK);
+    assert_eq!(blink.color_at(1100), Rgb::RED);
+
+    let double = Pattern::DoubleBlink { color: Rgb::GREEN, period_ms: 800 };
+    let on = (0..800).step_by(100).map(|t| double.color_at(t) == Rgb::GREEN).collect::<Vec<_>>();
+    assert_eq!(on, [true, false, true, false, false, false, false, false]);
+}
+
+#[test]
+fn breathe_fades_in_and_out() {
+    let breathe = Pattern::Breathe { color: Rgb::BLUE, period_ms: 1000 };
+    assert_eq!(breathe.color_at(0), Rgb::BLACK);
+    assert_eq!(breathe.color_at(500), Rgb::BLUE);
+    assert!(breathe.color_at(250).b > 100 && breathe.color_at(250).b < 150);
+    assert!(breathe.color_at(750).b > 100 && breathe.color_at(750).b < 150);
+}
+
+#[test]
+fn rainbow_and_sequence() {
+    let rainbow = Pattern::Rainbow { period_ms: 300 };
+    assert_eq!(rainbow.color_at(0), Rgb::RED);
+    assert_ne!(rainbow.color_at(100), rainbow.color_at(200));
+
+    const COLORS: [Rgb; 2] = [Rgb::YELLOW, Rgb::PURPLE];
+    let sequence = Pattern::Sequence { colors: &COLORS, step_ms: 100 };
+    assert_eq!(sequence.color_at(50), Rgb::YELLOW);
+    assert_eq!(sequence.color_at(150), Rgb::PURPLE);
+    assert_eq!(sequence.color_at(250), Rgb::YELLOW);
+}
+
+#[test]
+fn error_code_flashes_code_times() {
+    let pattern = Pattern::ErrorCode { color: Rgb::RED, code: 3 };
+
+    let mut flashes = 0;
+    let mut previous = Rgb::BLACK;
+    for t in (0..2200).step_by(10) {
+        let color = pattern.color_at(t);
+        if color == Rgb::RED && previous == Rgb::BLACK {
+            flashes += 1;
+        }
+        previous = color;
+    }
+
+    // One full cycle is three flashes and a pause
+    assert_eq!(flashes, 3);
+    assert_eq!(pattern.color_at(2200), Rgb::RED);
+}
+
+#[test]
+fn highest_priority_indication_wins() {
+    let armed = Indications::ARMED | Indications::LINK;
+    assert_eq!(LedEngine::pattern(armed), Pattern::Solid(Rgb::GREEN));
+
+    let low_battery = armed | Indications::LOW_BATTERY;
+    assert!(matches!(LedEngine::pattern(low_battery), Pattern::DoubleBlink { .. }));
+
+    let fault = low_battery | Indications::SENSOR_FAULT;
+    assert!(matches!(LedEngine::pattern(fault), Pattern::ErrorCode { code: 1, .. }));
+
+    // Nothing active, waiting for the host
+    assert!(matches!(LedEngine::pattern(Indications::empty()), Pattern::Blink { color: Rgb::RED, .. }));
+}
+
+#[test]
+fn pattern_restarts_on_change() {
+    let mut engine = LedEngine::default();
+
+    // Link blink is off in the second half of its period
+    assert_eq!(engine.update(Indications::LINK, 0), Rgb::GREEN);
+    assert_eq!(engine.update(Indications::LINK, 1500), Rgb::BLACK);
+
+    // Low battery starts on a flash
+    assert_eq!(engine.update(Indications::LINK | Indications::LOW_BATTERY, 1510), Rgb::ORANGE);
+}
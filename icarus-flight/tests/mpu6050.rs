@@ -0,0 +1,289 @@
+//
+// mpu6050.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 27 2022
+//
+
+use icarus_flight::{
+    drivers::mpu6050::{regs, AccelRange, Dlpf, GyroRange, Mpu6050, Mpu6050Config, Mpu6050Error, ReadMode},
+    hal::{Clock, DataReady, Imu, NoInterrupt},
+};
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use std::{cell::Cell, collections::VecDeque, rc::Rc};
+
+const ADDR: u8 = 0x68;
+
+/// Register level MPU6050 model
+struct FakeBus {
+    regs: [u8; 128],
+    fifo: VecDeque<u8>,
+    /// Register writes in order
+    writes: Vec<(u8, u8)>,
+    transactions: usize,
+    failing: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct BusError;
+
+impl Default for FakeBus {
+    fn default() -> Self {
+        let mut regs = [0u8; 128];
+        regs[regs::WHO_AM_I as usize] = regs::WHO_AM_I_VALUE;
+
+        Self { regs, fifo: VecDeque::new(), writes: Vec::new(), transactions: 0, failing: false }
+    }
+}
+
+impl FakeBus {
+    /// Latch a sample into the data registers. Accel and gyro are raw counts
+    fn set_sample(&mut self, accel: [i16; 3], gyro: [i16; 3]) {
+        let base = regs::ACCEL_XOUT_H as usize;
+        self.regs[base..base + 6].copy_from_slice(&to_bytes(accel));
+        self.regs[base + 8..base + 14].copy_from_slice(&to_bytes(gyro));
+        self.regs[regs::INT_STATUS as usize] |= regs::DATA_RDY;
+    }
+
+    fn push_fifo(&mut self, accel: [i16; 3], gyro: [i16; 3]) {
+        self.fifo.extend(to_bytes(accel));
+        self.fifo.extend(to_bytes(gyro));
+    }
+
+    fn written(&self, reg: u8) -> Option<u8> {
+        self.writes.iter().rev().find(|(r, _)| *r == reg).map(|(_, v)| *v)
+    }
+}
+
+fn to_bytes(v: [i16; 3]) -> Vec<u8> {
+    v.iter().flat_map(|a| a.to_be_bytes()).collect()
+}
+
+impl Write for FakeBus {
+    type Error = BusError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), BusError> {
+        assert_eq!(addr, ADDR);
+        if self.failing {
+            return Err(BusError);
+        }
+        self.transactions += 1;
+
+        let (reg, value) = (bytes[0], bytes[1]);
+        self.writes.push((reg, value));
+        self.regs[reg as usize] = value;
+
+        if reg == regs::USER_CTRL && value & regs::USER_FIFO_RESET != 0 {
+            self.fifo.clear();
+            self.regs[regs::INT_STATUS as usize] &= !regs::FIFO_OFLOW;
+        }
+
+        Ok(())
+    }
+}
+
+impl WriteRead for FakeBus {
+    type Error = BusError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), BusError> {
+        assert_eq!(addr, ADDR);
+        if self.failing {
+            return Err(BusError);
+        }
+        self.transactions += 1;
+
+        let reg = bytes[0];
+        match reg {
+            regs::FIFO_R_W => {
+                for b in buffer.iter_mut() {
+                    *b = self.fifo.pop_front().expect("FIFO underrun");
+                }
+            }
+            regs::FIFO_COUNT_H => buffer.copy_from_slice(&(self.fifo.len() as u16).to_be_bytes()),
+            _ => {
+                let start = reg as usize;
+                buffer.copy_from_slice(&self.regs[start..start + buffer.len()]);
+                // Reading clears the interrupt status. With INT_RD_CLEAR any read does
+                let clear_on_any = self.regs[regs::INT_PIN_CFG as usize] & regs::INT_RD_CLEAR != 0;
+                if clear_on_any || (start..start + buffer.len()).contains(&(regs::INT_STATUS as usize)) {
+                    self.regs[regs::INT_STATUS as usize] = 0;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct FakeClock(Rc<Cell<u64>>);
+
+impl Clock for FakeClock {
+    fn now_us(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+/// Interrupt handler stand in
+#[derive(Clone, Default)]
+struct FakeInterrupt(Rc<Cell<Option<u64>>>);
+
+impl DataReady for FakeInterrupt {
+    fn take(&mut self) -> Option<u64> {
+        self.0.take()
+    }
+}
+
+fn config(mode: ReadMode) -> Mpu6050Config {
+    Mpu6050Config {
+        sample_rate_divider: 1,
+        dlpf: Dlpf::Hz44,
+        accel_range: AccelRange::G4,
+        gyro_range: GyroRange::Deg500,
+        mode,
+    }
+}
+
+fn new_imu(mode: ReadMode) -> (Mpu6050<FakeBus, FakeClock, NoInterrupt>, FakeClock) {
+    let clock = FakeClock::default();
+    let mut imu = Mpu6050::new(FakeBus::default(), ADDR, config(mode), clock.clone(), NoInterrupt);
+    imu.init().unwrap();
+    (imu, clock)
+}
+
+#[test]
+fn init_writes_configuration() {
+    let (mut imu, _) = new_imu(ReadMode::Fifo);
+    let bus = imu.bus_mut();
+
+    assert_eq!(bus.written(regs::PWR_MGMT_1), Some(regs::CLKSEL_PLL_X));
+    assert_eq!(bus.written(regs::SMPLRT_DIV), Some(1));
+    assert_eq!(bus.written(regs::CONFIG), Some(3));
+    assert_eq!(bus.written(regs::GYRO_CONFIG), Some(1 << 3));
+    assert_eq!(bus.written(regs::ACCEL_CONFIG), Some(1 << 3));
+    assert_eq!(bus.written(regs::INT_PIN_CFG), Some(0));
+    assert_eq!(bus.written(regs::INT_ENABLE), Some(regs::DATA_RDY | regs::FIFO_OFLOW));
+    assert_eq!(bus.written(regs::FIFO_EN), Some(regs::FIFO_EN_GYRO_ACCEL));
+    assert_eq!(bus.written(regs::USER_CTRL), Some(regs::USER_FIFO_EN));
+
+    // 1 kHz gyro rate with the DLPF on, halved by the divider
+    assert_eq!(imu.config().sample_rate(), 500.0);
+}
+
+#[test]
+fn init_rejects_other_devices() {
+    let mut bus = FakeBus::default();
+    bus.regs[regs::WHO_AM_I as usize] = 0x70;
+
+    let mut imu = Mpu6050::new(bus, ADDR, config(ReadMode::Burst), FakeClock::default(), NoInterrupt);
+    assert!(matches!(imu.init(), Err(Mpu6050Error::WrongDevice(0x70))));
+}
+
+#[test]
+fn burst_read_is_one_transaction() {
+    let (mut imu, clock) = new_imu(ReadMode::Burst);
+    // +/-4g is 8192 LSB/g, +/-500 deg/s is 65.5 LSB/(deg/s)
+    imu.bus_mut().set_sample([8192, -4096, 16384], [655, -655, 0]);
+    imu.bus_mut().transactions = 0;
+    clock.0.set(1234);
+
+    let sample = imu.read().unwrap();
+    assert_eq!(imu.bus_mut().transactions, 1);
+
+    assert!((sample.accel.x - 1.0).abs() < 1e-6);
+    assert!((sample.accel.y + 0.5).abs() < 1e-6);
+    assert!((sample.accel.z - 2.0).abs() < 1e-6);
+    assert!((sample.gyro.x - 10f32.to_radians()).abs() < 1e-5);
+    assert!((sample.gyro.y + 10f32.to_radians()).abs() < 1e-5);
+    assert_eq!(sample.gyro.z, 0.0);
+    assert_eq!(sample.timestamp_us, 1234);
+}
+
+#[test]
+fn samples_stamped_with_data_ready_time() {
+    let clock = FakeClock::default();
+    let interrupt = FakeInterrupt::default();
+    let mut imu = Mpu6050::new(FakeBus::default(), ADDR, config(ReadMode::Burst), clock.clone(), interrupt.clone());
+    imu.init().unwrap();
+
+    interrupt.0.set(Some(900));
+    clock.0.set(1000);
+    assert_eq!(imu.read().unwrap().timestamp_us, 900);
+
+    // No interrupt since, falls back to the read time
+    assert_eq!(imu.read().unwrap().timestamp_us, 1000);
+}
+
+#[test]
+fn fifo_samples_are_averaged() {
+    let (mut imu, clock) = new_imu(ReadMode::Fifo);
+    imu.bus_mut().push_fifo([8192, 0, 0], [655, 0, 0]);
+    imu.bus_mut().push_fifo([0, 8192, 0], [0, 0, 0]);
+    clock.0.set(10_000);
+
+    let sample = imu.read().unwrap();
+    assert_eq!(imu.last_fifo_samples(), 2);
+    assert!((sample.accel.x - 0.5).abs() < 1e-6);
+    assert!((sample.accel.y - 0.5).abs() < 1e-6);
+    assert!((sample.gyro.x - 5f32.to_radians()).abs() < 1e-5);
+    assert_eq!(sample.timestamp_us, 10_000);
+    assert!(imu.bus_mut().fifo.is_empty());
+}
+
+#[test]
+fn fifo_samples_spaced_by_sample_period() {
+    let (mut imu, clock) = new_imu(ReadMode::Fifo);
+    // More than one I2C chunk
+    for i in 0..20 {
+        imu.bus_mut().push_fifo([i, 0, 0], [0, 0, 0]);
+    }
+    clock.0.set(100_000);
+
+    let mut samples = Vec::new();
+    assert_eq!(imu.read_fifo(|s| samples.push(s)).unwrap(), 20);
+
+    // 500 Hz, newest sample last
+    assert_eq!(samples.last().unwrap().timestamp_us, 100_000);
+    assert_eq!(samples[0].timestamp_us, 100_000 - 19 * 2000);
+    assert!(samples.windows(2).all(|w| w[1].accel.x > w[0].accel.x));
+}
+
+#[test]
+fn fifo_overflow_is_discarded() {
+    let (mut imu, _) = new_imu(ReadMode::Fifo);
+    imu.bus_mut().push_fifo([8192, 0, 0], [0, 0, 0]);
+    imu.bus_mut().regs[regs::INT_STATUS as usize] |= regs::FIFO_OFLOW;
+    imu.bus_mut().set_sample([0, 0, 8192], [0, 0, 0]);
+
+    // Falls back to the data registers
+    let sample = imu.read().unwrap();
+    assert_eq!(imu.fifo_overflows(), 1);
+    assert!(imu.bus_mut().fifo.is_empty());
+    assert!((sample.accel.z - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn fifo_overflow_survives_a_data_read() {
+    let (mut imu, _) = new_imu(ReadMode::Fifo);
+    imu.bus_mut().push_fifo([8192, 0, 0], [0, 0, 0]);
+    imu.bus_mut().regs[regs::INT_STATUS as usize] |= regs::FIFO_OFLOW;
+
+    // Reading the data registers leaves the status alone
+    imu.read_burst().unwrap();
+
+    imu.read().unwrap();
+    assert_eq!(imu.fifo_overflows(), 1);
+    assert!(imu.bus_mut().fifo.is_empty());
+}
+
+#[test]
+fn bus_errors_are_reported() {
+    let (mut imu, _) = new_imu(ReadMode::Fifo);
+    imu.bus_mut().failing = true;
+    assert!(matches!(imu.read(), Err(Mpu6050Error::I2c(BusError))));
+
+    imu.bus_mut().failing = false;
+    assert!(imu.reinit().is_ok());
+}
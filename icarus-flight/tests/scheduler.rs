@@ -0,0 +1,87 @@
+//
+// scheduler.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 26 2022
+//
+
+use icarus_flight::scheduler::{Scheduler, Stage};
+
+const PERIOD_US: u64 = 2000;
+
+/// Run one iteration starting at `start` that takes `busy` microseconds
+fn iterate(scheduler: &mut Scheduler, start: u64, busy: u64) {
+    scheduler.begin(start);
+    scheduler.end_stage(Stage::Sensors, start + busy / 2);
+    scheduler.end_stage(Stage::Estimation, start + busy);
+    scheduler.end_stage(Stage::Control, start + busy);
+    scheduler.end_stage(Stage::Output, start + busy);
+    scheduler.end(start + busy);
+}
+
+#[test]
+fn deadlines_do_not_drift_with_loop_time() {
+    let mut scheduler = Scheduler::new(500.0);
+
+    let mut now = 0;
+    for _ in 0..100 {
+        iterate(&mut scheduler, now, 700);
+        now += 700;
+        now += scheduler.time_until_next(now);
+    }
+
+    assert_eq!(now, 100 * PERIOD_US);
+}
+
+#[test]
+fn late_start_is_jitter_not_drift() {
+    let mut scheduler = Scheduler::new(500.0);
+
+    iterate(&mut scheduler, 0, 100);
+    // Woken 300us late
+    iterate(&mut scheduler, PERIOD_US + 300, 100);
+
+    // Next deadline is still on the original grid
+    assert_eq!(scheduler.time_until_next(PERIOD_US + 400), PERIOD_US - 400);
+
+    let stats = scheduler.report(2 * PERIOD_US);
+    assert_eq!(stats.jitter.max_us, 300);
+    assert_eq!(stats.jitter.mean_us, 150);
+    assert_eq!(stats.overruns, 0);
+}
+
+#[test]
+fn overrun_skips_missed_iterations() {
+    let mut scheduler = Scheduler::new(500.0);
+
+    // Takes two and a half periods
+    iterate(&mut scheduler, 0, 5000);
+    let stats = scheduler.report(5000);
+    assert_eq!(stats.overruns, 1);
+
+    // The loop resumes on the next deadline instead of running the missed ones back to back
+    scheduler.begin(5000);
+    assert_eq!(scheduler.time_until_next(5000), 1000);
+}
+
+#[test]
+fn report_stage_times_and_load() {
+    let mut scheduler = Scheduler::new(500.0);
+
+    for i in 0..500 {
+        iterate(&mut scheduler, i * PERIOD_US, 1000);
+    }
+
+    let stats = scheduler.report(500 * PERIOD_US);
+    assert_eq!(stats.rate, 500);
+    assert!((stats.measured_rate - 500.0).abs() < 1e-3);
+    assert_eq!(stats.sensors.mean_us, 500);
+    assert_eq!(stats.estimation.max_us, 500);
+    assert_eq!(stats.control.mean_us, 0);
+    assert!((stats.load - 0.5).abs() < 1e-3);
+
+    // A new window starts after each report
+    let stats = scheduler.report(501 * PERIOD_US);
+    assert_eq!(stats.measured_rate, 0.0);
+    assert_eq!(stats.sensors.max_us, 0);
+}
@@ -0,0 +1,97 @@
+//
+// telemetry.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_flight::telemetry::{default_rate, Telemetry};
+use icarus_wire::{IcarusState, MessageCounts, NackReason, TelemetryChannel};
+
+/// Count how often `channel` is due over `seconds` of a loop running at `loop_rate`
+fn count_due(telemetry: &mut Telemetry, channel: TelemetryChannel, loop_rate: u64, seconds: u64, start: u64) -> usize {
+    let period = 1_000_000 / loop_rate;
+    (0..loop_rate * seconds)
+        .filter(|i| telemetry.due(channel, start + i * period))
+        .count()
+}
+
+#[test]
+fn channels_start_at_their_default_rates() {
+    let mut telemetry = Telemetry::new(1000.0, 0);
+
+    for channel in TelemetryChannel::ALL {
+        assert_eq!(telemetry.rate(channel), default_rate(channel));
+    }
+
+    // Nothing is due until one period has passed
+    assert!(!telemetry.due(TelemetryChannel::Health, 0));
+    assert_eq!(count_due(&mut telemetry, TelemetryChannel::State, 1000, 2, 1000), 100);
+    assert_eq!(count_due(&mut telemetry, TelemetryChannel::Health, 1000, 2, 2_001_000), 2);
+}
+
+#[test]
+fn subscriptions_change_and_cap_the_rate() {
+    let mut telemetry = Telemetry::new(200.0, 0);
+
+    // Rates that do not divide the loop rate still average out
+    telemetry.subscribe(TelemetryChannel::Sensors, 30, 0);
+    assert_eq!(count_due(&mut telemetry, TelemetryChannel::Sensors, 200, 10, 0), 300);
+
+    // Off
+    telemetry.subscribe(TelemetryChannel::Battery, 0, 0);
+    assert!(!telemetry.is_enabled(TelemetryChannel::Battery));
+    assert_eq!(count_due(&mut telemetry, TelemetryChannel::Battery, 200, 5, 0), 0);
+
+    // Never more than once per iteration
+    telemetry.subscribe(TelemetryChannel::State, 1000, 0);
+    assert_eq!(telemetry.rate(TelemetryChannel::State), 200);
+    assert_eq!(count_due(&mut telemetry, TelemetryChannel::State, 200, 1, 0), 200);
+
+    let stats = telemetry.stats();
+    assert_eq!(stats.rates[TelemetryChannel::Sensors.index()], 30);
+    assert_eq!(stats.rates[TelemetryChannel::Battery.index()], 0);
+}
+
+#[test]
+fn missed_periods_are_skipped() {
+    let mut telemetry = Telemetry::new(100.0, 0);
+    telemetry.subscribe(TelemetryChannel::Sensors, 50, 0);
+
+    assert!(telemetry.due(TelemetryChannel::Sensors, 0));
+    // Stalled for a second, one message rather than fifty
+    assert!(telemetry.due(TelemetryChannel::Sensors, 1_000_000));
+    assert!(!telemetry.due(TelemetryChannel::Sensors, 1_010_000));
+    assert!(telemetry.due(TelemetryChannel::Sensors, 1_020_000));
+}
+
+#[test]
+fn dropped_messages_accumulate() {
+    let mut telemetry = Telemetry::new(100.0, 0);
+
+    let mut dropped = MessageCounts::default();
+    dropped.record(&IcarusState::Ack(1));
+    dropped.record(&IcarusState::Nack(2, NackReason::Busy));
+    dropped.record(&IcarusState::TelemetryStats(telemetry.stats()));
+    telemetry.record_dropped(&dropped);
+    telemetry.record_dropped(&dropped);
+
+    let stats = telemetry.stats();
+    assert_eq!(stats.dropped.other, 4);
+    assert_eq!(stats.dropped.get(TelemetryChannel::Stats), 2);
+    assert_eq!(stats.dropped.get(TelemetryChannel::Sensors), 0);
+    assert_eq!(stats.dropped.total(), 6);
+}
+
+#[test]
+fn corrupt_frames_accumulate() {
+    let mut telemetry = Telemetry::new(100.0, 0);
+    assert_eq!(telemetry.stats().corrupt_frames, 0);
+
+    telemetry.record_corrupt_frames(2);
+    telemetry.record_corrupt_frames(0);
+    telemetry.record_corrupt_frames(3);
+
+    assert_eq!(telemetry.stats().corrupt_frames, 5);
+    assert_eq!(telemetry.stats().dropped.total(), 0);
+}
@@ -0,0 +1,29 @@
+//
+// cli.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use clap::Parser;
+
+/// Software-in-the-loop simulator for the Icarus flight stack
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+pub struct Args {
+    /// TCP port to serve the controller interface on
+    #[clap(short = 'p', long = "port", default_value_t = 5000)]
+    pub port: u16,
+    /// Control loop rate (Hz)
+    #[clap(short = 'r', long = "rate", default_value_t = 500)]
+    pub rate: u16,
+    /// Physics update rate (Hz)
+    #[clap(long = "physics-rate", default_value_t = 2000.0)]
+    pub physics_rate: f32,
+    /// Random seed for sensor noise
+    #[clap(long = "seed", default_value_t = 0)]
+    pub seed: u64,
+    /// Run as fast as possible instead of in real time
+    #[clap(long = "fast")]
+    pub fast: bool,
+}
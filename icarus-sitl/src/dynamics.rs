@@ -0,0 +1,163 @@
+//
+// dynamics.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use icarus_core::mixer::NUM_MOTORS;
+
+use nalgebra::{UnitQuaternion, Vector3};
+
+/// Standard gravity (m/s^2)
+pub const GRAVITY: f32 = 9.80665;
+
+/// Physical properties of the simulated quad
+#[derive(Debug, Clone, Copy)]
+pub struct VehicleParams {
+    /// Mass (kg)
+    pub mass: f32,
+    /// Principal moments of inertia (kg m^2)
+    pub inertia: Vector3<f32>,
+    /// Distance from the center of mass to each rotor (m)
+    pub arm_length: f32,
+    /// Thrust of a single motor at full speed (N)
+    pub max_thrust: f32,
+    /// Reaction torque per unit of thrust (m)
+    pub torque_coefficient: f32,
+    /// Motor spin-up time constant (s)
+    pub motor_time_constant: f32,
+    /// Linear drag coefficient (N s/m)
+    pub linear_drag: f32,
+    /// Angular drag coefficient (N m s/rad)
+    pub angular_drag: f32,
+}
+
+impl Default for VehicleParams {
+    /// A 50 g micro quad with 65 mm props
+    fn default() -> Self {
+        Self {
+            mass: 0.05,
+            inertia: Vector3::new(2.5e-5, 2.5e-5, 4.5e-5),
+            arm_length: 0.045,
+            max_thrust: 0.3,
+            torque_coefficient: 0.006,
+            motor_time_constant: 0.03,
+            linear_drag: 0.02,
+            angular_drag: 2e-6,
+        }
+    }
+}
+
+impl VehicleParams {
+    /// Rotor positions in the body frame (x forward, y left, z up) using the quad X motor order
+    pub fn rotor_positions(&self) -> [Vector3<f32>; NUM_MOTORS] {
+        let d = self.arm_length * core::f32::consts::FRAC_1_SQRT_2;
+
+        [
+            Vector3::new(d, -d, 0.0),
+            Vector3::new(-d, -d, 0.0),
+            Vector3::new(-d, d, 0.0),
+            Vector3::new(d, d, 0.0),
+        ]
+    }
+
+    /// Direction of the reaction torque about body Z for each rotor. A counter-clockwise prop pushes the frame clockwise
+    pub fn rotor_directions(&self) -> [f32; NUM_MOTORS] {
+        [-1.0, 1.0, -1.0, 1.0]
+    }
+}
+
+/// Rigid body state of the vehicle
+///
+/// The world frame is x north, y west, z up. The ground is the plane z = 0.
+#[derive(Debug, Clone, Copy)]
+pub struct RigidBody {
+    /// Position in the world frame (m)
+    pub position: Vector3<f32>,
+    /// Velocity in the world frame (m/s)
+    pub velocity: Vector3<f32>,
+    /// Body to world rotation
+    pub orientation: UnitQuaternion<f32>,
+    /// Angular velocity in the body frame (rad/s)
+    pub angular_velocity: Vector3<f32>,
+    /// Non-gravitational acceleration in the body frame (m/s^2). This is what an accelerometer measures
+    pub specific_force: Vector3<f32>,
+}
+
+impl Default for RigidBody {
+    fn default() -> Self {
+        Self {
+            position: Vector3::zeros(),
+            velocity: Vector3::zeros(),
+            orientation: UnitQuaternion::identity(),
+            angular_velocity: Vector3::zeros(),
+            specific_force: Vector3::new(0.0, 0.0, GRAVITY),
+        }
+    }
+}
+
+impl RigidBody {
+    /// Whether the vehicle is resting on the ground
+    pub fn on_ground(&self) -> bool {
+        self.position.z <= 0.0 && self.velocity.z <= 0.0
+    }
+
+    /// Advance the simulation by `dt` seconds given the thrust of each rotor (N)
+    pub fn step(&mut self, params: &VehicleParams, thrusts: &[f32; NUM_MOTORS], dt: f32) {
+        let positions = params.rotor_positions();
+        let directions = params.rotor_directions();
+
+        // Forces and torques in the body frame
+        let mut force = Vector3::zeros();
+        let mut torque = Vector3::zeros();
+
+        for ((thrust, position), direction) in thrusts.iter().zip(positions.iter()).zip(directions.iter()) {
+            let f = Vector3::new(0.0, 0.0, *thrust);
+            force += f;
+            torque += position.cross(&f);
+            torque.z += direction * params.torque_coefficient * thrust;
+        }
+
+        torque -= self.angular_velocity * params.angular_drag;
+
+        // Translational dynamics in the world frame
+        let drag = -self.velocity * params.linear_drag;
+        let gravity = Vector3::new(0.0, 0.0, -GRAVITY * params.mass);
+        let mut accel = (self.orientation * force + drag + gravity) / params.mass;
+
+        // Ground contact. The ground pushes back until the thrust can lift the vehicle off
+        if self.position.z <= 0.0 && accel.z <= 0.0 {
+            accel = Vector3::zeros();
+            self.velocity = Vector3::zeros();
+            self.position.z = 0.0;
+            self.angular_velocity = Vector3::zeros();
+
+            // Settle flat on the ground keeping only the heading
+            let (_, _, yaw) = self.orientation.euler_angles();
+            self.orientation = UnitQuaternion::from_euler_angles(0.0, 0.0, yaw);
+        }
+        else {
+            // Euler's rotation equation for a body with a diagonal inertia tensor
+            let inertia = params.inertia;
+            let w = self.angular_velocity;
+            let gyroscopic = w.cross(&inertia.component_mul(&w));
+            let angular_accel = (torque - gyroscopic).component_div(&inertia);
+
+            self.angular_velocity += angular_accel * dt;
+            self.orientation *= UnitQuaternion::from_scaled_axis(self.angular_velocity * dt);
+        }
+
+        self.velocity += accel * dt;
+        self.position += self.velocity * dt;
+
+        if self.position.z < 0.0 {
+            self.position.z = 0.0;
+            self.velocity.z = self.velocity.z.max(0.0);
+        }
+
+        // The accelerometer sees every force except gravity
+        let gravity_accel = Vector3::new(0.0, 0.0, -GRAVITY);
+        self.specific_force = self.orientation.inverse() * (accel - gravity_accel);
+    }
+}
@@ -0,0 +1,12 @@
+//
+// lib.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+pub mod cli;
+pub mod dynamics;
+pub mod motor;
+pub mod sensors;
+pub mod server;
+pub mod sim;
@@ -0,0 +1,73 @@
+//
+// main.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use icarus_sitl::{
+    cli::Args,
+    server::Server,
+    sim::{SimConfig, Simulator},
+};
+
+use icarus_wire::MessageCounts;
+
+use clap::Parser;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let config = SimConfig {
+        control_rate: args.rate,
+        physics_rate: args.physics_rate,
+        seed: args.seed,
+        ..Default::default()
+    };
+
+    let mut sim = Simulator::new(config);
+    let mut server = Server::bind(args.port)?;
+
+    println!("Listening on port {}", args.port);
+
+    let period = Duration::from_secs_f32(1.0 / sim.task().rate_hz());
+    let mut deadline = Instant::now();
+
+    let mut commands = Vec::new();
+    let mut telemetry = Vec::new();
+
+    loop {
+        server.poll(&mut commands);
+        sim.record_corrupt_frames(server.take_corrupt_frames());
+        for request in commands.drain(..) {
+            sim.handle_request(request, &mut telemetry);
+        }
+
+        sim.step(&mut telemetry);
+
+        let mut dropped = MessageCounts::default();
+        for state in telemetry.drain(..) {
+            if !server.send(&state) {
+                dropped.record(&state);
+            }
+        }
+        sim.record_dropped(&dropped);
+
+        if !args.fast {
+            deadline += period;
+            let now = Instant::now();
+            if deadline > now {
+                thread::sleep(deadline - now);
+            }
+            else {
+                // Fell behind, don't try to catch up
+                deadline = now;
+            }
+        }
+    }
+}
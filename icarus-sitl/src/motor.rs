@@ -0,0 +1,45 @@
+//
+// motor.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+/// Brushed motor and propeller
+///
+/// Rotor speed follows the duty cycle with a first order lag. Thrust is proportional to speed squared.
+#[derive(Debug, Clone, Copy)]
+pub struct Motor {
+    /// Normalized rotor speed (0 - 1)
+    speed: f32,
+    /// Spin-up time constant (s)
+    time_constant: f32,
+    /// Thrust at full speed (N)
+    max_thrust: f32,
+}
+
+impl Motor {
+    pub fn new(time_constant: f32, max_thrust: f32) -> Self {
+        Self {
+            speed: 0.0,
+            time_constant,
+            max_thrust,
+        }
+    }
+
+    /// Update the rotor speed from a duty cycle in the range 0 - 1
+    pub fn update(&mut self, duty: f32, dt: f32) {
+        let target = duty.clamp(0.0, 1.0);
+        let alpha = dt / (self.time_constant + dt);
+        self.speed += alpha * (target - self.speed);
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Current thrust (N)
+    pub fn thrust(&self) -> f32 {
+        self.max_thrust * self.speed * self.speed
+    }
+}
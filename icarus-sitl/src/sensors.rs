@@ -0,0 +1,171 @@
+//
+// sensors.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use crate::dynamics::{RigidBody, GRAVITY};
+
+use icarus_core::data::{AccelerometerData, GyroscopeData};
+use icarus_flight::hal;
+use icarus_wire::BarometerRaw;
+
+use nalgebra::Vector3;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+
+/// MPU6050 error model
+#[derive(Debug, Clone, Copy)]
+pub struct ImuParams {
+    /// Accelerometer noise standard deviation (g)
+    pub accel_noise: f32,
+    /// Gyroscope noise standard deviation (rad/s)
+    pub gyro_noise: f32,
+    /// Maximum magnitude of the constant accelerometer bias on each axis (g)
+    pub accel_bias: f32,
+    /// Maximum magnitude of the constant gyroscope bias on each axis (rad/s)
+    pub gyro_bias: f32,
+    /// Accelerometer full scale range (g)
+    pub accel_range: f32,
+    /// Gyroscope full scale range (rad/s)
+    pub gyro_range: f32,
+}
+
+impl Default for ImuParams {
+    /// Datasheet noise densities at the default +/-2g and +/-250 deg/s ranges with the DLPF at ~44 Hz
+    fn default() -> Self {
+        Self {
+            accel_noise: 0.003,
+            gyro_noise: 0.05f32.to_radians() * 7.0,
+            accel_bias: 0.04,
+            gyro_bias: 2.0f32.to_radians(),
+            accel_range: 2.0,
+            gyro_range: 250.0f32.to_radians(),
+        }
+    }
+}
+
+/// Simulated MPU6050
+///
+/// Samples the vehicle state last passed to `set_body`.
+pub struct Imu {
+    params: ImuParams,
+    body: RigidBody,
+    time_us: u64,
+    rng: StdRng,
+    accel_bias: Vector3<f32>,
+    gyro_bias: Vector3<f32>,
+    accel_noise: Normal<f32>,
+    gyro_noise: Normal<f32>,
+}
+
+impl Imu {
+    /// Create an IMU with a random constant bias
+    pub fn new(params: ImuParams, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut bias = |limit: f32| Vector3::from_fn(|_, _| rng.gen_range(-limit..=limit));
+        let accel_bias = bias(params.accel_bias);
+        let gyro_bias = bias(params.gyro_bias);
+
+        Self {
+            body: RigidBody::default(),
+            time_us: 0,
+            rng,
+            accel_bias,
+            gyro_bias,
+            accel_noise: Normal::new(0.0, params.accel_noise).unwrap(),
+            gyro_noise: Normal::new(0.0, params.gyro_noise).unwrap(),
+            params,
+        }
+    }
+
+    /// Vehicle state at simulation time `time_us`
+    pub fn set_body(&mut self, body: RigidBody, time_us: u64) {
+        self.body = body;
+        self.time_us = time_us;
+    }
+}
+
+impl hal::Imu for Imu {
+    type Error = ();
+
+    fn read(&mut self) -> Result<hal::ImuSample, ()> {
+        let accel_range = self.params.accel_range;
+        let gyro_range = self.params.gyro_range;
+
+        let accel = self.body.specific_force / GRAVITY + self.accel_bias;
+        let accel = accel.map(|a| (a + self.accel_noise.sample(&mut self.rng)).clamp(-accel_range, accel_range));
+
+        let gyro = self.body.angular_velocity + self.gyro_bias;
+        let gyro = gyro.map(|g| (g + self.gyro_noise.sample(&mut self.rng)).clamp(-gyro_range, gyro_range));
+
+        Ok(hal::ImuSample {
+            accel: AccelerometerData { x: accel.x, y: accel.y, z: accel.z },
+            gyro: GyroscopeData { x: gyro.x, y: gyro.y, z: gyro.z },
+            timestamp_us: self.time_us,
+        })
+    }
+
+    fn reinit(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+/// BMP388 error model
+#[derive(Debug, Clone, Copy)]
+pub struct BarometerParams {
+    /// Altitude noise standard deviation (m)
+    pub altitude_noise: f32,
+    /// Temperature noise standard deviation (C)
+    pub temp_noise: f32,
+    /// Ambient temperature (C)
+    pub temperature: f32,
+}
+
+impl Default for BarometerParams {
+    /// Roughly the BMP388 with 2x pressure oversampling
+    fn default() -> Self {
+        Self {
+            altitude_noise: 0.1,
+            temp_noise: 0.05,
+            temperature: 22.0,
+        }
+    }
+}
+
+/// Simulated BMP388
+pub struct Barometer {
+    params: BarometerParams,
+    body: RigidBody,
+    rng: StdRng,
+    altitude_noise: Normal<f32>,
+    temp_noise: Normal<f32>,
+}
+
+impl Barometer {
+    pub fn new(params: BarometerParams, seed: u64) -> Self {
+        Self {
+            body: RigidBody::default(),
+            rng: StdRng::seed_from_u64(seed),
+            altitude_noise: Normal::new(0.0, params.altitude_noise).unwrap(),
+            temp_noise: Normal::new(0.0, params.temp_noise).unwrap(),
+            params,
+        }
+    }
+
+    pub fn set_body(&mut self, body: RigidBody) {
+        self.body = body;
+    }
+}
+
+impl hal::Barometer for Barometer {
+    type Error = ();
+
+    fn read(&mut self) -> Result<BarometerRaw, ()> {
+        Ok(BarometerRaw {
+            altitude: self.body.position.z + self.altitude_noise.sample(&mut self.rng),
+            temp: self.params.temperature + self.temp_noise.sample(&mut self.rng),
+        })
+    }
+}
@@ -0,0 +1,113 @@
+//
+// server.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use icarus_wire::{self, CommandRequest, IcarusState, FrameAccumulator, FeedResult};
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+/// TCP interface matching the firmware. Accepts a single host at a time, a new connection replaces the old one
+pub struct Server {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    raw_buf: [u8; 128],
+    cmd_decoder: FrameAccumulator<64>,
+    corrupt_frames: u32,
+}
+
+impl Server {
+    pub fn bind(port: u16) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            stream: None,
+            raw_buf: [0; 128],
+            cmd_decoder: FrameAccumulator::new(),
+            corrupt_frames: 0,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Accept new connections and read any pending commands
+    pub fn poll(&mut self, commands: &mut Vec<CommandRequest>) {
+        match self.listener.accept() {
+            Ok((stream, addr)) => {
+                if stream.set_nonblocking(true).is_ok() {
+                    println!("Host connected: {}", addr);
+                    self.stream = Some(stream);
+                    self.cmd_decoder = FrameAccumulator::new();
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => eprintln!("{:?}", e),
+        }
+
+        while let Some(stream) = self.stream.as_mut() {
+            match stream.read(&mut self.raw_buf) {
+                Ok(0) => {
+                    println!("Host disconnected");
+                    self.stream = None;
+                }
+                Ok(n) => {
+                    let mut window = &self.raw_buf[..n];
+                    'cobs: while !window.is_empty() {
+                        window = match self.cmd_decoder.feed::<CommandRequest>(window) {
+                            FeedResult::Consumed => break 'cobs,
+                            FeedResult::OverFull(new_window) => new_window,
+                            FeedResult::DeserError(new_window) => new_window,
+                            FeedResult::IntegrityError(new_window) => {
+                                self.corrupt_frames = self.corrupt_frames.wrapping_add(1);
+                                new_window
+                            }
+                            FeedResult::Success { data, remaining } => {
+                                commands.push(data);
+                                remaining
+                            }
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    self.stream = None;
+                }
+            }
+        }
+    }
+
+    /// Command frames dropped because they failed the integrity check since the last call
+    pub fn take_corrupt_frames(&mut self) -> u32 {
+        std::mem::take(&mut self.corrupt_frames)
+    }
+
+    /// Send telemetry to the connected host. Dropped if nobody is connected
+    ///
+    /// Returns false if the host is connected but the message could not be sent because the link is backed up.
+    pub fn send(&mut self, state: &IcarusState) -> bool {
+        let mut buf: [u8; 128] = [0; 128];
+
+        if let (Some(stream), Ok(used)) = (self.stream.as_mut(), icarus_wire::encode(state, &mut buf)) {
+            match stream.write_all(used) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return false,
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    self.stream = None;
+                }
+            }
+        }
+
+        true
+    }
+}
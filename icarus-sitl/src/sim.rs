@@ -0,0 +1,189 @@
+//
+// sim.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 20 2022
+//
+
+use crate::{
+    dynamics::{RigidBody, VehicleParams},
+    motor::Motor,
+    sensors::{Barometer, BarometerParams, Imu, ImuParams},
+};
+
+use icarus_core::{
+    mixer::NUM_MOTORS,
+    params::Parameters,
+    EstimatedState,
+};
+use icarus_flight::{
+    hal::{Clock, Motors, NoBattery},
+    FlightTask,
+};
+use icarus_wire::{CommandRequest, IcarusCommand, IcarusState, MessageCounts};
+
+/// Simulation settings
+#[derive(Debug, Clone, Copy)]
+pub struct SimConfig {
+    /// Flight task loop rate (Hz)
+    pub control_rate: u16,
+    /// Physics update rate (Hz)
+    pub physics_rate: f32,
+    /// Sensor noise seed
+    pub seed: u64,
+    pub vehicle: VehicleParams,
+    pub imu: ImuParams,
+    pub barometer: BarometerParams,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            control_rate: Parameters::default().loop_rate,
+            physics_rate: 2000.0,
+            seed: 0,
+            vehicle: VehicleParams::default(),
+            imu: ImuParams::default(),
+            barometer: BarometerParams::default(),
+        }
+    }
+}
+
+/// Latches the flight task's motor outputs for the physics step
+#[derive(Default)]
+pub struct SimMotors {
+    outputs: [f32; NUM_MOTORS],
+}
+
+impl Motors for SimMotors {
+    type Error = ();
+
+    fn set(&mut self, outputs: &[f32; NUM_MOTORS]) -> Result<(), ()> {
+        self.outputs = *outputs;
+        Ok(())
+    }
+}
+
+/// Simulated time
+#[derive(Default)]
+pub struct SimClock {
+    now_us: u64,
+}
+
+impl Clock for SimClock {
+    fn now_us(&self) -> u64 {
+        self.now_us
+    }
+}
+
+pub type SimFlightTask = FlightTask<Imu, Barometer, NoBattery, SimMotors, SimClock>;
+
+/// Simulated vehicle running the flight task
+pub struct Simulator {
+    config: SimConfig,
+    time_us: u64,
+    body: RigidBody,
+    motors: [Motor; NUM_MOTORS],
+    task: SimFlightTask,
+}
+
+impl Simulator {
+    pub fn new(config: SimConfig) -> Self {
+        let vehicle = config.vehicle;
+        let motor = Motor::new(vehicle.motor_time_constant, vehicle.max_thrust);
+
+        let task = FlightTask::new(
+            Imu::new(config.imu, config.seed),
+            Barometer::new(config.barometer, config.seed.wrapping_add(1)),
+            NoBattery,
+            SimMotors::default(),
+            SimClock::default(),
+            Parameters { loop_rate: config.control_rate, ..Default::default() },
+        );
+
+        Self {
+            config,
+            time_us: 0,
+            body: RigidBody::default(),
+            motors: [motor; NUM_MOTORS],
+            task,
+        }
+    }
+
+    /// Simulated time (s)
+    pub fn time(&self) -> f32 {
+        self.time_us as f32 * 1e-6
+    }
+
+    /// True vehicle state
+    pub fn body(&self) -> &RigidBody {
+        &self.body
+    }
+
+    /// Mutable access to the true vehicle state, to set up initial conditions or apply disturbances
+    pub fn body_mut(&mut self) -> &mut RigidBody {
+        &mut self.body
+    }
+
+    pub fn task(&self) -> &SimFlightTask {
+        &self.task
+    }
+
+    /// Latest output of the state estimator
+    pub fn estimated_state(&self) -> &EstimatedState {
+        self.task.estimated_state()
+    }
+
+    /// Apply a command from the host
+    pub fn handle_command(&mut self, cmd: IcarusCommand) {
+        if let Err(e) = self.task.handle_command(cmd) {
+            eprintln!("Command rejected: {:?}", e);
+        }
+    }
+
+    /// Apply a tagged command from the host. The response is pushed to `telemetry`
+    pub fn handle_request(&mut self, request: CommandRequest, telemetry: &mut Vec<IcarusState>) {
+        if let Some(Err(e)) = self.task.handle_request(request, |state| telemetry.push(state)) {
+            eprintln!("Command rejected: {:?}", e);
+        }
+    }
+
+    /// Count telemetry that could not be sent to the host
+    pub fn record_dropped(&mut self, dropped: &MessageCounts) {
+        self.task.record_dropped(dropped);
+    }
+
+    /// Count command frames that failed the integrity check
+    pub fn record_corrupt_frames(&mut self, n: u32) {
+        self.task.record_corrupt_frames(n);
+    }
+
+    /// Advance the simulation by one control period. Telemetry produced by the flight task is pushed to `telemetry`
+    pub fn step(&mut self, telemetry: &mut Vec<IcarusState>) {
+        let rate = self.task.rate_hz();
+        let period_us = (1e6 / rate) as u64;
+        let substeps = (self.config.physics_rate / rate).round().max(1.0) as usize;
+        let physics_dt = period_us as f32 * 1e-6 / substeps as f32;
+
+        // Motor outputs are held between control updates
+        let outputs = self.task.motors().outputs;
+
+        for _ in 0..substeps {
+            let mut thrusts = [0.0; NUM_MOTORS];
+            for ((motor, output), thrust) in self.motors.iter_mut().zip(outputs.iter()).zip(thrusts.iter_mut()) {
+                motor.update(*output, physics_dt);
+                *thrust = motor.thrust();
+            }
+
+            self.body.step(&self.config.vehicle, &thrusts, physics_dt);
+        }
+
+        self.time_us += period_us;
+
+        self.task.imu_mut().set_body(self.body, self.time_us);
+        self.task.barometer_mut().set_body(self.body);
+        self.task.clock_mut().now_us = self.time_us;
+
+        self.task.update(|state| telemetry.push(state));
+    }
+}
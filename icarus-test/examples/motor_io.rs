@@ -20,21 +20,19 @@ fn main() -> ! {
     let mut drv1_en = hw.drv1_en;
     let mut drv2_en = hw.drv2_en;
 
-    let mut rtrctl1 = hw.rtrctl1;
-    let mut rtrctl2 = hw.rtrctl2;
-    let mut rtrctl3 = hw.rtrctl3;
-    let mut rtrctl4 = hw.rtrctl4;
+    let mut rotors = hw.rotors;
 
     let mut delay = hw.delay;
 
     drv1_en.set_high().unwrap();
     drv2_en.set_high().unwrap();
 
+    let mut on = false;
+
     loop {
-        rtrctl1.toggle().unwrap();
-        rtrctl2.toggle().unwrap();
-        rtrctl3.toggle().unwrap();
-        rtrctl4.toggle().unwrap();
+        on = !on;
+        let output = if on { 1.0 } else { 0.0 };
+        rotors.set(&[output; 4]).unwrap();
 
         delay.delay_ms(1000u32);
     }
@@ -11,15 +11,25 @@ use panic_halt as _;
 
 use riscv_rt::entry;
 
-use icarus::{
-    prelude::*,
-    smart_leds::{
-        brightness, gamma,
-        hsv::{hsv2rgb, Hsv},
-        SmartLedsWrite,
-    },
-    Icarus,
-};
+use icarus::{prelude::*, Icarus};
+use icarus_flight::led::{Pattern, Rgb};
+
+/// Time each pattern is shown for
+const PATTERN_MS: u32 = 4000;
+/// Refresh period
+const STEP_MS: u32 = 20;
+
+const SEQUENCE: [Rgb; 3] = [Rgb::RED, Rgb::GREEN, Rgb::BLUE];
+
+const PATTERNS: [Pattern; 7] = [
+    Pattern::Rainbow { period_ms: 5000 },
+    Pattern::Solid(Rgb::PURPLE),
+    Pattern::Blink { color: Rgb::GREEN, period_ms: 1000 },
+    Pattern::DoubleBlink { color: Rgb::ORANGE, period_ms: 1000 },
+    Pattern::Breathe { color: Rgb::BLUE, period_ms: 1000 },
+    Pattern::Sequence { colors: &SEQUENCE, step_ms: 150 },
+    Pattern::ErrorCode { color: Rgb::RED, code: 3 },
+];
 
 #[entry]
 fn main() -> ! {
@@ -27,22 +37,12 @@ fn main() -> ! {
     let mut led = hw.stat;
     let mut delay = hw.delay;
 
-    let mut color = Hsv {
-        hue: 0,
-        sat: 255,
-        val: 255,
-    };
-    let mut data;
-
     loop {
-        for hue in 0..=255 {
-            color.hue = hue;
-            data = [hsv2rgb(color)];
-
-            led.write(brightness(gamma(data.iter().cloned()), 10))
-                .unwrap();
-
-            delay.delay_ms(20u8);
+        for pattern in PATTERNS.iter() {
+            for t in (0..PATTERN_MS).step_by(STEP_MS as usize) {
+                led.set(pattern.color_at(t)).unwrap();
+                delay.delay_ms(STEP_MS);
+            }
         }
     }
 }
@@ -11,15 +11,8 @@ use panic_halt as _;
 
 use riscv_rt::entry;
 
-use icarus::{
-    prelude::*,
-    smart_leds::{
-        brightness, gamma,
-        hsv::{hsv2rgb, Hsv},
-        SmartLedsWrite,
-    },
-    Icarus,
-};
+use icarus::{prelude::*, Icarus};
+use icarus_flight::led::Rgb;
 
 #[entry]
 fn main() -> ! {
@@ -28,44 +21,14 @@ fn main() -> ! {
     let btn = hw.user_btn;
     let mut delay = hw.delay;
 
-    let error_color = Hsv {
-        hue: 0,
-        sat: 255,
-        val: 255,
-    };
-
-    let off_color = Hsv {
-        hue: 50,
-        sat: 255,
-        val: 255,
-    };
-
-    let on_color = Hsv {
-        hue: 150,
-        sat: 255,
-        val: 255,
-    };
-
-    let mut data;
-
     loop {
-        let color = {
-            match btn.is_low() {
-                Ok(state) => {
-                    if state {
-                        off_color
-                    } else {
-                        on_color
-                    }
-                }
-                Err(_) => error_color,
-            }
+        let color = match btn.is_low() {
+            Ok(true) => Rgb::YELLOW,
+            Ok(false) => Rgb::CYAN,
+            Err(_) => Rgb::RED,
         };
 
-        data = [hsv2rgb(color)];
-
-        led.write(brightness(gamma(data.iter().cloned()), 10))
-            .unwrap();
+        led.set(color).unwrap();
         delay.delay_ms(20u8);
     }
 }
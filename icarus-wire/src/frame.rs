@@ -0,0 +1,176 @@
+//
+// frame.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use postcard::ser_flavors::{Cobs, Flavor, Slice};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Bytes of CRC at the end of each frame payload
+pub const CRC_SIZE: usize = 2;
+
+/// Framing errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Value could not be serialized, or the frame does not hold the expected type
+    Postcard(postcard::Error),
+    /// Frame is corrupted: bad COBS encoding, too short or the CRC does not match
+    Integrity,
+}
+
+impl From<postcard::Error> for Error {
+    fn from(e: postcard::Error) -> Self {
+        Error::Postcard(e)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// CRC-16/CCITT-FALSE
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| crc16_update(crc, *byte))
+}
+
+fn crc16_update(crc: u16, byte: u8) -> u16 {
+    let mut crc = crc ^ ((byte as u16) << 8);
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+    }
+    crc
+}
+
+/// Appends the CRC of everything serialized through it
+struct Crc<B: Flavor> {
+    flav: B,
+    crc: u16,
+}
+
+impl<B: Flavor> Flavor for Crc<B> {
+    type Output = B::Output;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.crc = crc16_update(self.crc, data);
+        self.flav.try_push(data)
+    }
+
+    fn finalize(mut self) -> postcard::Result<Self::Output> {
+        self.flav.try_extend(&self.crc.to_le_bytes())?;
+        self.flav.finalize()
+    }
+}
+
+/// Serialize `value` into a COBS frame with a CRC over the payload. Returns the used part of `buf`, including the
+/// zero delimiter
+pub fn encode<'a, T: Serialize + ?Sized>(value: &T, buf: &'a mut [u8]) -> Result<&'a mut [u8]> {
+    let flavor = Crc { flav: Cobs::try_new(Slice::new(buf))?, crc: 0xFFFF };
+    Ok(postcard::serialize_with_flavor(value, flavor)?)
+}
+
+/// Check and deserialize one frame. The frame is decoded in place, the zero delimiter is optional
+pub fn decode<'a, T: Deserialize<'a>>(frame: &'a mut [u8]) -> Result<T> {
+    let end = frame.iter().position(|b| *b == 0).unwrap_or(frame.len());
+    let frame = &mut frame[..end];
+
+    let len = cobs::decode_in_place(frame).map_err(|_| Error::Integrity)?;
+    if len < CRC_SIZE {
+        return Err(Error::Integrity);
+    }
+
+    let frame: &'a [u8] = &frame[..len];
+    let (payload, crc) = frame.split_at(len - CRC_SIZE);
+    if crc16(payload).to_le_bytes() != crc {
+        return Err(Error::Integrity);
+    }
+
+    Ok(postcard::from_bytes(payload)?)
+}
+
+/// Result of feeding bytes to a [`FrameAccumulator`]
+pub enum FeedResult<'a, T> {
+    /// All input was buffered, no frame is complete yet
+    Consumed,
+    /// Frame did not fit in the buffer and was dropped
+    OverFull(&'a [u8]),
+    /// Frame passed the integrity check but does not hold a `T`
+    DeserError(&'a [u8]),
+    /// Frame is corrupted and was dropped
+    IntegrityError(&'a [u8]),
+    /// Frame decoded. `remaining` is the input after the frame
+    Success { data: T, remaining: &'a [u8] },
+}
+
+/// Collects frames from a byte stream that may split or join them arbitrarily
+pub struct FrameAccumulator<const N: usize> {
+    buf: [u8; N],
+    idx: usize,
+}
+
+impl<const N: usize> Default for FrameAccumulator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FrameAccumulator<N> {
+    pub const fn new() -> Self {
+        Self { buf: [0; N], idx: 0 }
+    }
+
+    /// Feed bytes until a frame completes. Call again with the returned remainder until `Consumed`
+    pub fn feed<'a, T: DeserializeOwned>(&mut self, input: &'a [u8]) -> FeedResult<'a, T> {
+        // Delimiters with nothing between them are not frames
+        let input = if self.idx == 0 {
+            let start = input.iter().position(|b| *b != 0).unwrap_or(input.len());
+            &input[start..]
+        }
+        else {
+            input
+        };
+
+        if input.is_empty() {
+            return FeedResult::Consumed;
+        }
+
+        let end = match input.iter().position(|b| *b == 0) {
+            Some(end) => end,
+            None => {
+                if !self.extend(input) {
+                    return FeedResult::OverFull(&[]);
+                }
+                return FeedResult::Consumed;
+            }
+        };
+
+        let (frame, remaining) = input.split_at(end + 1);
+
+        if !self.extend(frame) {
+            return FeedResult::OverFull(remaining);
+        }
+
+        let result = decode(&mut self.buf[..self.idx]);
+        self.idx = 0;
+
+        match result {
+            Ok(data) => FeedResult::Success { data, remaining },
+            Err(Error::Integrity) => FeedResult::IntegrityError(remaining),
+            Err(Error::Postcard(_)) => FeedResult::DeserError(remaining),
+        }
+    }
+
+    /// Append to the frame in progress. Drops it if it no longer fits
+    fn extend(&mut self, data: &[u8]) -> bool {
+        match self.buf.get_mut(self.idx..self.idx + data.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(data);
+                self.idx += data.len();
+                true
+            }
+            None => {
+                self.idx = 0;
+                false
+            }
+        }
+    }
+}
@@ -3,17 +3,19 @@ use serde::{Serialize, Deserialize};
 
 use icarus_core::{
     EstimatedState, EstimatorInput,
+    arming::ArmingBlockers,
+    battery::BatteryStatus,
+    calibration::{CalibrationKind, CalibrationStatus},
+    control::{AttitudeSetpoint, RateSetpoint},
+    health::SensorHealth,
+    mode::{FlightMode, PilotInput},
+    params::Parameter,
 };
 
-// Re-export postcard functions for encoding and decoding
-pub use postcard::{
-    to_slice_cobs as encode,
-    take_from_bytes_cobs as decode,
-    to_vec_cobs as decode_vec,
-    accumulator::{CobsAccumulator, FeedResult},
-};
+mod frame;
 
-pub use postcard::{Result, Error};
+// COBS framed postcard with a CRC on every frame
+pub use frame::{crc16, decode, encode, Error, FeedResult, FrameAccumulator, Result, CRC_SIZE};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct BarometerRaw {
@@ -21,17 +23,51 @@ pub struct BarometerRaw {
     pub temp: f32,
 }
 
-/// IMU calibration offset
-pub struct ImuCalibrationOffset {}
-
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct BatteryState {
-    /// Battery voltage
+    /// Battery voltage (mV)
     pub voltage: u16,
     /// Raw value from ADC
     pub adc_raw: u16,
     /// Charge state from the LiPo charger
     pub charge_complete: bool,
+    /// Estimated resting voltage with the sag under load removed (mV)
+    pub compensated_voltage: u16,
+    /// Estimated voltage drop under the present load (mV)
+    pub sag: u16,
+    /// State of charge (%)
+    pub state_of_charge: u8,
+    pub status: BatteryStatus,
+}
+
+/// Mean and worst case of a duration in microseconds
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct TimingStats {
+    pub mean_us: u32,
+    pub max_us: u32,
+}
+
+/// Control loop timing over the last report window
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct LoopStats {
+    /// Configured loop rate (Hz)
+    pub rate: u16,
+    /// Iterations per second over the window
+    pub measured_rate: f32,
+    /// Iterations that finished after the next deadline
+    pub overruns: u32,
+    /// How late each iteration started relative to its deadline
+    pub jitter: TimingStats,
+    /// Reading the sensors
+    pub sensors: TimingStats,
+    /// Health checks, calibration and state estimation
+    pub estimation: TimingStats,
+    /// Attitude controller and mixer
+    pub control: TimingStats,
+    /// Writing the motor outputs
+    pub output: TimingStats,
+    /// Fraction of the window spent running the loop
+    pub load: f32,
 }
 
 /// Data reporting channels for Icarus
@@ -40,10 +76,197 @@ pub enum IcarusState {
     Sensors(EstimatorInput),
     EstimatedState(EstimatedState),
     Battery(BatteryState),
+    Health(SensorHealth),
+    Calibration(CalibrationStatus),
+    LoopStats(LoopStats),
+    /// Command with this id was accepted
+    Ack(u16),
+    /// Command with this id was rejected
+    Nack(u16, NackReason),
+    TelemetryStats(TelemetryStats),
+}
+
+impl IcarusState {
+    /// Channel the message is rate limited on. `None` for command responses and calibration progress
+    pub fn channel(&self) -> Option<TelemetryChannel> {
+        match self {
+            IcarusState::Sensors(_) => Some(TelemetryChannel::Sensors),
+            IcarusState::EstimatedState(_) => Some(TelemetryChannel::State),
+            IcarusState::Battery(_) => Some(TelemetryChannel::Battery),
+            IcarusState::Health(_) => Some(TelemetryChannel::Health),
+            IcarusState::LoopStats(_) => Some(TelemetryChannel::LoopStats),
+            IcarusState::TelemetryStats(_) => Some(TelemetryChannel::Stats),
+            IcarusState::Calibration(_) | IcarusState::Ack(_) | IcarusState::Nack(..) => None,
+        }
+    }
+}
+
+/// Periodic telemetry the host can subscribe to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryChannel {
+    /// Calibrated IMU and barometer readings
+    Sensors,
+    /// Estimated attitude
+    State,
+    Battery,
+    Health,
+    LoopStats,
+    /// Subscribed rates and dropped messages
+    Stats,
+}
+
+impl TelemetryChannel {
+    pub const COUNT: usize = 6;
+    pub const ALL: [TelemetryChannel; TelemetryChannel::COUNT] = [
+        TelemetryChannel::Sensors,
+        TelemetryChannel::State,
+        TelemetryChannel::Battery,
+        TelemetryChannel::Health,
+        TelemetryChannel::LoopStats,
+        TelemetryChannel::Stats,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Message counts per telemetry channel
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MessageCounts {
+    /// Indexed by `TelemetryChannel`
+    pub channels: [u32; TelemetryChannel::COUNT],
+    /// Command responses and calibration progress
+    pub other: u32,
+}
+
+impl MessageCounts {
+    /// Count one message
+    pub fn record(&mut self, state: &IcarusState) {
+        let count = match state.channel() {
+            Some(channel) => &mut self.channels[channel.index()],
+            None => &mut self.other,
+        };
+        *count = count.wrapping_add(1);
+    }
+
+    /// Add counts from another window
+    pub fn add(&mut self, other: &MessageCounts) {
+        for (count, n) in self.channels.iter_mut().zip(other.channels.iter()) {
+            *count = count.wrapping_add(*n);
+        }
+        self.other = self.other.wrapping_add(other.other);
+    }
+
+    pub fn get(&self, channel: TelemetryChannel) -> u32 {
+        self.channels[channel.index()]
+    }
+
+    pub fn total(&self) -> u32 {
+        self.channels.iter().fold(self.other, |total, n| total.wrapping_add(*n))
+    }
+}
+
+/// Telemetry subscriptions and delivery
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct TelemetryStats {
+    /// Rate of each channel (Hz), zero when unsubscribed. Indexed by `TelemetryChannel`
+    pub rates: [u16; TelemetryChannel::COUNT],
+    /// Messages dropped since boot because the link could not keep up
+    pub dropped: MessageCounts,
+    /// Command frames dropped since boot because they failed the integrity check
+    pub corrupt_frames: u32,
+}
+
+/// Why a command was rejected
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NackReason {
+    /// Not allowed while armed
+    Armed,
+    /// Pre-arm checks failed
+    ArmingBlocked(ArmingBlockers),
+    /// Not allowed while the IMU is being calibrated
+    Calibrating,
+    /// Motor tests require the propellers to be removed
+    PropsOn,
+    /// No such motor
+    InvalidMotor,
+    /// Setpoint does not match the current flight mode
+    WrongMode,
+    /// Altitude hold needs a valid barometer
+    NoAltitude,
+    /// Altitude hold can only be entered while flying
+    NotArmed,
+    /// Command queue was full, try again
+    Busy,
+    /// Thrust steps must confirm the rotor is on a thrust stand
+    NoThrustStand,
+    /// Duty is above the bench limit
+    DutyLimit,
+}
+
+/// Spin a single rotor on the bench
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct MotorTest {
+    /// Rotor in mixer order (0 - 3)
+    pub motor: u8,
+    /// Output (%)
+    pub duty: u8,
+    /// How long to spin for (ms). Zero stops a test in progress
+    pub duration_ms: u16,
+    /// The operator confirmed the propellers are removed
+    pub props_off: bool,
+}
+
+/// Hold one rotor at a fixed duty with its propeller on, for thrust measurements on a test stand
+///
+/// The duty may not exceed `Parameters::bench_max_duty`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ThrustStep {
+    /// Rotor in mixer order (0 - 3)
+    pub motor: u8,
+    /// Duty (%). Sent to the motor as is, the motor curve is not applied
+    pub duty: u8,
+    /// How long to hold the duty for (ms). Zero stops a step in progress
+    pub duration_ms: u16,
+    /// The operator confirmed the rotor is secured to a thrust stand
+    pub thrust_stand: bool,
 }
 
 /// Icarus command channels
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum IcarusCommand {
+    /// Roll angle (deg), pitch angle (deg) and thrust (%). Superseded by `Attitude`
     Throttle(i8, i8, i8),
+    /// Start an IMU calibration procedure
+    Calibrate(CalibrationKind),
+    /// Update a persistent parameter
+    SetParameter(Parameter),
+    /// Spin one rotor while disarmed
+    MotorTest(MotorTest),
+    /// Bench thrust measurement while disarmed
+    ThrustStep(ThrustStep),
+    /// Angle mode or altitude hold setpoint. A positive thrust arms, zero thrust disarms
+    Attitude(AttitudeSetpoint),
+    /// Rate mode setpoint. A positive thrust arms, zero thrust disarms
+    Rate(RateSetpoint),
+    /// Select how setpoints are interpreted
+    SetMode(FlightMode),
+    /// Restart the controller while disarmed
+    Reboot,
+    /// Disarm and stop every motor immediately
+    MotorStop,
+    /// Normalized sticks, interpreted by the current flight mode
+    Sticks(PilotInput),
+    /// Set the rate of a telemetry channel (Hz). Zero unsubscribes. Capped at the loop rate
+    Subscribe(TelemetryChannel, u16),
+}
+
+/// Command tagged with an id. The controller answers with an `Ack` or `Nack` carrying the same id
+///
+/// Retries reuse the id so the controller can answer them without running the command twice.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct CommandRequest {
+    pub id: u16,
+    pub command: IcarusCommand,
 }
@@ -0,0 +1,220 @@
+//
+// framing.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 07 2022
+//
+
+use icarus_wire::{
+    self, CommandRequest, Error, FeedResult, FrameAccumulator, IcarusCommand, IcarusState, MotorTest, NackReason,
+};
+use icarus_core::{
+    control::{AttitudeSetpoint, RateSetpoint},
+    mode::PilotInput,
+};
+
+/// Deterministic xorshift so failures reproduce
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn f32(&mut self) -> f32 {
+        (self.next() % 20_000) as f32 / 10_000.0 - 1.0
+    }
+}
+
+fn random_request(rng: &mut Rng) -> CommandRequest {
+    let command = match rng.below(5) {
+        0 => IcarusCommand::Throttle(rng.next() as i8, rng.next() as i8, rng.next() as i8),
+        1 => IcarusCommand::Attitude(AttitudeSetpoint {
+            roll: rng.f32(),
+            pitch: rng.f32(),
+            yaw_rate: rng.f32(),
+            thrust: rng.f32(),
+        }),
+        2 => IcarusCommand::Rate(RateSetpoint { roll: rng.f32(), pitch: rng.f32(), yaw: rng.f32(), thrust: rng.f32() }),
+        3 => IcarusCommand::Sticks(PilotInput { roll: rng.f32(), pitch: 0.0, yaw: 0.0, thrust: rng.f32() }),
+        _ => IcarusCommand::MotorTest(MotorTest {
+            motor: rng.next() as u8,
+            duty: rng.next() as u8,
+            duration_ms: rng.next() as u16,
+            props_off: rng.next() & 1 == 0,
+        }),
+    };
+
+    CommandRequest { id: rng.next() as u16, command }
+}
+
+fn encode(request: &CommandRequest) -> Vec<u8> {
+    let mut buf = [0; 64];
+    icarus_wire::encode(request, &mut buf).unwrap().to_vec()
+}
+
+/// Feed everything, collecting decoded requests and counting rejected frames
+fn feed_all<const N: usize>(acc: &mut FrameAccumulator<N>, input: &[u8]) -> (Vec<CommandRequest>, usize) {
+    let mut decoded = Vec::new();
+    let mut rejected = 0;
+
+    let mut window = input;
+    while !window.is_empty() {
+        window = match acc.feed::<CommandRequest>(window) {
+            FeedResult::Consumed => break,
+            FeedResult::OverFull(remaining)
+            | FeedResult::DeserError(remaining)
+            | FeedResult::IntegrityError(remaining) => {
+                rejected += 1;
+                remaining
+            }
+            FeedResult::Success { data, remaining } => {
+                decoded.push(data);
+                remaining
+            }
+        }
+    }
+
+    (decoded, rejected)
+}
+
+#[test]
+fn crc_check_value() {
+    assert_eq!(icarus_wire::crc16(b"123456789"), 0x29B1);
+    assert_eq!(icarus_wire::crc16(&[]), 0xFFFF);
+}
+
+#[test]
+fn frames_round_trip() {
+    let mut rng = Rng(0x1CA25EED);
+
+    for _ in 0..1000 {
+        let request = random_request(&mut rng);
+        let mut frame = encode(&request);
+
+        assert_eq!(frame.iter().filter(|b| **b == 0).count(), 1);
+        assert_eq!(*frame.last().unwrap(), 0);
+
+        let decoded: CommandRequest = icarus_wire::decode(&mut frame).unwrap();
+        assert_eq!(encode(&decoded), encode(&request));
+    }
+
+    let mut buf = [0; 32];
+    let state = IcarusState::Nack(7, NackReason::Busy);
+    let frame = icarus_wire::encode(&state, &mut buf).unwrap();
+    assert!(matches!(icarus_wire::decode(frame), Ok(IcarusState::Nack(7, NackReason::Busy))));
+}
+
+#[test]
+fn every_single_bit_flip_is_rejected() {
+    let mut rng = Rng(0x0B17F11B);
+
+    for _ in 0..200 {
+        let request = random_request(&mut rng);
+        let frame = encode(&request);
+
+        // The delimiter is left alone, a flipped delimiter just joins the frame onto the next one
+        for byte in 0..frame.len() - 1 {
+            for bit in 0..8 {
+                let mut corrupted = frame.clone();
+                corrupted[byte] ^= 1 << bit;
+
+                let mut acc = FrameAccumulator::<64>::new();
+                let (decoded, rejected) = feed_all(&mut acc, &corrupted);
+                assert!(decoded.is_empty(), "{:?} with byte {} bit {} flipped decoded", request, byte, bit);
+                assert!(rejected > 0);
+
+                let mut copy = corrupted.clone();
+                assert!(icarus_wire::decode::<CommandRequest>(&mut copy).is_err());
+            }
+        }
+    }
+}
+
+#[test]
+fn frames_without_a_crc_are_rejected() {
+    // Postcard COBS frame as sent before frames carried a CRC
+    let mut buf = [0; 32];
+    let frame = postcard::to_slice_cobs(&IcarusCommand::Throttle(0, 0, 100), &mut buf).unwrap();
+
+    let mut acc = FrameAccumulator::<64>::new();
+    assert!(matches!(acc.feed::<IcarusCommand>(frame), FeedResult::IntegrityError(_)));
+
+    let mut copy = frame.to_vec();
+    assert_eq!(icarus_wire::decode::<IcarusCommand>(&mut copy).err(), Some(Error::Integrity));
+}
+
+#[test]
+fn stream_split_at_random_points() {
+    let mut rng = Rng(0x057BEAA5);
+
+    let requests = (0..500).map(|_| random_request(&mut rng)).collect::<Vec<_>>();
+    let stream = requests.iter().flat_map(encode).collect::<Vec<_>>();
+
+    let mut acc = FrameAccumulator::<64>::new();
+    let mut decoded = Vec::new();
+    let mut rest = &stream[..];
+    while !rest.is_empty() {
+        let (chunk, remaining) = rest.split_at((1 + rng.below(40)).min(rest.len()));
+        let (frames, rejected) = feed_all(&mut acc, chunk);
+        assert_eq!(rejected, 0);
+        decoded.extend(frames);
+        rest = remaining;
+    }
+
+    assert_eq!(decoded.len(), requests.len());
+    for (decoded, request) in decoded.iter().zip(requests.iter()) {
+        assert_eq!(encode(decoded), encode(request));
+    }
+}
+
+#[test]
+fn recovers_after_garbage_and_oversized_frames() {
+    let mut rng = Rng(0x06A2BA6E);
+    let request = random_request(&mut rng);
+
+    // Random noise never panics and the next good frame still gets through
+    for _ in 0..2000 {
+        let noise = (0..rng.below(100)).map(|_| rng.next() as u8).collect::<Vec<_>>();
+
+        let mut input = noise;
+        input.push(0);
+        input.extend(encode(&request));
+
+        let mut acc = FrameAccumulator::<64>::new();
+        let (decoded, _) = feed_all(&mut acc, &input);
+        let last = decoded.last().expect("good frame after noise was lost");
+        assert_eq!(encode(last), encode(&request));
+    }
+
+    // Frame larger than the buffer
+    let mut input = vec![0x55; 100];
+    input.push(0);
+    input.extend(encode(&request));
+
+    let mut acc = FrameAccumulator::<64>::new();
+    let (decoded, rejected) = feed_all(&mut acc, &input);
+    assert_eq!(decoded.len(), 1);
+    assert!(rejected > 0);
+
+    // Empty frames are skipped quietly
+    let mut input = vec![0, 0];
+    input.extend(encode(&request));
+    let (decoded, rejected) = feed_all(&mut acc, &input);
+    assert_eq!((decoded.len(), rejected), (1, 0));
+
+    // However many there are
+    let mut input = vec![0; 1_000_000];
+    input.extend(encode(&request));
+    let (decoded, rejected) = feed_all(&mut acc, &input);
+    assert_eq!((decoded.len(), rejected), (1, 0));
+
+    assert!(matches!(acc.feed::<CommandRequest>(&[0; 16]), FeedResult::Consumed));
+}
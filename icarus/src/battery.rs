@@ -0,0 +1,42 @@
+//
+// battery.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 31 2022
+//
+
+use crate::hal::{
+    adc::{AdcPin, ADC, ADC1},
+    gpio::{Gpio0, Gpio3},
+    gpio_types::{Analog, Input, PullUp},
+    prelude::*,
+};
+
+use icarus_flight::hal::BatterySense;
+
+/// Battery voltage divider on ADC1 channel 3 and the charger's charge complete output on GPIO0
+///
+/// The charge complete output is open drain and pulled low once charging has finished.
+pub struct BatteryAdc {
+    adc: ADC<ADC1>,
+    sense: AdcPin<Gpio3<Analog>, ADC1>,
+    charge_complete: Gpio0<Input<PullUp>>,
+}
+
+impl BatteryAdc {
+    pub fn new(adc: ADC<ADC1>, sense: AdcPin<Gpio3<Analog>, ADC1>, charge_complete: Gpio0<Input<PullUp>>) -> Self {
+        Self { adc, sense, charge_complete }
+    }
+}
+
+impl BatterySense for BatteryAdc {
+    type Error = ();
+
+    fn read_raw(&mut self) -> Result<u16, Self::Error> {
+        nb::block!(self.adc.read(&mut self.sense))
+    }
+
+    fn charge_complete(&mut self) -> bool {
+        self.charge_complete.is_low().unwrap_or(false)
+    }
+}
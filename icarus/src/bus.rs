@@ -0,0 +1,87 @@
+//
+// bus.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 28 2022
+//
+
+use crate::hal::{i2c::I2C, pac::{GPIO, I2C0}, prelude::*, Delay};
+
+use icarus_flight::bus::{I2cBus, I2cLines, I2cProxy, ManagedI2c, NullMutex};
+
+type SensorBusMutex = NullMutex<ManagedI2c<I2C<I2C0>, IcarusI2cLines>>;
+
+/// I2C0, shared by every sensor on the board
+pub type SensorBus = I2cBus<SensorBusMutex>;
+/// A device's handle to the sensor bus
+pub type SensorBusProxy = I2cProxy<'static, SensorBusMutex>;
+
+const SDA_PIN: usize = 1;
+const SCL_PIN: usize = 2;
+
+/// GPIO matrix output signals
+const SIG_GPIO_OUT: u8 = 128;
+const SIG_I2CEXT0_SCL: u8 = 53;
+const SIG_I2CEXT0_SDA: u8 = 54;
+
+/// Raw control of the I2C0 pins for bus recovery
+///
+/// The pads are already open drain with pull ups from I2C setup, so recovery only needs to switch the GPIO matrix
+/// between the peripheral and the output registers.
+pub struct IcarusI2cLines {
+    delay: Delay,
+}
+
+impl IcarusI2cLines {
+    pub fn new(delay: Delay) -> Self {
+        Self { delay }
+    }
+
+    fn route(pin: usize, signal: u8) {
+        unsafe { &*GPIO::PTR }.func_out_sel_cfg[pin].modify(|_, w| unsafe { w.out_sel().bits(signal) });
+    }
+
+    fn set(pin: usize, high: bool) {
+        let gpio = unsafe { &*GPIO::PTR };
+        if high {
+            gpio.out_w1ts.write(|w| unsafe { w.bits(1 << pin) });
+        }
+        else {
+            gpio.out_w1tc.write(|w| unsafe { w.bits(1 << pin) });
+        }
+    }
+}
+
+impl I2cLines for IcarusI2cLines {
+    fn acquire(&mut self) {
+        Self::set(SDA_PIN, true);
+        Self::set(SCL_PIN, true);
+        Self::route(SDA_PIN, SIG_GPIO_OUT);
+        Self::route(SCL_PIN, SIG_GPIO_OUT);
+
+        let gpio = unsafe { &*GPIO::PTR };
+        gpio.enable_w1ts.write(|w| unsafe { w.bits((1 << SDA_PIN) | (1 << SCL_PIN)) });
+    }
+
+    fn release(&mut self) {
+        Self::route(SDA_PIN, SIG_I2CEXT0_SDA);
+        Self::route(SCL_PIN, SIG_I2CEXT0_SCL);
+    }
+
+    fn set_scl(&mut self, high: bool) {
+        Self::set(SCL_PIN, high);
+    }
+
+    fn set_sda(&mut self, high: bool) {
+        Self::set(SDA_PIN, high);
+    }
+
+    fn sda_is_high(&self) -> bool {
+        unsafe { &*GPIO::PTR }.in_.read().bits() & (1 << SDA_PIN) != 0
+    }
+
+    fn delay(&mut self) {
+        // Half a period at 100 kHz
+        self.delay.delay_us(5u32);
+    }
+}
@@ -0,0 +1,20 @@
+//
+// clock.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 29 2022
+//
+
+use crate::hal::systimer::SystemTimer;
+
+use icarus_flight::hal::Clock;
+
+/// Time since boot from the 16 MHz system timer
+#[derive(Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_us(&self) -> u64 {
+        SystemTimer::now() / (SystemTimer::TICKS_PER_SECOND / 1_000_000)
+    }
+}
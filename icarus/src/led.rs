@@ -0,0 +1,40 @@
+//
+// led.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Sep 01 2022
+//
+
+use crate::hal::{
+    gpio::Gpio21,
+    gpio_types::Unknown,
+    pulse_control::Channel0,
+    utils::{smart_leds_adapter::LedAdapterError, SmartLedsAdapter},
+};
+
+use icarus_flight::{hal::Led, led::Rgb};
+
+use smart_leds::{brightness, gamma, SmartLedsWrite, RGB8};
+
+/// Full brightness is uncomfortable to look at
+const BRIGHTNESS: u8 = 10;
+
+/// WS2812 status LED on GPIO21
+pub struct StatLed {
+    adapter: SmartLedsAdapter<Channel0, Gpio21<Unknown>, 25>,
+}
+
+impl StatLed {
+    pub fn new(adapter: SmartLedsAdapter<Channel0, Gpio21<Unknown>, 25>) -> Self {
+        Self { adapter }
+    }
+}
+
+impl Led for StatLed {
+    type Error = LedAdapterError;
+
+    fn set(&mut self, color: Rgb) -> Result<(), Self::Error> {
+        let data = [RGB8 { r: color.r, g: color.g, b: color.b }];
+        self.adapter.write(brightness(gamma(data.iter().cloned()), BRIGHTNESS))
+    }
+}
@@ -10,61 +10,113 @@ pub use esp32c3_hal as hal;
 use hal::gpio_types::Input;
 pub use smart_leds;
 
+pub mod battery;
+pub mod bus;
+pub mod clock;
+pub mod led;
+pub mod motors;
+pub mod usb;
+
 pub mod prelude {
     pub use crate::hal::prelude::*;
+    pub use icarus_flight::hal::{Barometer as _, BatterySense as _, Clock as _, Imu as _, Led as _, Motors as _};
 }
 
-use crate::hal::{
-    clock::ClockControl,
-    gpio::*,
-    gpio_types::{Floating, Output, PushPull, Unknown},
-    pac::Peripherals,
-    prelude::*,
-    pulse_control::{Channel0, ClockSource},
-    utils::{smartLedAdapter, SmartLedsAdapter},
-    Delay, PulseControl, RtcCntl, Timer,
+use crate::{
+    battery::BatteryAdc,
+    bus::{IcarusI2cLines, SensorBus, SensorBusProxy},
+    clock::SystemClock,
+    led::StatLed,
+    motors::RotorPwm,
+    usb::UsbSerial,
+    hal::{
+        adc::{AdcConfig, Attenuation, ADC, ADC1},
+        analog::SarAdcExt,
+        clock::{ClockControl, Clocks},
+        gpio::*,
+        gpio_types::{Floating, Output, PushPull},
+        i2c::I2C,
+        pac::Peripherals,
+        prelude::*,
+        pulse_control::ClockSource,
+        utils::smartLedAdapter,
+        Delay, PulseControl, RtcCntl, Timer,
+    },
+};
+
+use icarus_core::motor::MotorProtocol;
+use icarus_flight::{
+    esc::MotorOutput,
+    drivers::{
+        bmp388::Bmp388,
+        mpu6050::{self, Mpu6050, Mpu6050Config},
+    },
+    hal::NoInterrupt,
 };
 
+/// MPU6050 on the sensor bus. The INT line is not routed so samples are collected from the FIFO
+pub type Imu = Mpu6050<SensorBusProxy, SystemClock, NoInterrupt>;
+/// BMP388 on the sensor bus
+pub type Barometer = Bmp388<SensorBusProxy>;
+
 #[derive(Debug)]
 pub enum IcarusError {
     HardwareInitError,
+    /// The motor protocol needs an output stage this firmware does not have
+    UnsupportedMotorProtocol,
 }
 
 /// Icarus Hardware Interface
 pub struct Icarus {
-    // TODO: I2C
+    // Sensor I2C, shared by the MPU6050 and BMP388
+    pub i2c: &'static SensorBus,
+    // IMU
+    pub imu: Imu,
+    // Barometer
+    pub barometer: Barometer,
 
     // Drive 2 Enable
     pub drv2_en: Gpio6<Output<PushPull>>,
-    // Rotor 2 Control
-    pub rtrctl2: Gpio7<Output<PushPull>>,
-    // Robot 1 Control
-    pub rtrctl1: Gpio8<Output<PushPull>>,
-
     // Drive 1 Enable
     pub drv1_en: Gpio10<Output<PushPull>>,
-    // Rotor 4 Control
-    pub rtrctl4: Gpio4<Output<PushPull>>,
-    // Rotor 3 Control
-    pub rtrctl3: Gpio5<Output<PushPull>>,
+    // Rotor 1 - 4 Control
+    pub rotors: RotorPwm,
 
-    // User Button
+    // User Button. GPIO9 is a strapping pin, held through reset the chip enters download mode
     pub user_btn: Gpio9<Input<Floating>>,
 
     // Status LED
-    pub stat: SmartLedsAdapter<Channel0, Gpio21<Unknown>, 25>,
+    pub stat: StatLed,
+
+    // Battery sense and charger status
+    pub battery: BatteryAdc,
 
-    // Battery sense
-    pub battery_sense: Gpio3<Output<PushPull>>,
+    // USB serial / JTAG
+    pub serial: UsbSerial,
+
+    // Time since boot
+    pub clock: SystemClock,
 
     // Delay
     pub delay: Delay,
 }
 
 impl Icarus {
-    pub fn init(p: Peripherals) -> Result<Icarus, IcarusError> {
+    /// Bring up the board with rotor outputs for `motor_protocol`. DShot is not supported, the RMT driver in this HAL
+    /// can only drive the status LED
+    pub fn init(p: Peripherals, motor_protocol: MotorProtocol) -> Result<Icarus, IcarusError> {
+        let timing = match MotorOutput::from_protocol(motor_protocol) {
+            MotorOutput::Pwm(timing) => timing,
+            MotorOutput::DShot(_) => return Err(IcarusError::UnsupportedMotorProtocol),
+        };
+
         let mut system = p.SYSTEM.split();
-        let clocks = ClockControl::boot_defaults(system.clock_control).freeze();
+
+        // LEDC channels borrow the clocks for their lifetime. Only one Icarus can be created, Peripherals::take
+        // guards against a second
+        static mut CLOCKS: Option<Clocks> = None;
+        let clocks: &'static Clocks =
+            unsafe { CLOCKS.insert(ClockControl::boot_defaults(system.clock_control).freeze()) };
 
         // Disable watchdog timers
         let mut rtc_cntl = RtcCntl::new(p.RTC_CNTL);
@@ -78,13 +130,23 @@ impl Icarus {
 
         let io = IO::new(p.GPIO, p.IO_MUX);
 
-        let drv2_en = io.pins.gpio6.into_push_pull_output();
-        let rtrctl2 = io.pins.gpio7.into_push_pull_output();
-        let rtrctl1 = io.pins.gpio8.into_push_pull_output();
+        // Drivers stay disabled until the firmware enables them
+        let mut drv2_en = io.pins.gpio6.into_push_pull_output();
+        let mut drv1_en = io.pins.gpio10.into_push_pull_output();
+        drv1_en.set_low().ok();
+        drv2_en.set_low().ok();
 
-        let drv1_en = io.pins.gpio10.into_push_pull_output();
-        let rtrctl4 = io.pins.gpio4.into_push_pull_output();
-        let rtrctl3 = io.pins.gpio5.into_push_pull_output();
+        let rotors = RotorPwm::new(
+            p.LEDC,
+            clocks,
+            &mut system.peripheral_clock_control,
+            io.pins.gpio8.into_push_pull_output(),
+            io.pins.gpio7.into_push_pull_output(),
+            io.pins.gpio5.into_push_pull_output(),
+            io.pins.gpio4.into_push_pull_output(),
+            timing,
+        )
+        .map_err(|_| IcarusError::HardwareInitError)?;
 
         let user_btn = io.pins.gpio9.into_floating_input();
 
@@ -98,31 +160,70 @@ impl Icarus {
         )
         .map_err(|_| IcarusError::HardwareInitError)?;
 
-        let stat = <smartLedAdapter!(1)>::new(pulse.channel0, io.pins.gpio21);
+        let stat = StatLed::new(<smartLedAdapter!(1)>::new(pulse.channel0, io.pins.gpio21));
+
+        // Battery voltage through a divider on ADC1 channel 3
+        let analog = p.APB_SARADC.split();
+        let mut adc_config = AdcConfig::new();
+        let battery_sense = adc_config.enable_pin(io.pins.gpio3.into_analog(), Attenuation::Attenuation11dB);
+        let adc = ADC::<ADC1>::adc(&mut system.peripheral_clock_control, analog.adc1, adc_config)
+            .map_err(|_| IcarusError::HardwareInitError)?;
+        let battery = BatteryAdc::new(adc, battery_sense, io.pins.gpio0.into_pull_up_input());
+
+        let delay = Delay::new(clocks);
 
-        let battery_sense = io.pins.gpio3.into_push_pull_output();
+        let i2c = I2C::new(
+            p.I2C0,
+            io.pins.gpio1,
+            io.pins.gpio2,
+            400u32.kHz(),
+            &mut system.peripheral_clock_control,
+            clocks,
+        )
+        .map_err(|_| IcarusError::HardwareInitError)?;
 
-        let delay = Delay::new(&clocks);
+        static mut SENSOR_BUS: Option<SensorBus> = None;
+        let i2c: &'static SensorBus =
+            unsafe { SENSOR_BUS.insert(SensorBus::new(i2c, IcarusI2cLines::new(Delay::new(clocks)))) };
+
+        // Sensors are left unconfigured if they do not respond. The flight task re-initializes the IMU and the
+        // barometer sets itself up on first read
+        let clock = SystemClock;
+        let mut imu = Mpu6050::new(i2c.acquire(), mpu6050::DEFAULT_ADDRESS, Mpu6050Config::default(), clock, NoInterrupt);
+        imu.init().ok();
+
+        let mut barometer = Bmp388::new(i2c.acquire());
+        barometer.init().ok();
 
         Ok(Icarus {
+            i2c,
+            imu,
+            barometer,
             drv1_en,
             drv2_en,
-            rtrctl1,
-            rtrctl2,
-            rtrctl3,
-            rtrctl4,
+            rotors,
             user_btn,
             stat,
-            battery_sense,
+            battery,
+            serial: UsbSerial,
+            clock,
             delay,
         })
     }
 
     pub fn take() -> Result<Icarus, IcarusError> {
         if let Some(p) = Peripherals::take() {
-            Icarus::init(p)
+            Icarus::init(p, MotorProtocol::default())
         } else {
             Err(IcarusError::HardwareInitError)
         }
     }
 }
+
+/// Restart the chip through the RTC controller's software system reset
+pub fn reset() -> ! {
+    unsafe { (*hal::pac::RTC_CNTL::ptr()).options0.modify(|_, w| w.sw_sys_rst().set_bit()) };
+
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
@@ -0,0 +1,137 @@
+//
+// motors.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 29 2022
+//
+
+use crate::hal::{
+    clock::Clocks,
+    gpio::{Gpio4, Gpio5, Gpio7, Gpio8},
+    gpio_types::{Output, PushPull},
+    ledc::{
+        channel::{self, Channel, ChannelHW, ChannelIFace},
+        timer::{self, Timer, TimerIFace},
+        LowSpeed, LSGlobalClkSource, LEDC,
+    },
+    pac,
+    prelude::*,
+    system::PeripheralClockControl,
+};
+
+use icarus_core::mixer::NUM_MOTORS;
+use icarus_flight::{esc::PwmTiming, hal::Motors};
+
+/// Clock feeding the LEDC timer
+const SOURCE_HZ: u32 = 80_000_000;
+
+type RotorChannel<P> = Channel<'static, LowSpeed, P>;
+
+/// Rotor control PWM channels, in mixer order
+pub struct RotorPwm {
+    rtrctl1: RotorChannel<Gpio8<Output<PushPull>>>,
+    rtrctl2: RotorChannel<Gpio7<Output<PushPull>>>,
+    rtrctl3: RotorChannel<Gpio5<Output<PushPull>>>,
+    rtrctl4: RotorChannel<Gpio4<Output<PushPull>>>,
+    timing: PwmTiming,
+    max_duty: u32,
+}
+
+#[derive(Debug)]
+pub enum RotorPwmError {
+    Timer(timer::Error),
+    Channel(channel::Error),
+}
+
+impl RotorPwm {
+    /// Configure the LEDC timer for `timing` and attach the four rotor pins. Every output starts at zero
+    ///
+    /// Channels hold references to the LEDC timer, both live in statics so the channels can be `'static`. Can only be
+    /// called once.
+    pub fn new(
+        ledc: pac::LEDC,
+        clocks: &'static Clocks,
+        peripheral_clock_control: &mut PeripheralClockControl,
+        rtrctl1: Gpio8<Output<PushPull>>,
+        rtrctl2: Gpio7<Output<PushPull>>,
+        rtrctl3: Gpio5<Output<PushPull>>,
+        rtrctl4: Gpio4<Output<PushPull>>,
+        timing: PwmTiming,
+    ) -> Result<Self, RotorPwmError> {
+        static mut LEDC: Option<LEDC<'static>> = None;
+        static mut TIMER: Option<Timer<'static, LowSpeed>> = None;
+
+        let ledc = unsafe { LEDC.insert(LEDC::new(ledc, clocks, peripheral_clock_control)) };
+        ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+
+        let duty_bits = timing.duty_bits(SOURCE_HZ);
+
+        let timer = unsafe { TIMER.insert(ledc.get_timer::<LowSpeed>(timer::Number::Timer0)) };
+        timer
+            .configure(timer::config::Config {
+                duty: duty(duty_bits),
+                clock_source: timer::LSClockSource::APBClk,
+                frequency: timing.frequency_hz.Hz(),
+            })
+            .map_err(RotorPwmError::Timer)?;
+
+        let timer: &'static Timer<'static, LowSpeed> = timer;
+
+        let mut pwm = Self {
+            rtrctl1: ledc.get_channel(channel::Number::Channel0, rtrctl1),
+            rtrctl2: ledc.get_channel(channel::Number::Channel1, rtrctl2),
+            rtrctl3: ledc.get_channel(channel::Number::Channel2, rtrctl3),
+            rtrctl4: ledc.get_channel(channel::Number::Channel3, rtrctl4),
+            timing,
+            max_duty: (1 << duty_bits) - 1,
+        };
+
+        // Channel configuration rejects a zero duty. Start at the smallest percentage then drop to zero
+        let config = channel::config::Config { timer, duty_pct: 1 };
+        pwm.rtrctl1.configure(config).map_err(RotorPwmError::Channel)?;
+        pwm.rtrctl2.configure(config).map_err(RotorPwmError::Channel)?;
+        pwm.rtrctl3.configure(config).map_err(RotorPwmError::Channel)?;
+        pwm.rtrctl4.configure(config).map_err(RotorPwmError::Channel)?;
+
+        pwm.set(&[0.0; NUM_MOTORS]).ok();
+
+        Ok(pwm)
+    }
+}
+
+impl Motors for RotorPwm {
+    type Error = ();
+
+    fn set(&mut self, outputs: &[f32; NUM_MOTORS]) -> Result<(), Self::Error> {
+        let (timing, max_duty) = (self.timing, self.max_duty);
+        let duty = |output: f32| timing.duty(output, max_duty);
+
+        self.rtrctl1.set_duty_hw(duty(outputs[0]));
+        self.rtrctl2.set_duty_hw(duty(outputs[1]));
+        self.rtrctl3.set_duty_hw(duty(outputs[2]));
+        self.rtrctl4.set_duty_hw(duty(outputs[3]));
+
+        Ok(())
+    }
+}
+
+fn duty(bits: u32) -> timer::config::Duty {
+    use timer::config::Duty;
+
+    match bits {
+        1 => Duty::Duty1Bit,
+        2 => Duty::Duty2Bit,
+        3 => Duty::Duty3Bit,
+        4 => Duty::Duty4Bit,
+        5 => Duty::Duty5Bit,
+        6 => Duty::Duty6Bit,
+        7 => Duty::Duty7Bit,
+        8 => Duty::Duty8Bit,
+        9 => Duty::Duty9Bit,
+        10 => Duty::Duty10Bit,
+        11 => Duty::Duty11Bit,
+        12 => Duty::Duty12Bit,
+        13 => Duty::Duty13Bit,
+        _ => Duty::Duty14Bit,
+    }
+}
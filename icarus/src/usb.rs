@@ -0,0 +1,70 @@
+//
+// usb.rs
+//
+// @author Natesh Narain <nnaraindev@gmail.com>
+// @date Aug 30 2022
+//
+
+use crate::hal::pac::USB_DEVICE;
+
+/// EP1_CONF bits
+const WR_DONE: u32 = 1 << 0;
+const IN_EP_DATA_FREE: u32 = 1 << 1;
+const OUT_EP_DATA_AVAIL: u32 = 1 << 2;
+
+/// Hardware FIFO size, writes are flushed in chunks of this size
+const FIFO_SIZE: usize = 64;
+/// Polls of a full FIFO before giving up. Nothing drains the FIFO when no host is attached
+const WRITE_TIMEOUT_POLLS: u32 = 10_000;
+
+/// Binary access to the USB serial / JTAG controller
+///
+/// Unlike the hal's `UsbSerialJtag`, writes do not block forever without a host and bytes can be read back.
+pub struct UsbSerial;
+
+impl UsbSerial {
+    /// Write as much of `bytes` as the host accepts. Returns the number of bytes written
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        let usb = unsafe { &*USB_DEVICE::PTR };
+        let mut written = 0;
+
+        for chunk in bytes.chunks(FIFO_SIZE) {
+            for &b in chunk {
+                let mut polls = 0;
+                while usb.ep1_conf.read().bits() & IN_EP_DATA_FREE == 0 {
+                    polls += 1;
+                    if polls > WRITE_TIMEOUT_POLLS {
+                        return written;
+                    }
+                }
+
+                usb.ep1.write(|w| unsafe { w.bits(b as u32) });
+                written += 1;
+            }
+
+            usb.ep1_conf.write(|w| unsafe { w.bits(WR_DONE) });
+        }
+
+        written
+    }
+
+    /// Read whatever the host has sent, without blocking. Returns the number of bytes read
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let usb = unsafe { &*USB_DEVICE::PTR };
+        let mut count = 0;
+
+        while count < buf.len() && usb.ep1_conf.read().bits() & OUT_EP_DATA_AVAIL != 0 {
+            buf[count] = usb.ep1.read().bits() as u8;
+            count += 1;
+        }
+
+        count
+    }
+}
+
+impl core::fmt::Write for UsbSerial {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}